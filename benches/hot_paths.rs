@@ -0,0 +1,115 @@
+//! Benchmarks for the reader, writer, transform and statistics code shared by the tools.
+//! The tools have no library crate, so the shared modules are compiled in directly.
+
+// Only part of each shared module is exercised here, and the modules' #[test] functions
+// aren't compiled without the test harness, leaving their imports unused
+#![allow(dead_code, unused_imports)]
+
+#[path = "../src/args.rs"]
+pub mod args;
+#[cfg(feature = "clipboard")]
+#[path = "../src/clipboard.rs"]
+pub mod clipboard;
+#[path = "../src/csvutil.rs"]
+pub mod csvutil;
+#[path = "../src/dates.rs"]
+pub mod dates;
+#[cfg(feature = "fast-engine")]
+#[path = "../src/fastcsv.rs"]
+pub mod fastcsv;
+#[path = "../src/log.rs"]
+pub mod log;
+#[path = "../src/numbers.rs"]
+pub mod numbers;
+#[path = "../src/options.rs"]
+pub mod options;
+#[path = "../src/reader.rs"]
+pub mod reader;
+#[path = "../src/stats.rs"]
+pub mod stats;
+#[path = "../src/transform.rs"]
+pub mod transform;
+#[path = "../src/writer.rs"]
+pub mod writer;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use csv::StringRecord;
+use csvutil::RecordReader;
+use options::CsvOptions;
+use std::io::{self, BufRead, Cursor};
+
+fn reader(data: &str, options: &CsvOptions) -> csv::Reader<Box<dyn BufRead>> {
+    csvutil::csv_reader(options, Box::new(Cursor::new(data.as_bytes().to_vec())))
+}
+
+fn record_reader(data: &str, options: &CsvOptions) -> Box<dyn RecordReader> {
+    csvutil::record_reader(options, Box::new(Cursor::new(data.as_bytes().to_vec()))).unwrap()
+}
+
+fn cut(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cut");
+    let engines: &[(&str, bool)] = if cfg!(feature = "fast-engine") { &[("csv", false), ("fast", true)] } else { &[("csv", false)] };
+    for (name, rows, columns) in [("wide", 200, 500), ("long", 20_000, 5)] {
+        let data = csvutil::synthetic_csv(rows, columns);
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        for &(engine, fast_engine) in engines {
+            let options = CsvOptions { fast_engine, ..Default::default() };
+            let headers = record_reader(&data, &options).headers().unwrap();
+            let selected = csvutil::select_column_indices(&headers, &Some(vec!["1".to_string(), "3-5".to_string(), "-1".to_string()])).unwrap();
+            group.bench_function(format!("{}/{}", name, engine), |b| b.iter_batched(|| record_reader(&data, &options), |mut reader| {
+                let mut writer = csv::Writer::from_writer(io::sink());
+                let mut record = StringRecord::new();
+                while reader.read_record(&mut record).unwrap() {
+                    writer.write_record(selected.iter().flat_map(|&i| record.get(i))).unwrap();
+                }
+            }, BatchSize::SmallInput));
+        }
+    }
+    group.finish();
+}
+
+fn transform(c: &mut Criterion) {
+    let data = csvutil::synthetic_csv(20_000, 5);
+    let options = CsvOptions::default();
+    let headers = reader(&data, &options).headers().unwrap().clone();
+    let transforms = vec![
+        transform::parse_replace("col1:0=zero").unwrap(),
+        transform::parse_sub("col5:/text (\\d+)/t\\1/").unwrap(),
+    ];
+    let pipeline = transform::Pipeline::new(&transforms, &headers, false).unwrap();
+    let mut group = c.benchmark_group("transform");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("long", |b| b.iter_batched(|| reader(&data, &options), |mut reader| {
+        let mut record = StringRecord::new();
+        let mut values: Vec<String> = vec![];
+        let mut row = 0;
+        while reader.read_record(&mut record).unwrap() {
+            row += 1;
+            values.clear();
+            values.extend(record.iter().map(String::from));
+            pipeline.apply(row, &mut values);
+        }
+    }, BatchSize::SmallInput));
+    group.finish();
+}
+
+fn stat(c: &mut Criterion) {
+    let mut group = c.benchmark_group("stat");
+    for (name, rows, columns) in [("wide", 200, 500), ("long", 20_000, 5)] {
+        let data = csvutil::synthetic_csv(rows, columns);
+        let options = CsvOptions::default();
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_function(name, |b| b.iter_batched(|| reader(&data, &options), |mut reader| {
+            let mut statistics = (0..columns).map(|i| stats::CsvColumnStat::new(i, i.to_string())).collect::<Vec<_>>();
+            for result in reader.records() {
+                let record = result.unwrap();
+                statistics.iter_mut().for_each(|s| s.add(record.get(s.idx)));
+            }
+            statistics
+        }, BatchSize::SmallInput));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, cut, transform, stat);
+criterion_main!(benches);
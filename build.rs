@@ -0,0 +1,31 @@
+//! Records the git commit and enabled features for `--version` and the output manifests.
+
+use std::env;
+use std::fs;
+use std::process::Command;
+
+fn main() {
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=CSVSTAR_GIT_HASH={}", git_hash);
+
+    // Rebuild when a commit moves HEAD or the branch it points to
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    if let Some(branch) = fs::read_to_string(".git/HEAD").ok().and_then(|h| h.strip_prefix("ref: ").map(|r| r.trim().to_string())) {
+        println!("cargo:rerun-if-changed=.git/{}", branch);
+    }
+    println!("cargo:rerun-if-changed=.git/packed-refs");
+
+    let mut features = env::vars()
+        .filter_map(|(k, _)| k.strip_prefix("CARGO_FEATURE_").map(|f| f.to_lowercase().replace('_', "-")))
+        .filter(|f| f != "default")
+        .collect::<Vec<_>>();
+    features.sort();
+    println!("cargo:rustc-env=CSVSTAR_FEATURES={}", features.join(","));
+}
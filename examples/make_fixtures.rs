@@ -0,0 +1,65 @@
+//! Writes a large synthetic CSV file for manual and performance testing, mixing plain numbers
+//! with the awkward cases of the fixtures in `test/fixtures`: quoted delimiters and quotes,
+//! line breaks inside fields, non-ASCII text and, with `--ragged`, short and long rows.
+//!
+//!     cargo run --release --example make_fixtures -- big.csv --rows 1000000 --columns 20
+//!
+//! The same arguments always give the same file.
+
+use clap::{Arg, Command};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+const WORDS: [&str; 8] = ["alpha", "Zürich", "São Paulo", "Москва", "東京", "naïve café", "emoji 🚀", "plain"];
+
+fn value(rng: &mut StdRng, row: usize, column: usize) -> String {
+    match column % 5 {
+        0 => row.to_string(),
+        1 => format!("{:.2}", rng.gen_range(-1000.0..1000.0)),
+        2 => WORDS[rng.gen_range(0..WORDS.len())].to_string(),
+        3 => match rng.gen_range(0..10) {
+            0 => "comma, inside".to_string(),
+            1 => "say \"hi\"".to_string(),
+            2 => "first line\nsecond line".to_string(),
+            3 => String::new(),
+            _ => format!("text {}", rng.gen_range(0..100)),
+        },
+        _ => rng.gen_range(0..100_000).to_string(),
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut matches = Command::new("make_fixtures")
+        .about("Writes a large synthetic CSV file with quoted, multi-line and non-ASCII values.")
+        .arg(Arg::new("output").required(true).help("File to write"))
+        .arg(Arg::new("rows").long("rows").value_parser(clap::value_parser!(usize)).default_value("100000"))
+        .arg(Arg::new("columns").long("columns").value_parser(clap::value_parser!(usize)).default_value("10"))
+        .arg(Arg::new("seed").long("seed").value_parser(clap::value_parser!(u64)).default_value("1"))
+        .arg(Arg::new("ragged")
+            .long("ragged")
+            .action(clap::ArgAction::SetTrue)
+            .help("Give about one row in a hundred a field too few or too many"))
+        .get_matches();
+
+    let output = matches.remove_one::<String>("output").unwrap_or_default();
+    let rows = matches.remove_one::<usize>("rows").unwrap_or_default();
+    let columns = matches.remove_one::<usize>("columns").unwrap_or_default().max(1);
+    let ragged = matches.remove_one::<bool>("ragged").unwrap_or(false);
+    let mut rng = StdRng::seed_from_u64(matches.remove_one::<u64>("seed").unwrap_or_default());
+
+    let mut writer = csv::WriterBuilder::new().flexible(ragged).from_writer(BufWriter::new(File::create(&output)?));
+    writer.write_record((1..=columns).map(|c| format!("col{}", c)))?;
+    for row in 1..=rows {
+        let width = match ragged.then(|| rng.gen_range(0..200)) {
+            Some(0) => columns.saturating_sub(1).max(1),
+            Some(1) => columns + 1,
+            _ => columns,
+        };
+        writer.write_record((0..width).map(|c| value(&mut rng, row, c)).collect::<Vec<_>>())?;
+    }
+    writer.into_inner().map_err(|e| e.into_error())?.flush()?;
+    Ok(())
+}
@@ -1,15 +1,56 @@
-use crate::options::CsvOptions;
+use crate::csvutil;
+use crate::log;
+use crate::options::{self, ColorChoice, CsvOptions, Metrics};
+use crate::reader::{parse_range_assertion, parse_sha256, Constraint};
+use crate::transform::{parse_concat, parse_convert, parse_float_format, parse_json_extract, parse_replace, parse_replace_file, parse_split, parse_sub, parse_transform_config, parse_truncate, FloatFormat, Transform};
+use crate::writer::{parse_contract, Contract, ViolationPolicy};
 use clap::{Arg, ArgMatches, Command};
+use serde_json::json;
+use csv::{StringRecord, Trim};
+use std::error::Error;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// The commit the binaries were built from, or "unknown" outside a git checkout.
+pub const GIT_HASH: &str = env!("CSVSTAR_GIT_HASH");
+
+/// The optional features compiled in, comma separated.
+pub const FEATURES: &str = env!("CSVSTAR_FEATURES");
+
+/// The optional features compiled in, as the code sees them.
+pub fn features() -> Vec<&'static str> {
+    [
+        ("clipboard", cfg!(feature = "clipboard")),
+        ("fast-engine", cfg!(feature = "fast-engine")),
+        ("interactive", cfg!(feature = "interactive")),
+    ].into_iter().filter(|(_, enabled)| *enabled).map(|(feature, _)| feature).collect()
+}
+
+/// The `--log-json` event opening a run: what was run, and which build ran it.
+fn start_event() -> serde_json::Value {
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_hash": GIT_HASH,
+        "features": features(),
+        "arguments": std::env::args().skip(1).collect::<Vec<_>>(),
+    })
+}
+
+/// What `--version` reports: the crate version, commit and features.
+pub const VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), " (", env!("CSVSTAR_GIT_HASH"), ", features: ", env!("CSVSTAR_FEATURES"), ")");
 
 pub fn global_args() -> Command {
-    Command::new("CsvStar")
+    let command = Command::new("CsvStar")
+        .version(VERSION)
         .arg(Arg::new("input")
             .help("Input file to process")
             .required(false))
         .arg(Arg::new("output")
             .short('o')
             .long("output")
-            .help("Output file")
+            .help("Output file, or - for stdout. May be repeated to write the same output to several places, e.g. -o out.csv -o -")
+            .action(clap::ArgAction::Append)
             .required(false))
         .arg(Arg::new("no_output_headers")
             .long("no-output-headers")
@@ -20,44 +61,627 @@ pub fn global_args() -> Command {
             .long("no-header-row")
             .help("Input file has no headers")
             .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("header_row")
+            .long("header-row")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .conflicts_with("input_has_no_headers")
+            .help("Row number of the header row, where a quoted field spanning lines counts as one row. Rows before it are skipped"))
+        .arg(Arg::new("header_aliases")
+            .long("header-aliases")
+            .value_name("FILE")
+            .value_parser(csvutil::parse_header_aliases)
+            .help("Rename header names before selecting columns, using alias,name rows in this CSV file after its header row, \
+                   e.g. E-mail,email. Aliases match ignoring case and surrounding spaces"))
+        .arg(Arg::new("auto_header")
+            .long("auto-header")
+            .conflicts_with("input_has_no_headers")
+            .requires("input")
+            .help("Guess whether the input file has a header row by checking if the first row's values fit the rest")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("verbose")
+            .short('v')
+            .long("verbose")
+            .help("Report decisions such as --auto-header's guess on stderr")
+            .action(clap::ArgAction::SetTrue))
         .arg(Arg::new("flexible")
             .short('f')
             .long("flexible")
             .help("Allow variable number of fields per record")
             .action(clap::ArgAction::SetTrue))
-        .arg(Arg::new("trim_fields").short('m').long("trimfields").help("Trim fields and headers").action(clap::ArgAction::SetTrue))
-        .arg(Arg::new("delimiter").short('d').long("delimiter").help("Delimiter character"))
-        .arg(Arg::new("quote_char").short('q').long("quotechar").help("Quote character"))
-        .arg(Arg::new("escape_char").short('p').long("escapechar").help("Escape character"))
-        .arg(Arg::new("comment_char").short('n').long("commentchar").help("Comment character"))
+        .arg(Arg::new("trim_fields").short('m').long("trimfields").help("Trim fields and headers, the same as --trim all").action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("trim")
+            .long("trim")
+            .value_parser(["headers", "fields", "all", "none"])
+            .conflicts_with("trim_fields")
+            .help("Trim the whitespace around header names, data fields, both or neither"))
+        .arg(Arg::new("delimiter").short('d').long("delimiter").value_parser(parse_ascii_char).help("Delimiter character"))
+        .arg(Arg::new("quote_char").short('q').long("quotechar").value_parser(parse_ascii_char).help("Quote character"))
+        .arg(Arg::new("escape_char").short('p').long("escapechar").value_parser(parse_ascii_char).help("Escape character"))
+        .arg(Arg::new("comment_char").short('n').long("commentchar").value_parser(parse_ascii_char).help("Comment character"))
+        .arg(Arg::new("normalize_newlines")
+            .long("normalize-newlines")
+            .help("Read CRLF and lone CR line endings as LF, including inside quoted fields")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("skip_blank_lines")
+            .long("skip-blank-lines")
+            .help("Ignore lines holding only spaces or tabs between records")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("drop_empty_rows")
+            .long("drop-empty-rows")
+            .help("Drop records whose fields are all empty or whitespace, quoted or not")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("drop_if_empty")
+            .long("drop-if-empty")
+            .value_name("COLUMNS")
+            .help("Drop records where any of these columns is empty or whitespace, e.g. id,email. Takes the same syntax as -c"))
+        .arg(Arg::new("assert_unique")
+            .long("assert-unique")
+            .value_name("COLUMNS")
+            .action(clap::ArgAction::Append)
+            .help("Fail if a value repeats in any of these columns, naming both rows, e.g. id. Empty values are not checked"))
+        .arg(Arg::new("assert_not_null")
+            .long("assert-not-null")
+            .value_name("COLUMNS")
+            .action(clap::ArgAction::Append)
+            .help("Fail on an empty or whitespace value in any of these columns, naming the row, e.g. email"))
+        .arg(Arg::new("assert_range")
+            .long("assert-range")
+            .value_name("COLUMN:MIN..MAX")
+            .value_parser(parse_range_assertion)
+            .action(clap::ArgAction::Append)
+            .help("Fail on a value of this column that isn't a number from MIN to MAX inclusive, naming the row, \
+                   e.g. qty:0..1000. Either bound may be left out. Empty values are not checked"))
+        .arg(Arg::new("strict_rfc4180")
+            .long("strict-rfc4180")
+            .help("Reject input that isn't RFC 4180 compliant: bare CRs, stray quotes, text after a closing quote or rows with differing field counts")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("max_field_size")
+            .long("max-field-size")
+            .value_parser(parse_byte_size)
+            .help("Fail on any input field larger than this, e.g. 1MB, naming the record"))
+        .arg(Arg::new("max_record_size")
+            .long("max-record-size")
+            .value_parser(parse_byte_size)
+            .help("Fail on any input record larger than this, e.g. 10MB, naming the record"))
+        .arg(Arg::new("verify_sha256")
+            .long("verify-sha256")
+            .value_name("HEX")
+            .value_parser(parse_sha256)
+            .help("Fail after reading the input if its SHA-256 digest differs, e.g. from a truncated transfer"))
+        .arg(Arg::new("summary")
+            .long("summary")
+            .action(clap::ArgAction::SetTrue)
+            .help("After a successful run, print one line on stderr with the records read, written and skipped, \
+                   the bytes read and written, the wall time and the throughput, e.g. for cron job logs"))
+        .arg(Arg::new("log_json")
+            .long("log-json")
+            .action(clap::ArgAction::SetTrue)
+            .help("Report on stderr as JSON lines: start, progress, info, warning, error and end events, each with event, \
+                   tool and time fields, for orchestrators to parse"))
+        .arg(Arg::new("manifest_file")
+            .long("write-manifest")
+            .value_name("FILE")
+            .help("Write a JSON manifest of the output: record count, columns and types, size, SHA-256, tool version and arguments"))
+        .arg(Arg::new("max_output_bytes")
+            .long("max-output-bytes")
+            .value_parser(parse_byte_size)
+            .help("Split output into numbered part files of at most this size, e.g. 100MB. Each part repeats the header row"))
+        .arg(Arg::new("generate_man")
+            .long("generate-man")
+            .exclusive(true)
+            .action(clap::ArgAction::SetTrue)
+            .help("Print the tool's man page in roff and exit, e.g. csvcut --generate-man > csvcut.1"));
+    #[cfg(feature = "clipboard")]
+    let command = command.arg(Arg::new("clipboard")
+        .long("clipboard")
+        .conflicts_with_all(["input", "output", "max_output_bytes"])
+        .action(clap::ArgAction::SetTrue)
+        .help("Read the input from the clipboard and copy the output back to it. \
+               Both are tab separated, as spreadsheets copy and paste them, unless -d is given"));
+    command
+}
+
+/// Value transforms for the tools that rewrite records, see `transform::Pipeline`.
+pub fn transform_args(command: Command) -> Command {
+    command
+        .arg(Arg::new("replace")
+            .long("replace")
+            .value_parser(parse_replace)
+            .help("Recode a value in the given columns, e.g. gender:M=Male. May be repeated")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("replace_file")
+            .long("replace-file")
+            .value_parser(parse_replace_file)
+            .help("Recode values in the given columns using old,new pairs from a CSV file, e.g. country:codes.csv")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("sub")
+            .long("sub")
+            .value_parser(parse_sub)
+            .help("Regex substitution in the given columns, e.g. phone:/(\\d{3})-(\\d{4})/\\1\\2/g. Flags: g replaces every match, i ignores case. May be repeated")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("split_column")
+            .long("split-column")
+            .value_parser(parse_split)
+            .help("Split a column on a separator into new columns appended to each record, e.g. \"name: ,:first,last\". Missing parts are left empty and the last column takes the remainder. May be repeated")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("max_splits")
+            .long("max-splits")
+            .value_parser(clap::value_parser!(usize))
+            .help("Split at most this many times, leaving the remaining new columns empty"))
+        .arg(Arg::new("concat")
+            .long("concat")
+            .value_parser(parse_concat)
+            .help("Join columns and quoted literals into a new column appended to each record, e.g. \"full_name=first,' ',last\". May be repeated")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("json_extract")
+            .long("json-extract")
+            .value_parser(parse_json_extract)
+            .help("Extract a value from a column holding JSON into a new column appended to each record, e.g. payload:$.user.id=user_id. \
+                   Paths take .key, [0] and [\"key\"] steps. Missing values, null and invalid JSON give an empty value. May be repeated")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("convert")
+            .long("convert")
+            .value_parser(parse_convert)
+            .help("Convert the given columns between units, e.g. size:bytes:MB, height:ft:m, elapsed:s:human or created:epoch:iso. \
+                   Units: B, KB, MB, GB, TB (binary multiples); mm, cm, m, km, in, ft, yd, mi; ms, s, min, h, d. Values that don't parse are left alone. May be repeated")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("float_format")
+            .long("float-format")
+            .value_parser(parse_float_format)
+            .requires("convert")
+            .help("Write the numbers --convert gives with a printf-style format, %.Nf or %.Ne, e.g. %.2f. \
+                   By default they have at most six decimals, with trailing zeros dropped"))
+        .arg(Arg::new("truncate")
+            .long("truncate")
+            .value_parser(parse_truncate)
+            .help("Cut values in the given columns longer than a number of bytes short, without splitting a character, e.g. name=255. \
+                   The row of each cut value is logged as a warning. May be repeated")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("transform_config")
+            .long("transform-config")
+            .value_name("FILE")
+            .value_parser(parse_transform_config)
+            .help("Apply the per-column trim, null mapping, case and cast rules in a TOML file, \
+                   one [columns.NAME] table per column, e.g. trim = true, nulls = [\"N/A\"], case = \"lower\", cast = \"date\". May be repeated")
+            .action(clap::ArgAction::Append))
+}
+
+/// Hidden `--self-bench` flag for the tools with a benchmark mode, see `csvutil::self_bench`.
+pub fn self_bench_args(command: Command) -> Command {
+    command.arg(Arg::new("self_bench")
+        .long("self-bench")
+        .hide(true)
+        .action(clap::ArgAction::SetTrue))
+}
+
+/// `--engine` for the tools that can read through `csvutil::record_reader`.
+pub fn engine_args(command: Command) -> Command {
+    command.arg(Arg::new("engine")
+        .long("engine")
+        .value_parser(["csv", "fast"])
+        .default_value("csv")
+        .help("CSV parser. fast splits unquoted lines directly and only fully parses quoted records, which pays off \
+               with long fields; it doesn't support --escapechar, --commentchar or --trim"))
+}
+
+/// `--sort-columns` and `--columns-from` for the tools whose output columns are merged from several inputs.
+pub fn column_order_args(command: Command) -> Command {
+    command
+        .arg(Arg::new("sort_columns")
+            .long("sort-columns")
+            .help("Write the columns in order of their names")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("columns_from")
+            .long("columns-from")
+            .value_name("FILE")
+            .value_parser(parse_columns_from)
+            .help("Write the columns in the order of this CSV file's header row, then any others. Its columns missing \
+                   from the output are written empty"))
+}
+
+/// Reads the header row of a `--columns-from` file.
+fn parse_columns_from(path: &str) -> Result<Vec<String>, String> {
+    let mut reader = csv::Reader::from_path(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+    let headers = reader.headers().map_err(|e| format!("Unable to read {}: {}", path, e))?;
+    Ok(headers.iter().map(|h| h.to_string()).collect())
+}
+
+/// `--follow` for the streaming tools, which can emit each record as it's appended to the input.
+pub fn follow_args(command: Command) -> Command {
+    command.arg(Arg::new("follow")
+        .long("follow")
+        .action(clap::ArgAction::SetTrue)
+        .requires("input")
+        .help("Keep the input file open and process records as they are appended, like tail -f, flushing the output after each one. Stop with Ctrl-C"))
+}
+
+/// `--color`, for the tools writing for a terminal, see `CsvOptions::use_color`.
+pub fn color_args(command: Command) -> Command {
+    command.arg(Arg::new("color")
+        .long("color")
+        .value_parser(["auto", "always", "never"])
+        .default_value("auto")
+        .help("Color the output: always, never, or auto for when printing to a terminal and NO_COLOR isn't set"))
+}
+
+/// `--enforce-schema` for the tools writing records, see `writer::Contract`.
+pub fn contract_args(command: Command) -> Command {
+    command
+        .arg(Arg::new("enforce_schema")
+            .long("enforce-schema")
+            .value_name("FILE")
+            .value_parser(parse_contract)
+            .help("Make the output have exactly the columns of this Table Schema or csvschema JSON file, in its order: \
+                   missing columns are added empty and others dropped. Values are checked against the integer, number \
+                   and boolean types and required constraints. By position when the output has no header row"))
+        .arg(Arg::new("on_schema_violation")
+            .long("on-schema-violation")
+            .value_parser(["fail", "blank", "keep"])
+            .default_value("fail")
+            .requires("enforce_schema")
+            .help("What to do with a value that breaks --enforce-schema: fail, blank it (failing only in required columns) or keep it with a warning"))
+}
+
+/// `--add-source-columns` for the tools writing one record per input record.
+pub fn source_args(command: Command) -> Command {
+    command.arg(Arg::new("add_source_columns")
+        .long("add-source-columns")
+        .action(clap::ArgAction::SetTrue)
+        .help("Append _source_file and _source_row columns giving each record's input file (- for stdin) and 1-based record number in it"))
 }
 
-pub fn build_options(mut arg_matches: ArgMatches) -> CsvOptions {
+/// `-i/--in-place` for the tools writing records, see `writer::InPlace`.
+pub fn in_place_args(command: Command) -> Command {
+    command.arg(Arg::new("in_place")
+        .short('i')
+        .long("in-place")
+        .value_name("SUFFIX")
+        .num_args(0..=1)
+        .require_equals(true)
+        .default_missing_value("")
+        .requires("input")
+        .conflicts_with_all(["output", "max_output_bytes"])
+        .help("Replace the input file with the output once it has all been written. \
+               With a suffix, e.g. --in-place=.bak, keep the original under its name with the suffix appended"))
+}
+
+/// `--raw-passthrough`, for the tools that only drop or reorder whole records.
+pub fn raw_passthrough_args(command: Command) -> Command {
+    command.arg(Arg::new("raw_passthrough")
+        .long("raw-passthrough")
+        .action(clap::ArgAction::SetTrue)
+        .help("Copy each record's bytes as they were read, keeping their quoting and line endings, rather than writing \
+               its values again, which is also faster. The header row is still written by the tool"))
+}
+
+/// `--max-memory`, for the tools holding values in memory, see `csvutil::check_memory`.
+pub fn memory_args(command: Command) -> Command {
+    command.arg(Arg::new("max_memory")
+        .long("max-memory")
+        .value_parser(parse_byte_size)
+        .help("Fail rather than hold more than about this much in memory, e.g. 2GB, suggesting how to need less"))
+}
+
+/// `--true-values` and `--false-values` for the tools that infer boolean columns, and for csvcut's
+/// `cast = "boolean"`.
+pub fn boolean_args(command: Command) -> Command {
+    command
+        .arg(Arg::new("true_values")
+            .long("true-values")
+            .value_delimiter(',')
+            .help("Comma-separated spellings of true in boolean columns, compared case-insensitively [default: true,yes,t,1]"))
+        .arg(Arg::new("false_values")
+            .long("false-values")
+            .value_delimiter(',')
+            .help("Comma-separated spellings of false in boolean columns, compared case-insensitively [default: false,no,f,0]"))
+}
+
+/// Collects `--first`, `--last` and `--move` in the order they appeared on the command line.
+fn ordered_reorders(arg_matches: &mut ArgMatches) -> Vec<csvutil::Reorder> {
+    let mut reorders: Vec<(usize, csvutil::Reorder)> = vec![];
+    for id in ["first", "last", "move"] {
+        // Only csvcut takes them
+        if !matches!(arg_matches.try_contains_id(id), Ok(true)) {
+            continue;
+        }
+        let indices = arg_matches.indices_of(id).map(|i| i.collect::<Vec<_>>()).unwrap_or_default();
+        let values: Vec<csvutil::Reorder> = match id {
+            "move" => arg_matches.remove_many::<csvutil::Reorder>(id).map(|v| v.collect()).unwrap_or_default(),
+            _ => arg_matches.remove_many::<String>(id).map(|v| v.map(|s| {
+                let columns = csvutil::parse_column_list(&s);
+                if id == "first" { csvutil::Reorder::First(columns) } else { csvutil::Reorder::Last(columns) }
+            }).collect()).unwrap_or_default(),
+        };
+        reorders.extend(indices.into_iter().zip(values));
+    }
+    reorders.sort_by_key(|(i, _)| *i);
+    reorders.into_iter().map(|(_, r)| r).collect()
+}
+
+/// Collects the transform arguments in the order they appeared on the command line.
+fn ordered_transforms(arg_matches: &mut ArgMatches) -> Vec<Transform> {
+    let mut transforms: Vec<(usize, Transform)> = vec![];
+    for id in ["replace", "replace_file", "sub", "split_column", "concat", "convert", "json_extract", "truncate"] {
+        // Not every binary takes transform arguments
+        if !matches!(arg_matches.try_contains_id(id), Ok(true)) {
+            continue;
+        }
+        let indices = arg_matches.indices_of(id).map(|i| i.collect::<Vec<_>>()).unwrap_or_default();
+        if let Some(values) = arg_matches.remove_many::<Transform>(id) {
+            transforms.extend(indices.into_iter().zip(values));
+        }
+    }
+    // A config file gives its rules in the place the file was given
+    if matches!(arg_matches.try_contains_id("transform_config"), Ok(true)) {
+        let indices = arg_matches.indices_of("transform_config").map(|i| i.collect::<Vec<_>>()).unwrap_or_default();
+        if let Some(files) = arg_matches.remove_many::<Vec<Transform>>("transform_config") {
+            transforms.extend(indices.into_iter().zip(files).flat_map(|(i, rules)| rules.into_iter().map(move |t| (i, t))));
+        }
+    }
+    transforms.sort_by_key(|(i, _)| *i);
+
+    if let Ok(Some(max)) = arg_matches.try_remove_one::<usize>("max_splits") {
+        for (_, transform) in transforms.iter_mut() {
+            if let Transform::Split { max_splits, .. } = transform {
+                *max_splits = Some(max);
+            }
+        }
+    }
+    if let Ok(Some(format)) = arg_matches.try_remove_one::<FloatFormat>("float_format") {
+        for (_, transform) in transforms.iter_mut() {
+            if let Transform::Convert { float_format, .. } = transform {
+                *float_format = Some(format);
+            }
+        }
+    }
+
+    // Consecutive replacements on the same columns form one mapping, so they don't chain into each other
+    let mut merged: Vec<Transform> = vec![];
+    for (_, transform) in transforms {
+        match (merged.last_mut(), transform) {
+            (Some(Transform::Replace { columns, map }), Transform::Replace { columns: c, map: m }) if *columns == c => {
+                m.into_iter().for_each(|(k, v)| { map.entry(k).or_insert(v); });
+            }
+            (_, transform) => merged.push(transform),
+        }
+    }
+    merged
+}
+
+/// Parses a delimiter, quote, escape or comment character. The csv parser works on bytes, so
+/// only a single ASCII character will do; anything else is rejected rather than truncated.
+pub fn parse_ascii_char(s: &str) -> Result<char, String> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => options::ascii_char(c),
+        _ => Err(format!("'{}' is not a single character", s)),
+    }
+}
+
+/// Parses sizes like `500`, `64KB`, `100MB` or `2GB` (binary multiples) into a byte count.
+pub fn parse_byte_size(s: &str) -> Result<u64, String> {
+    let upper = s.trim().to_ascii_uppercase();
+    let digits = upper.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier: u64 = match upper[digits.len()..].trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        unit => return Err(format!("Unknown size unit '{}' in {}", unit, s)),
+    };
+    let n = digits.trim().parse::<u64>().map_err(|_| format!("Invalid size: {}", s))?;
+    if n == 0 {
+        return Err(format!("Size must be greater than zero: {}", s));
+    }
+    n.checked_mul(multiplier).ok_or_else(|| format!("Size too large: {}", s))
+}
+
+/// The options given on the command line, exiting with a usage error if they contradict each other.
+pub fn build_options(arg_matches: ArgMatches) -> CsvOptions {
+    let options = read_options(arg_matches);
+    if let Err(e) = options.validate() {
+        clap::Error::raw(clap::error::ErrorKind::ArgumentConflict, format!("{}\n", e)).exit();
+    }
+    options
+}
+
+fn read_options(mut arg_matches: ArgMatches) -> CsvOptions {
     let mut options = CsvOptions::new();
     options.input_file = arg_matches.remove_one("input").filter(|f| f != "-");
-    options.output_file = arg_matches.remove_one("output").filter(|f| f != "-");
+    let mut outputs = arg_matches.remove_many::<String>("output").map(|v| v.collect::<Vec<_>>()).unwrap_or_default();
+    if !outputs.is_empty() {
+        options.output_file = Some(outputs.remove(0)).filter(|f| f != "-");
+        options.tee = outputs;
+    }
 
     options.output_headers = arg_matches.remove_one::<bool>("no_output_headers").map(|v| !v);
     options.input_has_headers = arg_matches.remove_one::<bool>("input_has_no_headers").map(|v| !v);
     options.flexible = arg_matches.remove_one("flexible");
 
-    options.delimiter = arg_matches.remove_one::<String>("delimiter")
-        .map(|s| s.chars().next().unwrap());
-    options.quote_char = arg_matches.remove_one::<String>("quote_char")
-        .map(|s| s.chars().next().unwrap());
-    options.escape_char = arg_matches.remove_one::<String>("escape_char")
-        .map(|s| s.chars().next().unwrap());
-    options.comment_char = arg_matches.remove_one::<String>("comment_char")
-        .map(|s| s.chars().next().unwrap());
-    options.trim_fields = arg_matches.remove_one("trim_fields");
+    options.delimiter = arg_matches.remove_one("delimiter");
+    options.quote_char = arg_matches.remove_one("quote_char");
+    options.escape_char = arg_matches.remove_one("escape_char");
+    options.comment_char = arg_matches.remove_one("comment_char");
+    options.trim = match arg_matches.remove_one::<String>("trim").as_deref() {
+        Some("headers") => Trim::Headers,
+        Some("fields") => Trim::Fields,
+        Some("all") => Trim::All,
+        Some(_) => Trim::None,
+        None if arg_matches.remove_one("trim_fields").unwrap_or(false) => Trim::All,
+        None => Trim::None,
+    };
+    options.max_output_bytes = arg_matches.remove_one("max_output_bytes");
+    options.transforms = ordered_transforms(&mut arg_matches);
+    options.verbose = arg_matches.remove_one("verbose").unwrap_or(false);
+    options.normalize_newlines = arg_matches.remove_one("normalize_newlines").unwrap_or(false);
+    options.skip_blank_lines = arg_matches.remove_one("skip_blank_lines").unwrap_or(false);
+    options.drop_empty_rows = arg_matches.remove_one("drop_empty_rows").unwrap_or(false);
+    options.drop_if_empty = arg_matches.remove_one::<String>("drop_if_empty").map(|s| csvutil::parse_column_list(&s));
+    for (id, constraint) in [("assert_unique", Constraint::Unique), ("assert_not_null", Constraint::NotNull)] {
+        for columns in arg_matches.remove_many::<String>(id).into_iter().flatten() {
+            options.assertions.push((csvutil::parse_column_list(&columns), constraint.clone()));
+        }
+    }
+    options.assertions.extend(arg_matches.remove_many::<(String, Constraint)>("assert_range").into_iter().flatten()
+        .map(|(column, range)| (vec![column], range)));
+    options.strict_rfc4180 = arg_matches.remove_one("strict_rfc4180").unwrap_or(false);
+    options.max_field_size = arg_matches.remove_one("max_field_size");
+    options.max_record_size = arg_matches.remove_one("max_record_size");
+    options.header_row = arg_matches.remove_one("header_row");
+    options.header_aliases = arg_matches.remove_one("header_aliases");
+    options.manifest_file = arg_matches.remove_one("manifest_file");
+    if arg_matches.remove_one("log_json").unwrap_or(false) {
+        log::set_json(true);
+        log::event("start", start_event());
+    }
+    options.summary = arg_matches.remove_one("summary").unwrap_or(false);
+    options.max_memory = arg_matches.try_remove_one("max_memory").ok().flatten();
+    options.metrics = (options.summary || log::is_json()).then(|| Arc::new(Metrics::new()));
+    options.verify_sha256 = arg_matches.remove_one("verify_sha256");
+    options.self_bench = matches!(arg_matches.try_remove_one("self_bench"), Ok(Some(true)));
+    options.fast_engine = matches!(arg_matches.try_remove_one::<String>("engine"), Ok(Some(e)) if e == "fast");
+    options.sort_columns = matches!(arg_matches.try_remove_one("sort_columns"), Ok(Some(true)));
+    options.columns_from = arg_matches.try_remove_one("columns_from").ok().flatten();
+    options.follow = matches!(arg_matches.try_remove_one("follow"), Ok(Some(true)));
+    options.color = match arg_matches.try_remove_one::<String>("color").ok().flatten().as_deref() {
+        Some("always") => ColorChoice::Always,
+        Some("never") => ColorChoice::Never,
+        _ => ColorChoice::Auto,
+    };
+    options.zero_based = matches!(arg_matches.try_remove_one("zero_based"), Ok(Some(true)));
+    options.emit_spec = matches!(arg_matches.try_remove_one("emit_spec"), Ok(Some(true)));
+    options.reorders = ordered_reorders(&mut arg_matches);
+    options.add_source_columns = matches!(arg_matches.try_remove_one("add_source_columns"), Ok(Some(true)));
+    options.duplicate_names = if matches!(arg_matches.try_remove_one("select_first"), Ok(Some(true))) {
+        csvutil::DuplicateNames::First
+    } else if matches!(arg_matches.try_remove_one("select_all_matches"), Ok(Some(true))) {
+        csvutil::DuplicateNames::All
+    } else {
+        csvutil::DuplicateNames::Error
+    };
+    options.contract = arg_matches.try_remove_one::<Contract>("enforce_schema").ok().flatten();
+    if let Some(contract) = options.contract.as_mut() {
+        contract.policy = match arg_matches.remove_one::<String>("on_schema_violation").as_deref() {
+            Some("blank") => ViolationPolicy::Blank,
+            Some("keep") => ViolationPolicy::Keep,
+            _ => ViolationPolicy::Fail,
+        };
+    }
+    options.in_place = arg_matches.try_remove_one::<String>("in_place").ok().flatten();
+    options.raw_passthrough = matches!(arg_matches.try_remove_one("raw_passthrough"), Ok(Some(true)));
+    options.clipboard = matches!(arg_matches.try_remove_one("clipboard"), Ok(Some(true)));
+    if options.clipboard && options.delimiter.is_none() {
+        options.delimiter = Some('\t');
+    }
+    options.true_values = arg_matches.try_remove_many::<String>("true_values").ok().flatten().map(|v| v.collect());
+    options.false_values = arg_matches.try_remove_many::<String>("false_values").ok().flatten().map(|v| v.collect());
+
+    // An unreadable input is left for the tool itself to report
+    if arg_matches.remove_one("auto_header").unwrap_or(false) && options.input_file.is_some() {
+        match csvutil::detect_headers(&options) {
+            Ok(has_headers) => {
+                if options.verbose {
+                    log::info(&format!("--auto-header: treating the first row as {}", if has_headers { "a header" } else { "data" }));
+                }
+                options.input_has_headers = Some(has_headers);
+            }
+            Err(e) if options.verbose => log::info(&format!("--auto-header: {}; treating the first row as a header", e)),
+            Err(_) => {}
+        }
+    }
 
     options
 }
 
 
+/// The hidden first argument that turns any tool into a column name lister for shell completion.
+pub const COMPLETE_COLUMNS: &str = "__complete-columns";
+
+/// The first argument that makes any tool print its man page instead of running.
+pub const GENERATE_MAN: &str = "--generate-man";
+
+/// The column selection syntax, for the man pages of the tools taking `-c`.
+const COLUMN_SELECTION: [&str; 5] = [
+    "Columns are given to -c as a comma-separated list of names, 1-based offsets and inclusive ranges, \
+     e.g. -c 1,id,3-5. Names are matched against the header row, or against a, b, c, ... with --no-header-row.",
+    "Negative offsets count from the end, so -1 is the last column and -2 the one before it.",
+    "Ranges may also be of names, e.g. first_name-email, taking every column from the first to the second.",
+    "Names containing commas or dashes are quoted, e.g. -c '\"revenue, net\",id'. \
+     A name shared by several columns is an error unless the tool is told which to take.",
+    "With --zero-based, offsets and ranges count from 0 instead, where the tool takes it. -1 is still the last column.",
+];
+
+/// Writes the man page for a tool, built from its command line definition.
+pub fn man_page(command: Command, tool: &str, out: &mut dyn Write) -> io::Result<()> {
+    let takes_columns = command.get_arguments().any(|a| a.get_short() == Some('c'));
+    let has_after_help = command.get_after_help().is_some();
+    let man = clap_mangen::Man::new(command.display_name(tool).bin_name(tool)).title(tool.to_uppercase());
+    man.render_title(out)?;
+    man.render_name_section(out)?;
+    man.render_synopsis_section(out)?;
+    man.render_description_section(out)?;
+    man.render_options_section(out)?;
+    if takes_columns {
+        let mut roff = roff::Roff::new();
+        roff.control("SH", ["COLUMN SELECTION"]);
+        for (i, paragraph) in COLUMN_SELECTION.iter().enumerate() {
+            if i > 0 {
+                roff.control("PP", []);
+            }
+            roff.text([roff::roman(*paragraph)]);
+        }
+        roff.to_writer(out)?;
+    }
+    if has_after_help {
+        man.render_extra_section(out)?;
+    }
+    man.render_version_section(out)
+}
+
+/// Parses the tool's command line. `tool __complete-columns [options] FILE` instead prints the
+/// column names of FILE, read with the usual input options, one per line, so completion scripts
+/// can offer them after `-c`. It prints nothing on failure, as a completion shouldn't complain.
+/// `tool --generate-man` prints the tool's man page.
+pub fn get_matches_from(command: Command, args: Vec<String>) -> ArgMatches {
+    if args.get(1).is_some_and(|a| a == GENERATE_MAN) {
+        let tool = Path::new(&args[0]).file_name().map_or(args[0].clone(), |n| n.to_string_lossy().to_string());
+        let result = man_page(command, &tool, &mut io::stdout().lock());
+        std::process::exit(if result.is_ok() { 0 } else { 1 });
+    }
+    if args.get(1).is_some_and(|a| a == COMPLETE_COLUMNS) {
+        let Ok(matches) = global_args().no_binary_name(true).try_get_matches_from(&args[2..]) else {
+            std::process::exit(1);
+        };
+        match complete_columns(&read_options(matches)) {
+            Ok(names) => {
+                names.iter().for_each(|name| println!("{}", name));
+                std::process::exit(0);
+            }
+            Err(_) => std::process::exit(1),
+        }
+    }
+    command.get_matches_from(args)
+}
+
+/// The column names of the input file, for `__complete-columns`. Standard input isn't read,
+/// since a completion must not block.
+pub fn complete_columns(options: &CsvOptions) -> Result<StringRecord, Box<dyn Error>> {
+    if options.input_file.is_none() {
+        return Err(Box::from("No input file to complete columns from"));
+    }
+    let mut reader = csvutil::csv_reader(options, options.get_input_file()?);
+    Ok(csvutil::column_names(options, reader.headers()?.clone()))
+}
+
+
 #[cfg(test)]
 mod tests {
-    use crate::args::{build_options, global_args};
+    use crate::args::{build_options, complete_columns, global_args, man_page, parse_ascii_char, parse_byte_size, raw_passthrough_args, read_options, start_event, transform_args, FEATURES, GIT_HASH};
+    use crate::options::CsvOptions;
+    use crate::reader::Constraint;
+    use crate::transform::Transform;
+    use csv::Trim;
+    use std::collections::HashMap;
 
     #[test]
     fn test_build_args() {
@@ -80,12 +704,176 @@ mod tests {
         assert_eq!(options.input_file.unwrap(), "test.csv");
         assert_eq!(options.output_file.unwrap(), "output.csv");
 
-        assert_eq!(options.output_headers.unwrap(), false);
-        assert_eq!(options.input_has_headers.unwrap(), false);
+        assert!(!options.output_headers.unwrap());
+        assert!(!options.input_has_headers.unwrap());
         assert_eq!(options.delimiter.unwrap(), ';');
         assert_eq!(options.quote_char.unwrap(), '\'');
         assert_eq!(options.escape_char.unwrap(), '@');
         assert_eq!(options.comment_char.unwrap(), '$');
-        assert_eq!(options.trim_fields.unwrap(), true);
+        assert_eq!(options.trim, Trim::All);
+    }
+
+    #[test]
+    fn test_tee() {
+        let options = build_options(global_args().get_matches_from(["CsvStar", "-o", "-", "-o", "a.csv", "-o", "b.csv"]));
+        assert_eq!(options.output_file, None);
+        assert_eq!(options.tee, ["a.csv", "b.csv"]);
+        let options = read_options(global_args().get_matches_from(["CsvStar", "-o", "a.csv", "-o", "a.csv"]));
+        assert_eq!(options.validate().unwrap_err(), "-o a.csv is given more than once");
+    }
+
+    #[test]
+    fn test_assertions() {
+        let args = ["CsvStar", "--assert-range", "qty:0..1000", "--assert-unique", "id,sku", "--assert-not-null", "email"];
+        let options = build_options(global_args().get_matches_from(args));
+        assert_eq!(options.assertions, [
+            (vec!["id".to_string(), "sku".to_string()], Constraint::Unique),
+            (vec!["email".to_string()], Constraint::NotNull),
+            (vec!["qty".to_string()], Constraint::Range(Some(0.0), Some(1000.0))),
+        ]);
+        assert!(global_args().try_get_matches_from(["CsvStar", "--assert-range", "qty:1000"]).is_err());
+
+        let dir = crate::csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_assertions.csv");
+        std::fs::write(input_file, "id,sku,email,qty\n1,a,x@y.org,5\n2,a,z@y.org,7\n").unwrap();
+        let options = CsvOptions { input_file: Some(input_file.to_string()), ..options };
+        let mut reader = crate::csvutil::csv_reader(&options, options.get_input_file().unwrap());
+        let error = reader.records().collect::<Result<Vec<_>, _>>().unwrap_err();
+        assert_eq!(error.to_string(), "--assert-unique failed at row 2, column sku: 'a' repeats row 1");
+    }
+
+    #[test]
+    fn test_trim() {
+        for (value, trim) in [("headers", Trim::Headers), ("fields", Trim::Fields), ("all", Trim::All), ("none", Trim::None)] {
+            let options = build_options(global_args().get_matches_from(["CsvStar", "--trim", value]));
+            assert_eq!(options.trim, trim);
+        }
+        assert_eq!(build_options(global_args().get_matches_from(["CsvStar"])).trim, Trim::None);
+        assert!(global_args().try_get_matches_from(["CsvStar", "-m", "--trim", "headers"]).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_ascii_char() {
+        assert_eq!(parse_ascii_char(";"), Ok(';'));
+        assert_eq!(parse_ascii_char("\t"), Ok('\t'));
+        assert!(parse_ascii_char("\u{a6}").expect_err("non-ASCII should be rejected").contains("not an ASCII character"));
+        assert!(parse_ascii_char("\u{ff1b}").is_err());
+        assert!(parse_ascii_char(";;").expect_err("two characters should be rejected").contains("not a single character"));
+        assert!(parse_ascii_char("").is_err());
+        assert!(global_args().try_get_matches_from(["CsvStar", "-d", "\u{a6}"]).is_err());
+        assert!(global_args().try_get_matches_from(["CsvStar", "-q", "\u{201c}"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("500"), Ok(500));
+        assert_eq!(parse_byte_size("64KB"), Ok(64 * 1024));
+        assert_eq!(parse_byte_size("100MB"), Ok(100 * 1024 * 1024));
+        assert_eq!(parse_byte_size("2g"), Ok(2 * 1024 * 1024 * 1024));
+        assert!(parse_byte_size("10XB").is_err());
+        assert!(parse_byte_size("0").is_err());
+        assert_eq!(parse_byte_size("99999999999999G"), Err("Size too large: 99999999999999G".to_string()));
+    }
+
+    #[test]
+    fn test_transform_args() {
+        let args = ["CsvStar", "--replace", "g:M=Male", "--replace", "g:F=Female", "--replace", "2:x=y"]
+            .iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        let matches = transform_args(global_args()).get_matches_from(args);
+        let options = build_options(matches);
+
+        assert_eq!(options.transforms.len(), 2);
+        match &options.transforms[0] {
+            Transform::Replace { columns, map } => {
+                assert_eq!(columns, "g");
+                assert_eq!(map, &HashMap::from([("M".to_string(), "Male".to_string()), ("F".to_string(), "Female".to_string())]));
+            }
+            t => panic!("Unexpected transform {:?}", t),
+        }
+        assert!(matches!(&options.transforms[1], Transform::Replace { columns, .. } if columns == "2"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let options = CsvOptions::builder().delimiter(';').has_headers(false).build().expect("build failed");
+        let config = options.config();
+        assert_eq!((config.delimiter, config.quote, config.has_headers), (b';', b'"', false));
+        assert!(!config.output_headers);
+        assert!(config.report_headers);
+
+        assert_eq!(CsvOptions::builder().delimiter('\'').quote_char('\'').build().err().unwrap(),
+                   "The quote character and the delimiter are both '''");
+        assert_eq!(CsvOptions::builder().delimiter('\t').escape_char('\t').build().err().unwrap(),
+                   "The escape character and the delimiter are both '\\t'");
+        assert!(CsvOptions::builder().comment_char('"').build().is_err());
+        let not_ascii = "'\u{a6}' is not an ASCII character. Convert the input to use an ASCII separator first, e.g. with sed";
+        assert_eq!(CsvOptions::builder().delimiter('\u{a6}').build().err().unwrap(), not_ascii);
+        assert_eq!(CsvOptions::builder().comment_char('#').quote_char('\u{a6}').build().err().unwrap(), not_ascii);
+        assert_eq!(CsvOptions { escape_char: Some('\u{a6}'), ..Default::default() }.validate().unwrap_err(), not_ascii);
+        assert!(CsvOptions::builder().header_row(2).has_headers(false).build().is_err());
+        assert!(CsvOptions::builder().max_output_bytes(100).build().is_err());
+        assert!(CsvOptions::builder().max_output_bytes(100).output_file("out.csv").build().is_ok());
+        let clipboard = CsvOptions { clipboard: true, max_output_bytes: Some(100), ..Default::default() };
+        assert_eq!(clipboard.validate().err().unwrap(), "--max-output-bytes can't be used with --clipboard");
+        assert!(read_options(global_args().get_matches_from(["CsvStar", "-d", "|", "-q", "|"])).validate().is_err());
+        let raw = |args: &[&str]| read_options(raw_passthrough_args(global_args()).get_matches_from(args)).validate();
+        assert!(raw(&["CsvStar", "--raw-passthrough"]).is_ok());
+        assert!(raw(&["CsvStar", "--raw-passthrough", "-d", ";"]).is_err());
+        assert!(raw(&["CsvStar", "--raw-passthrough", "--trim", "fields"]).is_err());
+    }
+
+    #[test]
+    fn test_man_page() {
+        let mut page = vec![];
+        man_page(global_args().arg(clap::Arg::new("input_columns").short('c')), "csvcut", &mut page).expect("man_page failed");
+        let page = String::from_utf8(page).unwrap();
+        assert!(page.contains(".TH CSVCUT 1"));
+        assert!(page.contains(".SH NAME\ncsvcut"));
+        assert!(page.contains(".SH \"COLUMN SELECTION\""));
+
+        let mut page = vec![];
+        man_page(global_args(), "csvassert", &mut page).expect("man_page failed");
+        assert!(!String::from_utf8(page).unwrap().contains("COLUMN SELECTION"));
+    }
+
+    #[test]
+    fn test_version() {
+        let version = global_args().render_version();
+        assert!(version.starts_with(&format!("CsvStar {} (", env!("CARGO_PKG_VERSION"))));
+        assert!(version.contains(GIT_HASH));
+        assert!(version.contains(&format!("features: {})", FEATURES)));
+    }
+
+    #[test]
+    fn test_start_event() {
+        let event = start_event();
+        assert_eq!(event["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(event["git_hash"], GIT_HASH);
+        let features = event["features"].as_array().unwrap().iter().map(|f| f.as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(features.contains(&"fast-engine"), cfg!(feature = "fast-engine"));
+        assert_eq!(features.contains(&"interactive"), cfg!(feature = "interactive"));
+        assert_eq!(features.join(","), FEATURES);
+        assert!(event["arguments"].is_array());
+    }
+
+    #[test]
+    #[cfg(feature = "clipboard")]
+    fn test_clipboard() {
+        let options = build_options(global_args().get_matches_from(["CsvStar", "--clipboard"]));
+        assert!(options.clipboard);
+        assert_eq!(options.delimiter, Some('\t'));
+        let options = build_options(global_args().get_matches_from(["CsvStar", "--clipboard", "-d", ","]));
+        assert_eq!(options.delimiter, Some(','));
+        assert!(global_args().try_get_matches_from(["CsvStar", "--clipboard", "in.csv"]).is_err());
+    }
+
+    #[test]
+    fn test_complete_columns() {
+        let options = build_options(global_args().get_matches_from(["CsvStar", "test/test_input.csv"]));
+        assert_eq!(complete_columns(&options).expect("complete_columns failed"), vec!["col1", "col2", "col3"]);
+        let options = build_options(global_args().get_matches_from(["CsvStar", "-H", "test/test_input_no_headers.csv"]));
+        assert_eq!(complete_columns(&options).expect("complete_columns failed"), vec!["a", "b", "c"]);
+        assert!(complete_columns(&build_options(global_args().get_matches_from(["CsvStar"]))).is_err());
+    }
+}
@@ -0,0 +1,93 @@
+//! `--clipboard`: reads the input from and writes the output to the system clipboard by running
+//! the platform's clipboard commands, so no windowing libraries are linked in.
+
+use crate::log;
+use std::io::{self, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+/// The commands to try in order, each program followed by its arguments.
+fn commands(copy: bool, wayland: bool) -> Vec<&'static [&'static str]> {
+    if cfg!(target_os = "macos") {
+        vec![if copy { &["pbcopy"] } else { &["pbpaste"] }]
+    } else if cfg!(windows) {
+        vec![if copy { &["clip"] } else { &["powershell", "-NoProfile", "-Command", "Get-Clipboard -Raw"] }]
+    } else {
+        let mut commands: Vec<&'static [&'static str]> = vec![];
+        if wayland {
+            commands.push(if copy { &["wl-copy"] } else { &["wl-paste", "--no-newline"] });
+        }
+        commands.push(if copy { &["xclip", "-selection", "clipboard", "-in"] } else { &["xclip", "-selection", "clipboard", "-out"] });
+        commands.push(if copy { &["xsel", "--clipboard", "--input"] } else { &["xsel", "--clipboard", "--output"] });
+        commands
+    }
+}
+
+/// Runs the first clipboard command that is installed.
+fn spawn(copy: bool, configure: impl Fn(&mut Command) -> &mut Command) -> io::Result<Child> {
+    let wayland = std::env::var_os("WAYLAND_DISPLAY").is_some();
+    for command in commands(copy, wayland) {
+        match configure(Command::new(command[0]).args(&command[1..])).spawn() {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            result => return result,
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, "No clipboard command found; install wl-clipboard, xclip or xsel"))
+}
+
+/// The clipboard's contents.
+pub fn paste() -> io::Result<Vec<u8>> {
+    let output = spawn(false, |c| c.stdin(Stdio::null()).stdout(Stdio::piped()))?.wait_with_output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("Unable to read the clipboard ({})", output.status)));
+    }
+    Ok(output.stdout)
+}
+
+/// Streams into a clipboard command, which takes the output once the writer is dropped.
+pub struct ClipboardWriter {
+    child: Child,
+    stdin: Option<ChildStdin>,
+}
+
+impl ClipboardWriter {
+    pub fn new() -> io::Result<Self> {
+        let mut child = spawn(true, |c| c.stdin(Stdio::piped()).stdout(Stdio::null()))?;
+        let stdin = child.stdin.take();
+        Ok(ClipboardWriter { child, stdin })
+    }
+}
+
+impl Write for ClipboardWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdin.as_mut().ok_or_else(|| io::Error::from(io::ErrorKind::BrokenPipe))?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdin.as_mut().map_or(Ok(()), |s| s.flush())
+    }
+}
+
+impl Drop for ClipboardWriter {
+    fn drop(&mut self) {
+        // Closing its input tells the command the output is complete
+        drop(self.stdin.take());
+        match self.child.wait() {
+            Ok(status) if !status.success() => log::warning(&format!("unable to copy the output to the clipboard ({})", status)),
+            Err(e) => log::warning(&format!("unable to copy the output to the clipboard: {}", e)),
+            Ok(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(all(unix, not(target_os = "macos")))]
+    fn test_commands() {
+        assert_eq!(commands(true, false), [&["xclip", "-selection", "clipboard", "-in"][..], &["xsel", "--clipboard", "--input"]]);
+        assert_eq!(commands(false, true)[0], ["wl-paste", "--no-newline"]);
+        assert_eq!(commands(false, true).len(), 3);
+    }
+}
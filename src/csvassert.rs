@@ -0,0 +1,282 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+
+use crate::args::global_args;
+use clap::Arg;
+use csv::StringRecord;
+use options::CsvOptions;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+
+struct CsvAssertOptions {
+    actual_file: String,
+    /// Match rows regardless of their order
+    unordered: bool,
+    /// Numbers within this of each other are equal
+    epsilon: Option<f64>,
+    max_diffs: usize,
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>());
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> (CsvOptions, CsvAssertOptions) {
+    let executable_name = args[0].clone();
+
+    let command = global_args()
+        .display_name(executable_name)
+        .about("Checks that two CSV files hold the same data, ignoring column order, quoting style and the trailing newline. \
+                Prints the differences and exits with status 1 if they don't.")
+        .mut_arg("input", |a| a.value_name("EXPECTED").required(true).help("The expected file"))
+        .arg(Arg::new("actual")
+            .value_name("ACTUAL")
+            .required(true)
+            .help("The file to check, read with the same options"))
+        .arg(Arg::new("unordered")
+            .long("unordered")
+            .action(clap::ArgAction::SetTrue)
+            .help("Ignore the order of the rows"))
+        .arg(Arg::new("epsilon")
+            .long("epsilon")
+            .value_parser(clap::value_parser!(f64))
+            .help("Treat numbers differing by at most this much as equal, e.g. 1e-9"))
+        .arg(Arg::new("max_diffs")
+            .long("max-diffs")
+            .value_parser(clap::value_parser!(usize))
+            .default_value("20")
+            .help("Show at most this many differences"));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let action = CsvAssertOptions {
+        actual_file: matches.remove_one("actual").unwrap_or_default(),
+        unordered: matches.remove_one("unordered").unwrap_or(false),
+        epsilon: matches.remove_one("epsilon"),
+        max_diffs: matches.remove_one("max_diffs").unwrap_or(20),
+    };
+
+    (args::build_options(matches), action)
+}
+
+/// A file's headers and records.
+struct Table {
+    name: String,
+    headers: StringRecord,
+    records: Vec<StringRecord>,
+}
+
+fn read_table(options: &CsvOptions) -> Result<Table, Box<dyn Error>> {
+    let mut reader = csvutil::csv_reader(options, options.get_input_file()?);
+    let headers = csvutil::column_names(options, reader.headers()?.clone());
+    let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+    Ok(Table { name: options.input_file.clone().unwrap_or_else(|| "-".to_string()), headers, records })
+}
+
+/// Pairs each expected column with the actual column of the same name, matching repeated names
+/// by occurrence.
+fn match_columns(expected: &StringRecord, actual: &StringRecord) -> Vec<Option<usize>> {
+    let mut used = vec![false; actual.len()];
+    expected.iter().map(|name| {
+        let i = (0..actual.len()).find(|&i| !used[i] && &actual[i] == name)?;
+        used[i] = true;
+        Some(i)
+    }).collect()
+}
+
+fn values_equal(expected: &str, actual: &str, epsilon: Option<f64>) -> bool {
+    if expected == actual {
+        return true;
+    }
+    match (epsilon, expected.trim().parse::<f64>(), actual.trim().parse::<f64>()) {
+        (Some(epsilon), Ok(e), Ok(a)) => (e - a).abs() <= epsilon,
+        _ => false,
+    }
+}
+
+fn format_row(record: &[&str]) -> String {
+    let mut writer = csv::WriterBuilder::new().terminator(csv::Terminator::Any(b' ')).from_writer(vec![]);
+    let _ = writer.write_record(record);
+    String::from_utf8_lossy(&writer.into_inner().unwrap_or_default()).trim_end().to_string()
+}
+
+/// The differences between the tables, as lines of a report.
+fn compare(expected: &Table, actual: &Table, assert_options: &CsvAssertOptions) -> Vec<String> {
+    let mut diffs = vec![];
+    let columns = match_columns(&expected.headers, &actual.headers);
+    let missing = expected.headers.iter().zip(&columns).filter(|(_, c)| c.is_none()).map(|(h, _)| h).collect::<Vec<_>>();
+    if !missing.is_empty() {
+        diffs.push(format!("Columns only in {}: {}", expected.name, missing.join(", ")));
+    }
+    let extra = (0..actual.headers.len()).filter(|i| !columns.contains(&Some(*i))).map(|i| &actual.headers[i]).collect::<Vec<_>>();
+    if !extra.is_empty() {
+        diffs.push(format!("Columns only in {}: {}", actual.name, extra.join(", ")));
+    }
+
+    // Both sides' rows, restricted to the shared columns in the expected file's order
+    let shared = columns.iter().enumerate().filter_map(|(e, a)| a.map(|a| (e, a))).collect::<Vec<_>>();
+    if shared.is_empty() {
+        return diffs;
+    }
+    let names = shared.iter().map(|&(e, _)| &expected.headers[e]).collect::<Vec<_>>();
+    let expected_rows = expected.records.iter()
+        .map(|r| shared.iter().map(|&(e, _)| r.get(e).unwrap_or_default()).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let actual_rows = actual.records.iter()
+        .map(|r| shared.iter().map(|&(_, a)| r.get(a).unwrap_or_default()).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let rows_equal = |e: &[&str], a: &[&str]| e.iter().zip(a).all(|(e, a)| values_equal(e, a, assert_options.epsilon));
+
+    if !assert_options.unordered {
+        for (row, (e, a)) in expected_rows.iter().zip(&actual_rows).enumerate() {
+            for (i, (e, a)) in e.iter().zip(a).enumerate() {
+                if !values_equal(e, a, assert_options.epsilon) {
+                    diffs.push(format!("Row {}, column {}: expected {:?}, got {:?}", row + 1, names[i], e, a));
+                }
+            }
+        }
+        if expected_rows.len() != actual_rows.len() {
+            diffs.push(format!("{} has {} rows, {} has {}", expected.name, expected_rows.len(), actual.name, actual_rows.len()));
+        }
+        diffs.extend(expected_rows.iter().skip(actual_rows.len()).map(|r| format!("- {}", format_row(r))));
+        diffs.extend(actual_rows.iter().skip(expected_rows.len()).map(|r| format!("+ {}", format_row(r))));
+        return diffs;
+    }
+
+    // Identical rows pair off first, then any left over are paired within the tolerance
+    let mut unmatched: HashMap<&[&str], usize> = HashMap::new();
+    for row in &expected_rows {
+        *unmatched.entry(row).or_default() += 1;
+    }
+    let mut left_actual = vec![];
+    for row in &actual_rows {
+        match unmatched.get_mut(&row[..]) {
+            Some(n) if *n > 0 => *n -= 1,
+            _ => left_actual.push(row),
+        }
+    }
+    let mut left_expected = vec![];
+    for row in &expected_rows {
+        if let Some(n) = unmatched.get_mut(&row[..]).filter(|n| **n > 0) {
+            *n -= 1;
+            left_expected.push(row);
+        }
+    }
+    if assert_options.epsilon.is_some() {
+        left_expected.retain(|e| match left_actual.iter().position(|a| rows_equal(e, a)) {
+            Some(i) => {
+                left_actual.remove(i);
+                false
+            }
+            None => true,
+        });
+    }
+    diffs.extend(left_expected.iter().map(|r| format!("- {}", format_row(r))));
+    diffs.extend(left_actual.iter().map(|r| format!("+ {}", format_row(r))));
+    diffs
+}
+
+fn process_csv(options: &CsvOptions, assert_options: &CsvAssertOptions) -> Result<(), Box<dyn Error>> {
+    let expected = read_table(options)?;
+    let actual = read_table(&CsvOptions { input_file: Some(assert_options.actual_file.clone()), ..options.clone() })?;
+
+    let diffs = compare(&expected, &actual, assert_options);
+    if diffs.is_empty() {
+        return Ok(());
+    }
+
+    let mut out = options.get_output_file()?;
+    if diffs.iter().any(|d| d.starts_with(['-', '+'])) {
+        writeln!(out, "Rows marked - are only in {}, + only in {}", expected.name, actual.name)?;
+    }
+    for diff in diffs.iter().take(assert_options.max_diffs) {
+        writeln!(out, "{}", diff)?;
+    }
+    if diffs.len() > assert_options.max_diffs {
+        writeln!(out, "... and {} more", diffs.len() - assert_options.max_diffs)?;
+    }
+    out.flush()?;
+
+    Err(Box::from(format!("{} differs from {} in {} places", actual.name, expected.name, diffs.len())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn assert_options(unordered: bool, epsilon: Option<f64>) -> CsvAssertOptions {
+        CsvAssertOptions { actual_file: String::new(), unordered, epsilon, max_diffs: 20 }
+    }
+
+    fn table(name: &str, text: &str) -> Table {
+        let mut reader = csv::Reader::from_reader(text.as_bytes());
+        let headers = reader.headers().unwrap().clone();
+        Table { name: name.to_string(), headers, records: reader.records().collect::<Result<_, _>>().unwrap() }
+    }
+
+    #[test]
+    fn test_compare() {
+        let expected = table("e.csv", "id,price\n1,1.5\n2,\"2.25\"\n");
+        let reordered = table("a.csv", "price,id\n1.5,1\n2.25,2");
+        assert!(compare(&expected, &reordered, &assert_options(false, None)).is_empty());
+
+        let shuffled = table("a.csv", "id,price\n2,2.25\n1,1.5000001\n");
+        assert_eq!(compare(&expected, &shuffled, &assert_options(false, None)), [
+            "Row 1, column id: expected \"1\", got \"2\"",
+            "Row 1, column price: expected \"1.5\", got \"2.25\"",
+            "Row 2, column id: expected \"2\", got \"1\"",
+            "Row 2, column price: expected \"2.25\", got \"1.5000001\"",
+        ]);
+        assert_eq!(compare(&expected, &shuffled, &assert_options(true, None)), ["- 1,1.5", "+ 1,1.5000001"]);
+        assert!(compare(&expected, &shuffled, &assert_options(true, Some(1e-6))).is_empty());
+
+        let changed = table("a.csv", "id,total\n1,1.5\n2,2.25\n3,3\n");
+        assert_eq!(compare(&expected, &changed, &assert_options(false, None)), [
+            "Columns only in e.csv: price",
+            "Columns only in a.csv: total",
+            "e.csv has 2 rows, a.csv has 3",
+            "+ 3",
+        ]);
+    }
+
+    #[test]
+    fn test_process_csv() {
+        let dir = csvutil::TestDir::new();
+        let expected_file = &dir.path("test_input_expected.csv");
+        let actual_file = &dir.path("test_input_actual.csv");
+        let output_file = &dir.path("test_output_assert.txt");
+        fs::write(expected_file, "a,b\n1,x\n2,y\n").expect("Unable to write input file");
+        fs::write(actual_file, "b,a\nx,1\n\"z\",2").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(expected_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+        let action = CsvAssertOptions { actual_file: actual_file.to_string(), ..assert_options(true, None) };
+
+        let error = process_csv(&options, &action).expect_err("differences accepted");
+        assert_eq!(error.to_string(), format!("{} differs from {} in 2 places", actual_file, expected_file));
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, format!("Rows marked - are only in {}, + only in {}\n- 2,y\n+ 2,z\n", expected_file, actual_file));
+
+        let action = CsvAssertOptions { actual_file: expected_file.to_string(), ..assert_options(false, None) };
+        process_csv(&options, &action).expect("identical files differ");
+    }
+}
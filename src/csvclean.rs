@@ -0,0 +1,244 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+
+use crate::args::global_args;
+use clap::{Arg, ArgGroup};
+use csv::StringRecord;
+use options::CsvOptions;
+use serde_json::json;
+use std::error::Error;
+use std::io::BufRead;
+
+/// The fixers asked for; each is off unless its flag is given.
+#[derive(Default)]
+struct CsvCleanOptions {
+    /// `="0123"` → `0123`, Excel's way of keeping leading zeros
+    unformula: bool,
+    /// Non-breaking spaces → plain spaces
+    nbsp: bool,
+    /// Curly quotes → straight quotes
+    smart_quotes: bool,
+    /// Drop the empty last field every row gets from a delimiter at the end of each line
+    trailing_delimiter: bool,
+}
+
+/// What each fixer changed, reported at the end of the run.
+#[derive(Debug, Default, PartialEq)]
+struct Counts {
+    formulas: u64,
+    nbsp: u64,
+    smart_quotes: u64,
+    trailing_delimiters: u64,
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>())?;
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> Result<(CsvOptions, CsvCleanOptions), String> {
+    let executable_name = args[0].clone();
+
+    let command = args::in_place_args(global_args())
+        .display_name(executable_name)
+        .about("Repairs the artifacts Excel leaves in CSV files.")
+        .after_help("Each fixer is opt-in. The number of values or rows each one changed is reported on stderr \
+                     when the run ends, or as an info event with --log-json.")
+        .arg(Arg::new("unformula")
+            .long("unformula")
+            .action(clap::ArgAction::SetTrue)
+            .help("Unwrap formula-wrapped values, so =\"0123\" becomes 0123"))
+        .arg(Arg::new("nbsp")
+            .long("nbsp")
+            .action(clap::ArgAction::SetTrue)
+            .help("Replace non-breaking spaces with plain spaces"))
+        .arg(Arg::new("smart_quotes")
+            .long("smart-quotes")
+            .action(clap::ArgAction::SetTrue)
+            .help("Replace curly single and double quotes with straight ones"))
+        .arg(Arg::new("trailing_delimiter")
+            .long("trailing-delimiter")
+            .action(clap::ArgAction::SetTrue)
+            .help("Drop the empty last field left by a delimiter at the end of every line"))
+        .group(ArgGroup::new("fixers")
+            .args(["unformula", "nbsp", "smart_quotes", "trailing_delimiter"])
+            .multiple(true)
+            .required(true));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let action = CsvCleanOptions {
+        unformula: matches.remove_one("unformula").unwrap_or(false),
+        nbsp: matches.remove_one("nbsp").unwrap_or(false),
+        smart_quotes: matches.remove_one("smart_quotes").unwrap_or(false),
+        trailing_delimiter: matches.remove_one("trailing_delimiter").unwrap_or(false),
+    };
+
+    Ok((args::build_options(matches), action))
+}
+
+/// The value inside `="..."`, with its doubled quotes undone, or None if it isn't wrapped.
+fn unwrap_formula(value: &str) -> Option<String> {
+    let inner = value.strip_prefix("=\"")?.strip_suffix('"')?;
+    Some(inner.replace("\"\"", "\""))
+}
+
+fn straighten_quotes(value: &str) -> String {
+    value.chars().map(|c| match c {
+        '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}' => '\'',
+        '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{201F}' => '"',
+        c => c,
+    }).collect()
+}
+
+impl CsvCleanOptions {
+    /// The value with the value fixers applied, counting each that changed it.
+    fn fix(&self, value: &str, counts: &mut Counts) -> String {
+        let mut value = value.to_string();
+        if self.unformula {
+            if let Some(inner) = unwrap_formula(&value) {
+                value = inner;
+                counts.formulas += 1;
+            }
+        }
+        if self.nbsp && value.contains(['\u{A0}', '\u{202F}']) {
+            value = value.replace(['\u{A0}', '\u{202F}'], " ");
+            counts.nbsp += 1;
+        }
+        if self.smart_quotes {
+            let straight = straighten_quotes(&value);
+            if straight != value {
+                value = straight;
+                counts.smart_quotes += 1;
+            }
+        }
+        value
+    }
+
+    /// The record's values, fixed, without the empty field a trailing delimiter left past `width`.
+    fn fix_record(&self, record: &StringRecord, width: usize, counts: &mut Counts) -> Vec<String> {
+        let mut fields = record.iter().collect::<Vec<_>>();
+        if self.trailing_delimiter && fields.len() == width + 1 && fields.last() == Some(&"") {
+            fields.pop();
+            counts.trailing_delimiters += 1;
+        }
+        fields.into_iter().map(|v| self.fix(v, counts)).collect()
+    }
+}
+
+fn report(counts: &Counts) {
+    let message = format!("Unwrapped {} formula values, replaced non-breaking spaces in {} values, straightened quotes in {} values, dropped {} trailing delimiters",
+                          counts.formulas, counts.nbsp, counts.smart_quotes, counts.trailing_delimiters);
+    if log::is_json() {
+        log::event("info", json!({
+            "message": message,
+            "unformula": counts.formulas,
+            "nbsp": counts.nbsp,
+            "smart_quotes": counts.smart_quotes,
+            "trailing_delimiter": counts.trailing_delimiters,
+        }));
+    } else {
+        log::info(&message);
+    }
+}
+
+fn process_csv(options: &CsvOptions, clean_options: &CsvCleanOptions) -> Result<(), Box<dyn Error>> {
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    let mut reader = csvutil::csv_reader(options, input);
+    let has_headers = options.config().has_headers;
+    let mut counts = Counts::default();
+
+    // A header row ending in an empty name is the trailing delimiter pattern; without a header
+    // row, the first row sets the width the same way
+    let first_row = reader.headers()?.clone();
+    let mut width = first_row.len();
+    if clean_options.trailing_delimiter && width > 1 && first_row.get(width - 1) == Some("") {
+        width -= 1;
+        if has_headers {
+            counts.trailing_delimiters += 1;
+        }
+    }
+    let first_row = if has_headers {
+        first_row.iter().map(|v| clean_options.fix(v, &mut counts)).collect()
+    } else {
+        first_row
+    };
+    let first_row = csvutil::column_names(options, first_row);
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+
+    if options.output_has_headers() {
+        let indices = (0..width).collect::<Vec<_>>();
+        csv_writer.write_record(csvutil::enumerate_output_headers(has_headers, first_row, &indices))?;
+    }
+
+    for result in reader.records() {
+        csv_writer.write_record(clean_options.fix_record(&result?, width, &mut counts))?;
+    }
+
+    csv_writer.finish()?;
+    report(&counts);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_fix() {
+        let all = CsvCleanOptions { unformula: true, nbsp: true, smart_quotes: true, trailing_delimiter: true };
+        let mut counts = Counts::default();
+        assert_eq!(all.fix("=\"0123\"", &mut counts), "0123");
+        assert_eq!(all.fix("=\"say \"\"hi\"\"\"", &mut counts), "say \"hi\"");
+        assert_eq!(all.fix("=SUM(A1:A2)", &mut counts), "=SUM(A1:A2)");
+        assert_eq!(all.fix("10\u{A0}000", &mut counts), "10 000");
+        assert_eq!(all.fix("\u{201C}Bob\u{2019}s\u{201D}", &mut counts), "\"Bob's\"");
+        assert_eq!(counts, Counts { formulas: 2, nbsp: 1, smart_quotes: 1, trailing_delimiters: 0 });
+
+        let mut counts = Counts::default();
+        assert_eq!(CsvCleanOptions::default().fix("=\"0123\"\u{A0}", &mut counts), "=\"0123\"\u{A0}");
+        assert_eq!(counts, Counts::default());
+    }
+
+    #[test]
+    fn test_process_csv() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_clean.csv");
+        let output_file = &dir.path("test_output_clean.csv");
+        fs::write(input_file, "id,name,\n\"=\"\"007\"\"\",Ann\u{A0}Lee,\n\"=\"\"010\"\"\",\u{2018}Bo\u{2019},\n").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+
+        let all = CsvCleanOptions { unformula: true, nbsp: true, smart_quotes: true, trailing_delimiter: true };
+        process_csv(&options, &all).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, "id,name\n007,Ann Lee\n010,'Bo'\n");
+
+        // Only the fixers asked for run
+        let trailing = CsvCleanOptions { trailing_delimiter: true, ..Default::default() };
+        process_csv(&options, &trailing).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, "id,name\n\"=\"\"007\"\"\",Ann\u{A0}Lee\n\"=\"\"010\"\"\",\u{2018}Bo\u{2019}\n");
+    }
+}
@@ -1,17 +1,28 @@
 pub mod options;
 pub mod args;
-mod csvutil;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+#[cfg(feature = "interactive")]
+pub mod picker;
 
 use clap::Arg;
-use csv::{StringRecord, WriterBuilder};
+use csv::StringRecord;
 use options::CsvOptions;
+use stats::BooleanValues;
 use std::error::Error;
-use std::fs::File;
-use std::io;
 use std::io::{BufRead};
 use std::iter::{Iterator};
 use std::string::ToString;
-use serial_test::serial;
 use crate::args::global_args;
 
 struct CsvCutOptions { input_columns: Option<Vec<String>> }
@@ -19,85 +30,222 @@ struct CsvCutOptions { input_columns: Option<Vec<String>> }
 fn main() -> Result<(), String> {
     let (options, action) = parse_args(std::env::args().collect::<Vec<_>>());
 
-    match process_csv(&options, &action) {
-        Ok(()) => Ok(()),
-        Err(e) => Err(e.to_string()),
+    if options.self_bench {
+        let bench_options = CsvCutOptions { input_columns: Some(vec!["1".to_string(), "3-5".to_string(), "-1".to_string()]) };
+        return csvutil::self_bench(|o| process_csv(o, &bench_options)).map_err(|e| e.to_string());
     }
+
+    options.finish(process_csv(&options, &action))
 }
 
 fn parse_args(args: Vec<String>) -> (CsvOptions, CsvCutOptions) {
     let executable_name = args[0].clone();
 
-    let command = global_args()
+    let command = args::follow_args(args::engine_args(args::self_bench_args(args::boolean_args(args::transform_args(args::source_args(args::in_place_args(args::contract_args(global_args()))))))))
         .display_name(executable_name)
         .about("Selects columns from CSV files.")
         .arg(Arg::new("input_columns")
             .short('c')
             .long("columns")
             .allow_negative_numbers(true)
-            .help("List of column names, offsets or ranges to include, e.g. \"1,id,-2,3-5. Negative offsets are interpreted as relative to the end (-1 is the last column). Ranges are inclusive.")
+            .help("List of column names, offsets or ranges to include, e.g. \"1,id,-2,3-5. Negative offsets are interpreted as relative to the end (-1 is the last column). Ranges are inclusive and may be of names, e.g. first_name-email. Quote names containing commas or dashes, e.g. '\"revenue, net\",id'.")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("zero_based")
+            .long("zero-based")
+            .action(clap::ArgAction::SetTrue)
+            .help("Count column offsets and ranges in -c from 0, as awk users may expect. -1 is still the last column"))
+        .arg(Arg::new("emit_spec")
+            .long("emit-spec")
+            .action(clap::ArgAction::SetTrue)
+            .conflicts_with("follow")
+            .help("Print the selected columns as offsets and ranges on stderr, e.g. 1-3,7, and exit without cutting. The result can replace -c to pin the selection"))
+        .arg(Arg::new("select_first")
+            .long("select-first")
+            .action(clap::ArgAction::SetTrue)
+            .conflicts_with("select_all_matches")
+            .help("Take the first column when several share a name given to -c, rather than failing"))
+        .arg(Arg::new("select_all_matches")
+            .long("select-all-matches")
+            .action(clap::ArgAction::SetTrue)
+            .help("Take every column sharing a name given to -c, rather than failing"))
+        .arg(Arg::new("first")
+            .long("first")
+            .allow_negative_numbers(true)
+            .help("Move these selected columns to the front, in this order, e.g. id,created_at. Takes the same syntax as -c. May be repeated")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("last")
+            .long("last")
+            .allow_negative_numbers(true)
+            .help("Move these selected columns to the end, in this order. May be repeated")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("move")
+            .long("move")
+            .allow_negative_numbers(true)
+            .value_parser(csvutil::parse_move)
+            .help("Move a selected column to a 1-based position in the output, e.g. email:2. \
+                   --first, --last and --move apply in the order given. May be repeated")
             .action(clap::ArgAction::Append));
 
-    let mut matches = command.get_matches_from(args);
+    #[cfg(feature = "interactive")]
+    let command = command.arg(Arg::new("interactive")
+        .long("interactive")
+        .action(clap::ArgAction::SetTrue)
+        .requires("input")
+        .conflicts_with_all(["input_columns", "follow", "zero_based"])
+        .help("Pick the columns in a terminal list of the headers and sample values, then cut them. The equivalent -c is printed on stderr"));
+
+    let mut matches = args::get_matches_from(command, args);
 
-    let action = CsvCutOptions {
+    #[cfg(feature = "interactive")]
+    let interactive = matches.remove_one("interactive").unwrap_or(false);
+
+    #[allow(unused_mut)]
+    let mut action = CsvCutOptions {
         input_columns: matches.remove_many::<String>("input_columns")
-            .map(|v| v.flat_map(|s| s.split(',').map(|s| s.trim().to_string()).collect::<Vec<_>>())
+            .map(|v| v.flat_map(|s| csvutil::parse_column_list(&s))
                 .collect::<Vec<_>>())
     };
 
-    (args::build_options(matches), action)
+    let options = args::build_options(matches);
+
+    #[cfg(feature = "interactive")]
+    if interactive {
+        match pick_columns(&options) {
+            Ok(Some(columns)) => action.input_columns = Some(columns),
+            Ok(None) => std::process::exit(130),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    (options, action)
+}
+
+/// Runs the `--interactive` picker over the headers and first few records, returning the chosen columns as `-c` values.
+#[cfg(feature = "interactive")]
+fn pick_columns(options: &CsvOptions) -> Result<Option<Vec<String>>, Box<dyn Error>> {
+    let mut reader = csvutil::csv_reader(options, options.get_input_file()?);
+    let headers = csvutil::column_names(options, reader.headers()?.clone());
+    let samples = reader.records().take(3).collect::<Result<Vec<_>, _>>()?;
+    let Some(indices) = picker::pick_columns(&headers, &samples)? else {
+        return Ok(None);
+    };
+    if indices.is_empty() {
+        return Err(Box::from("No columns were selected"));
+    }
+    eprintln!("-c {}", picker::column_spec(&headers, &indices));
+    Ok(Some(indices.iter().map(|i| (i + 1).to_string()).collect()))
 }
 
 fn process_csv(options: &CsvOptions, cut_options: &CsvCutOptions) -> Result<(), Box<dyn Error>> {
     let input:Box<dyn BufRead> = options.get_input_file()?;
 
-    let mut reader = csvutil::csv_reader(options, input);
+    let mut reader = csvutil::record_reader(options, input)?;
 
     // Get the column headers
-    let first_row = reader.headers()?.clone();
+    let first_row = csvutil::column_names(options, reader.headers()?);
 
-    // Determine which columns to include
-    let selected_indices: Vec<usize> = csvutil::select_column_indices(&first_row, &cut_options.input_columns)?;
+    let pipeline = transform::Pipeline::new(&options.transforms, &first_row, options.zero_based)?
+        .boolean_values(BooleanValues::new(options.true_values.clone(), options.false_values.clone()));
 
-    let csv_file_handle = options.get_output_file()?;
+    // Determine which columns to include, including any added by the transforms
+    let first_row = pipeline.headers().clone();
+    let selected_indices: Vec<usize> = csvutil::select_column_indices_with(&first_row, &cut_options.input_columns, options.zero_based, options.duplicate_names)?;
+    let selected_indices = csvutil::reorder_indices(&first_row, selected_indices, &options.reorders, options.zero_based, options.duplicate_names)?;
 
-    let output_has_headers = options.output_headers
-        .or(options.input_has_headers)
-        .unwrap_or(true);
+    if options.emit_spec {
+        eprintln!("{}", csvutil::index_spec(&selected_indices, options.zero_based));
+        return Ok(());
+    }
 
-    let mut csv_writer = WriterBuilder::new().has_headers(output_has_headers)
-        .from_writer(csv_file_handle);
+    let output_has_headers = options.output_has_headers();
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+
+    let source_file = csvutil::source_file(options);
 
     if output_has_headers {
-        let out_headers = csvutil::enumerate_output_headers(options.input_has_headers.unwrap_or(true), first_row, &selected_indices);
+        let mut out_headers = csvutil::enumerate_output_headers(options.config().has_headers, first_row, &selected_indices);
+        if source_file.is_some() {
+            out_headers.extend(csvutil::SOURCE_COLUMNS.map(String::from));
+        }
         csv_writer.write_record(out_headers)?;
     }
 
     let mut record = StringRecord::new();
-    while !reader.is_done() {
-        reader.read_record(&mut record)?;
-        if !reader.is_done() {
+    let mut values: Vec<String> = vec![];
+    let mut row = 0;
+    while reader.read_record(&mut record)? {
+        row += 1;
+        let source = csvutil::source_values(&source_file, row);
+        let source = source.iter().map(String::as_str);
+        if pipeline.is_empty() {
             let selected_values = selected_indices.iter().flat_map(|&i| record.get(i));
-            csv_writer.write_record(selected_values)?;
+            csv_writer.write_record(selected_values.chain(source))?;
+        } else {
+            // Reuse the value buffers from the previous record
+            values.truncate(record.len());
+            for (i, field) in record.iter().enumerate() {
+                match values.get_mut(i) {
+                    Some(value) => {
+                        value.clear();
+                        value.push_str(field);
+                    }
+                    None => values.push(field.to_string()),
+                }
+            }
+            pipeline.apply(row, &mut values);
+            csv_writer.write_record(selected_indices.iter().flat_map(|&i| values.get(i).map(String::as_str)).chain(source))?;
         }
     }
 
-    csv_writer.flush()?;
+    csv_writer.finish()?;
 
     Ok(())
 }
 
 #[cfg(test)]
-#[serial] // tests must be serial because they write files with the same name
 mod tests {
     use super::*;
+    use sha2::Digest;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
     use std::fs;
 
+    /// Counts allocations made by the current thread, so tests can check the per-record loop doesn't allocate
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn count_allocations(f: impl FnOnce()) -> usize {
+        let before = ALLOCATIONS.with(|n| n.get());
+        f();
+        ALLOCATIONS.with(|n| n.get()) - before
+    }
+
     #[test]
     fn test_process_csv_with_valid_input() {
+        let dir = csvutil::TestDir::new();
         let input_file = "test/test_input.csv";
-        let output_file = "test_output.csv";
+        let output_file = &dir.path("test_output.csv");
 
         let action = CsvCutOptions {
             input_columns: Some(vec!["col1".to_string(), "col3".to_string()]),
@@ -114,14 +262,13 @@ mod tests {
         let expected_output = "col1,col3\n1,3\n4,6\n7,9\n";
         let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
         assert_eq!(actual_output, expected_output);
-
-        fs::remove_file(output_file).expect("Unable to delete test output file");
     }
 
     #[test]
     fn test_process_csv_with_valid_input_no_headers() {
+        let dir = csvutil::TestDir::new();
         let input_file = "test/test_input_no_headers.csv";
-        let output_file = "test_output.csv";
+        let output_file = &dir.path("test_output.csv");
 
         let action = CsvCutOptions {
             input_columns: Some(vec!["1".to_string(), "-1".to_string()]),
@@ -139,14 +286,13 @@ mod tests {
         let expected_output = "1,3\n4,6\n7,9\n";
         let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
         assert_eq!(actual_output, expected_output);
-
-        fs::remove_file(output_file).expect("Unable to delete test output file");
     }
 
     #[test]
     fn test_process_csv_with_valid_input_no_headers_range() {
+        let dir = csvutil::TestDir::new();
         let input_file = "test/test_input_no_headers.csv";
-        let output_file = "test_output.csv";
+        let output_file = &dir.path("test_output.csv");
 
         let action = CsvCutOptions {
             input_columns: Some(vec!["1-2".to_string()]),
@@ -164,14 +310,109 @@ mod tests {
         let expected_output = "1,2\n4,5\n7,8\n";
         let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
         assert_eq!(actual_output, expected_output);
+    }
 
-        fs::remove_file(output_file).expect("Unable to delete test output file");
+    #[test]
+    fn test_process_csv_no_headers_generated_names() {
+        let dir = csvutil::TestDir::new();
+        let input_file = "test/test_input_no_headers.csv";
+        let output_file = &dir.path("test_output.csv");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            input_has_headers: Some(false),
+            output_headers: Some(true),
+            ..Default::default()
+        };
+
+        process_csv(&options, &CsvCutOptions { input_columns: Some(vec!["c".to_string(), "a".to_string()]) })
+            .expect("process_csv failed");
+
+        let expected_output = "c,a\n3,1\n6,4\n9,7\n";
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, expected_output);
+
+        assert_eq!(process_csv(&options, &CsvCutOptions { input_columns: Some(vec!["col1".to_string()]) }).expect_err("").to_string(),
+                   "Column 'col1' not found in input file. It has no header row (-H), so its columns are named a, b, c, or use offsets");
+    }
+
+    #[test]
+    fn test_process_csv_header_row() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_header_row.csv");
+        let output_file = &dir.path("test_output.csv");
+        fs::write(input_file, "Quarterly export\ngenerated 2024-01-01\nid,name\n1,a\n").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            header_row: Some(3),
+            ..Default::default()
+        };
+
+        process_csv(&options, &CsvCutOptions { input_columns: Some(vec!["name".to_string()]) }).expect("process_csv failed");
+
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, "name\na\n");
+
+        // A quoted note spanning lines is one row
+        fs::write(input_file, "Quarterly export\n\"generated 2024-01-01\nby ops\"\nid,name\n1,a\n").expect("Unable to write input file");
+        process_csv(&options, &CsvCutOptions { input_columns: Some(vec!["name".to_string()]) }).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, "name\na\n");
+
+        // A header row the input doesn't reach is an error, not empty output
+        fs::write(input_file, "Quarterly export\n\"generated 2024-01-01\nby ops\"\n").expect("Unable to write input file");
+        let error = process_csv(&options, &CsvCutOptions { input_columns: None }).unwrap_err();
+        assert_eq!(error.to_string(), "Header row 3 is past the end of the input (2 rows)");
+        fs::write(input_file, "Quarterly export\n").expect("Unable to write input file");
+        let error = process_csv(&options, &CsvCutOptions { input_columns: None }).unwrap_err();
+        assert_eq!(error.to_string(), "Header row 3 is past the end of the input (1 row)");
+    }
+
+    #[test]
+    fn test_process_csv_verify_sha256() {
+        let dir = csvutil::TestDir::new();
+        let input_file = "test/test_input.csv";
+        let output_file = &dir.path("test_output.csv");
+
+        let mut options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            verify_sha256: Some("0".repeat(64)),
+            ..Default::default()
+        };
+        let error = process_csv(&options, &CsvCutOptions { input_columns: None }).expect_err("checksum should not match");
+        assert!(error.to_string().starts_with("SHA-256 mismatch"));
+
+        let digest = sha2::Sha256::digest(fs::read(input_file).expect("Unable to read input file"));
+        options.verify_sha256 = Some(format!("{:x}", digest));
+        process_csv(&options, &CsvCutOptions { input_columns: None }).expect("process_csv failed");
+    }
+
+    #[test]
+    fn test_process_csv_emit_spec() {
+        let dir = csvutil::TestDir::new();
+        let output_file = &dir.path("test_output.csv");
+        let options = CsvOptions {
+            input_file: Some("test/test_input.csv".to_string()),
+            output_file: Some(output_file.to_string()),
+            emit_spec: true,
+            ..Default::default()
+        };
+        process_csv(&options, &CsvCutOptions { input_columns: Some(vec!["col1-col3".to_string()]) }).expect("process_csv failed");
+        assert!(!std::path::Path::new(output_file).exists());
+
+        let error = process_csv(&options, &CsvCutOptions { input_columns: Some(vec!["nope".to_string()]) }).expect_err("column should not resolve");
+        assert!(error.to_string().contains("nope"));
     }
 
     #[test]
     fn test_process_csv_with_invalid_column() {
+        let dir = csvutil::TestDir::new();
         let input_file = "test/test_input.csv";
-        let output_file = "test_output.csv";
+        let output_file = &dir.path("test_output.csv");
 
         let action = CsvCutOptions {
             input_columns: Some(vec!["1-4".to_string()]),
@@ -201,8 +442,9 @@ mod tests {
 
     #[test]
     fn test_process_csv_without_columns_specified() {
+        let dir = csvutil::TestDir::new();
         let input_file = "test/test_input.csv";
-        let output_file = "test_output_no_columns.csv";
+        let output_file = &dir.path("test_output_no_columns.csv");
         let input_data = fs::read_to_string(input_file).expect("Unable to read test input file");
 
         let options = CsvOptions {
@@ -221,14 +463,13 @@ mod tests {
         let expected_output = input_data; // Since no columns are filtered, all columns are written
         let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
         assert_eq!(actual_output, expected_output);
-
-        fs::remove_file(output_file).expect("Unable to delete test output file");
     }
 
     #[test]
     fn test_process_csv_inventing_column_headers() {
+        let dir = csvutil::TestDir::new();
         let input_file = "test/100_empty_columns.csv";
-        let output_file = "test_output.csv";
+        let output_file = &dir.path("test_output.csv");
 
         let options = CsvOptions {
             input_file: Some(input_file.to_string()),
@@ -250,19 +491,421 @@ a,b,c,d,e,f,g,h,i,j,k,l,m,n,o,p,q,r,s,t,u,v,w,x,y,z,aa,bb,cc,dd,ee,ff,gg,hh,ii,j
 ";
         let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
         assert_eq!(actual_output, expected_output);
+    }
+
+    #[test]
+    fn test_process_csv_max_output_bytes() {
+        let dir = csvutil::TestDir::new();
+        let input_file = "test/test_input.csv";
+        let output_file = &dir.path("test_output.csv");
+
+        let action = CsvCutOptions {
+            input_columns: Some(vec!["col1".to_string(), "col3".to_string()]),
+        };
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            max_output_bytes: Some(18),
+            ..Default::default()
+        };
+
+        process_csv(&options, &action).expect("process_csv failed");
+
+        let part1 = &dir.path("test_output.part-0001.csv");
+        let part2 = &dir.path("test_output.part-0002.csv");
+        assert_eq!(fs::read_to_string(part1).expect("Unable to read part 1"), "col1,col3\n1,3\n4,6\n");
+        assert_eq!(fs::read_to_string(part2).expect("Unable to read part 2"), "col1,col3\n7,9\n");
+        assert!(fs::metadata(dir.path("test_output.part-0003.csv")).is_err());
+    }
+
+    #[test]
+    fn test_process_csv_max_output_bytes_long_records() {
+        // Longer than the csv writer's buffer, so each is flushed to the output in pieces
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_long.csv");
+        let output_file = &dir.path("test_output.csv");
+        let header = format!("{},b\n", "h".repeat(10000));
+        let long = format!("\"{}\n{}\",1\n", "x".repeat(6000), "y".repeat(6000));
+        fs::write(input_file, format!("{}short,0\n{}short,2\n", header, long)).expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            max_output_bytes: Some(header.len() as u64 + 100),
+            ..Default::default()
+        };
+
+        process_csv(&options, &CsvCutOptions { input_columns: None }).expect("process_csv failed");
 
-        fs::remove_file(output_file).expect("Unable to delete test output file");
+        let part = |n: usize| fs::read_to_string(dir.path(&format!("test_output.part-{:04}.csv", n))).expect("Unable to read part");
+        assert_eq!(part(1), format!("{}short,0\n", header));
+        assert_eq!(part(2), format!("{}{}", header, long));
+        assert_eq!(part(3), format!("{}short,2\n", header));
+        assert!(fs::metadata(dir.path("test_output.part-0004.csv")).is_err());
+    }
+
+    #[test]
+    fn test_process_csv_write_manifest() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_manifest.csv");
+        let output_file = &dir.path("test_output.csv");
+        let manifest_file = &dir.path("test_output.manifest.json");
+        fs::write(input_file, "id,name\n1,a\n2,\n").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            manifest_file: Some(manifest_file.to_string()),
+            ..Default::default()
+        };
+
+        process_csv(&options, &CsvCutOptions { input_columns: None }).expect("process_csv failed");
+
+        let manifest: serde_json::Value = serde_json::from_str(&fs::read_to_string(manifest_file).expect("Unable to read manifest"))
+            .expect("Invalid manifest");
+        assert_eq!(manifest["records"], 2);
+        assert_eq!(manifest["bytes"], 15);
+        assert_eq!(manifest["sha256"], "f3b25414ea3142be9eaffd040ba4f4dbd0939ef19aab29297f667b32ac31b235");
+        assert_eq!(manifest["columns"], serde_json::json!([{"name": "id", "type": "number"}, {"name": "name", "type": "text"}]));
+        assert_eq!(manifest["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(manifest["git_hash"], args::GIT_HASH);
+        assert_eq!(manifest["features"].as_array().map(Vec::len), Some(args::FEATURES.split(',').filter(|f| !f.is_empty()).count()));
+    }
+
+    #[test]
+    fn test_process_csv_tee() {
+        let dir = csvutil::TestDir::new();
+        let output_file = &dir.path("test_output.csv");
+        let copy_file = &dir.path("test_output_copy.csv");
+
+        let options = CsvOptions {
+            input_file: Some("test/test_input.csv".to_string()),
+            output_file: Some(output_file.to_string()),
+            tee: vec![copy_file.to_string()],
+            ..Default::default()
+        };
+
+        process_csv(&options, &CsvCutOptions { input_columns: Some(vec!["col2".to_string()]) }).expect("process_csv failed");
+
+        assert_eq!(fs::read_to_string(output_file).expect("Unable to read output file"), "col2\n2\n5\n8\n");
+        assert_eq!(fs::read_to_string(copy_file).expect("Unable to read copy"), "col2\n2\n5\n8\n");
+    }
+
+    /// An output whose reader has gone, like stdout piped into `head` that has exited.
+    struct ClosedPipe;
+
+    impl std::io::Write for ClosedPipe {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+    }
+
+    #[test]
+    fn test_process_csv_broken_pipe() {
+        let dir = csvutil::TestDir::new();
+        let copy_file = &dir.path("test_output_copy.csv");
+        let manifest_file = &dir.path("test_output_manifest.json");
+        let action = CsvCutOptions { input_columns: Some(vec!["col2".to_string()]) };
+
+        // The copy and the manifest are still written in full
+        let options = CsvOptions {
+            input_file: Some("test/test_input.csv".to_string()),
+            tee: vec![copy_file.to_string()],
+            manifest_file: Some(manifest_file.to_string()),
+            ..Default::default()
+        }.with_output(ClosedPipe);
+        process_csv(&options, &action).expect("process_csv failed");
+        assert_eq!(fs::read_to_string(copy_file).expect("Unable to read copy"), "col2\n2\n5\n8\n");
+        let manifest: serde_json::Value = serde_json::from_str(&fs::read_to_string(manifest_file).expect("Unable to read manifest")).unwrap();
+        assert_eq!(manifest["records"], 3);
+
+        // With no other output the run ends early, but quietly
+        let options = CsvOptions {
+            input_file: Some("test/test_input.csv".to_string()),
+            ..Default::default()
+        }.with_output(ClosedPipe);
+        let result = process_csv(&options, &action);
+        assert!(result.as_ref().is_err_and(|e| writer::is_broken_pipe(e.as_ref())));
+        assert_eq!(options.finish(result), Ok(()));
+    }
+
+    #[test]
+    fn test_process_csv_in_place() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_in_place.csv");
+        fs::write(input_file, "id,name\n1,a\n").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            in_place: Some(".bak".to_string()),
+            ..Default::default()
+        };
+
+        // A failure leaves the input untouched
+        let action = CsvCutOptions { input_columns: Some(vec!["missing".to_string()]) };
+        assert!(process_csv(&options, &action).is_err());
+        assert_eq!(fs::read_to_string(input_file).expect("Unable to read input file"), "id,name\n1,a\n");
+
+        let action = CsvCutOptions { input_columns: Some(vec!["name".to_string()]) };
+        process_csv(&options, &action).expect("process_csv failed");
+        assert_eq!(fs::read_to_string(input_file).expect("Unable to read input file"), "name\na\n");
+        assert_eq!(fs::read_to_string(dir.path("test_input_in_place.csv.bak")).expect("Unable to read backup"), "id,name\n1,a\n");
+        assert_eq!(fs::read_dir(dir.path("")).expect("Unable to list directory").count(), 2);
+    }
+
+    #[test]
+    fn test_process_csv_summary() {
+        let output = options::SharedBuffer::new();
+        let options = CsvOptions {
+            drop_empty_rows: true,
+            metrics: Some(std::sync::Arc::new(options::Metrics::new())),
+            ..Default::default()
+        }.with_input(std::io::Cursor::new("id,note\n1,\"a\nb\"\n,\n\n2,c\n")).with_output(output.clone());
+
+        let action = CsvCutOptions { input_columns: Some(vec!["note".to_string()]) };
+        process_csv(&options, &action).expect("process_csv failed");
+        assert_eq!(output.to_string_lossy(), "note\n\"a\nb\"\nc\n");
+        let summary = options.metrics.as_ref().unwrap().summary();
+        assert!(summary.starts_with("Summary: 3 records read, 2 written, 1 skipped, 23 bytes read, 13 bytes written, "), "{}", summary);
+    }
+
+    #[test]
+    fn test_process_csv_add_source_columns() {
+        let dir = csvutil::TestDir::new();
+        let input_file = "test/test_input.csv";
+        let output_file = &dir.path("test_output.csv");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            add_source_columns: true,
+            ..Default::default()
+        };
+
+        process_csv(&options, &CsvCutOptions { input_columns: Some(vec!["col2".to_string()]) }).expect("process_csv failed");
+
+        let expected_output = "col2,_source_file,_source_row\n2,test/test_input.csv,1\n5,test/test_input.csv,2\n8,test/test_input.csv,3\n";
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, expected_output);
+    }
+
+    #[test]
+    fn test_process_csv_enforce_schema() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_schema.csv");
+        let output_file = &dir.path("test_output.csv");
+        let schema_file = &dir.path("test_schema.json");
+        fs::write(input_file, "name,id,extra
+a,1,x
+b,two,y
+").expect("Unable to write input file");
+        fs::write(schema_file, r#"{"fields": [
+            {"name": "id", "type": "integer", "constraints": {"required": true}},
+            {"name": "name", "type": "string"},
+            {"name": "score", "type": "number"}
+        ]}"#).expect("Unable to write schema file");
+
+        let mut options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            contract: Some(writer::parse_contract(schema_file).expect("parse_contract failed")),
+            ..Default::default()
+        };
+
+        let error = process_csv(&options, &CsvCutOptions { input_columns: None }).expect_err("type violation accepted");
+        assert_eq!(error.to_string(), "Record 2: column id should be an integer, got \"two\"");
+
+        options.contract.as_mut().unwrap().policy = writer::ViolationPolicy::Keep;
+        process_csv(&options, &CsvCutOptions { input_columns: None }).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, "id,name,score\n1,a,\ntwo,b,\n");
+
+        // Blanking a required column's value would break the contract too
+        options.contract.as_mut().unwrap().policy = writer::ViolationPolicy::Blank;
+        assert!(process_csv(&options, &CsvCutOptions { input_columns: None }).is_err());
+
+        // csvschema's own JSON format works too
+        fs::write(schema_file, r#"[{"name": "id", "type": "Integer", "nullable": true}]"#).expect("Unable to write schema file");
+        options.contract = Some(writer::parse_contract(schema_file).expect("parse_contract failed"));
+        options.contract.as_mut().unwrap().policy = writer::ViolationPolicy::Blank;
+        process_csv(&options, &CsvCutOptions { input_columns: None }).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, "id\n1\n\"\"\n");
+    }
+
+    #[test]
+    fn test_process_csv_replace() {
+        let dir = csvutil::TestDir::new();
+        let input_file = "test/test_input.csv";
+        let output_file = &dir.path("test_output.csv");
+
+        let action = CsvCutOptions {
+            input_columns: Some(vec!["col1".to_string(), "col2".to_string()]),
+        };
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            transforms: vec![transform::parse_replace("1-2:4=four").unwrap(), transform::parse_replace("col2:8=eight").unwrap()],
+            ..Default::default()
+        };
+
+        process_csv(&options, &action).expect("process_csv failed");
+
+        let expected_output = "col1,col2\n1,2\nfour,5\n7,eight\n";
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, expected_output);
+
+        // --zero-based counts the transforms' offsets from 0 too
+        let options = CsvOptions { transforms: vec![transform::parse_replace("0:4=four").unwrap()], zero_based: true, ..options };
+        process_csv(&options, &action).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, "col1,col2\n1,2\nfour,5\n7,8\n");
+    }
+
+    #[test]
+    fn test_process_csv_truncate() {
+        let output = options::SharedBuffer::new();
+
+        let action = CsvCutOptions { input_columns: None };
+
+        let options = CsvOptions {
+            transforms: vec![transform::parse_truncate("2-3=4").unwrap()],
+            ..Default::default()
+        }.with_input(&b"id,name,city\n1,Jo,Oslo\n2,Zo\xc3\xab Smith,Z\xc3\xbcrich\n"[..]).with_output(output.clone());
+
+        process_csv(&options, &action).expect("process_csv failed");
+
+        assert_eq!(output.to_string_lossy(), "id,name,city\n1,Jo,Oslo\n2,Zo\u{eb},Z\u{fc}r\n");
+    }
+
+    #[test]
+    fn test_process_csv_split_column() {
+        let output = options::SharedBuffer::new();
+
+        let action = CsvCutOptions {
+            input_columns: Some(vec!["id".to_string(), "last".to_string(), "first".to_string()]),
+        };
+
+        let options = CsvOptions {
+            transforms: vec![transform::parse_split("name: ,:last,first").unwrap()],
+            ..Default::default()
+        }.with_input(&b"id,name\n1,\"Doe, Jane\"\n2,Cher\n"[..]).with_output(output.clone());
+
+        process_csv(&options, &action).expect("process_csv failed");
+
+        let expected_output = "id,last,first\n1,Doe,Jane\n2,Cher,\n";
+        assert_eq!(output.to_string_lossy(), expected_output);
+
+        // The stream is used up rather than read again
+        assert!(process_csv(&options, &action).is_err());
+    }
+
+    #[test]
+    fn test_process_csv_concat() {
+        let dir = csvutil::TestDir::new();
+        let input_file = "test/test_input.csv";
+        let output_file = &dir.path("test_output.csv");
+
+        let action = CsvCutOptions {
+            input_columns: Some(vec!["key".to_string(), "col2".to_string()]),
+        };
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            transforms: vec![transform::parse_concat("key=col1,'-',3").unwrap()],
+            ..Default::default()
+        };
+
+        process_csv(&options, &action).expect("process_csv failed");
+
+        let expected_output = "key,col2\n1-3,2\n4-6,5\n7-9,8\n";
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, expected_output);
+    }
+
+    #[test]
+    #[cfg(feature = "fast-engine")]
+    fn test_process_csv_fast_engine() {
+        let dir = csvutil::TestDir::new();
+        let input_file = "test/test_input.csv";
+        let output_file = &dir.path("test_output.csv");
+
+        let action = CsvCutOptions {
+            input_columns: Some(vec!["col1".to_string(), "col3".to_string()]),
+        };
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            fast_engine: true,
+            ..Default::default()
+        };
+
+        process_csv(&options, &action).expect("process_csv failed");
+
+        let expected_output = "col1,col3\n1,3\n4,6\n7,9\n";
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, expected_output);
+    }
+
+    #[test]
+    fn test_process_csv_allocations() {
+        let dir = csvutil::TestDir::new();
+        let output_file = &dir.path("test_output.csv");
+        let action = CsvCutOptions {
+            input_columns: Some(vec!["1".to_string(), "3-5".to_string()]),
+        };
+
+        // Allocations shouldn't grow with the number of records, with or without transforms
+        for transforms in [vec![], vec![transform::parse_replace("col2:7=seven").unwrap()]] {
+            let mut counts = vec![];
+            for rows in [1_000, 10_000] {
+                let input_file = &dir.path("test_input_allocations.csv");
+                fs::write(input_file, csvutil::synthetic_csv(rows, 5)).expect("Unable to write input file");
+                let options = CsvOptions {
+                    input_file: Some(input_file.to_string()),
+                    output_file: Some(output_file.to_string()),
+                    transforms: transforms.clone(),
+                    ..Default::default()
+                };
+                counts.push(count_allocations(|| process_csv(&options, &action).expect("process_csv failed")));
+            }
+            assert!(counts[1] < counts[0] + 50, "allocations grew from {} to {}", counts[0], counts[1]);
+        }
     }
 
     #[test]
     fn test_build_args() {
-        let args = vec!["CsvStar", "--columns", "col1,col2"]
+        let args = ["CsvStar", "--columns", "col1,col2"]
             .iter().map(|s| s.to_string()).collect::<Vec<_>>();
 
         let (_, action) = parse_args(args);
 
         let columns: Vec<String> = action.input_columns.unwrap();
         assert_eq!(columns, vec!["col1", "col2"]);
+
+        let args = ["CsvStar", "--last", "a,b", "--move", "c:2", "--first", "d"]
+            .iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        let (options, _) = parse_args(args);
+
+        assert_eq!(options.reorders, vec![
+            csvutil::Reorder::Last(vec!["a".to_string(), "b".to_string()]),
+            csvutil::Reorder::Move("c".to_string(), 2),
+            csvutil::Reorder::First(vec!["d".to_string()]),
+        ]);
+
+        let (options, _) = parse_args(["CsvStar", "-i", "in.csv"].iter().map(|s| s.to_string()).collect());
+        assert_eq!(options.in_place.as_deref(), Some(""));
+        let (options, _) = parse_args(["CsvStar", "--in-place=.orig", "in.csv"].iter().map(|s| s.to_string()).collect());
+        assert_eq!(options.in_place.as_deref(), Some(".orig"));
     }
 }
 
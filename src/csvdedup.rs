@@ -0,0 +1,251 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+
+use crate::args::global_args;
+use clap::Arg;
+use csv::WriterBuilder;
+use options::CsvOptions;
+use std::collections::HashSet;
+use std::error::Error;
+use std::io::BufRead;
+
+struct CsvDedupOptions {
+    columns: Option<Vec<String>>,
+    /// Report near-duplicates to this file instead of dropping them
+    report: Option<String>,
+    /// Largest edit distance, per column, between near-duplicate values
+    max_distance: usize,
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>());
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> (CsvOptions, CsvDedupOptions) {
+    let executable_name = args[0].clone();
+
+    let command = args::memory_args(args::in_place_args(args::contract_args(global_args())))
+        .display_name(executable_name)
+        .about("Drops records that repeat an earlier record, keeping the first. With --fuzzy, near-duplicates are kept and listed in a report.")
+        .arg(Arg::new("columns")
+            .short('c')
+            .long("columns")
+            .allow_negative_numbers(true)
+            .help("Columns that identify a duplicate, using the same syntax as csvcut's --columns. Defaults to the whole record")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("fuzzy")
+            .long("fuzzy")
+            .requires("report")
+            .help("Match ignoring case and runs of whitespace, and report the matches instead of dropping them")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("max_distance")
+            .long("max-distance")
+            .value_parser(clap::value_parser!(u64))
+            .requires("fuzzy")
+            .help("With --fuzzy, also match values within this Levenshtein distance of each other, column by column"))
+        .arg(Arg::new("report")
+            .long("report")
+            .requires("fuzzy")
+            .help("File to write the row,duplicate_of,distance report of near-duplicates to"));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let action = CsvDedupOptions {
+        columns: matches.remove_many::<String>("columns")
+            .map(|v| v.flat_map(|s| csvutil::parse_column_list(&s))
+                .collect::<Vec<_>>()),
+        report: matches.remove_one("report"),
+        max_distance: matches.remove_one::<u64>("max_distance").unwrap_or(0) as usize,
+    };
+
+    (args::build_options(matches), action)
+}
+
+/// Lowercases a value and collapses its whitespace, so values that differ only in those match.
+fn normalize(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// The number of single character insertions, deletions and substitutions turning `a` into `b`.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The largest distance between any column of two keys, or None if one is over `max_distance`.
+fn key_distance(a: &[String], b: &[String], max_distance: usize) -> Option<usize> {
+    a.iter().zip(b).try_fold(0, |worst, (a, b)| {
+        // Values further apart in length than the limit can't be within it
+        if a.chars().count().abs_diff(b.chars().count()) > max_distance {
+            return None;
+        }
+        let distance = if a == b { 0 } else { levenshtein(a, b) };
+        (distance <= max_distance).then_some(worst.max(distance))
+    })
+}
+
+fn process_csv(options: &CsvOptions, dedup_options: &CsvDedupOptions) -> Result<(), Box<dyn Error>> {
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    let mut reader = csvutil::csv_reader(options, input);
+
+    // Get the column headers
+    let first_row = csvutil::column_names(options, reader.headers()?.clone());
+
+    let key_indices = match &dedup_options.columns {
+        Some(_) => csvutil::select_column_indices(&first_row, &dedup_options.columns)?,
+        None => (0..first_row.len()).collect(),
+    };
+
+    let output_has_headers = options.output_has_headers();
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+
+    if output_has_headers {
+        let all_indices = (0..first_row.len()).collect::<Vec<_>>();
+        csv_writer.write_record(csvutil::enumerate_output_headers(options.config().has_headers, first_row, &all_indices))?;
+    }
+
+    let mut report_writer = match &dedup_options.report {
+        Some(report) => {
+            let mut writer = WriterBuilder::new().from_path(report)?;
+            writer.write_record(["row", "duplicate_of", "distance"])?;
+            Some(writer)
+        }
+        None => None,
+    };
+
+    let mut seen: HashSet<Vec<String>> = HashSet::new();
+    // Each distinct normalized key with the row it was first seen in, for fuzzy matching
+    let mut kept: Vec<(Vec<String>, usize)> = vec![];
+    let mut seen_bytes = 0;
+    for (row, result) in reader.records().enumerate() {
+        let record = result?;
+        let row = row + 1;
+        let key = key_indices.iter().map(|&i| record.get(i).unwrap_or_default().to_string()).collect::<Vec<_>>();
+
+        if !seen.insert(key.clone()) {
+            continue;
+        }
+        // --fuzzy keeps a second, normalized copy of the key
+        let copies = if report_writer.is_some() { 2 } else { 1 };
+        seen_bytes += copies * key.iter().map(|v| v.len() as u64 + csvutil::STRING_OVERHEAD).sum::<u64>();
+        csvutil::check_memory(options, seen_bytes, "The distinct keys seen",
+                              "Identify duplicates by fewer columns with --columns")?;
+
+        if let Some(report_writer) = &mut report_writer {
+            let key = key.iter().map(|v| normalize(v)).collect::<Vec<_>>();
+            let closest = kept.iter()
+                .filter_map(|(other, other_row)| Some((key_distance(&key, other, dedup_options.max_distance)?, *other_row)))
+                .min();
+            match closest {
+                Some((distance, other_row)) => report_writer.write_record([row.to_string(), other_row.to_string(), distance.to_string()])?,
+                None => kept.push((key, row)),
+            }
+        }
+
+        csv_writer.write_record(&record)?;
+    }
+
+    csv_writer.finish()?;
+    if let Some(mut report_writer) = report_writer {
+        report_writer.flush()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("café", "cafe"), 1);
+        assert_eq!(normalize("  Jane   SMITH "), "jane smith");
+    }
+
+    #[test]
+    fn test_process_csv() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_dedup.csv");
+        let output_file = &dir.path("test_output_dedup.csv");
+        fs::write(input_file, "name,city\nJane Smith,Leeds\nJane Smith,Leeds\nJANE  smith,Leeds\nJane Smyth,Leeds\nJohn Brown,York\n")
+            .expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+        let mut dedup_options = CsvDedupOptions { columns: None, report: None, max_distance: 0 };
+
+        process_csv(&options, &dedup_options).expect("process_csv failed");
+        let output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(output, "name,city\nJane Smith,Leeds\nJANE  smith,Leeds\nJane Smyth,Leeds\nJohn Brown,York\n");
+
+        dedup_options.columns = Some(vec!["city".to_string()]);
+        process_csv(&options, &dedup_options).expect("process_csv failed");
+        let output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(output, "name,city\nJane Smith,Leeds\nJohn Brown,York\n");
+
+        let options = CsvOptions { max_memory: Some(100), ..options };
+        assert_eq!(process_csv(&options, &dedup_options).map_err(|e| e.to_string()),
+                   Err("The distinct keys seen need more than --max-memory 100 bytes. Identify duplicates by fewer columns \
+                        with --columns, or raise --max-memory".to_string()));
+    }
+
+    #[test]
+    fn test_process_csv_fuzzy() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_dedup_fuzzy.csv");
+        let output_file = &dir.path("test_output_dedup_fuzzy.csv");
+        let report_file = &dir.path("test_report_dedup_fuzzy.csv");
+        fs::write(input_file, "name,city\nJane Smith,Leeds\nJane Smith,Leeds\nJANE  smith,Leeds\nJane Smyth,Leeds\nJohn Brown,York\n")
+            .expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+        let mut dedup_options = CsvDedupOptions { columns: None, report: Some(report_file.to_string()), max_distance: 0 };
+
+        process_csv(&options, &dedup_options).expect("process_csv failed");
+        let output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(output, "name,city\nJane Smith,Leeds\nJANE  smith,Leeds\nJane Smyth,Leeds\nJohn Brown,York\n");
+        let report = fs::read_to_string(report_file).expect("Unable to read report file");
+        assert_eq!(report, "row,duplicate_of,distance\n3,1,0\n");
+
+        dedup_options.max_distance = 1;
+        process_csv(&options, &dedup_options).expect("process_csv failed");
+        let report = fs::read_to_string(report_file).expect("Unable to read report file");
+        assert_eq!(report, "row,duplicate_of,distance\n3,1,0\n4,1,1\n");
+    }
+}
@@ -0,0 +1,279 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+
+use crate::args::global_args;
+use clap::Arg;
+use csv::StringRecord;
+use options::CsvOptions;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::BufRead;
+
+struct CsvDiffOptions {
+    new_file: String,
+    key_columns: Vec<String>,
+    summary: bool,
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>());
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> (CsvOptions, CsvDiffOptions) {
+    let executable_name = args[0].clone();
+
+    let command = args::color_args(global_args())
+        .display_name(executable_name)
+        .about("Compares two CSV files record by record, matched on key columns, and writes a long-format report \
+                of every changed, added and removed value. The old file is held in memory.")
+        .after_help("With --color, old values are red and new values green.")
+        .mut_arg("input", |a| a.required(true).help("Old input file, or - for standard input"))
+        .arg(Arg::new("new")
+            .required(true)
+            .help("New input file, read with the same dialect as the old"))
+        .arg(Arg::new("key")
+            .short('k')
+            .long("key")
+            .required(true)
+            .allow_negative_numbers(true)
+            .help("Columns identifying a record in both files, using the same syntax as csvcut's --columns")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("change_summary")
+            .long("change-summary")
+            .help("Instead of the report, count the changed values of each column, and the values of added and removed records")
+            .action(clap::ArgAction::SetTrue));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let action = CsvDiffOptions {
+        new_file: matches.remove_one("new").expect("the new file is required"),
+        key_columns: matches.remove_many::<String>("key")
+            .map(|v| v.flat_map(|s| csvutil::parse_column_list(&s))
+                .collect::<Vec<_>>())
+            .unwrap_or_default(),
+        summary: matches.remove_one::<bool>("change_summary").unwrap_or(false),
+    };
+
+    (args::build_options(matches), action)
+}
+
+/// One line of the report: a value that changed, or a value of an added or removed record.
+struct Change<'a> {
+    key: &'a [String],
+    change: &'static str,
+    column: usize,
+    old_value: &'a str,
+    new_value: &'a str,
+}
+
+fn key(record: &StringRecord, indices: &[usize]) -> Vec<String> {
+    indices.iter().map(|&i| record.get(i).unwrap_or_default().to_string()).collect()
+}
+
+fn process_csv(options: &CsvOptions, diff_options: &CsvDiffOptions) -> Result<(), Box<dyn Error>> {
+    let key_columns = Some(diff_options.key_columns.clone());
+
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+    let mut reader = csvutil::csv_reader(options, input);
+    let old_headers = csvutil::column_names(options, reader.headers()?.clone());
+    let old_keys = csvutil::select_column_indices(&old_headers, &key_columns)?;
+    let mut old_records: Vec<StringRecord> = vec![];
+    let mut old_index: HashMap<Vec<String>, usize> = HashMap::new();
+    for result in reader.records() {
+        let record = result?;
+        let key = key(&record, &old_keys);
+        if old_index.insert(key.clone(), old_records.len()).is_some() {
+            return Err(Box::from(format!("Key {} is repeated in the old file", key.join(","))));
+        }
+        old_records.push(record);
+    }
+
+    // The new file is read with the same dialect as the old
+    let new_input = CsvOptions { input_file: Some(diff_options.new_file.clone()), verify_sha256: None, ..options.clone() };
+    let mut new_reader = csvutil::csv_reader(options, new_input.get_input_file()?);
+    let new_headers = csvutil::column_names(options, new_reader.headers()?.clone());
+    let new_keys = csvutil::select_column_indices(&new_headers, &key_columns)?;
+    if old_keys.len() != new_keys.len() {
+        return Err(Box::from(format!("The key is {} columns in the old file but {} in the new", old_keys.len(), new_keys.len())));
+    }
+
+    // Columns are compared by name, and a column only in one file is empty in the other
+    let mut columns: Vec<(String, Option<usize>, Option<usize>)> = vec![];
+    for (i, name) in old_headers.iter().enumerate().filter(|(i, _)| !old_keys.contains(i)) {
+        columns.push((name.to_string(), Some(i), None));
+    }
+    for (i, name) in new_headers.iter().enumerate().filter(|(i, _)| !new_keys.contains(i)) {
+        match columns.iter_mut().find(|(n, _, new)| n == name && new.is_none()) {
+            Some(column) => column.2 = Some(i),
+            None => columns.push((name.to_string(), None, Some(i))),
+        }
+    }
+
+    let output_has_headers = options.output_has_headers();
+
+    let color = options.use_color();
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+
+    if output_has_headers {
+        if diff_options.summary {
+            csv_writer.write_record(["column", "changed", "added", "removed"])?;
+        } else {
+            let mut headers = old_keys.iter().map(|&i| old_headers[i].to_string()).collect::<Vec<_>>();
+            headers.extend(["change", "column", "old_value", "new_value"].map(String::from));
+            csv_writer.write_record(&headers)?;
+        }
+    }
+
+    // Per column, how many values changed, were added and were removed
+    let mut counts = vec![[0u64; 3]; columns.len()];
+    let mut report = |change: Change| -> csv::Result<()> {
+        if diff_options.summary {
+            match change.change {
+                "changed" => counts[change.column][0] += 1,
+                "added" => counts[change.column][1] += 1,
+                _ => counts[change.column][2] += 1,
+            }
+            return Ok(());
+        }
+        let mut line = change.key.to_vec();
+        line.extend([change.change, &columns[change.column].0].map(String::from));
+        if color {
+            line.extend([writer::colored(change.old_value, writer::RED), writer::colored(change.new_value, writer::GREEN)]);
+        } else {
+            line.extend([change.old_value, change.new_value].map(String::from));
+        }
+        csv_writer.write_record(&line)
+    };
+
+    let mut seen = vec![false; old_records.len()];
+    for result in new_reader.records() {
+        let record = result?;
+        let key = key(&record, &new_keys);
+        let old = old_index.get(&key).copied();
+        if let Some(old) = old {
+            if seen[old] {
+                return Err(Box::from(format!("Key {} is repeated in the new file", key.join(","))));
+            }
+            seen[old] = true;
+        }
+        for (c, (_, old_column, new_column)) in columns.iter().enumerate() {
+            let new_value = new_column.and_then(|i| record.get(i)).unwrap_or_default();
+            match old {
+                Some(old) => {
+                    let old_value = old_column.and_then(|i| old_records[old].get(i)).unwrap_or_default();
+                    if old_value != new_value {
+                        report(Change { key: &key, change: "changed", column: c, old_value, new_value })?;
+                    }
+                }
+                None if !new_value.is_empty() => report(Change { key: &key, change: "added", column: c, old_value: "", new_value })?,
+                None => {}
+            }
+        }
+        // An added record with only its key still needs a line
+        if old.is_none() && columns.iter().all(|(_, _, new_column)| new_column.and_then(|i| record.get(i)).unwrap_or_default().is_empty()) {
+            if let Some(c) = columns.iter().position(|(_, _, new_column)| new_column.is_some()) {
+                report(Change { key: &key, change: "added", column: c, old_value: "", new_value: "" })?;
+            }
+        }
+    }
+
+    for (record, _) in old_records.iter().zip(&seen).filter(|(_, &seen)| !seen) {
+        let key = key(record, &old_keys);
+        let mut reported = false;
+        for (c, (_, old_column, _)) in columns.iter().enumerate() {
+            let old_value = old_column.and_then(|i| record.get(i)).unwrap_or_default();
+            if !old_value.is_empty() {
+                reported = true;
+                report(Change { key: &key, change: "removed", column: c, old_value, new_value: "" })?;
+            }
+        }
+        if !reported {
+            if let Some(c) = columns.iter().position(|(_, old_column, _)| old_column.is_some()) {
+                report(Change { key: &key, change: "removed", column: c, old_value: "", new_value: "" })?;
+            }
+        }
+    }
+
+    if diff_options.summary {
+        for ((name, _, _), [changed, added, removed]) in columns.iter().zip(&counts) {
+            csv_writer.write_record([name.clone(), changed.to_string(), added.to_string(), removed.to_string()])?;
+        }
+    }
+
+    csv_writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn diff(old: &str, new: &str, summary: bool) -> Result<String, String> {
+        diff_with(old, new, summary, CsvOptions::default())
+    }
+
+    fn diff_with(old: &str, new: &str, summary: bool, options: CsvOptions) -> Result<String, String> {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_diff_old.csv");
+        let new_file = &dir.path("test_input_diff_new.csv");
+        let output_file = &dir.path("test_output_diff.csv");
+        fs::write(input_file, old).expect("Unable to write input file");
+        fs::write(new_file, new).expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..options
+        };
+        let diff_options = CsvDiffOptions { new_file: new_file.to_string(), key_columns: vec!["id".to_string()], summary };
+
+        let result = process_csv(&options, &diff_options).map_err(|e| e.to_string());
+        let output = fs::read_to_string(output_file).unwrap_or_default();
+        result.map(|_| output)
+    }
+
+    #[test]
+    fn test_process_csv() {
+        let old = "id,name,city\n1,Jane,Leeds\n2,Bob,York\n3,Ann,\n";
+        let new = "id,name,city\n1,Jane,Bath\n3,Ann,\n4,Tom,Hull\n";
+        assert_eq!(diff(old, new, false), Ok("id,change,column,old_value,new_value\n\
+                                              1,changed,city,Leeds,Bath\n\
+                                              4,added,name,,Tom\n\
+                                              4,added,city,,Hull\n\
+                                              2,removed,name,Bob,\n\
+                                              2,removed,city,York,\n".to_string()));
+        assert_eq!(diff(old, new, true), Ok("column,changed,added,removed\nname,0,1,1\ncity,1,1,1\n".to_string()));
+    }
+
+    #[test]
+    fn test_process_csv_columns() {
+        assert_eq!(diff("id,a\n1,x\n", "id,b\n1,y\n", false),
+                   Ok("id,change,column,old_value,new_value\n1,changed,a,x,\n1,changed,b,,y\n".to_string()));
+        assert_eq!(diff("id,a\n1,x\n1,y\n", "id,a\n", false), Err("Key 1 is repeated in the old file".to_string()));
+    }
+
+    #[test]
+    fn test_process_csv_color() {
+        let options = CsvOptions { color: options::ColorChoice::Always, ..Default::default() };
+        assert_eq!(diff_with("id,a\n1,x\n2,y\n", "id,a\n1,z\n", false, options),
+                   Ok("id,change,column,old_value,new_value\n\
+                       1,changed,a,\x1b[31mx\x1b[0m,\x1b[32mz\x1b[0m\n\
+                       2,removed,a,\x1b[31my\x1b[0m,\n".to_string()));
+    }
+}
@@ -0,0 +1,216 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+
+use crate::args::global_args;
+use csv::ByteRecord;
+use options::CsvOptions;
+use reader::{FieldParser, RecordScanner};
+use std::error::Error;
+use std::io::BufRead;
+
+const REPLACEMENT_CHARACTER: char = '\u{FFFD}';
+
+fn main() -> Result<(), String> {
+    let options = parse_args(std::env::args().collect::<Vec<_>>());
+
+    options.finish(process_csv(&options))
+}
+
+fn parse_args(args: Vec<String>) -> CsvOptions {
+    let executable_name = args[0].clone();
+
+    let command = global_args()
+        .display_name(executable_name)
+        .about("Reports fields containing invalid UTF-8 or replacement characters, one output row per problem field. \
+                Exits with an error when any are found.");
+
+    args::build_options(args::get_matches_from(command, args))
+}
+
+/// Describes what's wrong with a field, if anything, with the offset of the first bad byte within the field.
+fn check_field(field: &[u8]) -> Option<(usize, &'static str)> {
+    match std::str::from_utf8(field) {
+        Err(e) => Some((e.valid_up_to(), "invalid UTF-8")),
+        Ok(s) => s.find(REPLACEMENT_CHARACTER).map(|offset| (offset, "replacement character")),
+    }
+}
+
+/// Reads the records of the input as it is, for their offsets in it: blank lines and comments are
+/// skipped as the csv reader skips them, but nothing is cleaned or dropped.
+struct OffsetRecords {
+    input: Box<dyn BufRead>,
+    scanner: RecordScanner,
+    parser: FieldParser,
+    comment: Option<u8>,
+    flexible: bool,
+    raw: Vec<u8>,
+    /// The records, lines and bytes read so far
+    records: u64,
+    lines: u64,
+    offset: u64,
+    expected_len: Option<usize>,
+}
+
+impl OffsetRecords {
+    fn new(options: &CsvOptions) -> Result<Self, Box<dyn Error>> {
+        let config = options.config();
+        let (input, preamble) = options.get_unaltered_input()?;
+        Ok(OffsetRecords {
+            input,
+            scanner: RecordScanner::new(config.quote).escape(config.escape),
+            parser: FieldParser::new(&config),
+            comment: config.comment,
+            flexible: config.flexible,
+            raw: vec![],
+            records: 0,
+            lines: 0,
+            offset: preamble,
+            expected_len: None,
+        })
+    }
+
+    /// Reads the next record into `record`, returning its byte offset in the input.
+    fn read(&mut self, record: &mut ByteRecord, header: bool) -> Result<Option<u64>, Box<dyn Error>> {
+        loop {
+            self.raw.clear();
+            let start = self.offset;
+            let line = self.lines + 1;
+            let n = self.scanner.read_record(&mut self.input, &mut self.raw)?;
+            if n == 0 {
+                return Ok(None);
+            }
+            self.offset += n as u64;
+            self.lines += self.raw.iter().filter(|&&b| b == b'\n').count() as u64;
+            if reader::is_empty_line(&self.raw) || self.comment.is_some_and(|c| self.raw.first() == Some(&c)) {
+                continue;
+            }
+            self.parser.parse(&self.raw, header, record);
+            let expected_len = *self.expected_len.get_or_insert(record.len());
+            if expected_len != record.len() && !self.flexible {
+                // Worded as the csv crate words it, as the other tools read the input with it
+                return Err(Box::from(format!(
+                    "CSV error: record {} (line: {}, byte: {}): found record with {} fields, but the previous record has {} fields",
+                    self.records, line, start, record.len(), expected_len)));
+            }
+            self.records += 1;
+            return Ok(Some(start));
+        }
+    }
+}
+
+fn process_csv(options: &CsvOptions) -> Result<(), Box<dyn Error>> {
+    let mut records = OffsetRecords::new(options)?;
+    let has_headers = options.config().has_headers;
+
+    // Header names are decoded lossily so a bad header can still label its column
+    let mut first_row = ByteRecord::new();
+    let first_offset = records.read(&mut first_row, has_headers)?;
+    let all_indices = (0..first_row.len()).collect::<Vec<_>>();
+    let names = csvutil::enumerate_output_headers(has_headers,
+                                                  first_row.iter().map(|f| String::from_utf8_lossy(f).to_string()).collect(),
+                                                  &all_indices);
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+
+    if options.config().report_headers {
+        csv_writer.write_record(["row", "column", "record_byte_offset", "field_byte_offset", "issue", "value"])?;
+    }
+
+    let mut invalid = 0;
+    let mut replaced = 0;
+    // Valid non-ASCII text alongside invalid bytes suggests a file stitched together from several encodings
+    let mut multibyte = false;
+
+    let mut check_record = |row: usize, offset: u64, record: &ByteRecord| -> Result<(), Box<dyn Error>> {
+        for (i, field) in record.iter().enumerate() {
+            match check_field(field) {
+                Some((field_offset, issue)) => {
+                    if issue == "invalid UTF-8" { invalid += 1 } else { replaced += 1 }
+                    let column = names.get(i).map_or_else(|| (i + 1).to_string(), |n| n.clone());
+                    csv_writer.write_record([row.to_string(), column, offset.to_string(), field_offset.to_string(),
+                                            issue.to_string(), String::from_utf8_lossy(field).to_string()])?;
+                }
+                None => multibyte |= !field.is_ascii(),
+            }
+        }
+        Ok(())
+    };
+
+    let mut row = 0;
+    if let Some(offset) = first_offset {
+        if !has_headers {
+            row += 1;
+        }
+        check_record(row, offset, &first_row)?;
+    }
+
+    let mut record = ByteRecord::new();
+    while let Some(offset) = records.read(&mut record, false)? {
+        row += 1;
+        check_record(row, offset, &record)?;
+    }
+
+    csv_writer.finish()?;
+
+    if invalid + replaced > 0 {
+        let mixed = if invalid > 0 && multibyte { ". The file appears to mix UTF-8 with another encoding" } else { "" };
+        return Err(Box::from(format!("Found {} fields with invalid UTF-8 and {} with replacement characters{}",
+                                     invalid, replaced, mixed)));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_check_field() {
+        assert_eq!(check_field("plain".as_bytes()), None);
+        assert_eq!(check_field("café".as_bytes()), None);
+        assert_eq!(check_field(b"caf\xe9"), Some((3, "invalid UTF-8")));
+        assert_eq!(check_field("a\u{FFFD}".as_bytes()), Some((1, "replacement character")));
+    }
+
+    #[test]
+    fn test_process_csv() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_encoding.csv");
+        let output_file = &dir.path("test_output_encoding.csv");
+        fs::write(input_file, b"id,name\n1,caf\xc3\xa9\n2,caf\xe9\n").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(process_csv(&options).expect_err("").to_string(),
+                   "Found 1 fields with invalid UTF-8 and 0 with replacement characters. The file appears to mix UTF-8 with another encoding");
+
+        let expected_output = "row,column,record_byte_offset,field_byte_offset,issue,value\n2,name,16,3,invalid UTF-8,caf\u{FFFD}\n";
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, expected_output);
+
+        // Offsets are in the input as it is, whatever is skipped or cleaned on the way
+        fs::write(input_file, b"export\nid,name\r\n\r\n# note\r\n1,caf\xe9\r\n").expect("Unable to write input file");
+        let options = CsvOptions { header_row: Some(2), normalize_newlines: true, skip_blank_lines: true, comment_char: Some('#'), ..options };
+        assert!(process_csv(&options).is_err());
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, "row,column,record_byte_offset,field_byte_offset,issue,value\n1,name,26,3,invalid UTF-8,caf\u{FFFD}\n");
+    }
+}
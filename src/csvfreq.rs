@@ -0,0 +1,242 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+
+use crate::args::global_args;
+use clap::Arg;
+use options::CsvOptions;
+use priority_queue::DoublePriorityQueue;
+use std::error::Error;
+use std::io::BufRead;
+
+struct CsvFreqOptions {
+    input_columns: Option<Vec<String>>,
+    /// Count combinations of the selected columns' values instead of each column on its own
+    joint: bool,
+    top: Option<usize>,
+    max_distinct: Option<usize>,
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>());
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> (CsvOptions, CsvFreqOptions) {
+    let executable_name = args[0].clone();
+
+    let command = args::memory_args(global_args())
+        .display_name(executable_name)
+        .about("Counts how often each value occurs in the selected columns, most frequent first.")
+        .arg(Arg::new("input_columns")
+            .short('c')
+            .long("columns")
+            .allow_negative_numbers(true)
+            .help("Columns to count, using the same syntax as csvcut. Defaults to every column")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("joint")
+            .long("joint")
+            .action(clap::ArgAction::SetTrue)
+            .help("Count each combination of the selected columns' values, one output column per selected column"))
+        .arg(Arg::new("top")
+            .long("top")
+            .value_parser(clap::value_parser!(usize))
+            .help("Only output the N most frequent values of each column"))
+        .arg(Arg::new("max_distinct")
+            .long("max-distinct")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .help("Keep at most N distinct values per column in memory. Past that, counts are approximate upper bounds, \
+                   though every value occurring in more than 1/N of the rows is still found"));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let action = CsvFreqOptions {
+        input_columns: matches.remove_many::<String>("input_columns")
+            .map(|v| v.flat_map(|s| csvutil::parse_column_list(&s))
+                .collect::<Vec<_>>()),
+        joint: matches.remove_one("joint").unwrap_or(false),
+        top: matches.remove_one("top"),
+        max_distinct: matches.remove_one::<u64>("max_distinct").map(|n| n as usize),
+    };
+
+    (args::build_options(matches), action)
+}
+
+/// Counts values exactly or, given a capacity, with the Space-Saving algorithm: once full, a new
+/// value replaces the least frequent one and takes over its count plus one, so counts become
+/// upper bounds but memory stays bounded.
+struct Counter {
+    counts: DoublePriorityQueue<Vec<String>, u64>,
+    capacity: Option<usize>,
+    approximate: bool,
+    /// Estimated memory held by `counts`, see `csvutil::check_memory`
+    bytes: u64,
+}
+
+impl Counter {
+    fn new(capacity: Option<usize>) -> Self {
+        Counter { counts: DoublePriorityQueue::new(), capacity, approximate: false, bytes: 0 }
+    }
+
+    fn add(&mut self, value: Vec<String>) {
+        if self.counts.change_priority_by(&value, |c| *c += 1) {
+            return;
+        }
+        let mut count = 1;
+        if self.capacity.is_some_and(|capacity| self.counts.len() >= capacity) {
+            if let Some((evicted, min)) = self.counts.pop_min() {
+                count += min;
+                self.approximate = true;
+                self.bytes -= value_bytes(&evicted);
+            }
+        }
+        self.bytes += value_bytes(&value);
+        self.counts.push(value, count);
+    }
+
+    /// The values and counts, most frequent first and equally frequent ones in order.
+    fn into_sorted(self, top: Option<usize>) -> Vec<(Vec<String>, u64)> {
+        let mut counts = self.counts.into_iter().collect::<Vec<_>>();
+        counts.sort_by(|(a, m), (b, n)| n.cmp(m).then_with(|| a.cmp(b)));
+        counts.truncate(top.unwrap_or(usize::MAX));
+        counts
+    }
+}
+
+fn value_bytes(value: &[String]) -> u64 {
+    value.iter().map(|s| s.len() as u64 + csvutil::STRING_OVERHEAD).sum()
+}
+
+fn percent(count: u64, total: u64) -> String {
+    format!("{:.2}", count as f64 * 100.0 / total.max(1) as f64)
+}
+
+fn process_csv(options: &CsvOptions, freq_options: &CsvFreqOptions) -> Result<(), Box<dyn Error>> {
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    let mut reader = csvutil::csv_reader(options, input);
+
+    // Get the column headers
+    let first_row = csvutil::column_names(options, reader.headers()?.clone());
+    let selected_indices = csvutil::select_column_indices(&first_row, &freq_options.input_columns)?;
+    let names = csvutil::enumerate_output_headers(options.config().has_headers, first_row, &selected_indices);
+
+    let n_counters = if freq_options.joint { 1 } else { selected_indices.len() };
+    let mut counters = (0..n_counters).map(|_| Counter::new(freq_options.max_distinct)).collect::<Vec<_>>();
+    let check_memory = |counters: &[Counter]| csvutil::check_memory(options, counters.iter().map(|c| c.bytes).sum(),
+                                                                    "The distinct values counted",
+                                                                    "Bound them with --max-distinct, which makes counts approximate");
+    let mut total = 0;
+    for result in reader.records() {
+        let record = result?;
+        let value = |i: usize| record.get(i).unwrap_or_default().to_string();
+        if freq_options.joint {
+            counters[0].add(selected_indices.iter().map(|&i| value(i)).collect());
+        } else {
+            selected_indices.iter().zip(counters.iter_mut()).for_each(|(&i, counter)| counter.add(vec![value(i)]));
+        }
+        total += 1;
+        if total % 10_000 == 0 {
+            check_memory(&counters)?;
+        }
+    }
+    check_memory(&counters)?;
+
+    if counters.iter().any(|c| c.approximate) {
+        log::warning(&format!("more than {} distinct values, so the counts are upper bounds",
+                              freq_options.max_distinct.unwrap_or_default()));
+    }
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+
+    if options.config().report_headers {
+        let mut out_headers = if freq_options.joint { names.clone() } else { vec!["column".to_string(), "value".to_string()] };
+        out_headers.extend(["count".to_string(), "percent".to_string()]);
+        csv_writer.write_record(out_headers)?;
+    }
+
+    for (column, counter) in counters.into_iter().enumerate() {
+        for (value, count) in counter.into_sorted(freq_options.top) {
+            let mut out = if freq_options.joint { vec![] } else { vec![names[column].clone()] };
+            out.extend(value);
+            out.extend([count.to_string(), percent(count, total)]);
+            csv_writer.write_record(out)?;
+        }
+    }
+
+    csv_writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn freq_options(columns: &str, joint: bool) -> CsvFreqOptions {
+        CsvFreqOptions {
+            input_columns: Some(csvutil::parse_column_list(columns)),
+            joint,
+            top: None,
+            max_distinct: None,
+        }
+    }
+
+    #[test]
+    fn test_process_csv() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_freq.csv");
+        let output_file = &dir.path("test_output_freq.csv");
+        fs::write(input_file, "id,status,region\n1,open,eu\n2,closed,us\n3,open,us\n4,open,eu\n").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+
+        process_csv(&options, &freq_options("status,region", false)).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, "column,value,count,percent\nstatus,open,3,75.00\nstatus,closed,1,25.00\nregion,eu,2,50.00\nregion,us,2,50.00\n");
+
+        process_csv(&options, &freq_options("status,region", true)).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, "status,region,count,percent\nopen,eu,2,50.00\nclosed,us,1,25.00\nopen,us,1,25.00\n");
+
+        let top = CsvFreqOptions { top: Some(1), ..freq_options("region", false) };
+        process_csv(&options, &top).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, "column,value,count,percent\nregion,eu,2,50.00\n");
+    }
+
+    #[test]
+    fn test_counter_bounded() {
+        let mut counter = Counter::new(Some(2));
+        for value in ["a", "a", "a", "b", "c", "a", "d"] {
+            counter.add(vec![value.to_string()]);
+        }
+        assert!(counter.approximate);
+        assert_eq!(counter.counts.len(), 2);
+        let counts = counter.into_sorted(None);
+        assert_eq!(counts[0], (vec!["a".to_string()], 4));
+        assert_eq!(counts[1], (vec!["d".to_string()], 3));
+
+        let mut exact = Counter::new(None);
+        ["x", "y", "x"].iter().for_each(|v| exact.add(vec![v.to_string()]));
+        assert!(!exact.approximate);
+        assert_eq!(exact.into_sorted(None), vec![(vec!["x".to_string()], 2), (vec!["y".to_string()], 1)]);
+    }
+}
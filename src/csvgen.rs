@@ -0,0 +1,352 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+
+use crate::args::global_args;
+use clap::Arg;
+use options::CsvOptions;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::error::Error;
+
+/// How values, or with a cardinality the members of the column's pool, are chosen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Distribution {
+    Uniform,
+    /// Centred between the bounds, which are three standard deviations away
+    Normal,
+    /// The k-th value of the pool is chosen in proportion to 1/k
+    Zipf,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Kind {
+    Integer(i64, i64),
+    Number(f64, f64),
+    Boolean,
+    /// Days since the Unix epoch, written as YYYY-MM-DD
+    Date(i64, i64),
+    /// Lowercase words of up to this many letters
+    Text(usize),
+    /// Drawn from these values
+    Values(Vec<String>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct ColumnSpec {
+    name: String,
+    kind: Kind,
+    distribution: Distribution,
+    /// The fraction of values left empty
+    null_rate: f64,
+    /// Draw from a pool of this many distinct values
+    cardinality: Option<usize>,
+}
+
+struct CsvGenOptions {
+    columns: Vec<ColumnSpec>,
+    rows: u64,
+    seed: u64,
+}
+
+/// The pool a zipf column draws from when no cardinality is given.
+const DEFAULT_ZIPF_CARDINALITY: usize = 1000;
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>())?;
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> Result<(CsvOptions, CsvGenOptions), String> {
+    let executable_name = args[0].clone();
+
+    let command = global_args()
+        .display_name(executable_name)
+        .about("Generates synthetic CSV data from a column spec, the same for the same seed, for test fixtures and load tests.")
+        .after_help("The spec is a csvschema --format json or Table Schema file, so the output of csvschema on real data \
+                     is a starting point. Each field has a name and a type: Integer, Number, Boolean, Date or Text. \
+                     Optional keys: min and max (numbers, or YYYY-MM-DD for dates; Integer also takes csvschema's \
+                     max_magnitude), max_length for Text, values to draw from instead, distribution (uniform, normal \
+                     or zipf), null_rate from 0 to 1, and cardinality, the number of distinct values.")
+        .mut_arg("input", |a| a.value_name("SPEC").required(true).help("The column spec, a JSON file"))
+        .arg(Arg::new("rows")
+            .long("rows")
+            .value_parser(clap::value_parser!(u64))
+            .default_value("100")
+            .help("Number of records to generate"))
+        .arg(Arg::new("seed")
+            .long("seed")
+            .value_parser(clap::value_parser!(u64))
+            .default_value("1")
+            .help("Seed for the random values. The same spec, seed and row count always give the same output"));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let spec = matches.get_one::<String>("input").cloned().unwrap_or_default();
+    let action = CsvGenOptions {
+        columns: parse_spec(&spec)?,
+        rows: matches.remove_one("rows").unwrap_or(100),
+        seed: matches.remove_one("seed").unwrap_or(1),
+    };
+
+    Ok((args::build_options(matches), action))
+}
+
+fn parse_spec(path: &str) -> Result<Vec<ColumnSpec>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+    let schema: Value = serde_json::from_str(&text).map_err(|e| format!("Invalid spec {}: {}", path, e))?;
+    let fields = schema.get("fields").unwrap_or(&schema).as_array()
+        .ok_or_else(|| format!("Spec {} has no list of fields", path))?;
+    fields.iter().map(|field| {
+        let name = field["name"].as_str().ok_or_else(|| format!("Spec {} has a field without a name", path))?;
+        parse_column(name, field).map_err(|e| format!("Spec {}, field {}: {}", path, name, e))
+    }).collect()
+}
+
+fn parse_column(name: &str, field: &Value) -> Result<ColumnSpec, String> {
+    let bounds = |default: (f64, f64)| -> Result<(f64, f64), String> {
+        let bound = |key: &str, default: f64| match &field[key] {
+            Value::Null => Ok(default),
+            value => value.as_f64().ok_or_else(|| format!("{} should be a number", key)),
+        };
+        let (min, max) = (bound("min", default.0)?, bound("max", default.1)?);
+        if min > max {
+            return Err(format!("min {} is greater than max {}", min, max));
+        }
+        Ok((min, max))
+    };
+    let values = field["values"].as_array().map(|v| v.iter().map(|v| v.as_str().map_or_else(|| v.to_string(), str::to_string)).collect::<Vec<_>>());
+    let kind = match (values, field["type"].as_str().unwrap_or_default().to_lowercase().as_str()) {
+        (Some(values), _) if values.is_empty() => return Err("values is empty".to_string()),
+        (Some(values), _) => Kind::Values(values),
+        (None, "integer") => {
+            // csvschema writes the largest magnitude seen as a string, as it may not fit in a JSON number
+            let magnitude = field["max_magnitude"].as_str().and_then(|m| m.parse::<f64>().ok()).unwrap_or(1000.0);
+            let (min, max) = bounds((0.0, magnitude))?;
+            if min.ceil() > max.floor() {
+                return Err(format!("there is no integer from min {} to max {}", min, max));
+            }
+            Kind::Integer(min.ceil() as i64, max.floor() as i64)
+        }
+        (None, "number") => {
+            let (min, max) = bounds((0.0, 1000.0))?;
+            Kind::Number(min, max)
+        }
+        (None, "boolean") => Kind::Boolean,
+        (None, "date") => {
+            let day = |key: &str, default: &str| {
+                let text = field[key].as_str().unwrap_or(default);
+                dates::parse_days(text).ok_or_else(|| format!("{} should be a date such as 2024-01-31, got {:?}", key, text))
+            };
+            let (min, max) = (day("min", "2000-01-01")?, day("max", "2030-12-31")?);
+            if min > max {
+                return Err("min is after max".to_string());
+            }
+            Kind::Date(min, max)
+        }
+        (None, _) => Kind::Text(field["max_length"].as_u64().unwrap_or(12).max(1) as usize),
+    };
+    let distribution = match field["distribution"].as_str().unwrap_or("uniform") {
+        "uniform" => Distribution::Uniform,
+        "normal" => Distribution::Normal,
+        "zipf" => Distribution::Zipf,
+        other => return Err(format!("unknown distribution {:?}. Expected uniform, normal or zipf", other)),
+    };
+    let null_rate = field["null_rate"].as_f64().unwrap_or(0.0);
+    if !(0.0..=1.0).contains(&null_rate) {
+        return Err(format!("null_rate {} should be from 0 to 1", null_rate));
+    }
+    let cardinality = match &field["cardinality"] {
+        Value::Null => None,
+        value => Some(value.as_u64().filter(|&n| n > 0).ok_or("cardinality should be a positive integer")? as usize),
+    };
+    Ok(ColumnSpec { name: name.to_string(), kind, distribution, null_rate, cardinality })
+}
+
+/// A number from 0 to 1, spread as the distribution says. Zipf only applies to pools, so it
+/// is uniform here.
+fn unit(distribution: Distribution, rng: &mut StdRng) -> f64 {
+    match distribution {
+        Distribution::Normal => {
+            // Box-Muller, with the bounds three standard deviations from the middle
+            let (u, v) = (1.0 - rng.gen::<f64>(), rng.gen::<f64>());
+            let z = (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos();
+            (0.5 + z / 6.0).clamp(0.0, 1.0)
+        }
+        Distribution::Uniform | Distribution::Zipf => rng.gen(),
+    }
+}
+
+fn value(kind: &Kind, distribution: Distribution, rng: &mut StdRng) -> String {
+    match kind {
+        // In i128, as the span of the widest ranges doesn't fit in an i64
+        Kind::Integer(min, max) => {
+            let offset = (unit(distribution, rng) * (*max as i128 - *min as i128) as f64).round() as i128;
+            (*min as i128 + offset).clamp(*min as i128, *max as i128).to_string()
+        }
+        Kind::Number(min, max) => format!("{:.2}", min + unit(distribution, rng) * (max - min)),
+        Kind::Boolean => (unit(distribution, rng) < 0.5).to_string(),
+        Kind::Date(min, max) => {
+            let (year, month, day) = dates::civil_from_days(min + (unit(distribution, rng) * (max - min) as f64).round() as i64);
+            format!("{:04}-{:02}-{:02}", year, month, day)
+        }
+        Kind::Text(max_length) => (0..rng.gen_range(1..=*max_length)).map(|_| rng.gen_range(b'a'..=b'z') as char).collect(),
+        Kind::Values(values) => values[((unit(distribution, rng) * values.len() as f64) as usize).min(values.len() - 1)].clone(),
+    }
+}
+
+/// Draws a column's values, from a pool of distinct values when it has a cardinality or values.
+struct Generator {
+    spec: ColumnSpec,
+    pool: Vec<String>,
+    /// For zipf, the running totals of the pool's weights 1, 1/2, 1/3, ...
+    cumulative: Vec<f64>,
+}
+
+impl Generator {
+    fn new(spec: &ColumnSpec, rng: &mut StdRng) -> Self {
+        let mut pool = vec![];
+        let cardinality = spec.cardinality.or((spec.distribution == Distribution::Zipf).then_some(DEFAULT_ZIPF_CARDINALITY));
+        if let (Kind::Values(values), None) = (&spec.kind, spec.cardinality) {
+            pool.clone_from(values);
+        } else if let Some(cardinality) = cardinality {
+            // The pool is as large as the type allows, e.g. two values for a boolean
+            let mut seen = HashSet::new();
+            for _ in 0..cardinality.saturating_mul(20) {
+                let value = value(&spec.kind, Distribution::Uniform, rng);
+                if seen.insert(value.clone()) {
+                    pool.push(value);
+                }
+                if pool.len() == cardinality {
+                    break;
+                }
+            }
+        }
+        let cumulative = match spec.distribution {
+            Distribution::Zipf => (1..=pool.len()).scan(0.0, |total, k| {
+                *total += 1.0 / k as f64;
+                Some(*total)
+            }).collect(),
+            _ => vec![],
+        };
+        Generator { spec: spec.clone(), pool, cumulative }
+    }
+
+    /// A position in the pool.
+    fn index(&self, rng: &mut StdRng) -> usize {
+        let n = self.pool.len();
+        match self.cumulative.last() {
+            Some(total) => {
+                let target = rng.gen::<f64>() * total;
+                self.cumulative.partition_point(|&c| c < target).min(n - 1)
+            }
+            None => ((unit(self.spec.distribution, rng) * n as f64) as usize).min(n - 1),
+        }
+    }
+
+    fn next(&self, rng: &mut StdRng) -> String {
+        if self.spec.null_rate > 0.0 && rng.gen::<f64>() < self.spec.null_rate {
+            String::new()
+        } else if self.pool.is_empty() {
+            value(&self.spec.kind, self.spec.distribution, rng)
+        } else {
+            self.pool[self.index(rng)].clone()
+        }
+    }
+}
+
+fn process_csv(options: &CsvOptions, gen_options: &CsvGenOptions) -> Result<(), Box<dyn Error>> {
+    let mut rng = StdRng::seed_from_u64(gen_options.seed);
+    let generators = gen_options.columns.iter().map(|c| Generator::new(c, &mut rng)).collect::<Vec<_>>();
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+    if options.output_has_headers() {
+        csv_writer.write_record(gen_options.columns.iter().map(|c| &c.name))?;
+    }
+    for _ in 0..gen_options.rows {
+        let record = generators.iter().map(|g| g.next(&mut rng)).collect::<Vec<_>>();
+        csv_writer.write_record(&record)?;
+    }
+    csv_writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn spec(field: Value) -> ColumnSpec {
+        parse_column(field["name"].as_str().unwrap_or("c"), &field).expect("Invalid spec")
+    }
+
+    #[test]
+    fn test_parse_column() {
+        assert_eq!(spec(json!({"type": "Integer", "max_magnitude": "250"})).kind, Kind::Integer(0, 250));
+        assert_eq!(spec(json!({"type": "Number", "min": -1.5})).kind, Kind::Number(-1.5, 1000.0));
+        assert_eq!(spec(json!({"type": "Text", "values": ["a", 2]})).kind, Kind::Values(vec!["a".to_string(), "2".to_string()]));
+        assert_eq!(spec(json!({"type": "Date", "min": "2024-01-01", "max": "2024-01-31"})).kind,
+                   Kind::Date(dates::parse_days("2024-01-01").unwrap(), dates::parse_days("2024-01-31").unwrap()));
+        let column = spec(json!({"type": "Text", "max_length": 3, "distribution": "zipf", "null_rate": 0.25, "cardinality": 5}));
+        assert_eq!((column.kind, column.distribution, column.null_rate, column.cardinality), (Kind::Text(3), Distribution::Zipf, 0.25, Some(5)));
+
+        assert!(parse_column("c", &json!({"type": "Integer", "min": 5, "max": 1})).is_err());
+        assert_eq!(parse_column("c", &json!({"type": "Integer", "min": 0.2, "max": 0.7})).unwrap_err(), "there is no integer from min 0.2 to max 0.7");
+        assert!(parse_column("c", &json!({"type": "Text", "null_rate": 2})).is_err());
+        assert!(parse_column("c", &json!({"type": "Text", "distribution": "poisson"})).is_err());
+        assert!(parse_column("c", &json!({"type": "Text", "cardinality": 0})).is_err());
+    }
+
+    #[test]
+    fn test_value() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let widest = Kind::Integer(-9_000_000_000_000_000_000, 9_000_000_000_000_000_000);
+        for distribution in [Distribution::Uniform, Distribution::Normal] {
+            for _ in 0..100 {
+                let n = value(&widest, distribution, &mut rng).parse::<i64>().expect("Invalid integer");
+                assert!((-9_000_000_000_000_000_000..=9_000_000_000_000_000_000).contains(&n), "{}", n);
+            }
+        }
+        assert!((0..100).all(|_| value(&Kind::Integer(3, 3), Distribution::Uniform, &mut rng) == "3"));
+    }
+
+    #[test]
+    fn test_process_csv() {
+        let dir = csvutil::TestDir::new();
+        let output_file = &dir.path("test_output_gen.csv");
+        let options = CsvOptions { output_file: Some(output_file.to_string()), ..Default::default() };
+        let columns = vec![
+            spec(json!({"name": "id", "type": "Integer", "min": 1, "max": 9})),
+            spec(json!({"type": "Text", "cardinality": 3, "distribution": "zipf"})),
+            spec(json!({"type": "Date", "min": "2024-02-28", "max": "2024-03-01", "distribution": "normal", "null_rate": 0.5})),
+        ];
+        let action = CsvGenOptions { columns, rows: 500, seed: 7 };
+
+        process_csv(&options, &action).expect("process_csv failed");
+        let output = std::fs::read_to_string(output_file).expect("Unable to read output file");
+        let mut reader = csv::Reader::from_reader(output.as_bytes());
+        let records = reader.records().collect::<Result<Vec<_>, _>>().expect("Invalid output");
+        assert_eq!(records.len(), 500);
+        assert!(records.iter().all(|r| (1..=9).contains(&r[0].parse::<i64>().unwrap())));
+        assert_eq!(records.iter().map(|r| &r[1]).collect::<HashSet<_>>().len(), 3);
+        let nulls = records.iter().filter(|r| r[2].is_empty()).count();
+        assert!((200..300).contains(&nulls), "{} nulls", nulls);
+        assert!(records.iter().all(|r| ["", "2024-02-28", "2024-02-29", "2024-03-01"].contains(&&r[2])));
+
+        process_csv(&options, &action).expect("process_csv failed");
+        assert_eq!(std::fs::read_to_string(output_file).expect("Unable to read output file"), output);
+    }
+}
@@ -0,0 +1,213 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+
+use crate::args::global_args;
+use clap::{Arg, ArgGroup};
+use options::CsvOptions;
+use regex::Regex;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::io::BufRead;
+
+enum Pattern {
+    /// The value contains this text
+    Match(String),
+    Regex(Regex),
+    /// The value is one of these lines of a file
+    Values(HashSet<String>),
+}
+
+impl Pattern {
+    fn is_match(&self, value: &str) -> bool {
+        match self {
+            Pattern::Match(text) => value.contains(text.as_str()),
+            Pattern::Regex(regex) => regex.is_match(value),
+            Pattern::Values(values) => values.contains(value),
+        }
+    }
+}
+
+struct CsvGrepOptions {
+    columns: Option<Vec<String>>,
+    pattern: Pattern,
+    any_match: bool,
+    invert_match: bool,
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>())?;
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> Result<(CsvOptions, CsvGrepOptions), String> {
+    let executable_name = args[0].clone();
+
+    let command = args::raw_passthrough_args(args::follow_args(args::contract_args(global_args())))
+        .display_name(executable_name)
+        .about("Keeps the records whose values match a pattern.")
+        .arg(Arg::new("columns")
+            .short('c')
+            .long("columns")
+            .allow_negative_numbers(true)
+            .help("Columns to search, using the same syntax as csvcut's --columns. Defaults to every column")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("match")
+            .long("match")
+            .help("Text the values must contain"))
+        .arg(Arg::new("regex")
+            .short('r')
+            .long("regex")
+            .value_parser(|s: &str| Regex::new(s).map_err(|e| e.to_string()))
+            .help("Regular expression the values must match"))
+        .arg(Arg::new("match_file")
+            .long("match-file")
+            .help("File of values, one per line, that the values must equal"))
+        .group(ArgGroup::new("pattern").args(["match", "regex", "match_file"]).required(true))
+        .arg(Arg::new("any_match")
+            .short('a')
+            .long("any-match")
+            .help("Keep a record if any of the columns match, rather than all of them")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("invert_match")
+            .short('i')
+            .long("invert-match")
+            .help("Keep the records that don't match instead")
+            .action(clap::ArgAction::SetTrue));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let pattern = if let Some(text) = matches.remove_one::<String>("match") {
+        Pattern::Match(text)
+    } else if let Some(regex) = matches.remove_one::<Regex>("regex") {
+        Pattern::Regex(regex)
+    } else {
+        let file = matches.remove_one::<String>("match_file").expect("a pattern is required");
+        let values = fs::read_to_string(&file).map_err(|e| format!("Unable to read {}: {}", file, e))?;
+        Pattern::Values(values.lines().map(String::from).collect())
+    };
+    let action = CsvGrepOptions {
+        columns: matches.remove_many::<String>("columns")
+            .map(|v| v.flat_map(|s| csvutil::parse_column_list(&s))
+                .collect::<Vec<_>>()),
+        pattern,
+        any_match: matches.remove_one::<bool>("any_match").unwrap_or(false),
+        invert_match: matches.remove_one::<bool>("invert_match").unwrap_or(false),
+    };
+
+    Ok((args::build_options(matches), action))
+}
+
+fn process_csv(options: &CsvOptions, grep_options: &CsvGrepOptions) -> Result<(), Box<dyn Error>> {
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    // Get the column headers, and the records with the bytes they were read as for --raw-passthrough
+    let (headers, records) = csvutil::raw_records(options, input)?;
+    let first_row = csvutil::column_names(options, headers);
+
+    let indices = match &grep_options.columns {
+        Some(_) => csvutil::select_column_indices(&first_row, &grep_options.columns)?,
+        None => (0..first_row.len()).collect(),
+    };
+
+    let output_has_headers = options.output_has_headers();
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+
+    if output_has_headers {
+        let all_indices = (0..first_row.len()).collect::<Vec<_>>();
+        csv_writer.write_record(csvutil::enumerate_output_headers(options.config().has_headers, first_row, &all_indices))?;
+    }
+
+    for result in records {
+        let (record, raw) = result?;
+        let mut values = indices.iter().map(|&i| record.get(i).unwrap_or_default());
+        let matched = if grep_options.any_match {
+            values.any(|v| grep_options.pattern.is_match(v))
+        } else {
+            values.all(|v| grep_options.pattern.is_match(v))
+        };
+        if matched != grep_options.invert_match {
+            match &raw {
+                Some(raw) => csv_writer.write_raw(raw, &record)?,
+                None => csv_writer.write_record(&record)?,
+            }
+        }
+    }
+
+    csv_writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grep(columns: Option<&[&str]>, pattern: Pattern, any_match: bool, invert_match: bool) -> String {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_grep.csv");
+        let output_file = &dir.path("test_output_grep.csv");
+        fs::write(input_file, "name,city\nJane,Leeds\nBob,York\nAnn,Bath\n").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+        let grep_options = CsvGrepOptions {
+            columns: columns.map(|c| c.iter().map(|s| s.to_string()).collect()),
+            pattern,
+            any_match,
+            invert_match,
+        };
+
+        process_csv(&options, &grep_options).expect("process_csv failed");
+        fs::read_to_string(output_file).expect("Unable to read output file")
+    }
+
+    #[test]
+    fn test_process_csv() {
+        assert_eq!(grep(Some(&["city"]), Pattern::Match("e".to_string()), false, false), "name,city\nJane,Leeds\n");
+        assert_eq!(grep(None, Pattern::Regex(Regex::new("^[AB]").unwrap()), true, false), "name,city\nBob,York\nAnn,Bath\n");
+        assert_eq!(grep(None, Pattern::Regex(Regex::new("^[AB]").unwrap()), false, false), "name,city\nAnn,Bath\n");
+        assert_eq!(grep(Some(&["name"]), Pattern::Values(["Bob".to_string()].into()), false, true), "name,city\nJane,Leeds\nAnn,Bath\n");
+    }
+
+    #[test]
+    fn test_process_csv_raw_passthrough() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_grep_raw.csv");
+        let output_file = &dir.path("test_output_grep_raw.csv");
+        fs::write(input_file, "\"name\",note\r\n\"Jane\",\"x\r\ny\"\r\n\"Bob\",\"\"\"z\"\"\"\r\nAnn,w").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            raw_passthrough: true,
+            ..Default::default()
+        };
+        let grep_options = CsvGrepOptions {
+            columns: Some(vec!["name".to_string()]),
+            pattern: Pattern::Regex(Regex::new("^[JA]").unwrap()),
+            any_match: false,
+            invert_match: false,
+        };
+        process_csv(&options, &grep_options).expect("process_csv failed");
+        let output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(output, "name,note\n\"Jane\",\"x\r\ny\"\r\nAnn,w\n");
+    }
+}
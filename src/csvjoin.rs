@@ -0,0 +1,487 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+
+use crate::args::global_args;
+use clap::{Arg, ArgGroup};
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use options::CsvOptions;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{self, BufRead, BufReader, BufWriter, Seek};
+use std::iter::Peekable;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum JoinType {
+    Inner,
+    Left,
+    Right,
+    Outer,
+    /// Left records with no match
+    Anti,
+    /// Left records with at least one match, once each
+    Semi,
+}
+
+struct CsvJoinOptions {
+    right_file: String,
+    columns: Vec<String>,
+    join_type: JoinType,
+    /// Both files are sorted on the key, so they can be merged
+    presorted: bool,
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>());
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> (CsvOptions, CsvJoinOptions) {
+    let executable_name = args[0].clone();
+
+    let command = args::memory_args(args::column_order_args(args::contract_args(global_args())))
+        .display_name(executable_name)
+        .about("Joins the records of two CSV files on key columns. The right file is held in memory, unless it is larger than --max-memory.")
+        .mut_arg("input", |a| a.required(true).help("Left input file, or - for standard input"))
+        .mut_arg("max_memory", |a| a.help("Hold at most about this much of the right file in memory, e.g. 2GB. Past it both files \
+                                           are split by key into temporary files and joined a part at a time, so the output is no \
+                                           longer in the left file's order"))
+        .arg(Arg::new("right")
+            .required(true)
+            .help("Right input file, read with the same dialect as the left"))
+        .arg(Arg::new("columns")
+            .short('c')
+            .long("columns")
+            .required(true)
+            .allow_negative_numbers(true)
+            .help("Key columns, in both files, using the same syntax as csvcut's --columns")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("left")
+            .long("left")
+            .help("Also keep left records without a match, with the right columns empty")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("right_join")
+            .long("right")
+            .help("Also keep right records without a match, with the left columns other than the key empty")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("outer")
+            .long("outer")
+            .help("Keep the records of both files without a match")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("anti")
+            .long("anti")
+            .help("Only keep left records without a match, and only the left columns")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("semi")
+            .long("semi")
+            .help("Only keep left records with a match, once each, and only the left columns")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("presorted")
+            .long("presorted")
+            .conflicts_with("max_memory")
+            .help("Both files are sorted on the key columns, as by csvsort -c with the default collation, so merge them \
+                   holding only one key's right records in memory. The output is in key order")
+            .action(clap::ArgAction::SetTrue))
+        .group(ArgGroup::new("join_type").args(["left", "right_join", "outer", "anti", "semi"]));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let join_type = if matches.get_flag("left") {
+        JoinType::Left
+    } else if matches.get_flag("right_join") {
+        JoinType::Right
+    } else if matches.get_flag("outer") {
+        JoinType::Outer
+    } else if matches.get_flag("anti") {
+        JoinType::Anti
+    } else if matches.get_flag("semi") {
+        JoinType::Semi
+    } else {
+        JoinType::Inner
+    };
+    let action = CsvJoinOptions {
+        right_file: matches.remove_one("right").expect("the right file is required"),
+        columns: matches.remove_many::<String>("columns")
+            .map(|v| v.flat_map(|s| csvutil::parse_column_list(&s))
+                .collect::<Vec<_>>())
+            .unwrap_or_default(),
+        join_type,
+        presorted: matches.get_flag("presorted"),
+    };
+
+    (args::build_options(matches), action)
+}
+
+fn key(record: &StringRecord, indices: &[usize]) -> Vec<String> {
+    indices.iter().map(|&i| record.get(i).unwrap_or_default().to_string()).collect()
+}
+
+/// Roughly what holding a right record takes besides its values: its field bounds, its index
+/// entry and their allocations.
+const RECORD_OVERHEAD: u64 = 64;
+
+/// How many times the partitions are split again before giving up, when a key has more right
+/// records than fit in `--max-memory`.
+const MAX_PARTITION_DEPTH: u32 = 8;
+
+type Records<'a> = dyn Iterator<Item = csv::Result<StringRecord>> + 'a;
+
+/// Writes a left record, a right one or a matching pair as an output record.
+type WriteFn<'a> = dyn FnMut(Option<&StringRecord>, Option<&StringRecord>) -> csv::Result<()> + 'a;
+
+struct Join<'a> {
+    join_type: JoinType,
+    left_keys: &'a [usize],
+    right_keys: &'a [usize],
+    max_memory: Option<u64>,
+}
+
+impl Join<'_> {
+    /// Joins the left records against the right ones held in memory. When they don't fit in
+    /// `max_memory`, both sides are partitioned by key and joined a partition at a time.
+    fn run(&self, left: &mut Records, right: &mut Records, right_size: Option<u64>, depth: u32, write: &mut WriteFn) -> Result<(), Box<dyn Error>> {
+        let mut right_records: Vec<StringRecord> = vec![];
+        let mut right_index: HashMap<Vec<String>, Vec<usize>> = HashMap::new();
+        let mut held = 0;
+        while let Some(result) = right.next() {
+            let record = result?;
+            held += record.as_slice().len() as u64 + RECORD_OVERHEAD;
+            right_index.entry(key(&record, self.right_keys)).or_default().push(right_records.len());
+            right_records.push(record);
+            if self.max_memory.is_some_and(|max| held > max) {
+                let mut right = right_records.into_iter().map(Ok).chain(right);
+                return self.run_partitioned(left, &mut right, right_size, depth, write);
+            }
+        }
+        let mut right_matched = vec![false; right_records.len()];
+
+        let keep_left = matches!(self.join_type, JoinType::Left | JoinType::Outer);
+        for result in left {
+            let record = result?;
+            match right_index.get(&key(&record, self.left_keys)) {
+                Some(_) if self.join_type == JoinType::Semi => write(Some(&record), None)?,
+                Some(_) if self.join_type == JoinType::Anti => {}
+                None if self.join_type == JoinType::Anti => write(Some(&record), None)?,
+                Some(matches) => {
+                    for &r in matches {
+                        right_matched[r] = true;
+                        write(Some(&record), Some(&right_records[r]))?;
+                    }
+                }
+                None if keep_left => write(Some(&record), None)?,
+                None => {}
+            }
+        }
+
+        if matches!(self.join_type, JoinType::Right | JoinType::Outer) {
+            for (record, _) in right_records.iter().zip(&right_matched).filter(|(_, &matched)| !matched) {
+                write(None, Some(record))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Joins sides both sorted on the key by walking them together, holding only the right
+    /// records of the current key.
+    fn run_merge(&self, left: &mut Records, right: &mut Records, write: &mut WriteFn) -> Result<(), Box<dyn Error>> {
+        let mut right = right.peekable();
+        let mut right_row = 0;
+        let mut group = next_group(&mut right, self.right_keys, &mut right_row, None)?;
+        let mut group_matched = false;
+        let mut last_left_key: Option<Vec<String>> = None;
+
+        let keep_left = matches!(self.join_type, JoinType::Left | JoinType::Outer);
+        let keep_right = matches!(self.join_type, JoinType::Right | JoinType::Outer);
+        for (row, result) in left.enumerate() {
+            let record = result?;
+            let left_key = key(&record, self.left_keys);
+            if last_left_key.as_ref().is_some_and(|last| *last > left_key) {
+                return Err(Box::from(format!("--presorted: left record {} is out of order on the key", row + 1)));
+            }
+            // Pass the right keys smaller than this one
+            while let Some((right_key, records)) = group.take_if(|(right_key, _)| *right_key < left_key) {
+                if keep_right && !group_matched {
+                    records.iter().try_for_each(|r| write(None, Some(r)))?;
+                }
+                group = next_group(&mut right, self.right_keys, &mut right_row, Some(&right_key))?;
+                group_matched = false;
+            }
+            match &group {
+                Some((right_key, records)) if *right_key == left_key => {
+                    group_matched = true;
+                    match self.join_type {
+                        JoinType::Semi => write(Some(&record), None)?,
+                        JoinType::Anti => {}
+                        _ => records.iter().try_for_each(|r| write(Some(&record), Some(r)))?,
+                    }
+                }
+                _ if keep_left || self.join_type == JoinType::Anti => write(Some(&record), None)?,
+                _ => {}
+            }
+            last_left_key = Some(left_key);
+        }
+
+        while let Some((right_key, records)) = group {
+            if keep_right && !group_matched {
+                records.iter().try_for_each(|r| write(None, Some(r)))?;
+            }
+            group = next_group(&mut right, self.right_keys, &mut right_row, Some(&right_key))?;
+            group_matched = false;
+        }
+        Ok(())
+    }
+
+    /// Splits both sides into temporary files by the hash of their key, so that matching records
+    /// land in the same partition, and joins each partition in turn.
+    fn run_partitioned(&self, left: &mut Records, right: &mut Records, right_size: Option<u64>, depth: u32, write: &mut WriteFn) -> Result<(), Box<dyn Error>> {
+        if depth == MAX_PARTITION_DEPTH {
+            return Err(Box::from("Too many right records share a key to join them within --max-memory"));
+        }
+        // Enough partitions for each to fit, allowing for records taking twice their size in memory
+        let max_memory = self.max_memory.unwrap_or(u64::MAX).max(1);
+        let n = right_size.map_or(16, |size| (size.saturating_mul(2) / max_memory + 1).clamp(2, 256));
+        let right_parts = partition(right, self.right_keys, depth, n)?;
+        let left_parts = partition(left, self.left_keys, depth, n)?;
+        for ((left_file, _), (right_file, right_size)) in left_parts.into_iter().zip(right_parts) {
+            let mut left = partition_reader(left_file).into_records();
+            let mut right = partition_reader(right_file).into_records();
+            self.run(&mut left, &mut right, Some(right_size), depth + 1, write)?;
+        }
+        Ok(())
+    }
+}
+
+/// A key and the right records having it.
+type Group = (Vec<String>, Vec<StringRecord>);
+
+/// Reads the next run of right records sharing a key, checking it comes after `previous`.
+fn next_group(right: &mut Peekable<&mut Records>, keys: &[usize], row: &mut usize, previous: Option<&Vec<String>>) -> Result<Option<Group>, Box<dyn Error>> {
+    let Some(result) = right.next() else {
+        return Ok(None);
+    };
+    *row += 1;
+    let first = result?;
+    let group_key = key(&first, keys);
+    if previous.is_some_and(|previous| *previous >= group_key) {
+        return Err(Box::from(format!("--presorted: right record {} is out of order on the key", row)));
+    }
+    let mut records = vec![first];
+    while let Some(Ok(record)) = right.peek() {
+        if key(record, keys) != group_key {
+            break;
+        }
+        records.push(right.next().expect("peeked")?);
+        *row += 1;
+    }
+    Ok(Some((group_key, records)))
+}
+
+/// Writes records to `n` anonymous temporary files by the hash of their key, returning each
+/// file, rewound, and its size.
+fn partition(records: &mut Records, keys: &[usize], depth: u32, n: u64) -> Result<Vec<(File, u64)>, Box<dyn Error>> {
+    let mut writers = (0..n)
+        .map(|_| tempfile::tempfile().map(|file| WriterBuilder::new().flexible(true).from_writer(BufWriter::new(file))))
+        .collect::<io::Result<Vec<_>>>()?;
+    for result in records {
+        let record = result?;
+        // Salted with the depth, so that a partition split again spreads over new partitions
+        let mut hasher = DefaultHasher::new();
+        (depth, key(&record, keys)).hash(&mut hasher);
+        writers[(hasher.finish() % n) as usize].write_record(&record)?;
+    }
+    writers.into_iter().map(|writer| {
+        let buffer = writer.into_inner().map_err(|e| e.into_error())?;
+        let mut file = buffer.into_inner().map_err(|e| e.into_error())?;
+        let size = file.stream_position()?;
+        file.rewind()?;
+        Ok((file, size))
+    }).collect()
+}
+
+fn partition_reader(file: File) -> csv::Reader<BufReader<File>> {
+    ReaderBuilder::new().has_headers(false).flexible(true).from_reader(BufReader::new(file))
+}
+
+fn process_csv(options: &CsvOptions, join_options: &CsvJoinOptions) -> Result<(), Box<dyn Error>> {
+    let has_headers = options.config().has_headers;
+    let columns = Some(join_options.columns.clone());
+
+    // The right file is read with the same dialect as the input
+    let right_input = CsvOptions { input_file: Some(join_options.right_file.clone()), header_row: None, verify_sha256: None, assertions: vec![], metrics: None,
+                                  drop_if_empty: None, drop_empty_rows: false, ..options.clone() };
+    let right_size = fs::metadata(&join_options.right_file).ok().map(|m| m.len());
+    let mut right_reader = csvutil::csv_reader(options, right_input.get_input_file()?);
+    let right_headers = csvutil::column_names(options, right_reader.headers()?.clone());
+    let right_keys = csvutil::select_column_indices(&right_headers, &columns)?;
+    // Anti- and semi-joins only filter the left records
+    let filter_only = matches!(join_options.join_type, JoinType::Anti | JoinType::Semi);
+    let right_values = (0..right_headers.len()).filter(|i| !filter_only && !right_keys.contains(i)).collect::<Vec<_>>();
+
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    let mut reader = csvutil::csv_reader(options, input);
+
+    // Get the column headers
+    let first_row = csvutil::column_names(options, reader.headers()?.clone());
+    let left_keys = csvutil::select_column_indices(&first_row, &columns)?;
+    if left_keys.len() != right_keys.len() {
+        return Err(Box::from(format!("The key is {} columns in the left file but {} in the right", left_keys.len(), right_keys.len())));
+    }
+
+    let all_indices = (0..first_row.len()).collect::<Vec<_>>();
+    let mut names = csvutil::enumerate_output_headers(has_headers, first_row, &all_indices);
+    names.extend(csvutil::enumerate_output_headers(has_headers, right_headers, &right_values));
+    let order = csvutil::column_order(options, &names);
+    let n_left = all_indices.len();
+
+    let output_has_headers = options.output_has_headers();
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+
+    if output_has_headers {
+        csv_writer.write_record(order.iter().map(|(name, _)| name))?;
+    }
+
+    let n_columns = names.len();
+    let mut write = |left: Option<&StringRecord>, right: Option<&StringRecord>| -> csv::Result<()> {
+        let mut values = vec![""; n_columns];
+        match left {
+            Some(left) => left.iter().take(n_left).enumerate().for_each(|(i, v)| values[i] = v),
+            // An unmatched right record still has its key, in the left key columns
+            None => left_keys.iter().zip(&right_keys).for_each(|(&l, &r)| values[l] = right.and_then(|right| right.get(r)).unwrap_or_default()),
+        }
+        if let Some(right) = right {
+            right_values.iter().enumerate().for_each(|(i, &r)| values[n_left + i] = right.get(r).unwrap_or_default());
+        }
+        csv_writer.write_record(order.iter().map(|(_, i)| i.map_or("", |i| values[i])))
+    };
+
+    let join = Join { join_type: join_options.join_type, left_keys: &left_keys, right_keys: &right_keys, max_memory: options.max_memory };
+    if join_options.presorted {
+        join.run_merge(&mut reader.records(), &mut right_reader.records(), &mut write)?;
+    } else {
+        join.run(&mut reader.records(), &mut right_reader.records(), right_size, 0, &mut write)?;
+    }
+
+    csv_writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn join(options: &CsvOptions, join_type: JoinType) -> String {
+        join_within(options, join_type, None, false)
+    }
+
+    fn join_within(options: &CsvOptions, join_type: JoinType, max_memory: Option<u64>, presorted: bool) -> String {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_join_left.csv");
+        let right_file = &dir.path("test_input_join_right.csv");
+        let output_file = &dir.path("test_output_join.csv");
+        fs::write(input_file, "id,name\n1,Jane\n2,Bob\n3,Ann\n").expect("Unable to write input file");
+        fs::write(right_file, "city,id\nLeeds,1\nYork,1\nBath,4\n").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            max_memory,
+            ..options.clone()
+        };
+        let join_options = CsvJoinOptions { right_file: right_file.to_string(), columns: vec!["id".to_string()], join_type, presorted };
+
+        process_csv(&options, &join_options).expect("process_csv failed");
+        fs::read_to_string(output_file).expect("Unable to read output file")
+    }
+
+    #[test]
+    fn test_process_csv() {
+        let options = CsvOptions::default();
+        assert_eq!(join(&options, JoinType::Inner), "id,name,city\n1,Jane,Leeds\n1,Jane,York\n");
+        assert_eq!(join(&options, JoinType::Left), "id,name,city\n1,Jane,Leeds\n1,Jane,York\n2,Bob,\n3,Ann,\n");
+        assert_eq!(join(&options, JoinType::Right), "id,name,city\n1,Jane,Leeds\n1,Jane,York\n4,,Bath\n");
+        assert_eq!(join(&options, JoinType::Outer), "id,name,city\n1,Jane,Leeds\n1,Jane,York\n2,Bob,\n3,Ann,\n4,,Bath\n");
+        assert_eq!(join(&options, JoinType::Anti), "id,name\n2,Bob\n3,Ann\n");
+        assert_eq!(join(&options, JoinType::Semi), "id,name\n1,Jane\n");
+
+        // --drop-if-empty names and filters the left file, not the right one
+        let dropping = CsvOptions { drop_if_empty: Some(vec!["name".to_string()]), ..Default::default() };
+        assert_eq!(join(&dropping, JoinType::Inner), "id,name,city\n1,Jane,Leeds\n1,Jane,York\n");
+    }
+
+    #[test]
+    fn test_process_csv_column_order() {
+        let mut options = CsvOptions { sort_columns: true, ..Default::default() };
+        assert_eq!(join(&options, JoinType::Inner), "city,id,name\nLeeds,1,Jane\nYork,1,Jane\n");
+        options.sort_columns = false;
+        options.columns_from = Some(vec!["city".to_string(), "id".to_string()]);
+        assert_eq!(join(&options, JoinType::Inner), "city,id,name\nLeeds,1,Jane\nYork,1,Jane\n");
+    }
+
+    #[test]
+    fn test_process_csv_partitioned() {
+        let options = CsvOptions::default();
+        let sorted = |output: String| {
+            let mut lines = output.lines().map(String::from).collect::<Vec<_>>();
+            lines[1..].sort();
+            lines
+        };
+        // Room for two right records, so the third spills both files to partitions
+        for join_type in [JoinType::Inner, JoinType::Outer, JoinType::Anti, JoinType::Semi] {
+            assert_eq!(sorted(join_within(&options, join_type, Some(2 * RECORD_OVERHEAD + 20), false)), sorted(join(&options, join_type)));
+        }
+
+        // One key's records never fit, however often they are split
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_join.csv");
+        fs::write(input_file, "id\n1\n1\n").expect("Unable to write input file");
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(dir.path("test_output_join.csv")),
+            max_memory: Some(1),
+            ..Default::default()
+        };
+        let join_options = CsvJoinOptions { right_file: input_file.to_string(), columns: vec!["id".to_string()], join_type: JoinType::Inner, presorted: false };
+        assert_eq!(process_csv(&options, &join_options).map_err(|e| e.to_string()),
+                   Err("Too many right records share a key to join them within --max-memory".to_string()));
+    }
+
+    #[test]
+    fn test_process_csv_presorted() {
+        let options = CsvOptions::default();
+        for join_type in [JoinType::Inner, JoinType::Left, JoinType::Right, JoinType::Outer, JoinType::Anti, JoinType::Semi] {
+            assert_eq!(join_within(&options, join_type, None, true), join(&options, join_type));
+        }
+
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_join.csv");
+        let right_file = &dir.path("test_input_join_right.csv");
+        fs::write(input_file, "id\n1\n3\n2\n").expect("Unable to write input file");
+        fs::write(right_file, "id\n1\n2\n").expect("Unable to write input file");
+        let options = CsvOptions { input_file: Some(input_file.to_string()), output_file: Some(dir.path("test_output_join.csv")), ..Default::default() };
+        let mut join_options = CsvJoinOptions { right_file: right_file.to_string(), columns: vec!["id".to_string()], join_type: JoinType::Inner, presorted: true };
+        assert_eq!(process_csv(&options, &join_options).map_err(|e| e.to_string()),
+                   Err("--presorted: left record 3 is out of order on the key".to_string()));
+        let options = CsvOptions { input_file: Some(right_file.to_string()), ..options };
+        join_options.right_file = input_file.to_string();
+        assert_eq!(process_csv(&options, &join_options).map_err(|e| e.to_string()),
+                   Err("--presorted: right record 3 is out of order on the key".to_string()));
+    }
+}
@@ -0,0 +1,490 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+pub mod stats;
+pub mod numbers;
+
+use crate::args::global_args;
+use crate::stats::CsvColumnStat;
+use clap::Arg;
+use options::CsvOptions;
+use serde::ser::{SerializeMap, SerializeSeq};
+use serde::Serialize;
+use serde_json::Value;
+use std::error::Error;
+use std::io::{BufRead, Write};
+
+struct CsvJsonOptions {
+    indent: Option<usize>,
+    /// Whether to read numbers as JSON numbers rather than strings
+    infer_types: bool,
+    /// Whether `a.b` and `a.0` headers make nested objects and arrays
+    nested: bool,
+    /// Write a GeoJSON FeatureCollection with this geometry
+    geometry: Option<Geometry>,
+}
+
+/// Where a GeoJSON feature's geometry comes from.
+#[derive(Clone, Debug, PartialEq)]
+enum Geometry {
+    /// Latitude and longitude columns, making a point
+    LatLon(String, String),
+    /// A column of WKT, e.g. POINT (30 10)
+    Wkt(String),
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>());
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> (CsvOptions, CsvJsonOptions) {
+    let executable_name = args[0].clone();
+
+    let command = global_args()
+        .display_name(executable_name)
+        .about("Converts CSV files to a JSON array with an object per record.")
+        .arg(Arg::new("indent")
+            .long("indent")
+            .value_parser(clap::value_parser!(usize))
+            .help("Indent the JSON by this many spaces. By default it's all on one line"))
+        .arg(Arg::new("no_inference")
+            .short('I')
+            .long("no-inference")
+            .help("Write every value as a string, rather than columns holding only numbers as numbers")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("nested")
+            .long("nested")
+            .help("Read dotted headers as paths into nested objects, with numbers for array positions, \
+                   e.g. address.city and items.0.sku")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("lat")
+            .long("lat")
+            .requires("lon")
+            .conflicts_with("geometry")
+            .help("Latitude column. With --lon, writes a GeoJSON FeatureCollection of points, with the other columns as properties"))
+        .arg(Arg::new("lon")
+            .long("lon")
+            .requires("lat")
+            .help("Longitude column, see --lat"))
+        .arg(Arg::new("geometry")
+            .long("geometry")
+            .help("Column of WKT geometries: POINT, LINESTRING, POLYGON or their MULTI forms. Writes a GeoJSON \
+                   FeatureCollection with the other columns as properties"));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let geometry = match (matches.remove_one::<String>("lat"), matches.remove_one::<String>("lon"), matches.remove_one::<String>("geometry")) {
+        (Some(lat), Some(lon), _) => Some(Geometry::LatLon(lat, lon)),
+        (_, _, Some(column)) => Some(Geometry::Wkt(column)),
+        _ => None,
+    };
+
+    let action = CsvJsonOptions {
+        indent: matches.remove_one("indent"),
+        infer_types: !matches.remove_one::<bool>("no_inference").unwrap_or(false),
+        nested: matches.remove_one("nested").unwrap_or(false),
+        geometry,
+    };
+
+    (args::build_options(matches), action)
+}
+
+/// A value as JSON: empty is null, and a number in a numeric column is a number.
+fn json_value(value: &str, numeric: bool) -> Value {
+    if value.is_empty() {
+        Value::Null
+    } else if let (true, Ok(n)) = (numeric, value.parse::<i64>()) {
+        Value::from(n)
+    } else if let Some(n) = numeric.then(|| value.parse::<f64>().ok()).flatten().and_then(serde_json::Number::from_f64) {
+        Value::Number(n)
+    } else {
+        Value::String(value.to_string())
+    }
+}
+
+/// A parenthesized WKT list: coordinates, or lists of them.
+enum WktList {
+    Coordinate(Vec<f64>),
+    List(Vec<WktList>),
+}
+
+impl WktList {
+    /// Reads `(...)` from the start of `s`, returning the rest.
+    fn parse(s: &str) -> Option<(WktList, &str)> {
+        let mut rest = s.trim_start().strip_prefix('(')?;
+        let mut items = vec![];
+        loop {
+            rest = rest.trim_start();
+            let (item, after) = if rest.starts_with('(') {
+                WktList::parse(rest)?
+            } else {
+                let end = rest.find([',', ')'])?;
+                let numbers = rest[..end].split_whitespace().map(|n| n.parse::<f64>().ok()).collect::<Option<Vec<_>>>()?;
+                if !(2..=4).contains(&numbers.len()) {
+                    return None;
+                }
+                (WktList::Coordinate(numbers), &rest[end..])
+            };
+            items.push(item);
+            rest = after.trim_start();
+            match rest.chars().next()? {
+                ',' => rest = &rest[1..],
+                ')' => return Some((WktList::List(items), &rest[1..])),
+                _ => return None,
+            }
+        }
+    }
+
+    /// The GeoJSON coordinates of a list nested `depth` deep, where 0 is a single position.
+    fn coordinates(&self, depth: usize) -> Option<Value> {
+        match (self, depth) {
+            (WktList::Coordinate(numbers), 0) => Some(Value::from(numbers.clone())),
+            // POINT (30 10) is a list holding one position
+            (WktList::List(items), 0) if items.len() == 1 => items[0].coordinates(0),
+            (WktList::List(items), _) if depth > 0 => items.iter()
+                .map(|item| item.coordinates(depth - 1))
+                .collect::<Option<Vec<_>>>()
+                .map(Value::Array),
+            _ => None,
+        }
+    }
+}
+
+/// Converts WKT such as `POLYGON ((30 10, 40 40, 20 40, 30 10))` to a GeoJSON geometry. Z and M
+/// values are kept as extra coordinates, and EMPTY geometries are null.
+fn wkt_geometry(wkt: &str) -> Option<Node> {
+    let wkt = wkt.trim();
+    let split = wkt.find(|c: char| c == '(' || c.is_whitespace()).unwrap_or(wkt.len());
+    let kind = wkt[..split].to_ascii_uppercase();
+    let body = wkt[split..].trim_start();
+    let body = ["ZM", "Z", "M"].iter()
+        .filter(|d| body.to_ascii_uppercase().starts_with(*d))
+        .map(|d| &body[d.len()..])
+        .find(|b| b.trim_start().starts_with('(') || b.trim().eq_ignore_ascii_case("EMPTY"))
+        .unwrap_or(body);
+    if body.trim().eq_ignore_ascii_case("EMPTY") {
+        return Some(Node::Value(Value::Null));
+    }
+    let (geometry_type, depth) = match kind.as_str() {
+        "POINT" => ("Point", 0),
+        "LINESTRING" => ("LineString", 1),
+        "POLYGON" => ("Polygon", 2),
+        "MULTIPOINT" => ("MultiPoint", 1),
+        "MULTILINESTRING" => ("MultiLineString", 2),
+        "MULTIPOLYGON" => ("MultiPolygon", 3),
+        _ => return None,
+    };
+    let (list, rest) = WktList::parse(body)?;
+    if !rest.trim().is_empty() {
+        return None;
+    }
+    // MULTIPOINT may put each point in parentheses or not
+    let coordinates = match (&list, geometry_type) {
+        (WktList::List(points), "MultiPoint") => Value::Array(points.iter().map(|p| p.coordinates(0)).collect::<Option<Vec<_>>>()?),
+        _ => list.coordinates(depth)?,
+    };
+    Some(Node::Object(vec![
+        ("type".to_string(), Node::Value(Value::from(geometry_type))),
+        ("coordinates".to_string(), Node::Value(coordinates)),
+    ]))
+}
+
+/// A record as JSON, keeping its columns in order, which `serde_json::Map` would sort.
+#[derive(Debug, PartialEq)]
+enum Node {
+    Value(Value),
+    Object(Vec<(String, Node)>),
+    Array(Vec<Node>),
+}
+
+impl Serialize for Node {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Node::Value(value) => value.serialize(serializer),
+            Node::Object(entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, node) in entries {
+                    map.serialize_entry(key, node)?;
+                }
+                map.end()
+            }
+            Node::Array(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for node in items {
+                    seq.serialize_element(node)?;
+                }
+                seq.end()
+            }
+        }
+    }
+}
+
+impl Node {
+    /// Sets the value at a dotted path such as `items.0.sku`, creating the objects and arrays on
+    /// the way. Array positions past the end are filled with nulls.
+    fn insert_path(&mut self, header: &str, value: Value) -> Result<(), String> {
+        let conflict = || format!("Column {} conflicts with another column's path", header);
+        let mut current = self;
+        let parts = header.split('.').collect::<Vec<_>>();
+        for (n, part) in parts.iter().enumerate() {
+            let next = match parts.get(n + 1) {
+                None => Node::Value(value.clone()),
+                Some(p) if p.parse::<usize>().is_ok() => Node::Array(vec![]),
+                Some(_) => Node::Object(vec![]),
+            };
+            let slot = match current {
+                Node::Object(entries) => match entries.iter().position(|(k, _)| k == part) {
+                    Some(i) => &mut entries[i].1,
+                    None => {
+                        entries.push((part.to_string(), next));
+                        if n + 1 == parts.len() {
+                            return Ok(());
+                        }
+                        &mut entries.last_mut().unwrap().1
+                    }
+                },
+                Node::Array(items) => {
+                    let i = part.parse::<usize>().map_err(|_| conflict())?;
+                    if items.len() <= i {
+                        items.resize_with(i + 1, || Node::Value(Value::Null));
+                    }
+                    if items[i] == Node::Value(Value::Null) {
+                        items[i] = next;
+                        if n + 1 == parts.len() {
+                            return Ok(());
+                        }
+                    }
+                    &mut items[i]
+                }
+                Node::Value(_) => return Err(conflict()),
+            };
+            if n + 1 == parts.len() {
+                return Err(conflict());
+            }
+            current = slot;
+        }
+        Ok(())
+    }
+}
+
+fn process_csv(options: &CsvOptions, json_options: &CsvJsonOptions) -> Result<(), Box<dyn Error>> {
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    let mut reader = csvutil::csv_reader(options, input);
+
+    // Get the column headers
+    let first_row = csvutil::column_names(options, reader.headers()?.clone());
+    let all_indices = (0..first_row.len()).collect::<Vec<_>>();
+    let headers = csvutil::enumerate_output_headers(options.config().has_headers, first_row, &all_indices);
+
+    // Whether a column is numeric depends on all of its values, so the records are held
+    let mut statistics = headers.iter().enumerate()
+        // The values are written with str::parse, so "1,234" has to stay a string
+        .map(|(i, name)| CsvColumnStat::new(i, name.clone()).strict_numeric(true))
+        .collect::<Vec<_>>();
+    let mut records = vec![];
+    for result in reader.records() {
+        let record = result?;
+        if json_options.infer_types {
+            statistics.iter_mut().for_each(|s| s.add(record.get(s.idx)));
+        }
+        records.push(record);
+    }
+    let numeric = statistics.iter().map(|s| json_options.infer_types && s.is_numeric()).collect::<Vec<_>>();
+
+    // The columns the geometry is read from, which aren't properties
+    let column = |name: &String| -> Result<usize, Box<dyn Error>> {
+        csvutil::select_column_index(&csv::StringRecord::from(headers.clone()), name)
+    };
+    let geometry_columns = match &json_options.geometry {
+        Some(Geometry::LatLon(lat, lon)) => vec![column(lat)?, column(lon)?],
+        Some(Geometry::Wkt(wkt)) => vec![column(wkt)?],
+        None => vec![],
+    };
+
+    let mut objects = Vec::with_capacity(records.len());
+    for (row, record) in records.into_iter().enumerate() {
+        let mut object = Node::Object(vec![]);
+        for (i, header) in headers.iter().enumerate() {
+            if geometry_columns.contains(&i) {
+                continue;
+            }
+            let value = json_value(record.get(i).unwrap_or_default(), numeric[i]);
+            if json_options.nested {
+                object.insert_path(header, value)?;
+            } else if let Node::Object(entries) = &mut object {
+                entries.push((header.clone(), Node::Value(value)));
+            }
+        }
+        if json_options.geometry.is_none() {
+            objects.push(object);
+            continue;
+        }
+
+        let values = geometry_columns.iter().map(|&i| record.get(i).unwrap_or_default().trim()).collect::<Vec<_>>();
+        let invalid = |what: &str| format!("Invalid {} in row {}: {}", what, row + 1, values.join(","));
+        let geometry = match values.as_slice() {
+            ["", ""] | [""] => Node::Value(Value::Null),
+            [lat, lon] => match (lat.parse::<f64>(), lon.parse::<f64>()) {
+                (Ok(lat), Ok(lon)) => Node::Object(vec![
+                    ("type".to_string(), Node::Value(Value::from("Point"))),
+                    ("coordinates".to_string(), Node::Value(Value::from(vec![lon, lat]))),
+                ]),
+                _ => return Err(Box::from(invalid("latitude or longitude"))),
+            },
+            [wkt] => wkt_geometry(wkt).ok_or_else(|| invalid("WKT geometry"))?,
+            _ => Node::Value(Value::Null),
+        };
+        objects.push(Node::Object(vec![
+            ("type".to_string(), Node::Value(Value::from("Feature"))),
+            ("properties".to_string(), object),
+            ("geometry".to_string(), geometry),
+        ]));
+    }
+
+    let output = match json_options.geometry {
+        Some(_) => Node::Object(vec![
+            ("type".to_string(), Node::Value(Value::from("FeatureCollection"))),
+            ("features".to_string(), Node::Array(objects)),
+        ]),
+        None => Node::Array(objects),
+    };
+
+    let mut json = vec![];
+    match json_options.indent {
+        Some(n) => {
+            let indent = " ".repeat(n);
+            let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+            output.serialize(&mut serde_json::Serializer::with_formatter(&mut json, formatter))?;
+        }
+        None => serde_json::to_writer(&mut json, &output)?,
+    }
+    json.push(b'\n');
+
+    let mut out = options.get_output_file()?;
+    out.write_all(&json)?;
+    out.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::fs;
+
+    #[test]
+    fn test_insert_path() {
+        let mut object = Node::Object(vec![]);
+        object.insert_path("id", json!(1)).unwrap();
+        object.insert_path("address.city", json!("Leeds")).unwrap();
+        object.insert_path("items.1.sku", json!("B")).unwrap();
+        object.insert_path("items.0.sku", json!("A")).unwrap();
+        object.insert_path("items.0.qty", json!(2)).unwrap();
+        assert_eq!(serde_json::to_string(&object).unwrap(),
+                   r#"{"id":1,"address":{"city":"Leeds"},"items":[{"sku":"A","qty":2},{"sku":"B"}]}"#);
+
+        assert!(object.insert_path("id.x", json!(1)).is_err());
+        assert!(object.insert_path("address", json!(1)).is_err());
+        assert!(object.insert_path("items.x", json!(1)).is_err());
+        assert!(object.insert_path("items.1.sku", json!(1)).is_err());
+    }
+
+    #[test]
+    fn test_json_value() {
+        assert_eq!(json_value("3", true), json!(3));
+        assert_eq!(json_value("2.5", true), json!(2.5));
+        assert_eq!(json_value("3", false), json!("3"));
+        assert_eq!(json_value("", true), Value::Null);
+    }
+
+    #[test]
+    fn test_process_csv_nested() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_json.csv");
+        let output_file = &dir.path("test_output_json.json");
+        fs::write(input_file, "id,name,address.city,tags.0,tags.1\n1,Ann,Leeds,a,b\n2,Bob,,c,\n").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+        let mut json_options = CsvJsonOptions { indent: None, infer_types: true, nested: true, geometry: None };
+        process_csv(&options, &json_options).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, "[{\"id\":1,\"name\":\"Ann\",\"address\":{\"city\":\"Leeds\"},\"tags\":[\"a\",\"b\"]},\
+                                    {\"id\":2,\"name\":\"Bob\",\"address\":{\"city\":null},\"tags\":[\"c\",null]}]\n");
+
+        json_options.nested = false;
+        json_options.infer_types = false;
+        json_options.indent = Some(1);
+        process_csv(&options, &json_options).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert!(actual_output.starts_with("[\n {\n  \"id\": \"1\",\n  \"name\": \"Ann\",\n  \"address.city\": \"Leeds\","));
+    }
+
+    #[test]
+    fn test_wkt_geometry() {
+        let wkt_geometry = |wkt: &str| wkt_geometry(wkt).map(|g| serde_json::to_value(g).unwrap());
+        assert_eq!(wkt_geometry("POINT (30 10)"), Some(json!({"type": "Point", "coordinates": [30.0, 10.0]})));
+        assert_eq!(wkt_geometry("point z(1 2 3)"), Some(json!({"type": "Point", "coordinates": [1.0, 2.0, 3.0]})));
+        assert_eq!(wkt_geometry("LINESTRING (30 10, 10 30)"),
+                   Some(json!({"type": "LineString", "coordinates": [[30.0, 10.0], [10.0, 30.0]]})));
+        assert_eq!(wkt_geometry("POLYGON ((30 10, 40 40, 30 10), (1 1, 2 2, 1 1))"),
+                   Some(json!({"type": "Polygon", "coordinates": [[[30.0, 10.0], [40.0, 40.0], [30.0, 10.0]], [[1.0, 1.0], [2.0, 2.0], [1.0, 1.0]]]})));
+        assert_eq!(wkt_geometry("MULTIPOINT ((10 40), (40 30))"), wkt_geometry("MULTIPOINT (10 40, 40 30)"));
+        assert_eq!(wkt_geometry("MULTIPOLYGON (((1 1, 2 2, 1 1)))"),
+                   Some(json!({"type": "MultiPolygon", "coordinates": [[[[1.0, 1.0], [2.0, 2.0], [1.0, 1.0]]]]})));
+        assert_eq!(wkt_geometry("POINT EMPTY"), Some(Value::Null));
+        assert_eq!(wkt_geometry("POINT (30)"), None);
+        assert_eq!(wkt_geometry("POLYGON (30 10, 40 40)"), None);
+        assert_eq!(wkt_geometry("CIRCLE (1 1)"), None);
+    }
+
+    #[test]
+    fn test_process_csv_geojson() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_geojson.csv");
+        let output_file = &dir.path("test_output_geojson.json");
+        fs::write(input_file, "name,lat,lon,wkt\nA,51.5,-0.1,POINT (-0.1 51.5)\nB,,,\n").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+        let mut json_options = CsvJsonOptions {
+            indent: None,
+            infer_types: true,
+            nested: false,
+            geometry: Some(Geometry::LatLon("lat".to_string(), "lon".to_string())),
+        };
+        process_csv(&options, &json_options).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, "{\"type\":\"FeatureCollection\",\"features\":[\
+                                   {\"type\":\"Feature\",\"properties\":{\"name\":\"A\",\"wkt\":\"POINT (-0.1 51.5)\"},\"geometry\":{\"type\":\"Point\",\"coordinates\":[-0.1,51.5]}},\
+                                   {\"type\":\"Feature\",\"properties\":{\"name\":\"B\",\"wkt\":null},\"geometry\":null}]}\n");
+
+        json_options.geometry = Some(Geometry::Wkt("4".to_string()));
+        process_csv(&options, &json_options).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert!(actual_output.contains("\"properties\":{\"name\":\"A\",\"lat\":51.5,\"lon\":-0.1},\"geometry\":{\"type\":\"Point\",\"coordinates\":[-0.1,51.5]}"));
+
+        fs::write(input_file, "name,lat,lon\nA,north,1\n").expect("Unable to write input file");
+        json_options.geometry = Some(Geometry::LatLon("lat".to_string(), "lon".to_string()));
+        assert_eq!(process_csv(&options, &json_options).expect_err("").to_string(), "Invalid latitude or longitude in row 1: north,1");
+    }
+}
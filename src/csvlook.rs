@@ -0,0 +1,356 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+
+use crate::args::global_args;
+use clap::Arg;
+use csv::StringRecord;
+use options::CsvOptions;
+use std::error::Error;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+struct CsvLookOptions {
+    /// Records read ahead to size the columns
+    sample: usize,
+    /// Size the columns from every record, holding the file in memory
+    exact: bool,
+    max_column_width: Option<usize>,
+    /// Page the table through `$PAGER` when printing to a terminal
+    pager: bool,
+}
+
+/// The narrowest a column is drawn, so the separator row has room for `---`.
+const MIN_WIDTH: usize = 3;
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>());
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> (CsvOptions, CsvLookOptions) {
+    let executable_name = args[0].clone();
+
+    let command = args::color_args(global_args())
+        .display_name(executable_name)
+        .about("Renders a CSV file as a Markdown-style table in the terminal.")
+        .after_help("Column widths come from the header and the first --sample records, and the table is printed as \
+                     the rest of the file is read, so even a huge file starts printing at once. Later values wider \
+                     than their column are cut short with …; --exact reads the whole file first so none are. Numeric \
+                     columns are right-aligned, judged from the same records. Widths are counted in terminal columns, \
+                     so wide East Asian characters and emoji take two. With --color the header row is bold, numeric \
+                     columns cyan and date columns magenta.\n\n\
+                     When printing to a terminal the table is paged through $PAGER, or less -FRSX when it isn't \
+                     set; an empty $PAGER or --no-pager prints it directly. less 600 and later keep the header row \
+                     in view while scrolling.")
+        .arg(Arg::new("sample")
+            .long("sample")
+            .value_parser(clap::value_parser!(usize))
+            .default_value("1000")
+            .help("Number of records read before printing to size the columns"))
+        .arg(Arg::new("exact")
+            .long("exact")
+            .action(clap::ArgAction::SetTrue)
+            .conflicts_with("sample")
+            .help("Size the columns from every record, holding the file in memory, so no value is cut short"))
+        .arg(Arg::new("max_column_width")
+            .long("max-column-width")
+            .value_parser(clap::value_parser!(usize))
+            .help("Cut values wider than this many terminal columns short"))
+        .arg(Arg::new("no_pager")
+            .long("no-pager")
+            .action(clap::ArgAction::SetTrue)
+            .help("Print the table directly rather than through $PAGER when printing to a terminal"));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let action = CsvLookOptions {
+        sample: matches.remove_one("sample").unwrap_or(1000),
+        exact: matches.remove_one("exact").unwrap_or(false),
+        max_column_width: matches.remove_one("max_column_width"),
+        pager: !matches.remove_one("no_pager").unwrap_or(false),
+    };
+
+    (args::build_options(matches), action)
+}
+
+/// Streams into `$PAGER`, or less when it isn't set, which pages what it's given once the writer
+/// is dropped.
+struct PagerWriter {
+    child: Child,
+    stdin: Option<BufWriter<ChildStdin>>,
+}
+
+impl PagerWriter {
+    /// Starts the pager, or returns None where there is none to use. `header_lines` are kept in
+    /// view by a less that supports `--header`, unless `$LESS` says otherwise.
+    fn new(header_lines: usize) -> Option<Self> {
+        let pager = std::env::var("PAGER").unwrap_or_else(|_| "less".to_string());
+        if pager.trim().is_empty() {
+            return None;
+        }
+        let mut command = Command::new("sh");
+        command.args(["-c", &pager]).stdin(Stdio::piped());
+        if std::env::var_os("LESS").is_none() {
+            let header = if less_supports_header() { format!(" --header={}", header_lines) } else { String::new() };
+            // Quit if the table fits on one screen, pass colors through and scroll wide rows sideways
+            command.env("LESS", format!("-FRSX{}", header));
+        }
+        match command.spawn() {
+            Ok(mut child) => {
+                let stdin = child.stdin.take().map(BufWriter::new);
+                Some(PagerWriter { child, stdin })
+            }
+            Err(e) => {
+                log::warning(&format!("unable to run the pager '{}': {}", pager, e));
+                None
+            }
+        }
+    }
+}
+
+/// Whether the installed less is 600 or later, which added `--header`.
+fn less_supports_header() -> bool {
+    let Ok(output) = Command::new("less").arg("--version").stderr(Stdio::null()).output() else {
+        return false;
+    };
+    parse_less_version(&String::from_utf8_lossy(&output.stdout)).is_some_and(|v| v >= 600)
+}
+
+/// The version in `less --version` output, e.g. 643 from `less 643 (PCRE2 regular expressions)`.
+fn parse_less_version(output: &str) -> Option<u32> {
+    output.strip_prefix("less ")?.split(|c: char| !c.is_ascii_digit()).next()?.parse().ok()
+}
+
+impl Write for PagerWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdin.as_mut().ok_or_else(|| io::Error::from(io::ErrorKind::BrokenPipe))?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdin.as_mut().map_or(Ok(()), |s| s.flush())
+    }
+}
+
+impl Drop for PagerWriter {
+    fn drop(&mut self) {
+        // Closing its input lets the pager reach the end; the table is printed once it has quit
+        drop(self.stdin.take());
+        if let Err(e) = self.child.wait() {
+            log::warning(&format!("unable to wait for the pager: {}", e));
+        }
+    }
+}
+
+/// What a column holds, for aligning and coloring it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Text,
+    Number,
+    Date,
+}
+
+impl Kind {
+    fn of<'a>(values: impl Iterator<Item = &'a str>) -> Kind {
+        let values = values.filter(|v| !v.is_empty()).collect::<Vec<_>>();
+        if values.is_empty() {
+            Kind::Text
+        } else if values.iter().all(|v| numbers::parse_number(v, false).is_some()) {
+            Kind::Number
+        } else if values.iter().all(|v| dates::parse_timestamp(v).is_some()) {
+            Kind::Date
+        } else {
+            Kind::Text
+        }
+    }
+
+    /// The ANSI color for the column's values with --color
+    fn color(self) -> Option<&'static str> {
+        match self {
+            Kind::Text => None,
+            Kind::Number => Some(CYAN),
+            Kind::Date => Some(MAGENTA),
+        }
+    }
+}
+
+const BOLD: &str = "1";
+const CYAN: &str = "36";
+const MAGENTA: &str = "35";
+
+/// A value on one line, cut short with `…` to fit `width`, and the columns it takes up.
+fn fit(value: &str, width: usize) -> (String, usize) {
+    let value = value.replace("\r\n", " ").replace(['\n', '\r'], " ");
+    let value_width = csvutil::display_width(&value);
+    if value_width <= width {
+        return (value, value_width);
+    }
+    let (fitted, fitted_width) = csvutil::truncate_to_width(&value, width.saturating_sub(1));
+    (format!("{}…", fitted), fitted_width + 1)
+}
+
+/// Writes a row, right-aligning the values where `right` is set and coloring them with an ANSI
+/// SGR code where `colors` has one. Only the values are colored, so the padding stays aligned.
+fn write_row<'a>(out: &mut dyn Write, mut values: impl Iterator<Item = &'a str>, widths: &[usize], right: &[bool], colors: &[Option<&str>]) -> io::Result<()> {
+    let mut line = String::from("|");
+    for ((&width, &right), color) in widths.iter().zip(right).zip(colors) {
+        let (mut value, value_width) = fit(values.next().unwrap_or_default(), width);
+        let padding = " ".repeat(width - value_width);
+        if let Some(code) = color {
+            value = writer::colored(&value, code);
+        }
+        line += &if right { format!(" {}{} |", padding, value) } else { format!(" {}{} |", value, padding) };
+    }
+    writeln!(out, "{}", line)
+}
+
+fn process_csv(options: &CsvOptions, look_options: &CsvLookOptions) -> Result<(), Box<dyn Error>> {
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    let mut reader = csvutil::csv_reader(options, input);
+
+    // Get the column headers
+    let first_row = csvutil::column_names(options, reader.headers()?.clone());
+    let all_indices = (0..first_row.len()).collect::<Vec<_>>();
+    let names = csvutil::enumerate_output_headers(options.config().has_headers, first_row, &all_indices);
+
+    // The columns are sized from a sample, and the rest of the records streamed
+    let mut records = reader.records();
+    let sample_size = if look_options.exact { usize::MAX } else { look_options.sample };
+    let sample = records.by_ref().take(sample_size).collect::<Result<Vec<StringRecord>, _>>()?;
+
+    let n_columns = sample.iter().map(StringRecord::len).chain([names.len()]).max().unwrap_or(0);
+    let width_of = |value: &str| fit(value, usize::MAX).1.min(look_options.max_column_width.unwrap_or(usize::MAX));
+    let widths = (0..n_columns).map(|i| {
+        let header = names.get(i).map_or(0, |name| width_of(name));
+        sample.iter().filter_map(|r| r.get(i)).map(width_of).fold(header, usize::max).max(MIN_WIDTH)
+    }).collect::<Vec<_>>();
+    let kinds = (0..n_columns).map(|i| Kind::of(sample.iter().filter_map(|r| r.get(i)))).collect::<Vec<_>>();
+    let numeric = kinds.iter().map(|&k| k == Kind::Number).collect::<Vec<_>>();
+
+    let color = options.use_color();
+    let header_colors = vec![color.then_some(BOLD); n_columns];
+    let colors = kinds.iter().map(|k| k.color().filter(|_| color)).collect::<Vec<_>>();
+
+    // The header row and the separator below it
+    let pager = (look_options.pager && options.writes_to_terminal()).then(|| PagerWriter::new(2)).flatten();
+    let mut out: Box<dyn Write> = match pager {
+        Some(pager) => Box::new(pager),
+        None => options.get_output_file()?,
+    };
+    write_row(out.as_mut(), names.iter().map(String::as_str), &widths, &vec![false; n_columns], &header_colors)?;
+    let separator = widths.iter().zip(&numeric)
+        .map(|(&width, &right)| if right { format!(" {}: |", "-".repeat(width - 1)) } else { format!(" {} |", "-".repeat(width)) })
+        .collect::<String>();
+    writeln!(out, "|{}", separator)?;
+    for record in &sample {
+        write_row(out.as_mut(), record.iter(), &widths, &numeric, &colors)?;
+    }
+    for result in records {
+        write_row(out.as_mut(), result?.iter(), &widths, &numeric, &colors)?;
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_fit() {
+        assert_eq!(fit("abc", 5), ("abc".to_string(), 3));
+        assert_eq!(fit("abcdef", 4), ("abc…".to_string(), 4));
+        assert_eq!(fit("first\r\nsecond", 20), ("first second".to_string(), 12));
+        assert_eq!(fit("東京都庁", 6), ("東京…".to_string(), 5));
+    }
+
+    #[test]
+    fn test_kind_of() {
+        assert_eq!(Kind::of(["1,200", "-3.5", ""].into_iter()), Kind::Number);
+        assert_eq!(Kind::of(["1", "NaN"].into_iter()), Kind::Text);
+        assert_eq!(Kind::of(["2024-01-02", ""].into_iter()), Kind::Date);
+    }
+
+    #[test]
+    fn test_parse_less_version() {
+        assert_eq!(parse_less_version("less 643 (PCRE2 regular expressions)\nCopyright (C) 1984-2023  Mark Nudelman\n"), Some(643));
+        assert_eq!(parse_less_version("less 590 (GNU regular expressions)\n"), Some(590));
+        assert_eq!(parse_less_version("BusyBox v1.36.1\n"), None);
+    }
+
+    #[test]
+    fn test_process_csv() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_look.csv");
+        let output_file = &dir.path("test_output_look.txt");
+        fs::write(input_file, "id,name\n1,Ann\n22,Bob\n3,Christopher\n").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+
+        process_csv(&options, &CsvLookOptions { sample: 1000, exact: false, max_column_width: None, pager: true }).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, "| id  | name        |\n| --: | ----------- |\n|   1 | Ann         |\n|  22 | Bob         |\n|   3 | Christopher |\n");
+
+        // Sized from the first two records, so the third is cut short unless --exact
+        process_csv(&options, &CsvLookOptions { sample: 2, exact: false, max_column_width: None, pager: true }).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, "| id  | name |\n| --: | ---- |\n|   1 | Ann  |\n|  22 | Bob  |\n|   3 | Chr… |\n");
+
+        process_csv(&options, &CsvLookOptions { sample: 2, exact: true, max_column_width: None, pager: true }).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output.lines().last(), Some("|   3 | Christopher |"));
+
+        process_csv(&options, &CsvLookOptions { sample: 1000, exact: false, max_column_width: Some(5), pager: true }).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output.lines().last(), Some("|   3 | Chri… |"));
+    }
+
+    #[test]
+    fn test_process_csv_color() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_look_color.csv");
+        let output_file = &dir.path("test_output_look_color.txt");
+        fs::write(input_file, "id,day,name\n1,2024-01-02,Ann\n,,\n").expect("Unable to write input file");
+        let look_options = CsvLookOptions { sample: 1000, exact: false, max_column_width: None, pager: false };
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            color: options::ColorChoice::Always,
+            ..Default::default()
+        };
+        process_csv(&options, &look_options).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output.lines().collect::<Vec<_>>(), [
+            "| \x1b[1mid\x1b[0m  | \x1b[1mday\x1b[0m        | \x1b[1mname\x1b[0m |",
+            "| --: | ---------- | ---- |",
+            "|   \x1b[36m1\x1b[0m | \x1b[35m2024-01-02\x1b[0m | Ann  |",
+            "|     |            |      |",
+        ]);
+
+        // Auto leaves a file uncolored
+        let options = CsvOptions { color: options::ColorChoice::Auto, ..options };
+        process_csv(&options, &look_options).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert!(!actual_output.contains('\x1b'));
+    }
+}
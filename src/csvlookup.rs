@@ -0,0 +1,204 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+
+use crate::args::global_args;
+use clap::Arg;
+use options::CsvOptions;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::BufRead;
+
+struct CsvLookupOptions {
+    lookup_file: String,
+    /// Key column in the input, and in the lookup file if it is named differently
+    on: (String, Option<String>),
+    add_columns: Option<Vec<String>>,
+    strict: bool,
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>());
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> (CsvOptions, CsvLookupOptions) {
+    let executable_name = args[0].clone();
+
+    let command = args::memory_args(args::source_args(args::in_place_args(args::contract_args(global_args()))))
+        .display_name(executable_name)
+        .about("Appends columns from a small reference CSV to each record, matched on a key column.")
+        .arg(Arg::new("lookup")
+            .short('l')
+            .long("lookup")
+            .required(true)
+            .help("Reference CSV file, loaded into memory"))
+        .arg(Arg::new("on")
+            .long("on")
+            .required(true)
+            .allow_negative_numbers(true)
+            .help("Key column, or input_column=lookup_column when the names differ"))
+        .arg(Arg::new("add")
+            .long("add")
+            .allow_negative_numbers(true)
+            .help("Lookup columns to append, using the same syntax as --columns. Defaults to every lookup column except the key")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("strict")
+            .long("strict")
+            .action(clap::ArgAction::SetTrue)
+            .help("Fail on input keys missing from the lookup file instead of leaving the new columns empty"));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let on: String = matches.remove_one("on").unwrap();
+    let action = CsvLookupOptions {
+        lookup_file: matches.remove_one("lookup").unwrap(),
+        on: match on.split_once('=') {
+            Some((input, lookup)) => (input.to_string(), Some(lookup.to_string())),
+            None => (on, None),
+        },
+        add_columns: matches.remove_many::<String>("add")
+            .map(|v| v.flat_map(|s| csvutil::parse_column_list(&s))
+                .collect::<Vec<_>>()),
+        strict: matches.remove_one("strict").unwrap_or(false),
+    };
+
+    (args::build_options(matches), action)
+}
+
+fn process_csv(options: &CsvOptions, lookup_options: &CsvLookupOptions) -> Result<(), Box<dyn Error>> {
+    let has_headers = options.config().has_headers;
+
+    // Load the lookup file, read with the same dialect as the input
+    let lookup_input = CsvOptions { input_file: Some(lookup_options.lookup_file.clone()), header_row: None, verify_sha256: None, assertions: vec![], metrics: None,
+                                   drop_if_empty: None, drop_empty_rows: false, ..options.clone() };
+    let mut lookup_reader = csvutil::csv_reader(options, lookup_input.get_input_file()?);
+    let lookup_headers = csvutil::column_names(options, lookup_reader.headers()?.clone());
+    let lookup_key_idx = csvutil::select_column_index(&lookup_headers,
+                                                      lookup_options.on.1.as_ref().unwrap_or(&lookup_options.on.0))?;
+    let add_indices: Vec<usize> = match &lookup_options.add_columns {
+        Some(_) => csvutil::select_column_indices(&lookup_headers, &lookup_options.add_columns)?,
+        None => (0..lookup_headers.len()).filter(|&i| i != lookup_key_idx).collect(),
+    };
+
+    let mut lookup: HashMap<String, Vec<String>> = HashMap::new();
+    let mut lookup_bytes = 0;
+    for result in lookup_reader.records() {
+        let record = result?;
+        let key = record.get(lookup_key_idx).unwrap_or_default().to_string();
+        let values: Vec<String> = add_indices.iter().map(|&i| record.get(i).unwrap_or_default().to_string()).collect();
+        lookup_bytes += [&key].into_iter().chain(&values).map(|s| s.len() as u64 + csvutil::STRING_OVERHEAD).sum::<u64>();
+        csvutil::check_memory(options, lookup_bytes, "The lookup file's keys and added columns",
+                              "Add fewer columns with --add, or make the smaller file the lookup file")?;
+        if lookup.insert(key.clone(), values).is_some() {
+            return Err(Box::from(format!("Duplicate key '{}' in lookup file {}", key, lookup_options.lookup_file)));
+        }
+    }
+
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    let mut reader = csvutil::csv_reader(options, input);
+
+    // Get the column headers
+    let first_row = csvutil::column_names(options, reader.headers()?.clone());
+
+    let key_idx = csvutil::select_column_index(&first_row, &lookup_options.on.0)?;
+
+    let output_has_headers = options.output_has_headers();
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+
+    let source_file = csvutil::source_file(options);
+
+    if output_has_headers {
+        let all_indices = (0..first_row.len()).collect::<Vec<_>>();
+        let mut out_headers = csvutil::enumerate_output_headers(has_headers, first_row, &all_indices);
+        out_headers.extend(csvutil::enumerate_output_headers(has_headers, lookup_headers, &add_indices));
+        if source_file.is_some() {
+            out_headers.extend(csvutil::SOURCE_COLUMNS.map(String::from));
+        }
+        csv_writer.write_record(out_headers)?;
+    }
+
+    let missing = vec![String::new(); add_indices.len()];
+    for (row, result) in reader.records().enumerate() {
+        let record = result?;
+        let key = record.get(key_idx).unwrap_or_default();
+        let values = match lookup.get(key) {
+            Some(values) => values,
+            None if lookup_options.strict => {
+                return Err(Box::from(format!("Row {}: key '{}' not found in lookup file {}", row + 1, key, lookup_options.lookup_file)));
+            }
+            None => &missing,
+        };
+        let source = csvutil::source_values(&source_file, row as u64 + 1);
+        csv_writer.write_record(record.iter().chain(values.iter().map(|v| v.as_str())).chain(source.iter().map(String::as_str)))?;
+    }
+
+    csv_writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_process_csv() {
+        let dir = csvutil::TestDir::new();
+        let lookup_file = &dir.path("test_lookup.csv");
+        let output_file = &dir.path("test_output_lookup.csv");
+        fs::write(lookup_file, "code,name,region\n1,one,x\n7,seven,y\n").expect("Unable to write lookup file");
+
+        let options = CsvOptions {
+            input_file: Some("test/test_input.csv".to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+
+        let mut action = CsvLookupOptions {
+            lookup_file: lookup_file.to_string(),
+            on: ("col1".to_string(), Some("code".to_string())),
+            add_columns: Some(vec!["name".to_string()]),
+            strict: false,
+        };
+
+        process_csv(&options, &action).expect("process_csv failed");
+
+        let expected_output = "col1,col2,col3,name\n1,2,3,one\n4,5,6,\n7,8,9,seven\n";
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, expected_output);
+
+        // The lookup file is still read from disk when the input is in memory
+        let output = options::SharedBuffer::new();
+        let in_memory = CsvOptions::new().with_input(&b"col1\n7\n"[..]).with_output(output.clone());
+        process_csv(&in_memory, &action).expect("process_csv failed");
+        assert_eq!(output.to_string_lossy(), "col1,name\n7,seven\n");
+
+        // --drop-if-empty and --drop-empty-rows name and filter the input, not the lookup file
+        fs::write(lookup_file, "code,name,region\n1,,x\n\n7,seven,y\n").expect("Unable to write lookup file");
+        let output = options::SharedBuffer::new();
+        let dropping = CsvOptions { drop_if_empty: Some(vec!["col2".to_string()]), drop_empty_rows: true, ..CsvOptions::new() }
+            .with_input(&b"col1,col2\n1,a\n7,\n"[..]).with_output(output.clone());
+        process_csv(&dropping, &action).expect("process_csv failed");
+        assert_eq!(output.to_string_lossy(), "col1,col2,name\n1,a,\n");
+
+        action.strict = true;
+        assert_eq!(process_csv(&options, &action).expect_err("").to_string(),
+                   format!("Row 2: key '4' not found in lookup file {}", lookup_file));
+    }
+}
@@ -0,0 +1,263 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+
+use crate::args::global_args;
+use clap::Arg;
+use csv::StringRecord;
+use hmac::{Hmac, Mac};
+use options::CsvOptions;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::io::BufRead;
+
+#[derive(Clone, Debug, PartialEq)]
+enum MaskStrategy {
+    /// SHA-256 of the value, hex encoded
+    Hash,
+    /// Replace all but the last N characters with `*`
+    Redact(usize),
+    /// Permute the values of the column between rows
+    Shuffle,
+    /// HMAC-SHA256 of the value with `--key`, hex encoded; stable across runs and files
+    Hmac,
+}
+
+struct CsvMaskOptions {
+    masks: Vec<(String, MaskStrategy)>,
+    key: Option<String>,
+    seed: Option<u64>,
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>())?;
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_strategy(s: &str) -> Result<MaskStrategy, String> {
+    match s.split_once(':') {
+        None if s == "hash" => Ok(MaskStrategy::Hash),
+        None if s == "redact" => Ok(MaskStrategy::Redact(4)),
+        None if s == "shuffle" => Ok(MaskStrategy::Shuffle),
+        None if s == "hmac" => Ok(MaskStrategy::Hmac),
+        Some(("redact", n)) => n.parse::<usize>()
+            .map(MaskStrategy::Redact)
+            .map_err(|_| format!("Invalid number of characters to keep: {}", s)),
+        _ => Err(format!("Unknown mask strategy '{}'. Expected hash, redact[:N], shuffle or hmac", s)),
+    }
+}
+
+fn parse_mask(s: &str) -> Result<(String, MaskStrategy), String> {
+    let (column, strategy) = s.rsplit_once('=')
+        .ok_or_else(|| format!("Invalid mask '{}'. Expected column=strategy", s))?;
+    Ok((column.trim().to_string(), parse_strategy(strategy.trim())?))
+}
+
+fn parse_args(args: Vec<String>) -> Result<(CsvOptions, CsvMaskOptions), String> {
+    let executable_name = args[0].clone();
+
+    let command = args::source_args(args::in_place_args(args::contract_args(global_args())))
+        .display_name(executable_name)
+        .about("Masks sensitive columns in CSV files.")
+        .arg(Arg::new("mask")
+            .long("mask")
+            .required(true)
+            .allow_negative_numbers(true)
+            .help("column=strategy, where column is a name or offset and strategy is one of hash, redact[:N] (keep the last N characters, default 4), shuffle or hmac. May be repeated")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("key")
+            .long("key")
+            .env("CSVMASK_KEY")
+            .hide_env_values(true)
+            .help("Secret key for the hmac strategy"))
+        .arg(Arg::new("seed")
+            .long("seed")
+            .value_parser(clap::value_parser!(u64))
+            .help("Random seed for the shuffle strategy, for reproducible output"));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let action = CsvMaskOptions {
+        masks: matches.remove_many::<String>("mask")
+            .map(|v| v.map(|s| parse_mask(&s)).collect::<Result<Vec<_>, _>>())
+            .unwrap_or(Ok(vec![]))?,
+        key: matches.remove_one("key"),
+        seed: matches.remove_one("seed"),
+    };
+
+    if action.key.is_none() && action.masks.iter().any(|(_, m)| *m == MaskStrategy::Hmac) {
+        return Err("The hmac strategy requires --key (or CSVMASK_KEY)".to_string());
+    }
+
+    Ok((args::build_options(matches), action))
+}
+
+fn mask_value(value: &str, strategy: &MaskStrategy, key: Option<&str>) -> String {
+    if value.is_empty() {
+        return String::new();
+    }
+    match strategy {
+        MaskStrategy::Hash => format!("{:x}", Sha256::digest(value.as_bytes())),
+        MaskStrategy::Redact(keep) => {
+            let n = value.chars().count();
+            value.chars().enumerate()
+                .map(|(i, c)| if i + keep < n { '*' } else { c })
+                .collect()
+        }
+        MaskStrategy::Hmac => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(key.unwrap_or_default().as_bytes())
+                .expect("HMAC accepts keys of any length");
+            mac.update(value.as_bytes());
+            format!("{:x}", mac.finalize().into_bytes())
+        }
+        MaskStrategy::Shuffle => value.to_string(),
+    }
+}
+
+fn process_csv(options: &CsvOptions, mask_options: &CsvMaskOptions) -> Result<(), Box<dyn Error>> {
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    let mut reader = csvutil::csv_reader(options, input);
+
+    // Get the column headers
+    let first_row = csvutil::column_names(options, reader.headers()?.clone());
+
+    // Resolve each mask to a column index
+    let mut masks: Vec<(usize, &MaskStrategy)> = vec![];
+    for (column, strategy) in &mask_options.masks {
+        for i in csvutil::select_column_indices(&first_row, &Some(vec![column.clone()]))? {
+            masks.push((i, strategy));
+        }
+    }
+
+    let output_has_headers = options.output_has_headers();
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+
+    let source_file = csvutil::source_file(options);
+
+    if output_has_headers {
+        let all_indices = (0..first_row.len()).collect::<Vec<_>>();
+        let mut out_headers = csvutil::enumerate_output_headers(options.config().has_headers, first_row, &all_indices);
+        if source_file.is_some() {
+            out_headers.extend(csvutil::SOURCE_COLUMNS.map(String::from));
+        }
+        csv_writer.write_record(out_headers)?;
+    }
+
+    let key = mask_options.key.as_deref();
+    // The source columns go after the masked ones, so shuffling doesn't reach them
+    let mask_record = |row: usize, record: &StringRecord| -> Vec<String> {
+        let mut values = record.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        for &(i, strategy) in &masks {
+            if let Some(v) = values.get_mut(i) {
+                *v = mask_value(v, strategy, key);
+            }
+        }
+        values.extend(csvutil::source_values(&source_file, row as u64 + 1));
+        values
+    };
+
+    let shuffled: Vec<usize> = masks.iter()
+        .filter(|(_, strategy)| **strategy == MaskStrategy::Shuffle)
+        .map(|&(i, _)| i)
+        .collect();
+
+    if shuffled.is_empty() {
+        for (row, result) in reader.records().enumerate() {
+            csv_writer.write_record(mask_record(row, &result?))?;
+        }
+    } else {
+        // Shuffling needs the whole column, so buffer the file
+        let mut rows = reader.records().enumerate()
+            .map(|(row, r)| r.map(|record| mask_record(row, &record)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut rng = match mask_options.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        for i in shuffled {
+            let mut column = rows.iter_mut().filter_map(|row| row.get_mut(i).map(std::mem::take)).collect::<Vec<_>>();
+            column.shuffle(&mut rng);
+            let mut column = column.into_iter();
+            rows.iter_mut().filter_map(|row| row.get_mut(i)).for_each(|v| *v = column.next().unwrap());
+        }
+        for row in rows {
+            csv_writer.write_record(row)?;
+        }
+    }
+
+    csv_writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_mask_value() {
+        assert_eq!(mask_value("1", &MaskStrategy::Hash, None),
+                   "6b86b273ff34fce19d6b804eff5a3f5747ada4eaa22f1d49c01e52ddb7875b4b");
+        assert_eq!(mask_value("4111111111111234", &MaskStrategy::Redact(4), None), "************1234");
+        assert_eq!(mask_value("12", &MaskStrategy::Redact(4), None), "12");
+        assert_eq!(mask_value("", &MaskStrategy::Hash, None), "");
+
+        let a = mask_value("jane@example.com", &MaskStrategy::Hmac, Some("secret"));
+        assert_eq!(a, mask_value("jane@example.com", &MaskStrategy::Hmac, Some("secret")));
+        assert_ne!(a, mask_value("jane@example.com", &MaskStrategy::Hmac, Some("other")));
+    }
+
+    #[test]
+    fn test_parse_mask() {
+        assert_eq!(parse_mask("email=hash"), Ok(("email".to_string(), MaskStrategy::Hash)));
+        assert_eq!(parse_mask("2=redact:2"), Ok(("2".to_string(), MaskStrategy::Redact(2))));
+        assert!(parse_mask("email").is_err());
+        assert!(parse_mask("email=rot13").is_err());
+    }
+
+    #[test]
+    fn test_process_csv_redact_and_shuffle() {
+        let dir = csvutil::TestDir::new();
+        let output_file = &dir.path("test_output_mask.csv");
+
+        let options = CsvOptions {
+            input_file: Some("test/test_input.csv".to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+
+        let action = CsvMaskOptions {
+            masks: vec![("col1".to_string(), MaskStrategy::Redact(0)), ("col3".to_string(), MaskStrategy::Shuffle)],
+            key: None,
+            seed: Some(42),
+        };
+
+        process_csv(&options, &action).expect("process_csv failed");
+
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        let lines = actual_output.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "col1,col2,col3");
+        assert_eq!(lines[1..].iter().map(|l| &l[..4]).collect::<Vec<_>>(), vec!["*,2,", "*,5,", "*,8,"]);
+        let mut col3 = lines[1..].iter().map(|l| &l[4..]).collect::<Vec<_>>();
+        col3.sort();
+        assert_eq!(col3, vec!["3", "6", "9"]);
+    }
+}
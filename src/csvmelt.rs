@@ -0,0 +1,173 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+
+use crate::args::global_args;
+use clap::Arg;
+use options::CsvOptions;
+use std::error::Error;
+use std::io::BufRead;
+
+struct CsvMeltOptions {
+    id_columns: Option<Vec<String>>,
+    value_columns: Option<Vec<String>>,
+    variable_name: String,
+    value_name: String,
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>());
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> (CsvOptions, CsvMeltOptions) {
+    let executable_name = args[0].clone();
+
+    let command = args::contract_args(global_args())
+        .display_name(executable_name)
+        .about("Converts wide CSV files to long format, one row per id and measure column.")
+        .arg(Arg::new("id_columns")
+            .short('i')
+            .long("id-columns")
+            .allow_negative_numbers(true)
+            .help("Columns to repeat on every output row, using the same syntax as --columns")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("input_columns")
+            .short('c')
+            .long("columns")
+            .allow_negative_numbers(true)
+            .help("Measure columns to unpivot, e.g. \"1,id,-2,3-5\". Defaults to every column that isn't an id column")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("variable_name")
+            .long("variable-name")
+            .default_value("variable")
+            .help("Name of the output column holding the measure column's name"))
+        .arg(Arg::new("value_name")
+            .long("value-name")
+            .default_value("value")
+            .help("Name of the output column holding the measure's value"));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let action = CsvMeltOptions {
+        id_columns: matches.remove_many::<String>("id_columns")
+            .map(|v| v.flat_map(|s| csvutil::parse_column_list(&s))
+                .collect::<Vec<_>>()),
+        value_columns: matches.remove_many::<String>("input_columns")
+            .map(|v| v.flat_map(|s| csvutil::parse_column_list(&s))
+                .collect::<Vec<_>>()),
+        variable_name: matches.remove_one("variable_name").unwrap(),
+        value_name: matches.remove_one("value_name").unwrap(),
+    };
+
+    (args::build_options(matches), action)
+}
+
+fn process_csv(options: &CsvOptions, melt_options: &CsvMeltOptions) -> Result<(), Box<dyn Error>> {
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    let mut reader = csvutil::csv_reader(options, input);
+
+    // Get the column headers
+    let first_row = csvutil::column_names(options, reader.headers()?.clone());
+
+    let id_indices: Vec<usize> = match &melt_options.id_columns {
+        Some(_) => csvutil::select_column_indices(&first_row, &melt_options.id_columns)?,
+        None => vec![],
+    };
+    let value_indices: Vec<usize> = match &melt_options.value_columns {
+        Some(_) => csvutil::select_column_indices(&first_row, &melt_options.value_columns)?,
+        None => (0..first_row.len()).filter(|i| !id_indices.contains(i)).collect(),
+    };
+
+    let all_indices = (0..first_row.len()).collect::<Vec<_>>();
+    let names = csvutil::enumerate_output_headers(options.config().has_headers, first_row, &all_indices);
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+
+    if options.config().report_headers {
+        let mut out_headers = id_indices.iter().map(|&i| names[i].as_str()).collect::<Vec<_>>();
+        out_headers.push(&melt_options.variable_name);
+        out_headers.push(&melt_options.value_name);
+        csv_writer.write_record(out_headers)?;
+    }
+
+    for result in reader.records() {
+        let record = result?;
+        let ids = id_indices.iter().map(|&i| record.get(i).unwrap_or_default()).collect::<Vec<_>>();
+        for &i in &value_indices {
+            let mut out = ids.clone();
+            out.push(&names[i]);
+            out.push(record.get(i).unwrap_or_default());
+            csv_writer.write_record(out)?;
+        }
+    }
+
+    csv_writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_process_csv() {
+        let dir = csvutil::TestDir::new();
+        let output_file = &dir.path("test_output_melt.csv");
+
+        let options = CsvOptions {
+            input_file: Some("test/test_input.csv".to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+
+        let action = CsvMeltOptions {
+            id_columns: Some(vec!["col1".to_string()]),
+            value_columns: None,
+            variable_name: "variable".to_string(),
+            value_name: "value".to_string(),
+        };
+
+        process_csv(&options, &action).expect("process_csv failed");
+
+        let expected_output = "\
+col1,variable,value
+1,col2,2
+1,col3,3
+4,col2,5
+4,col3,6
+7,col2,8
+7,col3,9
+";
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, expected_output);
+    }
+
+    #[test]
+    fn test_build_args() {
+        let args = ["csvmelt", "--id-columns", "1", "-c", "2-3", "--value-name", "amount"]
+            .iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        let (_, action) = parse_args(args);
+
+        assert_eq!(action.id_columns.unwrap(), vec!["1"]);
+        assert_eq!(action.value_columns.unwrap(), vec!["2-3"]);
+        assert_eq!(action.variable_name, "variable");
+        assert_eq!(action.value_name, "amount");
+    }
+}
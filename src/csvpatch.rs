@@ -0,0 +1,214 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+
+use crate::args::global_args;
+use clap::Arg;
+use csv::{ReaderBuilder, StringRecord};
+use options::CsvOptions;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::BufRead;
+
+struct CsvPatchOptions {
+    patch_file: String,
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>());
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> (CsvOptions, CsvPatchOptions) {
+    let executable_name = args[0].clone();
+
+    let command = args::in_place_args(args::contract_args(global_args()))
+        .display_name(executable_name)
+        .about("Applies a csvdiff change report to the file it was made from, reproducing the new file. Added records \
+                are written at the end. Fails without writing anything if the input doesn't hold the report's old values.")
+        .arg(Arg::new("patch")
+            .long("patch")
+            .required(true)
+            .help("csvdiff report to apply"));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let action = CsvPatchOptions {
+        patch_file: matches.remove_one("patch").expect("--patch is required"),
+    };
+
+    (args::build_options(matches), action)
+}
+
+/// The lines of a change report that apply to one record.
+struct RecordPatch {
+    key: Vec<String>,
+    change: String,
+    /// Column name, old value and new value
+    values: Vec<(String, String, String)>,
+}
+
+/// Reads a csvdiff report: the key column names, then each key's change.
+fn read_patch(path: &str) -> Result<(Vec<String>, Vec<RecordPatch>), Box<dyn Error>> {
+    let mut reader = ReaderBuilder::new().from_path(path)
+        .map_err(|e| format!("Unable to read {}: {}", path, e))?;
+    let headers = reader.headers()?.clone();
+    let n_keys = headers.iter().position(|h| h == "change")
+        .filter(|&n| headers.iter().skip(n).eq(["change", "column", "old_value", "new_value"]))
+        .ok_or_else(|| format!("{} is not a csvdiff report: it needs key columns then change,column,old_value,new_value", path))?;
+    let key_names = headers.iter().take(n_keys).map(String::from).collect::<Vec<_>>();
+
+    // Each key's lines in the order they first appear
+    let mut patches: Vec<RecordPatch> = vec![];
+    let mut index: HashMap<Vec<String>, usize> = HashMap::new();
+    for result in reader.records() {
+        let line = result?;
+        let key = line.iter().take(n_keys).map(String::from).collect::<Vec<_>>();
+        let [change, column, old_value, new_value] = [n_keys, n_keys + 1, n_keys + 2, n_keys + 3]
+            .map(|i| line.get(i).unwrap_or_default().to_string());
+        if !["changed", "added", "removed"].contains(&change.as_str()) {
+            return Err(Box::from(format!("Unknown change '{}' for key {}", change, key.join(","))));
+        }
+        let p = *index.entry(key.clone()).or_insert_with(|| {
+            patches.push(RecordPatch { key: key.clone(), change: change.clone(), values: vec![] });
+            patches.len() - 1
+        });
+        if patches[p].change != change {
+            return Err(Box::from(format!("Key {} is both {} and {}", key.join(","), patches[p].change, change)));
+        }
+        patches[p].values.push((column, old_value, new_value));
+    }
+    Ok((key_names, patches))
+}
+
+fn process_csv(options: &CsvOptions, patch_options: &CsvPatchOptions) -> Result<(), Box<dyn Error>> {
+    let (key_names, patches) = read_patch(&patch_options.patch_file)?;
+
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    let mut reader = csvutil::csv_reader(options, input);
+
+    // Get the column headers
+    let first_row = csvutil::column_names(options, reader.headers()?.clone());
+    let key_indices = csvutil::select_column_indices(&first_row, &Some(key_names))?;
+    let column = |name: &str| first_row.iter().position(|h| h == name)
+        .ok_or_else(|| format!("The patch changes column '{}', which isn't in the input", name));
+
+    let mut pending: HashMap<&[String], &RecordPatch> = HashMap::new();
+    for patch in patches.iter().filter(|patch| patch.change != "added") {
+        pending.insert(&patch.key, patch);
+    }
+
+    // Every record is held, so a patch that doesn't apply fails before anything is written
+    let mut records: Vec<StringRecord> = vec![];
+    for (row, result) in reader.records().enumerate() {
+        let record = result?;
+        let key = key_indices.iter().map(|&i| record.get(i).unwrap_or_default().to_string()).collect::<Vec<_>>();
+        let Some(patch) = pending.remove(key.as_slice()) else {
+            records.push(record);
+            continue;
+        };
+        let mut values = record.iter().map(String::from).collect::<Vec<_>>();
+        values.resize(first_row.len(), String::new());
+        for (name, old_value, new_value) in &patch.values {
+            let i = column(name)?;
+            if values[i] != *old_value {
+                return Err(Box::from(format!("Row {}: {} is '{}' but the patch expects '{}'", row + 1, name, values[i], old_value)));
+            }
+            values[i] = new_value.clone();
+        }
+        if patch.change == "changed" {
+            records.push(StringRecord::from(values));
+        }
+    }
+    if let Some(key) = pending.keys().next() {
+        return Err(Box::from(format!("The patch changes key {}, which isn't in the input", key.join(","))));
+    }
+
+    for patch in patches.iter().filter(|patch| patch.change == "added") {
+        let mut values = vec![String::new(); first_row.len()];
+        for (&i, value) in key_indices.iter().zip(&patch.key) {
+            values[i] = value.clone();
+        }
+        for (name, _, new_value) in &patch.values {
+            values[column(name)?] = new_value.clone();
+        }
+        records.push(StringRecord::from(values));
+    }
+
+    let output_has_headers = options.output_has_headers();
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+
+    if output_has_headers {
+        let all_indices = (0..first_row.len()).collect::<Vec<_>>();
+        csv_writer.write_record(csvutil::enumerate_output_headers(options.config().has_headers, first_row.clone(), &all_indices))?;
+    }
+
+    for record in &records {
+        csv_writer.write_record(record)?;
+    }
+
+    csv_writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn patch(input: &str, report: &str) -> Result<String, String> {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_patch.csv");
+        let patch_file = &dir.path("test_patch_patch.csv");
+        let output_file = &dir.path("test_output_patch.csv");
+        fs::write(input_file, input).expect("Unable to write input file");
+        fs::write(patch_file, report).expect("Unable to write patch file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+        let patch_options = CsvPatchOptions { patch_file: patch_file.to_string() };
+
+        let result = process_csv(&options, &patch_options).map_err(|e| e.to_string());
+        let output = fs::read_to_string(output_file).unwrap_or_default();
+        result.map(|_| output)
+    }
+
+    #[test]
+    fn test_process_csv() {
+        let report = "id,change,column,old_value,new_value\n\
+                      1,changed,city,Leeds,Bath\n\
+                      4,added,name,,Tom\n\
+                      4,added,city,,Hull\n\
+                      2,removed,name,Bob,\n\
+                      2,removed,city,York,\n";
+        assert_eq!(patch("id,name,city\n1,Jane,Leeds\n2,Bob,York\n3,Ann,\n", report),
+                   Ok("id,name,city\n1,Jane,Bath\n3,Ann,\n4,Tom,Hull\n".to_string()));
+    }
+
+    #[test]
+    fn test_process_csv_mismatch() {
+        let report = "id,change,column,old_value,new_value\n1,changed,city,Leeds,Bath\n";
+        assert_eq!(patch("id,name,city\n1,Jane,York\n", report), Err("Row 1: city is 'York' but the patch expects 'Leeds'".to_string()));
+        assert_eq!(patch("id,name,city\n2,Bob,York\n", report), Err("The patch changes key 1, which isn't in the input".to_string()));
+        let error = patch("id,name\n1,Jane\n", "id,column\n").expect_err("a report without change columns accepted");
+        assert!(error.ends_with("test_patch_patch.csv is not a csvdiff report: it needs key columns then change,column,old_value,new_value"), "{}", error);
+    }
+}
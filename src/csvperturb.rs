@@ -0,0 +1,379 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+
+use crate::args::global_args;
+use clap::Arg;
+use options::CsvOptions;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use stats::{BooleanValues, CsvColumnStat};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io::BufRead;
+
+struct CsvPerturbOptions {
+    /// Columns written unchanged
+    keep: Option<Vec<String>>,
+    /// The noise added to numbers and dates, as a fraction of the column's standard deviation
+    jitter: f64,
+    seed: Option<u64>,
+}
+
+/// How a column is perturbed, from the type its values were inferred to have.
+#[derive(Clone, Debug, PartialEq)]
+enum Perturbation {
+    Keep,
+    /// Normal noise with this standard deviation, kept within the column's range
+    Integer { noise: f64, min: i128, max: i128 },
+    Number { noise: f64, min: f64, max: f64 },
+    /// Day numbers, see `split_date`
+    Date { noise: f64, min: i64, max: i64 },
+    /// Permute the values between rows, keeping how often each occurs
+    Shuffle,
+    /// Replace each distinct value with a random one of about its length, for text too varied
+    /// to be categories, such as names or emails, which shuffling would leave in the output
+    Replace,
+}
+
+/// A text column is shuffled as categories when its values repeat this often on average, and
+/// replaced otherwise.
+const MIN_CATEGORY_COUNT: u64 = 10;
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>())?;
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> Result<(CsvOptions, CsvPerturbOptions), String> {
+    let executable_name = args[0].clone();
+
+    let command = args::boolean_args(global_args())
+        .display_name(executable_name)
+        .about("Writes an anonymized variant of a CSV file whose columns keep roughly the same statistics, for realistic test data.")
+        .after_help("Each column's type is inferred as csvschema does. Integers and numbers get normal noise and \
+                     YYYY-MM-DD dates are moved by a normal number of days, both kept within the column's range. \
+                     Booleans and text whose values repeat, e.g. categories, are shuffled between rows, so every value \
+                     keeps its count. Other text, such as names or emails, has each distinct value replaced with random \
+                     letters and digits, the same for each repeat. Empty values stay where they are. The whole file is \
+                     held in memory.")
+        .arg(Arg::new("keep")
+            .long("keep")
+            .allow_negative_numbers(true)
+            .help("Columns to leave unchanged, e.g. keys, as names, offsets or ranges like csvcut -c")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("jitter")
+            .long("jitter")
+            .value_parser(parse_jitter)
+            .default_value("0.1")
+            .help("The standard deviation of the noise added to numbers and dates, as a fraction of the column's"))
+        .arg(Arg::new("seed")
+            .long("seed")
+            .value_parser(clap::value_parser!(u64))
+            .help("Random seed, for reproducible output"));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let action = CsvPerturbOptions {
+        keep: matches.remove_many::<String>("keep").map(|v| v.flat_map(|s| csvutil::parse_column_list(&s)).collect()),
+        jitter: matches.remove_one("jitter").unwrap_or(0.1),
+        seed: matches.remove_one("seed"),
+    };
+
+    Ok((args::build_options(matches), action))
+}
+
+fn parse_jitter(s: &str) -> Result<f64, String> {
+    s.parse::<f64>().ok().filter(|j| j.is_finite() && *j >= 0.0)
+        .ok_or_else(|| format!("Invalid jitter '{}'. Expected a number of 0 or more", s))
+}
+
+/// The day number of a value starting with a YYYY-MM-DD date, and what follows the date, e.g. a time.
+fn split_date(value: &str) -> Option<(i64, &str)> {
+    let (date, rest) = (value.get(..10)?, &value[10..]);
+    if date.as_bytes()[4] != b'-' || date.as_bytes()[7] != b'-' || !(rest.is_empty() || rest.starts_with(['T', ' '])) {
+        return None;
+    }
+    Some((dates::parse_days(date)?, rest))
+}
+
+/// Picks a column's perturbation from its statistics. `days` has the statistics of the day
+/// numbers when every value is a date. NaN and infinite values can't be jittered or clamped, so a
+/// column holding them, or whose spread overflows, is taken as text.
+fn perturbation(statistic: &CsvColumnStat, days: Option<&CsvColumnStat>, jitter: f64) -> Perturbation {
+    let finite = statistic.sum.is_finite() && statistic.stdev().is_finite();
+    if statistic.is_boolean() {
+        Perturbation::Shuffle
+    } else if statistic.is_integer() && finite {
+        Perturbation::Integer { noise: jitter * statistic.stdev(), min: statistic.min_integer, max: statistic.max_integer }
+    } else if statistic.is_numeric() && finite {
+        Perturbation::Number { noise: jitter * statistic.stdev(), min: statistic.min, max: statistic.max }
+    } else if let Some(days) = days.filter(|d| d.is_integer()) {
+        Perturbation::Date { noise: jitter * days.stdev(), min: days.min_integer as i64, max: days.max_integer as i64 }
+    } else {
+        let n_values = statistic.n - statistic.n_missing - statistic.n_empty;
+        let n_distinct = statistic.unique() as u64 - (statistic.n_empty > 0) as u64;
+        if n_distinct * MIN_CATEGORY_COUNT <= n_values {
+            Perturbation::Shuffle
+        } else {
+            Perturbation::Replace
+        }
+    }
+}
+
+/// Random lowercase letters and digits as long as `value`, or 4 at least, that aren't in `used`.
+/// Values too short to have one left get a longer one.
+fn pseudonym(value: &str, used: &mut HashSet<String>, rng: &mut StdRng) -> String {
+    const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+    let mut len = value.chars().count().max(4);
+    loop {
+        for _ in 0..10 {
+            let token = (0..len).map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char).collect::<String>();
+            if used.insert(token.clone()) {
+                return token;
+            }
+        }
+        len += 1;
+    }
+}
+
+/// A standard normal number, by the Box-Muller transform.
+fn gaussian(rng: &mut StdRng) -> f64 {
+    let (u, v) = (1.0 - rng.gen::<f64>(), rng.gen::<f64>());
+    (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()
+}
+
+/// The number of digits after the decimal point, so a perturbed number is written like the original.
+fn decimals(value: &str) -> usize {
+    value.split_once('.').map_or(0, |(_, fraction)| fraction.chars().take_while(char::is_ascii_digit).count())
+}
+
+fn perturb_value(value: &str, perturbation: &Perturbation, rng: &mut StdRng) -> String {
+    if value.is_empty() {
+        return String::new();
+    }
+    match *perturbation {
+        Perturbation::Integer { noise, min, max } => match numbers::parse_integer(value, true) {
+            Some(n) => ((n as f64 + noise * gaussian(rng)).round() as i128).clamp(min, max).to_string(),
+            None => value.to_string(),
+        },
+        Perturbation::Number { noise, min, max } => match numbers::parse_number(value, true).filter(|n| n.is_finite()) {
+            Some(n) => format!("{:.*}", decimals(value), (n + noise * gaussian(rng)).clamp(min, max)),
+            None => value.to_string(),
+        },
+        Perturbation::Date { noise, min, max } => match split_date(value) {
+            Some((days, rest)) => {
+                let (year, month, day) = dates::civil_from_days((days + (noise * gaussian(rng)).round() as i64).clamp(min, max));
+                format!("{:04}-{:02}-{:02}{}", year, month, day, rest)
+            }
+            None => value.to_string(),
+        },
+        Perturbation::Keep | Perturbation::Shuffle | Perturbation::Replace => value.to_string(),
+    }
+}
+
+fn process_csv(options: &CsvOptions, perturb_options: &CsvPerturbOptions) -> Result<(), Box<dyn Error>> {
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    let mut reader = csvutil::csv_reader(options, input);
+
+    // Get the column headers
+    let first_row = csvutil::column_names(options, reader.headers()?.clone());
+    let kept = match &perturb_options.keep {
+        Some(_) => csvutil::select_column_indices(&first_row, &perturb_options.keep)?,
+        None => vec![],
+    };
+
+    // The statistics need every value before any can be perturbed, so buffer the file
+    let rows = reader.records().map(|r| r.map(|record| record.iter().map(str::to_string).collect::<Vec<_>>()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let boolean_values = BooleanValues::new(options.true_values.clone(), options.false_values.clone());
+    let perturbations = (0..first_row.len()).map(|i| {
+        if kept.contains(&i) {
+            return Perturbation::Keep;
+        }
+        // Numbers are parsed strictly, so that they can be written back the same way
+        let mut statistic = CsvColumnStat::new(i, String::new()).strict_numeric(true).boolean_values(boolean_values.clone());
+        let mut days = Some(CsvColumnStat::new(i, String::new()));
+        for value in rows.iter().filter_map(|row| row.get(i)) {
+            statistic.add(Some(value));
+            if let Some(statistic) = days.as_mut().filter(|_| !value.is_empty()) {
+                match split_date(value) {
+                    Some((day, _)) => statistic.add(Some(&day.to_string())),
+                    None => days = None,
+                }
+            }
+        }
+        perturbation(&statistic, days.as_ref(), perturb_options.jitter)
+    }).collect::<Vec<_>>();
+
+    let mut rng = match perturb_options.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut rows = rows.into_iter()
+        .map(|row| row.iter().zip(&perturbations).map(|(v, p)| perturb_value(v, p, &mut rng)).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    // Only the non-empty values change, so each column keeps its empty cells in place
+    for (i, perturbation) in perturbations.iter().enumerate() {
+        let mut values = rows.iter_mut().filter_map(|row| row.get_mut(i)).filter(|v| !v.is_empty()).collect::<Vec<_>>();
+        match perturbation {
+            Perturbation::Shuffle => {
+                let mut column = values.iter_mut().map(|v| std::mem::take(*v)).collect::<Vec<_>>();
+                column.shuffle(&mut rng);
+                values.into_iter().zip(column).for_each(|(v, value)| *v = value);
+            }
+            Perturbation::Replace => {
+                let (mut pseudonyms, mut used) = (HashMap::new(), HashSet::new());
+                for v in values {
+                    let value = std::mem::take(v);
+                    *v = pseudonyms.entry(value).or_insert_with_key(|value| pseudonym(value, &mut used, &mut rng)).clone();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+    if options.output_has_headers() {
+        let all_indices = (0..first_row.len()).collect::<Vec<_>>();
+        csv_writer.write_record(csvutil::enumerate_output_headers(options.config().has_headers, first_row, &all_indices))?;
+    }
+    for row in rows {
+        csv_writer.write_record(row)?;
+    }
+
+    csv_writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_date() {
+        let day = dates::parse_days("2024-03-07").unwrap();
+        assert_eq!(split_date("2024-03-07"), Some((day, "")));
+        assert_eq!(split_date("2024-03-07T12:30:15Z"), Some((day, "T12:30:15Z")));
+        assert_eq!(split_date("20240307"), None);
+        assert_eq!(split_date("2024-03-07x"), None);
+        assert_eq!(split_date("Zürich"), None);
+    }
+
+    #[test]
+    fn test_perturb_value() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let number = Perturbation::Number { noise: 1.0, min: 0.0, max: 10.0 };
+        for _ in 0..100 {
+            let value = perturb_value("9.50", &number, &mut rng);
+            assert_eq!(decimals(&value), 2, "{}", value);
+            assert!((0.0..=10.0).contains(&value.parse::<f64>().unwrap()), "{}", value);
+        }
+        let date = Perturbation::Date { noise: 30.0, min: dates::parse_days("2024-01-01").unwrap(), max: dates::parse_days("2024-01-31").unwrap() };
+        assert!((0..100).all(|_| perturb_value("2024-01-15 08:00", &date, &mut rng).starts_with("2024-01-")));
+        assert_eq!(perturb_value("", &number, &mut rng), "");
+        assert_eq!(perturb_value("n/a", &number, &mut rng), "n/a");
+        assert_eq!(perturb_value("inf", &number, &mut rng), "inf");
+    }
+
+    #[test]
+    fn test_process_csv_non_finite() {
+        let mut input = "x,y\n".to_string();
+        for i in 0..40 {
+            input += &format!("{}.5,{}\n", i, if i % 10 == 0 { "NaN" } else if i % 10 == 5 { "inf" } else { "2.5" });
+        }
+        let output = options::SharedBuffer::new();
+        let options = CsvOptions::new().with_input(std::io::Cursor::new(input.clone())).with_output(output.clone());
+        // NaN and inf make a column text, so the numbers around them aren't turned into NaN
+        for column in [vec!["NaN"], vec!["inf"], vec!["NaN", "inf"]] {
+            let mut statistic = CsvColumnStat::new(0, String::new()).strict_numeric(true);
+            ["1.5", "2"].iter().chain(&column).for_each(|v| statistic.add(Some(v)));
+            let perturbation = perturbation(&statistic, None, 0.1);
+            assert!(matches!(perturbation, Perturbation::Shuffle | Perturbation::Replace), "{:?}: {:?}", column, perturbation);
+        }
+        process_csv(&options, &CsvPerturbOptions { keep: None, jitter: 0.1, seed: Some(1) }).expect("process_csv failed");
+        let mut y = output.to_string_lossy().lines().skip(1).map(|l| l.split(',').nth(1).unwrap().to_string()).collect::<Vec<_>>();
+        y.sort();
+        assert_eq!(y.iter().filter(|v| *v == "2.5").count(), 32);
+        assert_eq!((y.iter().filter(|v| *v == "NaN").count(), y.iter().filter(|v| *v == "inf").count()), (4, 4));
+    }
+
+    #[test]
+    fn test_perturbation() {
+        let text = |values: &[&str]| {
+            let mut statistic = CsvColumnStat::new(0, String::new());
+            values.iter().for_each(|v| statistic.add(Some(v)));
+            perturbation(&statistic, None, 0.1)
+        };
+        assert_eq!(text(&[&["Oslo"; 10][..], &["Lima"; 10], &[""; 5]].concat()), Perturbation::Shuffle);
+        assert_eq!(text(&[&["Oslo"; 10][..], &["Lima"; 9]].concat()), Perturbation::Replace);
+        assert_eq!(text(&["Ann", "Bob", "Cy"]), Perturbation::Replace);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut used = HashSet::new();
+        assert_eq!(pseudonym("jane@x.org", &mut used, &mut rng).len(), 10);
+        assert!((0..100).map(|_| pseudonym("a", &mut used, &mut rng)).all(|p| p.len() >= 4));
+        assert_eq!(used.len(), 101);
+    }
+
+    #[test]
+    fn test_process_csv() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_perturb.csv");
+        let output_file = &dir.path("test_output_perturb.csv");
+        let mut input = "id,amount,city,day,email\n".to_string();
+        for i in 0..400 {
+            let city = if i % 40 == 0 { "" } else { ["Oslo", "Lima", "Pune", "Oslo"][i % 4] };
+            input += &format!("{},{}.{:02},{},2024-02-{:02},user{}@x.org\n", i, i % 50, i % 100, city, i % 28 + 1, i % 200);
+        }
+        std::fs::write(input_file, &input).expect("Unable to write input file");
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+        let action = CsvPerturbOptions { keep: Some(vec!["id".to_string()]), jitter: 0.1, seed: Some(3) };
+
+        process_csv(&options, &action).expect("process_csv failed");
+        let output = std::fs::read_to_string(output_file).expect("Unable to read output file");
+        let mut reader = csv::Reader::from_reader(output.as_bytes());
+        let records = reader.records().collect::<Result<Vec<_>, _>>().expect("Invalid output");
+        assert_eq!(records.len(), 400);
+        assert!(records.iter().enumerate().all(|(i, r)| r[0] == i.to_string()));
+        assert!(records.iter().enumerate().all(|(i, r)| r[2].is_empty() == (i % 40 == 0)));
+
+        let amounts = records.iter().map(|r| r[1].parse::<f64>().unwrap()).collect::<Vec<_>>();
+        assert!(amounts.iter().all(|a| (0.0..=49.99).contains(a)));
+        let mean = amounts.iter().sum::<f64>() / 400.0;
+        assert!((mean - 24.995).abs() < 1.0, "mean {}", mean);
+        assert!(records.iter().enumerate().filter(|(i, r)| &r[1] != input.lines().nth(i + 1).unwrap().split(',').nth(1).unwrap()).count() > 300);
+
+        let mut cities = records.iter().map(|r| r[2].to_string()).collect::<Vec<_>>();
+        cities.sort();
+        assert_eq!(cities.iter().filter(|c| *c == "Oslo").count(), 190);
+        assert!(records.iter().all(|r| ("2024-02-01"..="2024-02-28").contains(&&r[3])));
+
+        // Too varied to be categories, the emails are replaced, each the same way each time it occurs
+        assert!(records.iter().enumerate().all(|(i, r)| !r[4].contains('@') && r[4].len() == format!("user{}@x.org", i % 200).len()));
+        assert!((0..200).all(|i| records[i][4] == records[i + 200][4]));
+        assert_eq!(records.iter().map(|r| &r[4]).collect::<HashSet<_>>().len(), 200);
+
+        process_csv(&options, &action).expect("process_csv failed");
+        assert_eq!(std::fs::read_to_string(output_file).expect("Unable to read output file"), output);
+    }
+}
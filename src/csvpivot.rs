@@ -0,0 +1,248 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+
+use crate::args::global_args;
+use clap::Arg;
+use options::CsvOptions;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::BufRead;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CellAggregate { Error, First, Last, Sum, Mean, Min, Max, Count, Concat }
+
+struct CsvPivotOptions {
+    key_columns: Option<Vec<String>>,
+    pivot_column: String,
+    value_column: String,
+    aggregate: CellAggregate,
+    /// Keep new columns in order of first appearance instead of sorting them
+    first_seen_order: bool,
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>());
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> (CsvOptions, CsvPivotOptions) {
+    let executable_name = args[0].clone();
+
+    let command = args::contract_args(global_args())
+        .display_name(executable_name)
+        .about("Converts long CSV files to wide format, one column per distinct pivot value.")
+        .arg(Arg::new("key_columns")
+            .short('k')
+            .long("key-columns")
+            .allow_negative_numbers(true)
+            .help("Columns identifying an output row, using the same syntax as --columns. Defaults to every column except the pivot and value columns")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("pivot")
+            .long("pivot")
+            .required(true)
+            .allow_negative_numbers(true)
+            .help("Column whose distinct values become the new columns"))
+        .arg(Arg::new("value")
+            .long("value")
+            .required(true)
+            .allow_negative_numbers(true)
+            .help("Column whose values fill the new columns"))
+        .arg(Arg::new("aggregate")
+            .short('a')
+            .long("agg")
+            .value_parser(["error", "first", "last", "sum", "mean", "min", "max", "count", "concat"])
+            .default_value("error")
+            .help("How to combine several values for the same key and pivot value. The default fails on duplicates"))
+        .arg(Arg::new("column_order")
+            .long("column-order")
+            .value_parser(["sorted", "first-seen"])
+            .default_value("sorted")
+            .help("Order of the new columns"));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let action = CsvPivotOptions {
+        key_columns: matches.remove_many::<String>("key_columns")
+            .map(|v| v.flat_map(|s| csvutil::parse_column_list(&s))
+                .collect::<Vec<_>>()),
+        pivot_column: matches.remove_one("pivot").unwrap(),
+        value_column: matches.remove_one("value").unwrap(),
+        aggregate: match matches.remove_one::<String>("aggregate").as_deref() {
+            Some("first") => CellAggregate::First,
+            Some("last") => CellAggregate::Last,
+            Some("sum") => CellAggregate::Sum,
+            Some("mean") => CellAggregate::Mean,
+            Some("min") => CellAggregate::Min,
+            Some("max") => CellAggregate::Max,
+            Some("count") => CellAggregate::Count,
+            Some("concat") => CellAggregate::Concat,
+            _ => CellAggregate::Error,
+        },
+        first_seen_order: matches.remove_one::<String>("column_order").as_deref() == Some("first-seen"),
+    };
+
+    (args::build_options(matches), action)
+}
+
+fn combine(values: &[String], aggregate: CellAggregate) -> String {
+    let parsed = || values.iter().filter_map(|v| numbers::parse_number(v, false));
+    match aggregate {
+        CellAggregate::Error | CellAggregate::First => values[0].clone(),
+        CellAggregate::Last => values[values.len() - 1].clone(),
+        CellAggregate::Count => values.len().to_string(),
+        CellAggregate::Concat => values.join("|"),
+        CellAggregate::Sum => parsed().reduce(|a, b| a + b).map(transform::format_number).unwrap_or_default(),
+        CellAggregate::Mean => {
+            let (n, sum) = parsed().fold((0, 0.0), |(n, sum), v| (n + 1, sum + v));
+            if n == 0 { String::new() } else { transform::format_number(sum / n as f64) }
+        }
+        CellAggregate::Min => parsed().reduce(f64::min).map(transform::format_number).unwrap_or_default(),
+        CellAggregate::Max => parsed().reduce(f64::max).map(transform::format_number).unwrap_or_default(),
+    }
+}
+
+fn process_csv(options: &CsvOptions, pivot_options: &CsvPivotOptions) -> Result<(), Box<dyn Error>> {
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    let mut reader = csvutil::csv_reader(options, input);
+
+    // Get the column headers
+    let first_row = csvutil::column_names(options, reader.headers()?.clone());
+
+    let pivot_idx = csvutil::select_column_index(&first_row, &pivot_options.pivot_column)?;
+    let value_idx = csvutil::select_column_index(&first_row, &pivot_options.value_column)?;
+    let key_indices: Vec<usize> = match &pivot_options.key_columns {
+        Some(_) => csvutil::select_column_indices(&first_row, &pivot_options.key_columns)?,
+        None => (0..first_row.len()).filter(|&i| i != pivot_idx && i != value_idx).collect(),
+    };
+
+    // The whole input is held in memory: keys and pivot values in order of first appearance
+    let mut keys: Vec<Vec<String>> = vec![];
+    let mut key_rows: HashMap<Vec<String>, usize> = HashMap::new();
+    let mut pivot_values: Vec<String> = vec![];
+    let mut pivot_cols: HashMap<String, usize> = HashMap::new();
+    let mut cells: HashMap<(usize, usize), Vec<String>> = HashMap::new();
+
+    for result in reader.records() {
+        let record = result?;
+        let key = key_indices.iter().map(|&i| record.get(i).unwrap_or_default().to_string()).collect::<Vec<_>>();
+        let row = *key_rows.entry(key.clone()).or_insert_with(|| {
+            keys.push(key.clone());
+            keys.len() - 1
+        });
+        let pivot_value = record.get(pivot_idx).unwrap_or_default().to_string();
+        let col = *pivot_cols.entry(pivot_value.clone()).or_insert_with(|| {
+            pivot_values.push(pivot_value.clone());
+            pivot_values.len() - 1
+        });
+        let cell = cells.entry((row, col)).or_default();
+        if pivot_options.aggregate == CellAggregate::Error && !cell.is_empty() {
+            return Err(Box::from(format!("Duplicate value for key ({}) and {}. Use --agg to combine duplicates",
+                                         key.join(", "), pivot_value)));
+        }
+        cell.push(record.get(value_idx).unwrap_or_default().to_string());
+    }
+
+    let mut column_order = (0..pivot_values.len()).collect::<Vec<_>>();
+    if !pivot_options.first_seen_order {
+        column_order.sort_by(|&a, &b| pivot_values[a].cmp(&pivot_values[b]));
+    }
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+
+    if options.config().report_headers {
+        let all_indices = (0..first_row.len()).collect::<Vec<_>>();
+        let names = csvutil::enumerate_output_headers(options.config().has_headers, first_row, &all_indices);
+        let mut out_headers = key_indices.iter().map(|&i| names[i].clone()).collect::<Vec<_>>();
+        out_headers.extend(column_order.iter().map(|&c| pivot_values[c].clone()));
+        csv_writer.write_record(out_headers)?;
+    }
+
+    for (row, key) in keys.into_iter().enumerate() {
+        let mut out = key;
+        out.extend(column_order.iter()
+            .map(|&col| cells.get(&(row, col)).map(|v| combine(v, pivot_options.aggregate)).unwrap_or_default()));
+        csv_writer.write_record(out)?;
+    }
+
+    csv_writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn pivot_options(aggregate: CellAggregate) -> CsvPivotOptions {
+        CsvPivotOptions {
+            key_columns: None,
+            pivot_column: "variable".to_string(),
+            value_column: "value".to_string(),
+            aggregate,
+            first_seen_order: false,
+        }
+    }
+
+    #[test]
+    fn test_process_csv() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_pivot.csv");
+        let output_file = &dir.path("test_output_pivot.csv");
+        fs::write(input_file, "id,variable,value\n1,b,2\n1,a,3\n2,a,5\n1,a,4\n").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(process_csv(&options, &pivot_options(CellAggregate::Error)).expect_err("").to_string(),
+                   "Duplicate value for key (1) and a. Use --agg to combine duplicates");
+
+        process_csv(&options, &pivot_options(CellAggregate::Sum)).expect("process_csv failed");
+
+        let expected_output = "id,a,b\n1,7,2\n2,5,\n";
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, expected_output);
+    }
+
+    #[test]
+    fn test_combine() {
+        let values = vec!["3".to_string(), "x".to_string(), "1".to_string()];
+        assert_eq!(combine(&values, CellAggregate::First), "3");
+        assert_eq!(combine(&values, CellAggregate::Last), "1");
+        assert_eq!(combine(&values, CellAggregate::Count), "3");
+        assert_eq!(combine(&values, CellAggregate::Concat), "3|x|1");
+        assert_eq!(combine(&values, CellAggregate::Mean), "2");
+        assert_eq!(combine(&values, CellAggregate::Max), "3");
+
+        // Thousands separators are read as the other tools read them, and NaN is a word
+        let separated = vec!["1,500".to_string(), "NaN".to_string(), " 500 ".to_string()];
+        assert_eq!(combine(&separated, CellAggregate::Sum), "2000");
+        assert_eq!(combine(&separated, CellAggregate::Mean), "1000");
+
+        let tenths = vec!["0.1".to_string(), "0.2".to_string()];
+        assert_eq!(combine(&tenths, CellAggregate::Sum), "0.3");
+        assert_eq!(combine(&tenths, CellAggregate::Mean), "0.15");
+
+        let text = vec!["x".to_string(), "".to_string()];
+        for aggregate in [CellAggregate::Sum, CellAggregate::Mean, CellAggregate::Min, CellAggregate::Max] {
+            assert_eq!(combine(&text, aggregate), "");
+        }
+    }
+}
@@ -0,0 +1,278 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+
+use crate::args::global_args;
+use clap::Arg;
+use csv::StringRecord;
+use options::CsvOptions;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::BufRead;
+
+struct CsvSampleOptions {
+    /// Records to keep in all
+    n: Option<usize>,
+    group_columns: Option<Vec<String>>,
+    /// Most records kept from a group
+    per_group: Option<usize>,
+    /// Fewest records kept from a group, where it has that many
+    min_per_group: usize,
+    seed: Option<u64>,
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>());
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> (CsvOptions, CsvSampleOptions) {
+    let executable_name = args[0].clone();
+
+    let command = args::raw_passthrough_args(args::in_place_args(args::contract_args(global_args())))
+        .display_name(executable_name)
+        .about("Takes a random sample of the records in CSV files, keeping their order.")
+        .arg(Arg::new("n")
+            .short('N')
+            .long("rows")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .required_unless_present("per_group")
+            .help("Records to sample. With --stratify-by they are shared between the groups in proportion to their size"))
+        .arg(Arg::new("group_columns")
+            .long("stratify-by")
+            .allow_negative_numbers(true)
+            .help("Columns identifying a group to sample separately, using the same syntax as csvcut's --columns")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("per_group")
+            .long("per-group")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .requires("group_columns")
+            .help("Most records to keep from each group, or all of a smaller group"))
+        .arg(Arg::new("min_per_group")
+            .long("min-per-group")
+            .value_parser(clap::value_parser!(u64))
+            .requires("n")
+            .requires("group_columns")
+            .help("Fewest records to keep from each group, or all of a smaller group, however small its share of --rows"))
+        .arg(Arg::new("seed")
+            .long("seed")
+            .value_parser(clap::value_parser!(u64))
+            .help("Random seed, for a reproducible sample"));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let action = CsvSampleOptions {
+        n: matches.remove_one::<u64>("n").map(|n| n as usize),
+        group_columns: matches.remove_many::<String>("group_columns")
+            .map(|v| v.flat_map(|s| csvutil::parse_column_list(&s))
+                .collect::<Vec<_>>()),
+        per_group: matches.remove_one::<u64>("per_group").map(|n| n as usize),
+        min_per_group: matches.remove_one::<u64>("min_per_group").unwrap_or(0) as usize,
+        seed: matches.remove_one("seed"),
+    };
+
+    (args::build_options(matches), action)
+}
+
+/// A uniform random sample of up to `size` records from a stream, with their row numbers.
+struct Reservoir {
+    size: usize,
+    seen: usize,
+    /// Each with its row and, for `--raw-passthrough`, the bytes it was read as
+    records: Vec<(usize, StringRecord, Option<Vec<u8>>)>,
+}
+
+impl Reservoir {
+    fn new(size: usize) -> Self {
+        Reservoir { size, seen: 0, records: vec![] }
+    }
+
+    fn add(&mut self, row: usize, record: StringRecord, raw: Option<Vec<u8>>, rng: &mut StdRng) {
+        self.seen += 1;
+        if self.records.len() < self.size {
+            self.records.push((row, record, raw));
+        } else {
+            let j = rng.gen_range(0..self.seen);
+            if j < self.size {
+                self.records[j] = (row, record, raw);
+            }
+        }
+    }
+}
+
+/// Shares `n` records between groups of the given sizes in proportion to them, rounding by the
+/// largest remainders, then holds each share between `min` and `max` and to the group's size.
+fn allocate(sizes: &[usize], n: usize, min: usize, max: usize) -> Vec<usize> {
+    let total = sizes.iter().sum::<usize>().max(1);
+    let exact = sizes.iter().map(|&size| n as f64 * size as f64 / total as f64).collect::<Vec<_>>();
+    let mut shares = exact.iter().map(|e| e.floor() as usize).collect::<Vec<_>>();
+    let mut by_remainder = (0..sizes.len()).collect::<Vec<_>>();
+    by_remainder.sort_by(|&a, &b| (exact[b] - exact[b].floor()).total_cmp(&(exact[a] - exact[a].floor())));
+    let left = n.saturating_sub(shares.iter().sum());
+    for &i in by_remainder.iter().take(left) {
+        shares[i] += 1;
+    }
+    shares.iter().zip(sizes).map(|(&share, &size)| share.max(min).min(max).min(size)).collect()
+}
+
+fn process_csv(options: &CsvOptions, sample_options: &CsvSampleOptions) -> Result<(), Box<dyn Error>> {
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    // Get the column headers, and the records with the bytes they were read as for --raw-passthrough
+    let (headers, records) = csvutil::raw_records(options, input)?;
+    let first_row = csvutil::column_names(options, headers);
+
+    let group_indices = match &sample_options.group_columns {
+        Some(_) => csvutil::select_column_indices(&first_row, &sample_options.group_columns)?,
+        None => vec![],
+    };
+
+    let mut rng = match sample_options.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    // No group can end up with more than the whole sample or its own limit
+    let max = sample_options.per_group.unwrap_or(usize::MAX);
+    let size = sample_options.n.unwrap_or(usize::MAX).min(max);
+    let mut groups: Vec<Reservoir> = vec![];
+    let mut group_of: HashMap<Vec<String>, usize> = HashMap::new();
+    for (row, result) in records.enumerate() {
+        let (record, raw) = result?;
+        let key = group_indices.iter().map(|&i| record.get(i).unwrap_or_default().to_string()).collect::<Vec<_>>();
+        let group = *group_of.entry(key).or_insert_with(|| {
+            groups.push(Reservoir::new(size));
+            groups.len() - 1
+        });
+        groups[group].add(row, record, raw, &mut rng);
+    }
+
+    let sizes = groups.iter().map(|g| g.seen).collect::<Vec<_>>();
+    let shares = match sample_options.n {
+        Some(n) => allocate(&sizes, n, sample_options.min_per_group, max),
+        None => sizes.iter().map(|&size| size.min(max)).collect(),
+    };
+
+    let mut kept = vec![];
+    for (mut group, share) in groups.into_iter().zip(shares) {
+        group.records.shuffle(&mut rng);
+        group.records.truncate(share);
+        kept.extend(group.records);
+    }
+    kept.sort_by_key(|(row, _, _)| *row);
+
+    let output_has_headers = options.output_has_headers();
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+
+    if output_has_headers {
+        let all_indices = (0..first_row.len()).collect::<Vec<_>>();
+        csv_writer.write_record(csvutil::enumerate_output_headers(options.config().has_headers, first_row, &all_indices))?;
+    }
+
+    for (_, record, raw) in kept {
+        match &raw {
+            Some(raw) => csv_writer.write_raw(raw, &record)?,
+            None => csv_writer.write_record(&record)?,
+        }
+    }
+
+    csv_writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_allocate() {
+        assert_eq!(allocate(&[90, 9, 1], 10, 0, usize::MAX), vec![9, 1, 0]);
+        assert_eq!(allocate(&[90, 9, 1], 10, 2, usize::MAX), vec![9, 2, 1]);
+        assert_eq!(allocate(&[90, 9, 1], 10, 0, 5), vec![5, 1, 0]);
+        assert_eq!(allocate(&[3, 3, 3], 4, 0, usize::MAX).iter().sum::<usize>(), 4);
+    }
+
+    #[test]
+    fn test_process_csv() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_sample.csv");
+        let output_file = &dir.path("test_output_sample.csv");
+        let mut input = "region,id\n".to_string();
+        for i in 0..100 {
+            input += &format!("{},{}\n", if i % 10 == 0 { "south" } else { "north" }, i);
+        }
+        fs::write(input_file, input).expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+        let read_output = || {
+            let output = fs::read_to_string(output_file).expect("Unable to read output file");
+            output.lines().skip(1).map(|l| l.split_once(',').unwrap())
+                .map(|(region, id)| (region.to_string(), id.parse::<usize>().unwrap()))
+                .collect::<Vec<_>>()
+        };
+
+        let mut sample_options = CsvSampleOptions { n: Some(20), group_columns: None, per_group: None, min_per_group: 0, seed: Some(1) };
+        process_csv(&options, &sample_options).expect("process_csv failed");
+        let rows = read_output();
+        assert_eq!(rows.len(), 20);
+        assert!(rows.windows(2).all(|w| w[0].1 < w[1].1), "the sample keeps the input order");
+
+        sample_options.group_columns = Some(vec!["region".to_string()]);
+        sample_options.n = None;
+        sample_options.per_group = Some(3);
+        process_csv(&options, &sample_options).expect("process_csv failed");
+        let rows = read_output();
+        assert_eq!(rows.iter().filter(|(region, _)| region == "south").count(), 3);
+        assert_eq!(rows.iter().filter(|(region, _)| region == "north").count(), 3);
+
+        sample_options.n = Some(10);
+        sample_options.per_group = None;
+        sample_options.min_per_group = 4;
+        process_csv(&options, &sample_options).expect("process_csv failed");
+        let rows = read_output();
+        assert_eq!(rows.iter().filter(|(region, _)| region == "south").count(), 4);
+        assert_eq!(rows.iter().filter(|(region, _)| region == "north").count(), 9);
+    }
+
+    #[test]
+    fn test_process_csv_raw_passthrough() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_sample_raw.csv");
+        let output_file = &dir.path("test_output_sample_raw.csv");
+        let input = "id,note\n1,\"a\"\n2,\"b,c\"\n3,\"\"\"d\"\"\"\n";
+        fs::write(input_file, input).expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            raw_passthrough: true,
+            ..Default::default()
+        };
+        let sample_options = CsvSampleOptions { n: Some(3), group_columns: None, per_group: None, min_per_group: 0, seed: Some(1) };
+        process_csv(&options, &sample_options).expect("process_csv failed");
+        let output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(output, input);
+    }
+}
@@ -0,0 +1,586 @@
+use crate::args::global_args;
+use crate::options::CsvOptions;
+use crate::stats::{BooleanValues, CsvColumnStat};
+use clap::{Arg, ArgMatches};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+pub mod csvutil;
+pub mod numbers;
+pub mod reader;
+pub mod dates;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+pub mod args;
+pub mod options;
+pub mod stats;
+pub mod transform;
+pub mod writer;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum SchemaFormat { Json, TableSchema, Sql }
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum SqlDialect { Postgres, Mysql, Sqlite }
+
+struct CsvSchemaOptions {
+    input_columns: Option<Vec<String>>,
+    format: SchemaFormat,
+    dialect: SqlDialect,
+    table: Option<String>,
+    sample: Option<usize>,
+    load: bool,
+    /// SQL types declared by column name, used instead of the inferred ones
+    column_types: Vec<(String, String)>,
+    /// Load into this Postgres database instead of printing the SQL
+    dsn: Option<String>,
+}
+
+fn main() -> Result<(), String> {
+    let (options, schema_options) = parse_args(std::env::args().collect::<Vec<_>>());
+
+    options.finish(process_csv(&options, &schema_options))
+}
+
+fn process_csv(options: &CsvOptions, schema_options: &CsvSchemaOptions) -> Result<(), Box<dyn Error>> {
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    let mut reader = csvutil::csv_reader(options, input);
+
+    // Get the column headers
+    let first_row = csvutil::column_names(options, reader.headers()?.clone());
+
+    // Determine which columns to include
+    let selected_indices: Vec<usize> = csvutil::select_column_indices(&first_row, &schema_options.input_columns)?;
+    let n_columns = first_row.len();
+
+    let out_headers = csvutil::enumerate_output_headers(options.config().has_headers, first_row, &selected_indices);
+
+    let boolean_values = BooleanValues::new(options.true_values.clone(), options.false_values.clone());
+    let mut statistics: Vec<CsvColumnStat> = selected_indices.iter().zip(out_headers)
+        // Loaders parse numbers strictly, so "1,234" has to stay text in the schema
+        .map(|(&i, name)| CsvColumnStat::new(i, name).strict_numeric(true).boolean_values(boolean_values.clone()))
+        .collect();
+
+    for result in reader.records().take(schema_options.sample.unwrap_or(usize::MAX)) {
+        let record = result?;
+        selected_indices.iter().zip(statistics.iter_mut())
+            .for_each(|(&i, statistic)| statistic.add(record.get(i)));
+    }
+
+    stats::warn_leading_zeros(&statistics);
+
+    let output = match schema_options.format {
+        SchemaFormat::Json => serde_json::to_string_pretty(&json_schema(&statistics))? + "\n",
+        SchemaFormat::TableSchema => serde_json::to_string_pretty(&table_schema(&statistics))? + "\n",
+        SchemaFormat::Sql => {
+            let table = table_name(options, schema_options);
+            let mut sql = create_table(&table, &statistics, schema_options.dialect, &schema_options.column_types)?;
+            if (schema_options.load || schema_options.dsn.is_some()) && !selected_indices.iter().copied().eq(0..n_columns) {
+                return Err(Box::from("--load and --to load the whole file, so they can't be combined with a column selection"));
+            }
+            if let Some(dsn) = &schema_options.dsn {
+                return load_postgres(dsn, &sql, &table, options);
+            }
+            if schema_options.load {
+                sql += &load_command(&table, options, schema_options.dialect)?;
+            }
+            sql
+        }
+    };
+
+    let mut out = options.get_output_file()?;
+    out.write_all(output.as_bytes())?;
+    out.flush()?;
+
+    Ok(())
+}
+
+fn json_schema(statistics: &[CsvColumnStat]) -> Value {
+    Value::Array(statistics.iter().map(|s| {
+        let mut column = json!({
+            "name": s.name(),
+            "type": s.infer_type(),
+            "nullable": s.nullable(),
+            "max_length": s.max_len,
+            "examples": s.top_values(3).into_iter().map(|(v, _)| v.clone()).collect::<Vec<_>>(),
+        });
+        if s.is_integer() {
+            // As a string, since it may not fit in a JSON number
+            column["max_magnitude"] = json!(s.max_integer_magnitude().to_string());
+        }
+        column
+    }).collect())
+}
+
+/// Frictionless Table Schema, see https://specs.frictionlessdata.io/table-schema/
+fn table_schema(statistics: &[CsvColumnStat]) -> Value {
+    json!({
+        "fields": statistics.iter().map(|s| {
+            let mut constraints = json!({ "required": !s.nullable() });
+            if s.is_boolean() {
+                // Table Schema matches these exactly, so list the spellings the file uses
+                let (true_values, false_values) = s.boolean_spellings();
+                return json!({
+                    "name": s.name(),
+                    "type": "boolean",
+                    "trueValues": true_values,
+                    "falseValues": false_values,
+                    "constraints": constraints,
+                });
+            }
+            if !s.is_numeric() {
+                constraints["maxLength"] = json!(s.max_len);
+            }
+            json!({
+                "name": s.name(),
+                "type": if s.is_integer() { "integer" } else if s.is_numeric() { "number" } else { "string" },
+                "constraints": constraints,
+            })
+        }).collect::<Vec<_>>()
+    })
+}
+
+fn table_name(options: &CsvOptions, schema_options: &CsvSchemaOptions) -> String {
+    schema_options.table.clone()
+        .or_else(|| options.input_file.as_ref()
+            .and_then(|f| Path::new(f).file_stem())
+            .map(|s| s.to_string_lossy().to_string()))
+        .unwrap_or_else(|| "stdin".to_string())
+}
+
+fn quote_identifier(name: &str, dialect: SqlDialect) -> String {
+    match dialect {
+        SqlDialect::Mysql => format!("`{}`", name.replace('`', "``")),
+        SqlDialect::Postgres | SqlDialect::Sqlite => format!("\"{}\"", name.replace('"', "\"\"")),
+    }
+}
+
+fn sql_type(statistic: &CsvColumnStat, dialect: SqlDialect) -> String {
+    // Before the integers, as a 0/1 column is inferred boolean
+    if statistic.is_boolean() {
+        return match dialect {
+            SqlDialect::Mysql => "TINYINT(1)",
+            SqlDialect::Postgres | SqlDialect::Sqlite => "BOOLEAN",
+        }.to_string();
+    }
+    if statistic.is_integer() {
+        let fits = |min: i64, max: i64| statistic.min_integer >= min.into() && statistic.max_integer <= max.into();
+        let int32 = fits(i32::MIN.into(), i32::MAX.into());
+        let int64 = fits(i64::MIN, i64::MAX);
+        return match dialect {
+            SqlDialect::Postgres if int32 => "INTEGER",
+            SqlDialect::Mysql if int32 => "INT",
+            SqlDialect::Postgres | SqlDialect::Mysql if int64 => "BIGINT",
+            SqlDialect::Sqlite if int64 => "INTEGER",
+            SqlDialect::Mysql => "DECIMAL(65, 0)",
+            SqlDialect::Postgres | SqlDialect::Sqlite => "NUMERIC",
+        }.to_string();
+    }
+    match (statistic.is_numeric(), dialect) {
+        (true, SqlDialect::Postgres) => "NUMERIC".to_string(),
+        (true, SqlDialect::Mysql) => "DOUBLE".to_string(),
+        (true, SqlDialect::Sqlite) => "REAL".to_string(),
+        (false, SqlDialect::Mysql) => format!("VARCHAR({})", statistic.max_len.max(1)),
+        (false, _) => "TEXT".to_string(),
+    }
+}
+
+fn create_table(table: &str, statistics: &[CsvColumnStat], dialect: SqlDialect, column_types: &[(String, String)]) -> Result<String, Box<dyn Error>> {
+    if let Some((name, _)) = column_types.iter().find(|(name, _)| !statistics.iter().any(|s| s.name() == name)) {
+        return Err(format!("--column-type names an unknown column: {}", name).into());
+    }
+    let columns = statistics.iter()
+        .map(|s| format!("    {} {}{}",
+                         quote_identifier(s.name(), dialect),
+                         column_types.iter().rev().find(|(name, _)| name == s.name())
+                             .map(|(_, sql_type)| sql_type.clone())
+                             .unwrap_or_else(|| sql_type(s, dialect)),
+                         if s.nullable() { "" } else { " NOT NULL" }))
+        .collect::<Vec<_>>();
+    Ok(format!("CREATE TABLE {} (\n{}\n);\n", quote_identifier(table, dialect), columns.join(",\n")))
+}
+
+fn sql_string(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// The client-side bulk load command matching `create_table` for the given dialect.
+fn load_command(table: &str, options: &CsvOptions, dialect: SqlDialect) -> Result<String, Box<dyn Error>> {
+    let config = options.config();
+    let delimiter = (config.delimiter as char).to_string();
+    let quote = (config.quote as char).to_string();
+    // Lines to skip: those above the header row and the header row itself
+    let skip = if config.has_headers { options.header_row.unwrap_or(1) } else { 0 };
+    let table = quote_identifier(table, dialect);
+
+    Ok(match (dialect, &options.input_file) {
+        // COPY only skips the header row itself, so the lines above it are cut off with tail
+        (SqlDialect::Postgres, Some(file)) if skip > 1 => postgres_copy(
+            &table, &format!("PROGRAM {}", sql_string(&format!("tail -n +{} {}", skip, shell_quote(file)))), options),
+        (SqlDialect::Postgres, None) if skip > 1 => return Err(Box::from("--load can't skip the lines above --header-row in standard input for postgres")),
+        (SqlDialect::Postgres, file) => postgres_copy(
+            &table, &file.as_deref().map(sql_string).unwrap_or_else(|| "STDIN".to_string()), options),
+        // MySQL reads backslashes as escapes unless told otherwise, where CSV doubles quotes
+        (SqlDialect::Mysql, Some(file)) => format!(
+            "LOAD DATA LOCAL INFILE {} INTO TABLE {} FIELDS TERMINATED BY {} OPTIONALLY ENCLOSED BY {} ESCAPED BY {} LINES TERMINATED BY '\\n'{};\n",
+            mysql_string(file),
+            table,
+            mysql_string(&delimiter),
+            mysql_string(&quote),
+            mysql_string(&config.escape.map(|c| (c as char).to_string()).unwrap_or_default()),
+            if skip > 0 { format!(" IGNORE {} LINES", skip) } else { String::new() }),
+        (SqlDialect::Sqlite, Some(file)) => format!(
+            ".mode csv\n.separator {}\n.import{} {} {}\n",
+            sqlite_argument(&delimiter),
+            if skip > 0 { format!(" --skip {}", skip) } else { String::new() },
+            sqlite_argument(file),
+            table),
+        (_, None) => return Err(Box::from("--load needs an input file for this dialect")),
+    })
+}
+
+/// A MySQL string literal, in which backslashes are escapes.
+fn mysql_string(s: &str) -> String {
+    sql_string(&s.replace('\\', "\\\\"))
+}
+
+/// Quotes a word for the shell that runs a `\copy ... FROM PROGRAM` command.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Quotes an argument to a sqlite3 dot-command, which unescapes backslashes inside double quotes.
+fn sqlite_argument(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn postgres_copy(table: &str, source: &str, options: &CsvOptions) -> String {
+    let config = options.config();
+    format!("\\copy {} FROM {} WITH (FORMAT csv, HEADER {}, DELIMITER {}, QUOTE {})\n",
+            table,
+            source,
+            config.has_headers,
+            sql_string(&(config.delimiter as char).to_string()),
+            sql_string(&(config.quote as char).to_string()))
+}
+
+/// Runs `create` and then a `\copy` from psql's standard input in one transaction, so a failed
+/// load leaves no table behind.
+fn psql_command(dsn: &str, create: &str, copy: &str) -> Command {
+    let mut command = Command::new("psql");
+    command.args(["--no-psqlrc", "--quiet", "--single-transaction", "--set", "ON_ERROR_STOP=1", "--dbname", dsn])
+        .args(["--command", create.trim_end(), "--command", copy.trim_end()])
+        .stdin(Stdio::piped());
+    command
+}
+
+/// Creates the table and streams the input file into it through psql, whose `\copy` uses the
+/// COPY protocol.
+fn load_postgres(dsn: &str, create: &str, table: &str, options: &CsvOptions) -> Result<(), Box<dyn Error>> {
+    if options.input_file.is_none() {
+        return Err(Box::from("--to postgres needs an input file, since it is read once for the types and again to load it"));
+    }
+    let copy = postgres_copy(&quote_identifier(table, SqlDialect::Postgres), "pstdin", options);
+    let mut child = psql_command(dsn, create, &copy).spawn()
+        .map_err(|e| format!("Unable to run psql: {}", e))?;
+
+    let mut stdin = child.stdin.take().ok_or("Unable to write to psql")?;
+    let copied = io::copy(&mut options.get_input_file()?, &mut stdin);
+    drop(stdin);
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(format!("psql failed ({}), so nothing was loaded", status).into());
+    }
+    copied?;
+    Ok(())
+}
+
+fn parse_column_type(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, sql_type)) if !name.is_empty() && !sql_type.trim().is_empty() => Ok((name.to_string(), sql_type.trim().to_string())),
+        _ => Err(format!("expected NAME=TYPE, got {}", s)),
+    }
+}
+
+fn parse_args(args: Vec<String>) -> (CsvOptions, CsvSchemaOptions) {
+    let executable_name = args[0].clone();
+
+    let command = args::boolean_args(global_args())
+        .display_name(executable_name)
+        .about("Infers a schema (column names, types, nullability, lengths) from CSV files.")
+        .arg(Arg::new("input_columns")
+            .short('c')
+            .long("columns")
+            .allow_negative_numbers(true)
+            .help("List of column names, offsets or ranges to include, e.g. \"1,id,-2,3-5. Negative offsets are interpreted as relative to the end (-1 is the last column). Ranges are inclusive and may be of names, e.g. first_name-email. Quote names containing commas or dashes, e.g. '\"revenue, net\",id'.")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("format")
+            .long("format")
+            .value_parser(["json", "table-schema", "sql"])
+            .default_value("json")
+            .help("Schema output format"))
+        .arg(Arg::new("dialect")
+            .long("dialect")
+            .value_parser(["postgres", "mysql", "sqlite"])
+            .help("SQL dialect for the CREATE TABLE statement. Implies --format sql"))
+        .arg(Arg::new("table")
+            .long("table")
+            .help("Table name for SQL output (defaults to the input file name)"))
+        .arg(Arg::new("sample")
+            .long("sample")
+            .value_parser(clap::value_parser!(usize))
+            .help("Only scan the first N records instead of the whole file"))
+        .arg(Arg::new("load")
+            .long("load")
+            .action(clap::ArgAction::SetTrue)
+            .help("Also print the \\copy / LOAD DATA / .import command that loads the input file into the table. Implies --format sql"))
+        .arg(Arg::new("column_types")
+            .long("column-type")
+            .value_parser(parse_column_type)
+            .action(clap::ArgAction::Append)
+            .help("Declare a column's SQL type instead of inferring it, e.g. id=UUID. Repeatable"))
+        .arg(Arg::new("to")
+            .long("to")
+            .value_parser(["postgres"])
+            .requires("dsn")
+            .conflicts_with_all(["dialect", "load"])
+            .help("Create the table and load the input file into this kind of database instead of printing the SQL. Uses psql's \\copy, in one transaction"))
+        .arg(Arg::new("dsn")
+            .long("dsn")
+            .requires("to")
+            .help("Connection string for --to, e.g. postgresql://user@host/db"));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let action = build_schema_options(&mut matches);
+
+    (args::build_options(matches), action)
+}
+
+fn build_schema_options(matches: &mut ArgMatches) -> CsvSchemaOptions {
+    let dialect = matches.remove_one::<String>("dialect");
+    let load = matches.remove_one::<bool>("load").unwrap_or(false);
+    // "postgres" is the only destination so far
+    let dsn = matches.remove_one::<String>("to").and(matches.remove_one::<String>("dsn"));
+    let format = match matches.remove_one::<String>("format").as_deref() {
+        _ if load || dialect.is_some() || dsn.is_some() => SchemaFormat::Sql,
+        Some("sql") => SchemaFormat::Sql,
+        Some("table-schema") => SchemaFormat::TableSchema,
+        _ => SchemaFormat::Json,
+    };
+
+    CsvSchemaOptions {
+        input_columns: matches.remove_many::<String>("input_columns")
+            .map(|v| v.flat_map(|s| csvutil::parse_column_list(&s))
+                .collect::<Vec<_>>()),
+        format,
+        dialect: match dialect.as_deref() {
+            Some("mysql") => SqlDialect::Mysql,
+            Some("sqlite") => SqlDialect::Sqlite,
+            _ => SqlDialect::Postgres,
+        },
+        table: matches.remove_one("table"),
+        sample: matches.remove_one("sample"),
+        load,
+        column_types: matches.remove_many("column_types").map(|v| v.collect()).unwrap_or_default(),
+        dsn,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn schema_options(format: SchemaFormat, dialect: SqlDialect) -> CsvSchemaOptions {
+        CsvSchemaOptions { input_columns: None, format, dialect, table: None, sample: None, load: false, column_types: vec![], dsn: None }
+    }
+
+    #[test]
+    fn test_process_csv_sql() {
+        let dir = csvutil::TestDir::new();
+        let output_file = &dir.path("test_output.sql");
+        let options = CsvOptions {
+            input_file: Some("test/test_input.csv".to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+
+        process_csv(&options, &schema_options(SchemaFormat::Sql, SqlDialect::Postgres)).expect("process_csv failed");
+
+        let expected_output = "\
+CREATE TABLE \"test_input\" (
+    \"col1\" INTEGER NOT NULL,
+    \"col2\" INTEGER NOT NULL,
+    \"col3\" INTEGER NOT NULL
+);
+";
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, expected_output);
+    }
+
+    #[test]
+    fn test_sql_type() {
+        let column = |values: &[&str]| {
+            let mut statistic = CsvColumnStat::new(0, "col".to_string());
+            values.iter().for_each(|v| statistic.add(Some(v)));
+            statistic
+        };
+        let small = column(&["-2147483648", "2147483647"]);
+        let large = column(&["2147483648"]);
+        let huge = column(&["9223372036854775808"]);
+        let decimal = column(&["1.5"]);
+        let flag = column(&["yes", "no", ""]);
+        assert_eq!([&small, &large, &huge, &decimal, &flag].map(|s| sql_type(s, SqlDialect::Postgres)), ["INTEGER", "BIGINT", "NUMERIC", "NUMERIC", "BOOLEAN"]);
+        assert_eq!([&small, &large, &huge, &decimal, &flag].map(|s| sql_type(s, SqlDialect::Mysql)), ["INT", "BIGINT", "DECIMAL(65, 0)", "DOUBLE", "TINYINT(1)"]);
+        assert_eq!([&small, &large, &huge, &decimal, &flag].map(|s| sql_type(s, SqlDialect::Sqlite)), ["INTEGER", "INTEGER", "NUMERIC", "REAL", "BOOLEAN"]);
+        assert_eq!(sql_type(&column(&["0", "1"]), SqlDialect::Postgres), "BOOLEAN");
+    }
+
+    #[test]
+    fn test_load_command() {
+        let options = CsvOptions {
+            input_file: Some("data/o'brien.csv".to_string()),
+            delimiter: Some(';'),
+            ..Default::default()
+        };
+
+        assert_eq!(load_command("t", &options, SqlDialect::Postgres).unwrap(),
+                   "\\copy \"t\" FROM 'data/o''brien.csv' WITH (FORMAT csv, HEADER true, DELIMITER ';', QUOTE '\"')\n");
+        assert_eq!(load_command("t", &options, SqlDialect::Mysql).unwrap(),
+                   "LOAD DATA LOCAL INFILE 'data/o''brien.csv' INTO TABLE `t` FIELDS TERMINATED BY ';' OPTIONALLY ENCLOSED BY '\"' ESCAPED BY '' LINES TERMINATED BY '\\n' IGNORE 1 LINES;\n");
+        assert_eq!(load_command("t", &options, SqlDialect::Sqlite).unwrap(),
+                   ".mode csv\n.separator \";\"\n.import --skip 1 \"data/o'brien.csv\" \"t\"\n");
+
+        let preamble_options = CsvOptions {
+            input_file: Some("my data/\"q\".csv".to_string()),
+            header_row: Some(3),
+            ..Default::default()
+        };
+        assert!(load_command("t", &preamble_options, SqlDialect::Mysql).unwrap().ends_with(" IGNORE 3 LINES;\n"));
+        assert_eq!(load_command("t", &preamble_options, SqlDialect::Postgres).unwrap(),
+                   "\\copy \"t\" FROM PROGRAM 'tail -n +3 ''my data/\"q\".csv''' WITH (FORMAT csv, HEADER true, DELIMITER ',', QUOTE '\"')\n");
+        let preamble_stdin_options = CsvOptions { input_file: None, ..preamble_options.clone() };
+        assert!(load_command("t", &preamble_stdin_options, SqlDialect::Postgres).is_err());
+        let escape_options = CsvOptions { input_file: Some("C:\\t.csv".to_string()), escape_char: Some('\\'), ..Default::default() };
+        assert!(load_command("t", &escape_options, SqlDialect::Mysql).unwrap()
+            .starts_with("LOAD DATA LOCAL INFILE 'C:\\\\t.csv' INTO TABLE `t` FIELDS TERMINATED BY ',' OPTIONALLY ENCLOSED BY '\"' ESCAPED BY '\\\\' "));
+        assert_eq!(load_command("t", &preamble_options, SqlDialect::Sqlite).unwrap(),
+                   ".mode csv\n.separator \",\"\n.import --skip 3 \"my data/\\\"q\\\".csv\" \"t\"\n");
+        let headerless_options = CsvOptions { input_file: Some("t.csv".to_string()), input_has_headers: Some(false), ..Default::default() };
+        assert!(load_command("t", &headerless_options, SqlDialect::Mysql).unwrap().ends_with("LINES TERMINATED BY '\\n';\n"));
+
+        let stdin_options = CsvOptions { input_has_headers: Some(false), ..Default::default() };
+        assert_eq!(load_command("t", &stdin_options, SqlDialect::Postgres).unwrap(),
+                   "\\copy \"t\" FROM STDIN WITH (FORMAT csv, HEADER false, DELIMITER ',', QUOTE '\"')\n");
+        assert!(load_command("t", &stdin_options, SqlDialect::Mysql).is_err());
+    }
+
+    #[test]
+    fn test_psql_command() {
+        let command = psql_command("postgresql://localhost/db", "CREATE TABLE \"t\" ();\n", "\\copy \"t\" FROM pstdin\n");
+        assert_eq!(command.get_program(), "psql");
+        assert_eq!(command.get_args().collect::<Vec<_>>(), [
+            "--no-psqlrc", "--quiet", "--single-transaction", "--set", "ON_ERROR_STOP=1", "--dbname", "postgresql://localhost/db",
+            "--command", "CREATE TABLE \"t\" ();", "--command", "\\copy \"t\" FROM pstdin",
+        ]);
+
+        let options = CsvOptions { input_file: None, ..Default::default() };
+        assert!(load_postgres("db", "", "t", &options).is_err());
+    }
+
+    #[test]
+    fn test_column_types() {
+        let dir = csvutil::TestDir::new();
+        let output_file = &dir.path("test_output.sql");
+        let options = CsvOptions {
+            input_file: Some("test/test_input.csv".to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+        let mut action = schema_options(SchemaFormat::Sql, SqlDialect::Postgres);
+        action.column_types = vec![parse_column_type("col2=SMALLINT").unwrap()];
+
+        process_csv(&options, &action).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert!(actual_output.contains("    \"col2\" SMALLINT NOT NULL,\n"));
+
+        action.column_types = vec![("missing".to_string(), "TEXT".to_string())];
+        assert!(process_csv(&options, &action).is_err());
+        assert!(parse_column_type("col2").is_err());
+    }
+
+    #[test]
+    fn test_process_csv_json() {
+        let dir = csvutil::TestDir::new();
+        let output_file = &dir.path("test_output.json");
+        let options = CsvOptions {
+            input_file: Some("test/test_input.csv".to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+        let mut action = schema_options(SchemaFormat::Json, SqlDialect::Postgres);
+        action.input_columns = Some(vec!["col2".to_string()]);
+        action.sample = Some(1);
+
+        process_csv(&options, &action).expect("process_csv failed");
+
+        let actual: Value = serde_json::from_str(&fs::read_to_string(output_file).expect("Unable to read output file"))
+            .expect("Invalid JSON");
+        assert_eq!(actual, json!([{
+            "name": "col2", "type": "Integer", "nullable": false, "max_length": 1, "examples": ["2"], "max_magnitude": "2"
+        }]));
+    }
+
+    #[test]
+    fn test_process_csv_table_schema_boolean() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_boolean.csv");
+        let output_file = &dir.path("test_output.json");
+        fs::write(input_file, "active,score\nYes,1\nno,0\n,1\n").expect("Unable to write input file");
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+
+        process_csv(&options, &schema_options(SchemaFormat::TableSchema, SqlDialect::Postgres)).expect("process_csv failed");
+
+        let actual: Value = serde_json::from_str(&fs::read_to_string(output_file).expect("Unable to read output file"))
+            .expect("Invalid JSON");
+        assert_eq!(actual, json!({"fields": [
+            {"name": "active", "type": "boolean", "trueValues": ["Yes"], "falseValues": ["no"], "constraints": {"required": false}},
+            {"name": "score", "type": "boolean", "trueValues": ["1"], "falseValues": ["0"], "constraints": {"required": true}},
+        ]}));
+    }
+
+    #[test]
+    fn test_build_args() {
+        let args = ["csvschema", "--dialect", "mysql", "--table", "t"]
+            .iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        let (_, action) = parse_args(args);
+
+        assert_eq!(action.format, SchemaFormat::Sql);
+        assert_eq!(action.dialect, SqlDialect::Mysql);
+        assert_eq!(action.table.unwrap(), "t");
+
+        let args = ["csvschema", "--to", "postgres", "--dsn", "postgresql://localhost/db", "--column-type", "id=UUID"]
+            .iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        let (_, action) = parse_args(args);
+
+        assert_eq!(action.format, SchemaFormat::Sql);
+        assert_eq!(action.dialect, SqlDialect::Postgres);
+        assert_eq!(action.dsn.unwrap(), "postgresql://localhost/db");
+        assert_eq!(action.column_types, vec![("id".to_string(), "UUID".to_string())]);
+    }
+}
@@ -0,0 +1,191 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+
+use crate::args::global_args;
+use clap::Arg;
+use options::CsvOptions;
+use std::error::Error;
+use std::io::BufRead;
+
+/// Rows `start` to `end`, 1-based and inclusive, taking every `step`th row.
+#[derive(Debug, Clone, PartialEq)]
+struct RowRange {
+    start: usize,
+    end: Option<usize>,
+    step: usize,
+}
+
+impl RowRange {
+    /// Parses `5`, `10-20` or `100-`, each optionally followed by `:step`.
+    fn parse(s: &str) -> Result<RowRange, String> {
+        let (range, step) = match s.split_once(':') {
+            Some((range, step)) => (range, step.parse::<usize>().ok().filter(|&n| n > 0)
+                .ok_or_else(|| format!("Invalid step in row range: {}", s))?),
+            None => (s, 1),
+        };
+        let (start, end) = if let Some(start) = range.strip_suffix('-') {
+            (start.parse::<usize>().ok(), None)
+        } else if let Some(range) = csvutil::parse_range(range) {
+            (Some(*range.start()), Some(*range.end()))
+        } else {
+            let row = range.parse::<usize>().ok();
+            (row, row)
+        };
+        match (start, end) {
+            (Some(start), end) if start > 0 && end.is_none_or(|end| end >= start) => Ok(RowRange { start, end, step }),
+            (Some(_), Some(_)) => Err(format!("Invalid row range. Must be increasing and start at 1: {}", s)),
+            _ => Err(format!("Invalid row range: {}", s)),
+        }
+    }
+
+    fn contains(&self, row: usize) -> bool {
+        row >= self.start && self.end.is_none_or(|end| row <= end) && (row - self.start).is_multiple_of(self.step)
+    }
+}
+
+struct CsvSliceOptions {
+    rows: Vec<RowRange>,
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>());
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> (CsvOptions, CsvSliceOptions) {
+    let executable_name = args[0].clone();
+
+    let command = args::raw_passthrough_args(args::in_place_args(args::contract_args(global_args())))
+        .display_name(executable_name)
+        .about("Keeps the records in the given row ranges.")
+        .arg(Arg::new("rows")
+            .short('r')
+            .long("rows")
+            .required(true)
+            .value_delimiter(',')
+            .value_parser(RowRange::parse)
+            .help("Rows to keep, counting from 1 after the header: 5, 10-20 or 100-, with :N to take every Nth, e.g. 1-1000:10. \
+                   Repeat or separate with commas to keep the rows of several ranges")
+            .action(clap::ArgAction::Append));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let action = CsvSliceOptions {
+        rows: matches.remove_many::<RowRange>("rows").map(|v| v.collect()).unwrap_or_default(),
+    };
+
+    (args::build_options(matches), action)
+}
+
+fn process_csv(options: &CsvOptions, slice_options: &CsvSliceOptions) -> Result<(), Box<dyn Error>> {
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    // Get the column headers, and the records with the bytes they were read as for --raw-passthrough
+    let (first_row, records) = csvutil::raw_records(options, input)?;
+
+    let output_has_headers = options.output_has_headers();
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+
+    if output_has_headers {
+        let all_indices = (0..first_row.len()).collect::<Vec<_>>();
+        csv_writer.write_record(csvutil::enumerate_output_headers(options.config().has_headers, first_row, &all_indices))?;
+    }
+
+    // Once past the end of every range, the rest of the input can be left unread
+    let last_row = slice_options.rows.iter().map(|r| r.end).collect::<Option<Vec<_>>>().and_then(|ends| ends.into_iter().max());
+
+    for (row, result) in records.enumerate() {
+        let row = row + 1;
+        if last_row.is_some_and(|last| row > last) {
+            break;
+        }
+        let (record, raw) = result?;
+        if slice_options.rows.iter().any(|r| r.contains(row)) {
+            match &raw {
+                Some(raw) => csv_writer.write_raw(raw, &record)?,
+                None => csv_writer.write_record(&record)?,
+            }
+        }
+    }
+
+    csv_writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_parse_row_range() {
+        assert_eq!(RowRange::parse("5"), Ok(RowRange { start: 5, end: Some(5), step: 1 }));
+        assert_eq!(RowRange::parse("10-20"), Ok(RowRange { start: 10, end: Some(20), step: 1 }));
+        assert_eq!(RowRange::parse("100-"), Ok(RowRange { start: 100, end: None, step: 1 }));
+        assert_eq!(RowRange::parse("1-1000:10"), Ok(RowRange { start: 1, end: Some(1000), step: 10 }));
+        assert!(RowRange::parse("20-10").is_err());
+        assert!(RowRange::parse("0").is_err());
+        assert!(RowRange::parse("1-10:0").is_err());
+        assert!(RowRange::parse("x").is_err());
+    }
+
+    #[test]
+    fn test_process_csv() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_slice.csv");
+        let output_file = &dir.path("test_output_slice.csv");
+        let mut input = "id\n".to_string();
+        for i in 1..=30 {
+            input += &format!("{}\n", i);
+        }
+        fs::write(input_file, input).expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+        let slice_options = CsvSliceOptions {
+            rows: ["2", "1-20:5", "28-"].iter().map(|r| RowRange::parse(r).unwrap()).collect(),
+        };
+
+        process_csv(&options, &slice_options).expect("process_csv failed");
+
+        let output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(output, "id\n1\n2\n6\n11\n16\n28\n29\n30\n");
+    }
+
+    #[test]
+    fn test_process_csv_raw_passthrough() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_slice_raw.csv");
+        let output_file = &dir.path("test_output_slice_raw.csv");
+        fs::write(input_file, "id,note\r\n1,\"a\"\r\n2,\"b\r\nc\"\r\n3,d\r\n").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            raw_passthrough: true,
+            ..Default::default()
+        };
+        let slice_options = CsvSliceOptions { rows: vec![RowRange::parse("2-").unwrap()] };
+        process_csv(&options, &slice_options).expect("process_csv failed");
+        let output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(output, "id,note\n2,\"b\r\nc\"\r\n3,d\r\n");
+    }
+}
@@ -0,0 +1,387 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+pub mod numbers;
+pub mod stats;
+
+use crate::args::global_args;
+use clap::Arg;
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use options::CsvOptions;
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Seek};
+
+/// How values are compared.
+#[derive(Clone, Debug, PartialEq)]
+enum Collation {
+    /// By their UTF-8 bytes
+    Bytes,
+    /// As numbers, with values that aren't numbers after them
+    Numeric,
+    /// Runs of digits as numbers and the rest ignoring case, so file2 comes before file10
+    Natural,
+    /// Ignoring accents, then case, with the alphabet of a language
+    Locale(String),
+}
+
+struct CsvSortOptions {
+    columns: Option<Vec<String>>,
+    reverse: bool,
+    collation: Collation,
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>());
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_collation(s: &str) -> Result<Collation, String> {
+    match s {
+        "bytes" => Ok(Collation::Bytes),
+        "numeric" => Ok(Collation::Numeric),
+        "natural" => Ok(Collation::Natural),
+        _ => match s.strip_prefix("locale:") {
+            Some(locale) if !locale.is_empty() => Ok(Collation::Locale(locale.to_string())),
+            _ => Err(format!("Expected natural, numeric, bytes or locale:NAME, e.g. locale:sv_SE: {}", s)),
+        },
+    }
+}
+
+fn parse_args(args: Vec<String>) -> (CsvOptions, CsvSortOptions) {
+    let executable_name = args[0].clone();
+
+    let command = args::memory_args(args::in_place_args(args::contract_args(global_args())))
+        .display_name(executable_name)
+        .about("Sorts records by the values of some columns.")
+        .after_help("--collate natural compares runs of digits by their value and the rest ignoring case, so that \
+                     v1.9 sorts before v1.10 and ID-7 before id-12. numeric puts numbers in order, followed by the \
+                     values that aren't numbers. locale:NAME, e.g. locale:de_DE, compares letters without their \
+                     accents first, then accents, then case with lowercase first. Danish, Norwegian, Swedish, \
+                     Finnish and Spanish put their extra letters where their alphabets do, such as å after z in \
+                     Swedish; other languages use the default order. The sort is stable. The input is read into \
+                     memory, unless it is larger than --max-memory: then it is sorted a part at a time into \
+                     temporary files, which are merged.")
+        .arg(Arg::new("columns")
+            .short('c')
+            .long("columns")
+            .allow_negative_numbers(true)
+            .help("Columns to sort by, using the same syntax as csvcut's --columns. Defaults to every column")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("reverse")
+            .short('r')
+            .long("reverse")
+            .help("Sort in descending order")
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("collate")
+            .long("collate")
+            .value_name("COLLATION")
+            .value_parser(parse_collation)
+            .default_value("bytes")
+            .help("How to compare values: bytes, natural, numeric or locale:NAME"));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let action = CsvSortOptions {
+        columns: matches.remove_many::<String>("columns")
+            .map(|v| v.flat_map(|s| csvutil::parse_column_list(&s))
+                .collect::<Vec<_>>()),
+        reverse: matches.remove_one::<bool>("reverse").unwrap_or(false),
+        collation: matches.remove_one("collate").unwrap_or(Collation::Bytes),
+    };
+
+    (args::build_options(matches), action)
+}
+
+/// A run of a value under natural collation. Digits come before other text, as in ASCII.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Chunk {
+    /// The digits without leading zeros, ordered by length first so the longer number is larger
+    Number(usize, String),
+    Text(String),
+}
+
+/// A value prepared for comparison, so it is parsed once rather than at every comparison.
+/// Every key ends with the value itself, which breaks ties between values that collate equal.
+#[derive(Debug, PartialEq)]
+enum SortKey {
+    Bytes(String),
+    Numeric(Option<f64>, String),
+    Natural(Vec<Chunk>, String),
+    /// Letter weights, the value in lowercase to compare accents, and the value with its case
+    /// swapped so lowercase comes first
+    Locale(Vec<u32>, String, String, String),
+}
+
+impl SortKey {
+    fn new(collation: &Collation, value: &str) -> SortKey {
+        match collation {
+            Collation::Bytes => SortKey::Bytes(value.to_string()),
+            Collation::Numeric => SortKey::Numeric(numbers::parse_number(value, false).filter(|n| !n.is_nan()), value.to_string()),
+            Collation::Natural => SortKey::Natural(natural_chunks(value), value.to_string()),
+            Collation::Locale(locale) => {
+                let swapped = value.chars().map(|c| if c.is_lowercase() { c.to_uppercase().next().unwrap_or(c) } else { c.to_lowercase().next().unwrap_or(c) }).collect();
+                SortKey::Locale(letter_weights(locale, value), value.to_lowercase(), swapped, value.to_string())
+            }
+        }
+    }
+
+    fn compare(&self, other: &SortKey) -> Ordering {
+        match (self, other) {
+            (SortKey::Bytes(a), SortKey::Bytes(b)) => a.cmp(b),
+            (SortKey::Numeric(a, a_value), SortKey::Numeric(b, b_value)) => match (a, b) {
+                (Some(a), Some(b)) => a.total_cmp(b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }.then_with(|| a_value.cmp(b_value)),
+            (SortKey::Natural(a, a_value), SortKey::Natural(b, b_value)) => a.cmp(b).then_with(|| a_value.cmp(b_value)),
+            (SortKey::Locale(a, a_lower, a_case, a_value), SortKey::Locale(b, b_lower, b_case, b_value)) => {
+                a.cmp(b).then_with(|| a_lower.cmp(b_lower)).then_with(|| a_case.cmp(b_case)).then_with(|| a_value.cmp(b_value))
+            }
+            _ => Ordering::Equal,
+        }
+    }
+}
+
+/// Splits a value into runs of digits and of other characters, the latter in lowercase.
+fn natural_chunks(value: &str) -> Vec<Chunk> {
+    let mut chunks = vec![];
+    let mut rest = value;
+    while let Some(first) = rest.chars().next() {
+        let is_digit = first.is_ascii_digit();
+        let end = rest.find(|c: char| c.is_ascii_digit() != is_digit).unwrap_or(rest.len());
+        let run = &rest[..end];
+        chunks.push(if is_digit {
+            let digits = run.trim_start_matches('0');
+            Chunk::Number(digits.len(), digits.to_string())
+        } else {
+            Chunk::Text(run.to_lowercase())
+        });
+        rest = &rest[end..];
+    }
+    chunks
+}
+
+/// Accented Latin letters and the letter each sorts with.
+const BASE_LETTERS: [(&str, char); 19] = [
+    ("àáâãäåāăą", 'a'), ("çćĉċč", 'c'), ("ďđð", 'd'), ("èéêëēĕėęě", 'e'), ("ĝğġģ", 'g'), ("ĥħ", 'h'),
+    ("ìíîïĩīĭįı", 'i'), ("ĵ", 'j'), ("ķ", 'k'), ("ĺļľŀł", 'l'), ("ñńņňŉ", 'n'), ("òóôõöøōŏő", 'o'),
+    ("ŕŗř", 'r'), ("śŝşšſ", 's'), ("ţťŧ", 't'), ("ùúûüũūŭůűų", 'u'), ("ŵ", 'w'), ("ýÿŷ", 'y'), ("źżž", 'z'),
+];
+
+/// The letters a language sorts as letters of their own after z, or after another letter.
+fn tailoring(locale: &str) -> &'static [(char, char, u32)] {
+    // (letter, the letter it follows, its place among the letters following that one)
+    match locale.split(['_', '-', '.']).next().unwrap_or_default() {
+        "da" | "nb" | "nn" | "no" => &[('æ', 'z', 1), ('ä', 'z', 1), ('ø', 'z', 2), ('ö', 'z', 2), ('å', 'z', 3)],
+        "sv" | "fi" => &[('å', 'z', 1), ('ä', 'z', 2), ('æ', 'z', 2), ('ö', 'z', 3), ('ø', 'z', 3)],
+        "es" => &[('ñ', 'n', 1)],
+        _ => &[],
+    }
+}
+
+/// The primary weights of a value's letters: the letter without its accent, or the place a
+/// language gives it. Ligatures such as æ and ß count as two letters elsewhere.
+fn letter_weights(locale: &str, value: &str) -> Vec<u32> {
+    let tailored = tailoring(locale);
+    let mut weights = vec![];
+    for c in value.chars().flat_map(char::to_lowercase) {
+        if let Some(&(_, after, place)) = tailored.iter().find(|(letter, _, _)| *letter == c) {
+            weights.push((after as u32) * 4 + place);
+            continue;
+        }
+        let expanded = match c {
+            'æ' => "ae",
+            'œ' => "oe",
+            'ß' => "ss",
+            'þ' => "th",
+            _ => "",
+        };
+        if !expanded.is_empty() {
+            weights.extend(expanded.chars().map(|c| c as u32 * 4));
+            continue;
+        }
+        let base = BASE_LETTERS.iter().find(|(accented, _)| accented.contains(c)).map_or(c, |&(_, base)| base);
+        weights.push(base as u32 * 4);
+    }
+    weights
+}
+
+type Row = (Vec<SortKey>, StringRecord);
+
+fn compare_rows(a: &[SortKey], b: &[SortKey], reverse: bool) -> Ordering {
+    let order = a.iter().zip(b).map(|(a, b)| a.compare(b)).find(|o| o.is_ne()).unwrap_or(Ordering::Equal);
+    if reverse { order.reverse() } else { order }
+}
+
+/// Roughly what a row costs in memory, for `--max-memory`: its text twice, as the keys copy
+/// the values they are made from, and the strings holding them.
+fn row_bytes(record: &StringRecord, n_keys: usize) -> u64 {
+    2 * record.as_slice().len() as u64 + csvutil::STRING_OVERHEAD * (1 + n_keys as u64)
+}
+
+/// Sorts the rows held and writes them to a temporary file, returned rewound to its start.
+fn spill(rows: &mut Vec<Row>, reverse: bool) -> Result<File, Box<dyn Error>> {
+    rows.sort_by(|(a, _), (b, _)| compare_rows(a, b, reverse));
+    let mut writer = WriterBuilder::new().flexible(true).from_writer(BufWriter::new(tempfile::tempfile()?));
+    for (_, record) in rows.drain(..) {
+        writer.write_record(&record)?;
+    }
+    let buffer = writer.into_inner().map_err(|e| e.into_error())?;
+    let mut file = buffer.into_inner().map_err(|e| e.into_error())?;
+    file.rewind()?;
+    Ok(file)
+}
+
+fn process_csv(options: &CsvOptions, sort_options: &CsvSortOptions) -> Result<(), Box<dyn Error>> {
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    let mut reader = csvutil::csv_reader(options, input);
+
+    // Get the column headers
+    let first_row = csvutil::column_names(options, reader.headers()?.clone());
+
+    let indices = match &sort_options.columns {
+        Some(_) => csvutil::select_column_indices(&first_row, &sort_options.columns)?,
+        None => (0..first_row.len()).collect(),
+    };
+    let sort_key = |record: &StringRecord| indices.iter().map(|&i| SortKey::new(&sort_options.collation, record.get(i).unwrap_or_default())).collect();
+
+    // Past --max-memory the rows held are sorted into a run on disk, and the runs merged
+    let mut rows: Vec<Row> = vec![];
+    let mut held = 0;
+    let mut runs = vec![];
+    for result in reader.records() {
+        let record = result?;
+        held += row_bytes(&record, indices.len());
+        rows.push((sort_key(&record), record));
+        if options.max_memory.is_some_and(|max| held > max) {
+            runs.push(spill(&mut rows, sort_options.reverse)?);
+            held = 0;
+        }
+    }
+    if !runs.is_empty() {
+        if options.verbose {
+            log::info(&format!("--max-memory: merging {} sorted runs from temporary files", runs.len() + 1));
+        }
+        runs.push(spill(&mut rows, sort_options.reverse)?);
+    }
+
+    let output_has_headers = options.output_has_headers();
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+
+    if output_has_headers {
+        let all_indices = (0..first_row.len()).collect::<Vec<_>>();
+        csv_writer.write_record(csvutil::enumerate_output_headers(options.config().has_headers, first_row, &all_indices))?;
+    }
+
+    if runs.is_empty() {
+        rows.sort_by(|(a, _), (b, _)| compare_rows(a, b, sort_options.reverse));
+        for (_, record) in rows {
+            csv_writer.write_record(&record)?;
+        }
+    } else {
+        let mut readers = runs.into_iter()
+            .map(|file| ReaderBuilder::new().has_headers(false).flexible(true).from_reader(BufReader::new(file)))
+            .collect::<Vec<_>>();
+        let mut heads = readers.iter_mut()
+            .map(|reader| reader.records().next().transpose().map(|r| r.map(|record| (sort_key(&record), record))))
+            .collect::<Result<Vec<Option<Row>>, _>>()?;
+        // The earliest run wins a tie, which keeps the sort stable
+        while let Some(i) = (0..heads.len()).filter(|&i| heads[i].is_some()).reduce(|best, i| {
+            let key = |i: usize| heads[i].as_ref().map(|(k, _)| k.as_slice()).unwrap_or_default();
+            if compare_rows(key(i), key(best), sort_options.reverse).is_lt() { i } else { best }
+        }) {
+            let next = readers[i].records().next().transpose()?.map(|record| (sort_key(&record), record));
+            let (_, record) = std::mem::replace(&mut heads[i], next).expect("filtered on being some");
+            csv_writer.write_record(&record)?;
+        }
+    }
+
+    csv_writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn sorted(collation: &str, values: &[&str]) -> Vec<String> {
+        let collation = parse_collation(collation).unwrap();
+        let mut keys = values.iter().map(|v| SortKey::new(&collation, v)).collect::<Vec<_>>();
+        keys.sort_by(SortKey::compare);
+        keys.into_iter().map(|k| match k {
+            SortKey::Bytes(v) | SortKey::Numeric(_, v) | SortKey::Natural(_, v) | SortKey::Locale(_, _, _, v) => v,
+        }).collect()
+    }
+
+    #[test]
+    fn test_collations() {
+        let versions = ["v1.10", "v1.9", "V1.2", "v1.02", "v10"];
+        assert_eq!(sorted("bytes", &versions), ["V1.2", "v1.02", "v1.10", "v1.9", "v10"]);
+        assert_eq!(sorted("natural", &versions), ["V1.2", "v1.02", "v1.9", "v1.10", "v10"]);
+        assert_eq!(sorted("numeric", &["10", "x", "-1.5", "", "2", "1,000"]), ["-1.5", "2", "10", "1,000", "", "x"]);
+
+        let names = ["Zoë", "Ångström", "Andersson", "Émile", "eve", "Eve", "Ödman", "Ñandú", "Nora"];
+        assert_eq!(sorted("locale:en_US", &names), ["Andersson", "Ångström", "Émile", "eve", "Eve", "Ñandú", "Nora", "Ödman", "Zoë"]);
+        assert_eq!(sorted("locale:sv_SE", &names), ["Andersson", "Émile", "eve", "Eve", "Ñandú", "Nora", "Zoë", "Ångström", "Ödman"]);
+        assert_eq!(sorted("locale:es", &names), ["Andersson", "Ångström", "Émile", "eve", "Eve", "Nora", "Ñandú", "Ödman", "Zoë"]);
+
+        assert!(parse_collation("locale:").is_err());
+        assert!(parse_collation("alphabetical").is_err());
+    }
+
+    #[test]
+    fn test_process_csv() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_sort.csv");
+        let output_file = &dir.path("test_output_sort.csv");
+        fs::write(input_file, "file,size\nfile10,3\nfile2,20\nFile1,3\n").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+        let mut sort_options = CsvSortOptions { columns: None, reverse: false, collation: Collation::Natural };
+
+        process_csv(&options, &sort_options).expect("process_csv failed");
+        let output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(output, "file,size\nFile1,3\nfile2,20\nfile10,3\n");
+
+        // Stable, so rows with equal sizes keep their order
+        sort_options.columns = Some(vec!["size".to_string()]);
+        sort_options.collation = Collation::Numeric;
+        sort_options.reverse = true;
+        process_csv(&options, &sort_options).expect("process_csv failed");
+        let output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(output, "file,size\nfile2,20\nfile10,3\nFile1,3\n");
+
+        // Room for one row at a time, so every row is a run of its own on disk
+        let options = CsvOptions { max_memory: Some(1), ..options };
+        process_csv(&options, &sort_options).expect("process_csv failed");
+        let output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(output, "file,size\nfile2,20\nfile10,3\nFile1,3\n");
+        sort_options.columns = None;
+        sort_options.collation = Collation::Natural;
+        sort_options.reverse = false;
+        process_csv(&options, &sort_options).expect("process_csv failed");
+        let output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(output, "file,size\nFile1,3\nfile2,20\nfile10,3\n");
+    }
+}
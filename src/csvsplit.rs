@@ -0,0 +1,197 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+
+use crate::args::global_args;
+use clap::Arg;
+use options::CsvOptions;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::BufRead;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Period {
+    Day,
+    Week,
+    Month,
+}
+
+impl Period {
+    /// The name of the period a day falls in: `2024-03-07`, the ISO week `2024-W10`, or `2024-03`.
+    fn name(&self, days: i64) -> String {
+        let (year, month, day) = dates::civil_from_days(days);
+        match self {
+            Period::Day => format!("{:04}-{:02}-{:02}", year, month, day),
+            Period::Month => format!("{:04}-{:02}", year, month),
+            Period::Week => {
+                // An ISO week belongs to the year its Thursday falls in
+                let weekday = (days + 3).rem_euclid(7);
+                let thursday = days - weekday + 3;
+                let (week_year, _, _) = dates::civil_from_days(thursday);
+                let week = (thursday - dates::days_from_civil(week_year, 1, 1)) / 7 + 1;
+                format!("{:04}-W{:02}", week_year, week)
+            }
+        }
+    }
+}
+
+struct CsvSplitOptions {
+    column: String,
+    prefix: String,
+    by_period: Option<Period>,
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>());
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> (CsvOptions, CsvSplitOptions) {
+    let executable_name = args[0].clone();
+
+    let command = args::contract_args(global_args())
+        .display_name(executable_name)
+        .about("Splits CSV files into one file per value of a column, each with the header row.")
+        .arg(Arg::new("column")
+            .short('c')
+            .long("column")
+            .required(true)
+            .allow_negative_numbers(true)
+            .help("Column to split by, as a name or 1-based offset"))
+        .arg(Arg::new("prefix")
+            .long("prefix")
+            .default_value("split")
+            .help("Start of the output file names, which are PREFIX-VALUE.csv. May include a directory. With --write-manifest, each file gets a PREFIX-VALUE.csv.manifest.json"))
+        .arg(Arg::new("by_period")
+            .long("by-period")
+            .value_parser(["day", "week", "month"])
+            .help("Treat the column as dates and split by the day, ISO week or month they fall in, e.g. PREFIX-2024-03.csv"));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let action = CsvSplitOptions {
+        column: matches.remove_one("column").expect("--column is required"),
+        prefix: matches.remove_one("prefix").expect("--prefix has a default"),
+        by_period: match matches.remove_one::<String>("by_period").as_deref() {
+            Some("day") => Some(Period::Day),
+            Some("week") => Some(Period::Week),
+            Some("month") => Some(Period::Month),
+            _ => None,
+        },
+    };
+
+    (args::build_options(matches), action)
+}
+
+/// Makes a value safe to use in a file name.
+fn file_name_part(value: &str) -> String {
+    if value.is_empty() {
+        return "empty".to_string();
+    }
+    value.chars().map(|c| if c.is_alphanumeric() || "-_.".contains(c) { c } else { '_' }).collect()
+}
+
+fn process_csv(options: &CsvOptions, split_options: &CsvSplitOptions) -> Result<(), Box<dyn Error>> {
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    let mut reader = csvutil::csv_reader(options, input);
+
+    // Get the column headers
+    let first_row = csvutil::column_names(options, reader.headers()?.clone());
+
+    let column = csvutil::select_column_index(&first_row, &split_options.column)?;
+
+    let output_has_headers = options.output_has_headers();
+    let all_indices = (0..first_row.len()).collect::<Vec<_>>();
+    let headers = csvutil::enumerate_output_headers(options.config().has_headers, first_row, &all_indices);
+
+    let mut writers: HashMap<String, writer::CsvWriter> = HashMap::new();
+    for (row, result) in reader.records().enumerate() {
+        let record = result?;
+        let value = record.get(column).unwrap_or_default();
+        let part = match split_options.by_period {
+            Some(period) => {
+                let days = dates::parse_days(value)
+                    .ok_or_else(|| format!("Row {}: '{}' is not a date", row + 1, value))?;
+                period.name(days)
+            }
+            None => file_name_part(value),
+        };
+
+        if !writers.contains_key(&part) {
+            // Each file is written like -o, so --max-output-bytes applies to each, and each gets its own manifest
+            let file = format!("{}-{}.csv", split_options.prefix, part);
+            let manifest_file = options.manifest_file.as_ref().map(|_| format!("{}.manifest.json", file));
+            let file_options = CsvOptions { output_file: Some(file), manifest_file, ..options.clone() };
+            let mut csv_writer = writer::CsvWriter::new(&file_options)?;
+            if output_has_headers {
+                csv_writer.write_record(&headers)?;
+            }
+            writers.insert(part.clone(), csv_writer);
+        }
+        writers.get_mut(&part).expect("inserted above").write_record(&record)?;
+    }
+
+    for (_, csv_writer) in writers {
+        csv_writer.finish()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_period_name() {
+        let days = dates::parse_days("2024-03-07").unwrap();
+        assert_eq!(Period::Day.name(days), "2024-03-07");
+        assert_eq!(Period::Week.name(days), "2024-W10");
+        assert_eq!(Period::Month.name(days), "2024-03");
+        // 2021-01-03 is a Sunday in the last ISO week of 2020
+        assert_eq!(Period::Week.name(dates::parse_days("2021-01-03").unwrap()), "2020-W53");
+        assert_eq!(Period::Week.name(dates::parse_days("2024-12-30").unwrap()), "2025-W01");
+    }
+
+    #[test]
+    fn test_process_csv() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_split.csv");
+        fs::write(input_file, "region,day\nnorth,2024-03-07\nsouth east,2024-04-01T09:00:00Z\nnorth,2024-03-30\n")
+            .expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            ..Default::default()
+        };
+        let mut split_options = CsvSplitOptions { column: "region".to_string(), prefix: dir.path("test_split"), by_period: None };
+
+        process_csv(&options, &split_options).expect("process_csv failed");
+        assert_eq!(fs::read_to_string(dir.path("test_split-north.csv")).expect("Unable to read output file"),
+                   "region,day\nnorth,2024-03-07\nnorth,2024-03-30\n");
+        assert_eq!(fs::read_to_string(dir.path("test_split-south_east.csv")).expect("Unable to read output file"),
+                   "region,day\nsouth east,2024-04-01T09:00:00Z\n");
+
+        split_options.column = "day".to_string();
+        split_options.by_period = Some(Period::Month);
+        process_csv(&options, &split_options).expect("process_csv failed");
+        assert_eq!(fs::read_to_string(dir.path("test_split-2024-03.csv")).expect("Unable to read output file"),
+                   "region,day\nnorth,2024-03-07\nnorth,2024-03-30\n");
+        assert_eq!(fs::read_to_string(dir.path("test_split-2024-04.csv")).expect("Unable to read output file"),
+                   "region,day\nsouth east,2024-04-01T09:00:00Z\n");
+    }
+}
@@ -0,0 +1,290 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+
+use crate::args::global_args;
+use clap::Arg;
+use options::CsvOptions;
+use regex::Regex;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+struct CsvStackOptions {
+    /// The files to stack, with None for standard input
+    inputs: Vec<Option<String>>,
+    /// Whether directories among the inputs are searched for CSV files
+    recursive: bool,
+    /// Files found in directories must match it; its named groups become columns
+    filename_regex: Option<Regex>,
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>());
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> (CsvOptions, CsvStackOptions) {
+    let executable_name = args[0].clone();
+
+    let command = args::column_order_args(args::contract_args(global_args()))
+        .display_name(executable_name)
+        .about("Stacks the records of several CSV files. Columns are matched by name, and the output has every input's columns.")
+        .after_help("With --recursive, directories are searched for .csv files, in name order. With --filename-regex, \
+                     only files whose path below the directory matches are stacked, and each named group, like \
+                     (?P<date>\\d{4}-\\d{2}-\\d{2}), becomes a column before the files' own, holding what it matched \
+                     in the file's path. Files named directly are always stacked, with empty values where their path \
+                     doesn't match.")
+        .mut_arg("input", |a| a.num_args(1..).help("Input files or directories to stack, or - for standard input"))
+        .arg(Arg::new("recursive")
+            .short('R')
+            .long("recursive")
+            .action(clap::ArgAction::SetTrue)
+            .help("Stack the .csv files in input directories and the directories below them"))
+        .arg(Arg::new("filename_regex")
+            .long("filename-regex")
+            .value_parser(|s: &str| Regex::new(s).map_err(|e| e.to_string()))
+            .help("Regular expression file paths must match; its named groups are added as columns"));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let action = CsvStackOptions {
+        inputs: matches.get_many::<String>("input")
+            .map(|v| v.map(|f| Some(f.clone()).filter(|f| f != "-")).collect())
+            .unwrap_or_else(|| vec![None]),
+        recursive: matches.get_flag("recursive"),
+        filename_regex: matches.remove_one("filename_regex"),
+    };
+
+    (args::build_options(matches), action)
+}
+
+/// The files to stack, with the directories among the inputs replaced by the CSV files in them.
+fn expand_inputs(stack_options: &CsvStackOptions) -> Result<Vec<Option<String>>, Box<dyn Error>> {
+    let mut files = vec![];
+    for input in &stack_options.inputs {
+        match input {
+            Some(path) if Path::new(path).is_dir() => {
+                if !stack_options.recursive {
+                    return Err(format!("{} is a directory; use --recursive to stack the CSV files in it", path).into());
+                }
+                let mut found = vec![];
+                find_csv_files(Path::new(path), Path::new(path), stack_options.filename_regex.as_ref(), &mut found)?;
+                files.extend(found.into_iter().map(Some));
+            }
+            _ => files.push(input.clone()),
+        }
+    }
+    Ok(files)
+}
+
+/// Adds the .csv files below `dir` whose path from `root` matches the regex, in name order.
+/// Hidden files and directories are skipped.
+fn find_csv_files(root: &Path, dir: &Path, regex: Option<&Regex>, found: &mut Vec<String>) -> Result<(), Box<dyn Error>> {
+    let mut entries = fs::read_dir(dir)
+        .map_err(|e| format!("Unable to read directory {}: {}", dir.display(), e))?
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            find_csv_files(root, &path, regex, found)?;
+        } else if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("csv")) {
+            let relative = path.strip_prefix(root).unwrap_or(&path).to_string_lossy();
+            if regex.is_none_or(|r| r.is_match(&relative)) {
+                found.push(path.to_string_lossy().to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The values of the regex's named groups in a file's path, empty where they didn't match.
+fn filename_values(regex: &Regex, input_file: Option<&str>) -> Vec<String> {
+    let captures = input_file.and_then(|f| regex.captures(f));
+    regex.capture_names().flatten()
+        .map(|name| captures.as_ref().and_then(|c| c.name(name)).map_or("", |m| m.as_str()).to_string())
+        .collect()
+}
+
+/// Adds a file's columns to the union of all the columns, returning where each of them went.
+/// The nth column of a name matches the nth column of that name already there.
+fn merge_columns(union: &mut Vec<String>, names: &[String]) -> Vec<usize> {
+    let mut used = vec![false; union.len()];
+    names.iter().map(|name| {
+        match (0..union.len()).find(|&i| !used[i] && union[i] == *name) {
+            Some(i) => {
+                used[i] = true;
+                i
+            }
+            None => {
+                union.push(name.clone());
+                used.push(true);
+                union.len() - 1
+            }
+        }
+    }).collect()
+}
+
+fn process_csv(options: &CsvOptions, stack_options: &CsvStackOptions) -> Result<(), Box<dyn Error>> {
+    let inputs = expand_inputs(stack_options)?;
+    let group_names = stack_options.filename_regex.as_ref()
+        .map(|r| r.capture_names().flatten().map(str::to_string).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    // Every header is needed before the first record can be written. Files are opened again
+    // for their records, so a large directory doesn't hold every file open at once.
+    let input_options = |n: usize, input_file: &Option<String>| {
+        // --verify-sha256 can only describe one input, the first
+        let verify_sha256 = if n == 0 { options.verify_sha256.clone() } else { None };
+        CsvOptions { input_file: input_file.clone(), verify_sha256, ..options.clone() }
+    };
+    let mut union: Vec<String> = vec![];
+    let mut inputs_positions = vec![];
+    let mut stdin_reader = None;
+    for (n, input_file) in inputs.iter().enumerate() {
+        let mut reader = csvutil::csv_reader(options, input_options(n, input_file).get_input_file()?);
+        let first_row = csvutil::column_names(options, reader.headers()?.clone());
+        let names = first_row.iter().map(|h| h.to_string()).collect::<Vec<_>>();
+        inputs_positions.push(merge_columns(&mut union, &names));
+        if input_file.is_none() {
+            stdin_reader = Some(reader);
+        }
+    }
+
+    let names = group_names.iter().chain(&union).cloned().collect::<Vec<_>>();
+    let order = csvutil::column_order(options, &names);
+
+    let output_has_headers = options.output_has_headers();
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+
+    if output_has_headers {
+        csv_writer.write_record(order.iter().map(|(name, _)| name))?;
+    }
+
+    let mut values = vec![String::new(); names.len()];
+    for (n, (input_file, positions)) in inputs.iter().zip(&inputs_positions).enumerate() {
+        let mut reader = match input_file {
+            None => stdin_reader.take().ok_or("Standard input can only be stacked once")?,
+            Some(_) => csvutil::csv_reader(options, input_options(n, input_file).get_input_file()?),
+        };
+        let file_values = stack_options.filename_regex.as_ref()
+            .map(|r| filename_values(r, input_file.as_deref()))
+            .unwrap_or_default();
+        for result in reader.records() {
+            let record = result?;
+            values.iter_mut().for_each(|v| v.clear());
+            for (value, file_value) in values.iter_mut().zip(&file_values) {
+                value.push_str(file_value);
+            }
+            for (value, &i) in record.iter().zip(positions) {
+                values[group_names.len() + i].push_str(value);
+            }
+            csv_writer.write_record(order.iter().map(|(_, i)| i.map_or("", |i| values[i].as_str())))?;
+        }
+    }
+
+    csv_writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_merge_columns() {
+        let mut union = vec![];
+        assert_eq!(merge_columns(&mut union, &["id".to_string(), "name".to_string()]), vec![0, 1]);
+        assert_eq!(merge_columns(&mut union, &["name".to_string(), "x".to_string(), "x".to_string()]), vec![1, 2, 3]);
+        assert_eq!(union, vec!["id", "name", "x", "x"]);
+    }
+
+    #[test]
+    fn test_process_csv() {
+        let dir = csvutil::TestDir::new();
+        let input_files = [dir.path("test_input_stack_1.csv"), dir.path("test_input_stack_2.csv")];
+        let output_file = &dir.path("test_output_stack.csv");
+        fs::write(&input_files[0], "id,name\n1,Jane\n").expect("Unable to write input file");
+        fs::write(&input_files[1], "name,city,id\nBob,Leeds,2\n").expect("Unable to write input file");
+
+        let mut options = CsvOptions {
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+        let stack_options = CsvStackOptions {
+            inputs: input_files.iter().map(|f| Some(f.to_string())).collect(),
+            recursive: false,
+            filename_regex: None,
+        };
+
+        process_csv(&options, &stack_options).expect("process_csv failed");
+        let output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(output, "id,name,city\n1,Jane,\n2,Bob,Leeds\n");
+
+        options.sort_columns = true;
+        process_csv(&options, &stack_options).expect("process_csv failed");
+        let output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(output, "city,id,name\n,1,Jane\nLeeds,2,Bob\n");
+
+        options.sort_columns = false;
+        options.columns_from = Some(vec!["name".to_string(), "zip".to_string()]);
+        process_csv(&options, &stack_options).expect("process_csv failed");
+        let output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(output, "name,zip,id,city\nJane,,1,\nBob,,2,Leeds\n");
+    }
+
+    #[test]
+    fn test_process_csv_recursive() {
+        let dir = csvutil::TestDir::new();
+        let logs = dir.path("logs");
+        fs::create_dir_all(format!("{}/2024/.old", logs)).expect("Unable to create directories");
+        fs::write(format!("{}/2024/access-2024-03-08.csv", logs), "path,status\n/b,404\n").expect("Unable to write input file");
+        fs::write(format!("{}/2024/.old/access-2024-01-01.csv", logs), "path,status\n/x,200\n").expect("Unable to write input file");
+        fs::write(format!("{}/access-2024-03-07.csv", logs), "path,status\n/a,200\n").expect("Unable to write input file");
+        fs::write(format!("{}/notes.csv", logs), "path\n/n\n").expect("Unable to write input file");
+        fs::write(format!("{}/access-2024-03-09.txt", logs), "path,status\n/t,200\n").expect("Unable to write input file");
+        let output_file = &dir.path("test_output_stack.csv");
+
+        let options = CsvOptions {
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+        let mut stack_options = CsvStackOptions {
+            inputs: vec![Some(logs.clone())],
+            recursive: false,
+            filename_regex: Some(Regex::new(r"access-(?P<date>\d{4}-\d{2}-\d{2})").unwrap()),
+        };
+
+        assert_eq!(process_csv(&options, &stack_options).map_err(|e| e.to_string()),
+                   Err(format!("{} is a directory; use --recursive to stack the CSV files in it", logs)));
+
+        stack_options.recursive = true;
+        process_csv(&options, &stack_options).expect("process_csv failed");
+        let output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(output, "date,path,status\n2024-03-08,/b,404\n2024-03-07,/a,200\n");
+
+        stack_options.filename_regex = None;
+        process_csv(&options, &stack_options).expect("process_csv failed");
+        let output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(output, "path,status\n/b,404\n/a,200\n/n,\n");
+    }
+}
@@ -1,261 +1,314 @@
-use std::fmt::write;
 use crate::args::global_args;
 use crate::options::CsvOptions;
 use clap::Arg;
+use csv::StringRecord;
 use clap::ArgAction::SetTrue;
-use multiset::HashMultiSet;
-use priority_queue::DoublePriorityQueue;
-use std::fs::File;
+use crate::stats::{BooleanValues, CsvColumnStat};
+use crate::transform::FloatFormat;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
 use std::io;
-use std::io::{BufRead, Write};
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
 
 pub mod csvutil;
+pub mod numbers;
+pub mod reader;
+pub mod dates;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
 pub mod args;
 pub mod options;
+pub mod stats;
+pub mod transform;
+pub mod writer;
 
-struct CsvStatOptions { input_columns: Option<Vec<String>>, csv: bool }
-
-struct CsvColumnStat {
-    idx: usize,
-    name: String,
-    n: u64,
-    n_numeric: u64,
-    sum: f64,
-    mean: f64,
-    v_k: f64,
-    variance: f64,
-    n_zeros: u64,
-    min: f64,
-    max: f64,
-    min_str: String,
-    max_str: String,
-    n_missing: u64,
-    n_empty: u64,
-    distinct: HashMultiSet<String>,
-    max_len: usize
-}
-
-impl CsvColumnStat {
-    pub(crate) fn freq(&self) -> Vec<String> {
-        let mut v: Vec<String> = vec![];
-        let mut p: DoublePriorityQueue<&String, usize> = DoublePriorityQueue::new();
-        self.distinct.iter().for_each(|d| {
-            p.push(d, self.distinct.count_of(d));
-            while p.len() > 100 {
-                p.pop_min();
-            }
-        });
+struct CsvStatOptions { input_columns: Option<Vec<String>>, csv: bool, jobs: usize, cache_dir: Option<String>, include_empty: bool, strict_numeric: bool, float_format: Option<FloatFormat> }
 
-        while !p.is_empty() {
-            let (d, c) = p.pop_min().unwrap();
-            v.push(d.clone() + " (" + &c.to_string() + "X)");
-        }
+fn main() -> Result<(), String> {
+    let (options, stat_options) = parse_args(std::env::args().collect::<Vec<_>>());
 
-        v
+    if options.self_bench {
+        let bench_options = CsvStatOptions { input_columns: None, csv: true, jobs: stat_options.jobs, cache_dir: None, include_empty: false, strict_numeric: false, float_format: None };
+        return csvutil::self_bench(|o| process_csv(o, &bench_options)).map_err(|e| e.to_string());
     }
+
+    options.finish(process_csv(&options, &stat_options))
 }
 
-impl CsvColumnStat {
-    pub(crate) fn stdev(&self) -> f64 {
-        if self.n_numeric < 2 {
-            return 0.0;
+fn process_csv(options: &CsvOptions, stat_options: &CsvStatOptions) -> Result<(), Box<dyn std::error::Error>> {
+    // Standard input and pipes can't be hashed before they're read, so only regular files are cached.
+    // A cache hit never reads the input, so checks on it and the --summary counts need a real run.
+    // The size guards and the engine are in the cache key instead: an entry is only written once
+    // the input passed them
+    let checks_input = options.verify_sha256.is_some() || !options.assertions.is_empty() || options.metrics.is_some();
+    let cache_file = match (&stat_options.cache_dir, &options.input_file) {
+        (Some(_), _) if checks_input => {
+            if options.verbose {
+                log::info("Not using --cache-dir, as --verify-sha256, --assert-*, --summary or --log-json needs the input read");
+            }
+            None
         }
-        return (self.v_k / (self.n_numeric as f64 - 1.0)).sqrt();
-    }
+        (Some(dir), Some(input_file)) if csvutil::is_regular_file(input_file) => Some(cache_path(dir, input_file, options, stat_options)?),
+        _ => None,
+    };
 
-    pub(crate) fn median(&self) -> f64 {
-        if self.n_numeric < 2 {
-            return 0.0;
+    let cached = cache_file.as_ref().and_then(|f| read_cache(f).ok());
+    let rows = match cached {
+        Some(rows) => {
+            if options.verbose {
+                log::info(&format!("Using cached statistics from {}", cache_file.as_ref().unwrap().display()));
+            }
+            rows
         }
-        return 0.0;
-    }
-
-    pub(crate) fn mean(&self) -> f64 {
-        if self.sum == 0.0 {
-            return 0.0;
+        None => {
+            let rows = statistics_rows(options, stat_options)?;
+            if let Some(cache_file) = &cache_file {
+                write_cache(cache_file, &rows)?;
+            }
+            rows
         }
+    };
 
-        self.n_numeric as f64 / self.sum
-    }
-
-    pub(crate) fn unique(&self) -> usize {
-        self.distinct.len()
-    }
-
-    pub(crate) fn nulls(&self) -> bool {
-        self.n_missing > 0
-    }
-    pub(crate) fn name(&self) -> &String {
-        &self.name
-    }
-
-    pub fn infer_type(&self) -> String {
-        if self.is_numeric() {
-            "numeric".to_string()
-        } else {
-            "string".to_string()
-        }
+    // The output's header row is the statistics', whatever the input had
+    let output_has_headers = options.config().report_headers;
+    let output_options = CsvOptions { output_headers: Some(output_has_headers), ..options.clone() };
+    let mut csv_writer = writer::CsvWriter::new(&output_options)?;
+    for row in rows.iter().skip(usize::from(!output_has_headers)) {
+        csv_writer.write_record(row)?;
     }
+    csv_writer.finish()?;
 
-    fn is_numeric(&self) -> bool {
-        (self.n_numeric == self.n - self.n_missing) && (self.n_numeric > 0)
-    }
+    Ok(())
+}
 
-    pub fn max(&self) -> String {
-        if self.is_numeric() {
-            String::from(self.max.to_string())
-        } else {
-            self.max_str.clone()
-        }
-    }
+fn read_cache(cache_file: &Path) -> Result<Vec<Vec<String>>, Box<dyn std::error::Error>> {
+    let mut reader = csv::ReaderBuilder::new().has_headers(false).flexible(true).from_path(cache_file)?;
+    let rows = reader.records()
+        .map(|r| r.map(|r| r.iter().map(String::from).collect()))
+        .collect::<Result<_, _>>()?;
+    Ok(rows)
+}
 
-    pub fn min(&self) -> String {
-        if self.is_numeric() {
-            String::from(self.min.to_string())
-        } else {
-            self.min_str.clone()
-        }
+fn write_cache(cache_file: &Path, rows: &[Vec<String>]) -> Result<(), Box<dyn std::error::Error>> {
+    // Written aside and renamed so a concurrent run never reads a partial entry
+    let partial = cache_file.with_extension("partial");
+    let mut writer = csv::WriterBuilder::new().flexible(true).from_path(&partial)?;
+    for row in rows {
+        writer.write_record(row)?;
     }
+    writer.flush()?;
+    fs::rename(&partial, cache_file)?;
+    Ok(())
 }
-fn main() -> Result<(), String> {
-    let (options, stat_options) = parse_args(std::env::args().collect::<Vec<_>>());
 
-    match process_csv(&options, &stat_options) {
-        Ok(()) => Ok(()),
-        Err(e) => Err(e.to_string()),
-    }
+/// The cache entry for a file: its contents, the version and build and every option that changes the output are hashed into the name.
+fn cache_path(dir: &str, input_file: &str, options: &CsvOptions, stat_options: &CsvStatOptions) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(input_file)?, &mut hasher)?;
+    let mut header_aliases = options.header_aliases.iter().flatten().collect::<Vec<_>>();
+    header_aliases.sort();
+    hasher.update(format!("{}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{}|{}|{}|{:?}|{:?}|{:?}|{}|{}|{:?}|{}|{:?}|{:?}|{:?}|{}",
+                          args::VERSION,
+                          options.delimiter,
+                          options.input_has_headers,
+                          options.output_headers,
+                          options.quote_char,
+                          options.escape_char,
+                          options.trim,
+                          options.flexible,
+                          options.comment_char,
+                          options.header_row,
+                          stat_options.input_columns,
+                          stat_options.csv,
+                          options.strict_rfc4180,
+                          stat_options.include_empty,
+                          stat_options.strict_numeric,
+                          options.true_values,
+                          options.false_values,
+                          stat_options.float_format,
+                          options.normalize_newlines,
+                          options.skip_blank_lines,
+                          header_aliases,
+                          options.drop_empty_rows,
+                          options.drop_if_empty,
+                          options.max_field_size,
+                          options.max_record_size,
+                          options.fast_engine));
+    fs::create_dir_all(dir)?;
+    Ok(Path::new(dir).join(format!("{:x}.csv", hasher.finalize())))
 }
 
-fn process_csv(options: &CsvOptions, stat_options: &CsvStatOptions) -> Result<(), Box<dyn std::error::Error>> {
+/// The statistics table, header row first.
+fn statistics_rows(options: &CsvOptions, stat_options: &CsvStatOptions) -> Result<Vec<Vec<String>>, Box<dyn std::error::Error>> {
     let input:Box<dyn BufRead> = options.get_input_file()?;
 
-    let mut reader = csvutil::csv_reader(options, input);
+    let mut reader = csvutil::record_reader(options, input)?;
 
     // Get the column headers
-    let first_row = reader.headers()?.clone();
+    let first_row = csvutil::column_names(options, reader.headers()?);
 
     // Determine which columns to include
     let selected_indices: Vec<usize> = csvutil::select_column_indices(&first_row, &stat_options.input_columns)?;
 
-    let mut csv_file_handle:Box<dyn io::Write>;
-    if let Some(file) = &options.output_file {
-        csv_file_handle = Box::new(File::create(file)?);
+    let out_headers = csvutil::enumerate_output_headers(options.config().has_headers, first_row, &selected_indices);
+
+    let boolean_values = BooleanValues::new(options.true_values.clone(), options.false_values.clone());
+    let new_statistics = || -> Vec<CsvColumnStat> {
+        selected_indices.iter().zip(out_headers.iter())
+            .map(|(&i, name)| CsvColumnStat::new(i, name.clone()).include_empty(stat_options.include_empty)
+                .strict_numeric(stat_options.strict_numeric)
+                .boolean_values(boolean_values.clone()))
+            .collect()
+    };
+
+    let check_memory = |used: u64| csvutil::check_memory(options, used, "The distinct values of the columns",
+                                                         "Select fewer columns with -c, such as leaving out unique ids");
+    let statistics = if stat_options.jobs > 1 {
+        parallel_statistics(reader.as_mut(), &selected_indices, new_statistics, stat_options.jobs, check_memory)?
     } else {
-        csv_file_handle = Box::new(io::stdout());
-    }
+        let mut statistics = new_statistics();
+        let mut record = StringRecord::new();
+        let mut n = 0;
+        while reader.read_record(&mut record)? {
+            selected_indices.iter().zip(statistics.iter_mut())
+                .for_each(|(&i, statistic)| statistic.add(record.get(i)));
+            n += 1;
+            if n % CHUNK_SIZE == 0 {
+                check_memory(distinct_bytes(&statistics))?;
+            }
+        }
+        statistics
+    };
+    // Between checks, and before the workers have reported, the budget may have been passed unseen
+    check_memory(distinct_bytes(&statistics))?;
 
-    let output_has_headers = options.output_headers
-        .unwrap_or(true);
-
-    let out_headers = csvutil::enumerate_output_headers(options.input_has_headers.unwrap_or(true), first_row, &selected_indices);
-
-    let mut statistics: Vec<CsvColumnStat> = vec![];
-    selected_indices.iter().for_each(|&i| statistics.push(CsvColumnStat {
-        idx: i,
-        name: out_headers[i].clone(),
-        n: 0,
-        n_numeric: 0,
-        sum: 0.0,
-        mean: 0.0,
-        v_k: 0.0,
-        variance: 0.0,
-        min: 0.0,
-        max: 0.0,
-        min_str: "".to_string(),
-        max_str: "".to_string(),
-        max_len: 0,
-        n_zeros: 0,
-        n_missing: 0,
-        n_empty: 0,
-        distinct: HashMultiSet::new()
-    }));
-
-    for result in reader.records() {
-        let record = result?;
-        selected_indices.iter()
-            .for_each(|&i| add_statistic(record.get(i), &mut statistics[i]));
-    }
+    stats::warn_leading_zeros(&statistics);
+    stats::warn_sum_precision(&statistics);
 
+    let mut rows = vec![];
     if stat_options.csv {
-        let out_headers = vec!["column_id","column_name","type","nulls","unique","min","max","sum","mean","median","stdev","len","freq"];
-        if output_has_headers {
-            csv_file_handle.write(format_args!("{}\n", out_headers.join(",")).to_string().as_bytes())?;
-        }
+        rows.push(["column_id","column_name","type","nulls","unique","min","max","sum","mean","median","stdev","len","true_count","false_count","freq"]
+            .map(String::from).to_vec());
         for statistic in statistics {
-            if statistic.is_numeric() {
-                csv_file_handle.write(format_args!("{},{},Number,{},{},{},{},{},{},{},{},,{}\n",
-                       statistic.idx,
-                       statistic.name,
-                       statistic.nulls(),
-                       statistic.unique(),
-                       statistic.min,
-                       statistic.max,
-                       statistic.sum,
-                       statistic.mean(),
-                       statistic.median(),
-                       statistic.stdev(),
-                       statistic.freq().join(",")).to_string().as_bytes())?;
-
+            let (n_true, n_false) = if statistic.is_boolean() {
+                (statistic.n_true.to_string(), statistic.n_false.to_string())
             } else {
-                csv_file_handle.write(format_args!("{},{},Text,{},{},{},{},,,,,{},\"{}\"\n",
-                                                   statistic.idx,
-                                                   statistic.name,
-                                                   statistic.nulls(),
-                                                   statistic.unique(),
-                                                   statistic.min_str,
-                                                   statistic.max_str,
-                                                   statistic.max_len,
-                                                   statistic.freq().join(",")).to_string().as_bytes())?;
-            }
+                (String::new(), String::new())
+            };
+            let float = |n: f64| stat_options.float_format.map_or_else(|| n.to_string(), |f| f.format(n));
+            let (sum, mean, median, stdev, len) = if statistic.is_numeric() {
+                let sum = statistic.exact_sum().map_or_else(|| float(statistic.sum), |sum| sum.to_string());
+                (sum, float(statistic.mean()), float(statistic.median()), float(statistic.stdev()), String::new())
+            } else {
+                (String::new(), String::new(), String::new(), String::new(), statistic.max_len.to_string())
+            };
+            // Integer columns keep their exact min and max
+            let (min, max) = if statistic.is_numeric() && !statistic.is_integer() {
+                (float(statistic.min), float(statistic.max))
+            } else {
+                (statistic.min(), statistic.max())
+            };
+            rows.push(vec![
+                statistic.idx.to_string(),
+                statistic.name().clone(),
+                statistic.infer_type(),
+                statistic.nulls().to_string(),
+                statistic.unique().to_string(),
+                min,
+                max,
+                sum,
+                mean,
+                median,
+                stdev,
+                len,
+                n_true,
+                n_false,
+                statistic.freq().join(","),
+            ]);
         }
-    } else {
-
     }
 
-    Ok(())
+    Ok(rows)
 }
 
-fn add_statistic(value: Option<&str>, p1: &mut CsvColumnStat) -> () {
-    p1.n += 1;
+/// Records per chunk handed to a worker thread, and between `--max-memory` checks
+const CHUNK_SIZE: usize = 10_000;
 
-    if value.is_none() {
-        p1.n_missing += 1;
-        return;
-    }
-    let string = value.unwrap().to_string();
+fn distinct_bytes(statistics: &[CsvColumnStat]) -> u64 {
+    statistics.iter().map(|s| s.distinct_bytes).sum()
+}
 
-    if p1.n - (p1.n_missing+p1.n_empty) == 1 || string > p1.max_str {
-        p1.max_str = string.clone();
-    }
-    if p1.n - (p1.n_missing+p1.n_empty) == 1 || string < p1.min_str {
-        p1.min_str = string.clone();
-    }
-    if string.is_empty() {
-        p1.n_empty += 1;
-    }
-    p1.distinct.insert(string.clone());
-    if let Ok(float) = string.parse::<f64>() {
-        p1.n_numeric += 1;
-        p1.sum += float;
-        let prev_mean = p1.mean;
-        // This method for computing the stream mean and variance is apparently from Knuth
-        // and I found it at https://math.stackexchange.com/questions/20593/calculate-variance-from-a-stream-of-sample-values
-        // n.b. if this is the first numeric value, then m_1 will be x_1 here as long as n_numeric has been previously incremented.
-        p1.mean = p1.mean + (float - p1.mean) / p1.n_numeric as f64;
-        p1.variance = p1.variance + (float - p1.mean) * (float - prev_mean);
-        if p1.n_numeric == 1 || float > p1.max {
-            p1.max = float;
-        }
-        if p1.n_numeric == 1 || float < p1.min {
-            p1.min = float;
+/// Reads chunks of records on this thread while `jobs` workers accumulate statistics, then merges them.
+/// `check_memory` is given the workers' combined `distinct_bytes` after each chunk is handed out.
+fn parallel_statistics<F, C>(reader: &mut dyn csvutil::RecordReader, selected_indices: &[usize], new_statistics: F, jobs: usize, check_memory: C)
+    -> Result<Vec<CsvColumnStat>, Box<dyn std::error::Error>>
+    where F: Fn() -> Vec<CsvColumnStat> + Sync,
+          C: Fn(u64) -> Result<(), Box<dyn std::error::Error>> {
+    let (sender, receiver) = mpsc::sync_channel::<Vec<StringRecord>>(jobs * 2);
+    let receiver = Mutex::new(receiver);
+    let used = AtomicU64::new(0);
+
+    thread::scope(|scope| {
+        let workers = (0..jobs).map(|_| scope.spawn(|| {
+            let mut statistics = new_statistics();
+            let mut reported = 0;
+            loop {
+                // The lock is only held while waiting for the next chunk
+                let Ok(chunk) = receiver.lock().unwrap().recv() else {
+                    break;
+                };
+                for record in &chunk {
+                    selected_indices.iter().zip(statistics.iter_mut())
+                        .for_each(|(&i, statistic)| statistic.add(record.get(i)));
+                }
+                let bytes = distinct_bytes(&statistics);
+                used.fetch_add(bytes - reported, Ordering::Relaxed);
+                reported = bytes;
+            }
+            statistics
+        })).collect::<Vec<_>>();
+
+        let mut read = || -> Result<(), Box<dyn std::error::Error>> {
+            loop {
+                let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+                let mut record = StringRecord::new();
+                while chunk.len() < CHUNK_SIZE && reader.read_record(&mut record)? {
+                    chunk.push(record.clone());
+                }
+                let done = chunk.len() < CHUNK_SIZE;
+                if !chunk.is_empty() {
+                    sender.send(chunk)?;
+                }
+                check_memory(used.load(Ordering::Relaxed))?;
+                if done {
+                    return Ok(());
+                }
+            }
+        };
+        let result = read();
+        drop(sender);
+
+        let mut statistics = new_statistics();
+        for worker in workers {
+            for (statistic, other) in statistics.iter_mut().zip(worker.join().expect("statistics worker panicked")) {
+                statistic.merge(other);
+            }
         }
-    }
+        result.map(|_| statistics)
+    })
 }
 
 fn parse_args(args: Vec<String>) -> (CsvOptions, CsvStatOptions) {
     let executable_name = args[0].clone();
 
-    let command = global_args()
+    let command = args::memory_args(args::boolean_args(args::engine_args(args::self_bench_args(global_args()))))
         .display_name(executable_name)
         .about("Computes statistics from CSV files.")
         .arg(Arg::new("csv")
@@ -266,17 +319,228 @@ fn parse_args(args: Vec<String>) -> (CsvOptions, CsvStatOptions) {
             .short('c')
             .long("columns")
             .allow_negative_numbers(true)
-            .help("List of column names, offsets or ranges to include, e.g. \"1,id,-2,3-5. Negative offsets are interpreted as relative to the end (-1 is the last column). Ranges are inclusive.")
-            .action(clap::ArgAction::Append));
+            .help("List of column names, offsets or ranges to include, e.g. \"1,id,-2,3-5. Negative offsets are interpreted as relative to the end (-1 is the last column). Ranges are inclusive and may be of names, e.g. first_name-email. Quote names containing commas or dashes, e.g. '\"revenue, net\",id'.")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("jobs")
+            .short('j')
+            .long("jobs")
+            .value_parser(clap::value_parser!(usize))
+            .help("Worker threads computing statistics over chunks of records. Defaults to the number of CPUs; 1 computes them on the reading thread"))
+        .arg(Arg::new("cache_dir")
+            .long("cache-dir")
+            .value_name("DIR")
+            .help("Reuse statistics computed earlier for the same file contents and options, storing new ones in DIR. Standard input isn't cached"))
+        .arg(Arg::new("include_empty")
+            .long("include-empty")
+            .action(SetTrue)
+            .help("Count empty values towards the text min and max, where an empty value is always the min"))
+        .arg(Arg::new("strict_numeric")
+            .long("strict-numeric")
+            .action(SetTrue)
+            .help("Only treat plain numbers as numeric, not values like \"1,234\", \" 42 \" or \"+7\""))
+        .arg(Arg::new("float_format")
+            .long("float-format")
+            .value_parser(transform::parse_float_format)
+            .help("Write the non-integer statistics with a printf-style format, %.Nf or %.Ne, e.g. %.2f, rather than in full"));
 
-    let mut matches = command.get_matches_from(args);
+    let mut matches = args::get_matches_from(command, args);
 
     let action = CsvStatOptions {
+        jobs: matches.remove_one("jobs")
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get())),
         input_columns: matches.remove_many::<String>("input_columns")
-            .map(|v| v.flat_map(|s| s.split(',').map(|s| s.trim().to_string()).collect::<Vec<_>>())
+            .map(|v| v.flat_map(|s| csvutil::parse_column_list(&s))
                 .collect::<Vec<_>>()),
         csv: matches.remove_one("csv").unwrap_or(false),
+        cache_dir: matches.remove_one("cache_dir"),
+        include_empty: matches.remove_one("include_empty").unwrap_or(false),
+        strict_numeric: matches.remove_one("strict_numeric").unwrap_or(false),
+        float_format: matches.remove_one("float_format"),
     };
 
     (args::build_options(matches), action)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::Constraint;
+    use std::fs;
+
+    /// The output up to the len column, leaving out the boolean counts and freq
+    fn read_output(output_file: &str) -> Vec<Vec<String>> {
+        let mut reader = csv::ReaderBuilder::new().flexible(true).from_path(output_file).expect("Unable to read output file");
+        reader.records()
+            .map(|r| r.expect("Invalid output").iter().take(12).map(String::from).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_process_csv_parallel() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_stat.csv");
+        let output_file = &dir.path("test_output_stat.csv");
+        fs::write(input_file, csvutil::synthetic_csv(3 * CHUNK_SIZE + 17, 5)).expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+
+        process_csv(&options, &CsvStatOptions { input_columns: None, csv: true, jobs: 1, cache_dir: None, include_empty: false, strict_numeric: false, float_format: None }).expect("process_csv failed");
+        let sequential = read_output(output_file);
+        process_csv(&options, &CsvStatOptions { input_columns: None, csv: true, jobs: 4, cache_dir: None, include_empty: false, strict_numeric: false, float_format: None }).expect("process_csv failed");
+        let parallel = read_output(output_file);
+
+        assert_eq!(sequential.len(), 5);
+        for (s, p) in sequential.iter().zip(&parallel) {
+            // Sums of many floats can differ in the last digits depending on how they were grouped
+            for (a, b) in s.iter().zip(p) {
+                match (a.parse::<f64>(), b.parse::<f64>()) {
+                    (Ok(a), Ok(b)) => assert!((a - b).abs() <= 1e-9 * a.abs().max(1.0), "{} != {}", a, b),
+                    _ => assert_eq!(a, b),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_process_csv_column_subset() {
+        let dir = csvutil::TestDir::new();
+        let output_file = &dir.path("test_output_stat_subset.csv");
+
+        let options = CsvOptions {
+            input_file: Some("test/test_input.csv".to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+        let stat_options = CsvStatOptions { input_columns: Some(vec!["col3".to_string()]), csv: true, jobs: 1, cache_dir: None, include_empty: false, strict_numeric: false, float_format: None };
+
+        process_csv(&options, &stat_options).expect("process_csv failed");
+        let output = read_output(output_file);
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0][1..9], ["col3", "Integer", "false", "3", "3", "9", "18", "6"]);
+    }
+
+    #[test]
+    fn test_process_csv_float_format() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_stat_float.csv");
+        let output_file = &dir.path("test_output_stat_float.csv");
+        fs::write(input_file, "price,qty\n0.1,1\n0.2,2\n").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+        let stat_options = CsvStatOptions { input_columns: None, csv: true, jobs: 1, cache_dir: None, include_empty: false, strict_numeric: false, float_format: Some(FloatFormat::Fixed(2)) };
+
+        process_csv(&options, &stat_options).expect("process_csv failed");
+        let output = read_output(output_file);
+        assert_eq!(output[0][5..11], ["0.10", "0.20", "0.30", "0.15", "0.00", "0.07"]);
+        assert_eq!(output[1][5..11], ["1", "2", "3", "1.50", "0.00", "0.71"]);
+    }
+
+    #[test]
+    fn test_process_csv_max_memory() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_stat_memory.csv");
+        let output_file = &dir.path("test_output_stat_memory.csv");
+        fs::write(input_file, csvutil::synthetic_csv(2 * CHUNK_SIZE, 2)).expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            max_memory: Some(100_000),
+            ..Default::default()
+        };
+        for jobs in [1, 4] {
+            let stat_options = CsvStatOptions { input_columns: None, csv: true, jobs, cache_dir: None, include_empty: false, strict_numeric: false, float_format: None };
+            let error = process_csv(&options, &stat_options).expect_err("--max-memory passed");
+            assert!(error.to_string().starts_with("The distinct values of the columns need more than --max-memory 100000 bytes."));
+        }
+
+        let options = CsvOptions { max_memory: Some(10_000_000), ..options };
+        let stat_options = CsvStatOptions { input_columns: None, csv: true, jobs: 1, cache_dir: None, include_empty: false, strict_numeric: false, float_format: None };
+        process_csv(&options, &stat_options).expect("process_csv failed");
+    }
+
+    #[test]
+    fn test_process_csv_cache_dir() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_stat_cache.csv");
+        let output_file = &dir.path("test_output_stat_cache.csv");
+        let cache_dir = &dir.path("test_stat_cache");
+        fs::write(input_file, "a,b\n1,x\n2,y\n").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+        let stat_options = CsvStatOptions { input_columns: None, csv: true, jobs: 1, cache_dir: Some(cache_dir.to_string()), include_empty: false, strict_numeric: false, float_format: None };
+
+        process_csv(&options, &stat_options).expect("process_csv failed");
+        let computed = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(fs::read_dir(cache_dir).expect("Cache directory not created").count(), 1);
+
+        // A stale entry for the same key shows the cache was used rather than the input
+        let entry = fs::read_dir(cache_dir).unwrap().next().unwrap().unwrap().path();
+        fs::write(&entry, "cached\n").expect("Unable to write cache entry");
+        process_csv(&options, &stat_options).expect("process_csv failed");
+        assert_eq!(fs::read_to_string(output_file).expect("Unable to read output file"), "cached\n");
+
+        // Checks on the input read it rather than trusting the cache
+        let verify_options = CsvOptions { verify_sha256: Some("0".repeat(64)), ..options.clone() };
+        assert!(process_csv(&verify_options, &stat_options).is_err());
+        let assert_options = CsvOptions { assertions: vec![(vec!["a".to_string()], Constraint::Range(None, Some(1.0)))], ..options.clone() };
+        assert!(process_csv(&assert_options, &stat_options).is_err());
+
+        // So do the size guards, which miss the cache rather than reuse an unguarded run's entry
+        let limit_options = CsvOptions { max_field_size: Some(0), ..options.clone() };
+        assert!(process_csv(&limit_options, &stat_options).is_err());
+        let limit_options = CsvOptions { max_record_size: Some(1), ..options.clone() };
+        assert!(process_csv(&limit_options, &stat_options).is_err());
+
+        // Options changing what the reader passes on miss the cache
+        let filtered_options = CsvOptions { drop_if_empty: Some(vec!["b".to_string()]), ..options.clone() };
+        process_csv(&filtered_options, &stat_options).expect("process_csv failed");
+        assert_eq!(fs::read_to_string(output_file).expect("Unable to read output file"), computed);
+        assert_eq!(fs::read_dir(cache_dir).unwrap().count(), 2);
+
+        // Changed contents miss the cache
+        fs::write(input_file, "a,b\n1,x\n2,y\n3,z\n").expect("Unable to write input file");
+        process_csv(&options, &stat_options).expect("process_csv failed");
+        let recomputed = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_ne!(recomputed, computed);
+        assert_eq!(fs::read_dir(cache_dir).unwrap().count(), 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_process_csv_cache_dir_fifo() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_stat_fifo.csv");
+        let output_file = &dir.path("test_output_stat_fifo.csv");
+        let cache_dir = &dir.path("test_stat_cache_fifo");
+        assert!(std::process::Command::new("mkfifo").arg(input_file).status().expect("Unable to run mkfifo").success());
+        let fifo = input_file.clone();
+        let writer = thread::spawn(move || fs::write(fifo, "a\n1\n2\n").expect("Unable to write to fifo"));
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+        let stat_options = CsvStatOptions { input_columns: None, csv: true, jobs: 1, cache_dir: Some(cache_dir.to_string()), include_empty: false, strict_numeric: false, float_format: None };
+
+        // Hashing the pipe for the cache key would leave nothing for the statistics
+        process_csv(&options, &stat_options).expect("process_csv failed");
+        writer.join().unwrap();
+        let output = read_output(output_file);
+        assert_eq!(output[0][3..7], ["false", "2", "1", "2"]);
+        assert!(!std::path::Path::new(cache_dir).exists());
+    }
+}
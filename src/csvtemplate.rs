@@ -0,0 +1,150 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+
+use crate::args::global_args;
+use clap::{Arg, ArgGroup};
+use minijinja::{Environment, UndefinedBehavior, Value};
+use options::CsvOptions;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::{BufRead, Write};
+
+struct CsvTemplateOptions {
+    template: String,
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>())?;
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> Result<(CsvOptions, CsvTemplateOptions), String> {
+    let executable_name = args[0].clone();
+
+    let command = global_args()
+        .display_name(executable_name)
+        .about("Renders each record through a Jinja template, e.g. to generate SQL statements, config snippets or emails.")
+        .after_help("Columns are variables by name, e.g. {{ email }}. Names that aren't identifiers can be \
+                     reached through columns, e.g. {{ columns[\"first name\"] }}, and every value by position \
+                     through fields, e.g. {{ fields[0] }}. row is the 1-based record number. A column with one \
+                     of these names hides it. The sql filter quotes a value as an SQL string literal, e.g. \
+                     {{ name | sql }}. Each rendered record is followed by a newline.")
+        .arg(Arg::new("template")
+            .short('t')
+            .long("template")
+            .help("Template text"))
+        .arg(Arg::new("template_file")
+            .long("template-file")
+            .value_name("FILE")
+            .help("File holding the template"))
+        .group(ArgGroup::new("template_source")
+            .args(["template", "template_file"])
+            .required(true));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let template = match matches.remove_one::<String>("template_file") {
+        Some(file) => std::fs::read_to_string(&file).map_err(|e| format!("Unable to read {}: {}", file, e))?,
+        None => matches.remove_one("template").unwrap_or_default(),
+    };
+
+    Ok((args::build_options(matches), CsvTemplateOptions { template }))
+}
+
+/// Quotes a value as an SQL string literal.
+fn sql(value: String) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn environment(template: &str) -> Result<Environment<'_>, Box<dyn Error>> {
+    let mut env = Environment::new();
+    // A misspelt column name should fail rather than render as nothing
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    env.add_filter("sql", sql);
+    env.add_template("template", template)?;
+    Ok(env)
+}
+
+fn process_csv(options: &CsvOptions, template_options: &CsvTemplateOptions) -> Result<(), Box<dyn Error>> {
+    let env = environment(&template_options.template)?;
+    let template = env.get_template("template")?;
+
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    let mut reader = csvutil::csv_reader(options, input);
+
+    // Get the column headers
+    let first_row = csvutil::column_names(options, reader.headers()?.clone());
+    let all_indices = (0..first_row.len()).collect::<Vec<_>>();
+    let names = csvutil::enumerate_output_headers(options.config().has_headers, first_row, &all_indices);
+
+    let mut out = options.get_output_file()?;
+    for (row, result) in reader.records().enumerate() {
+        let record = result?;
+        let fields = record.iter().map(Value::from).collect::<Vec<_>>();
+        let columns = names.iter().zip(record.iter())
+            .map(|(name, value)| (name.clone(), Value::from(value)))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut context = BTreeMap::new();
+        context.insert("row".to_string(), Value::from(row + 1));
+        context.insert("fields".to_string(), Value::from(fields));
+        context.insert("columns".to_string(), Value::from(columns.clone()));
+        context.extend(columns);
+
+        let rendered = template.render(context).map_err(|e| format!("Record {}: {}", row + 1, e))?;
+        writeln!(out, "{}", rendered)?;
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_process_csv() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_template.csv");
+        let output_file = &dir.path("test_output_template.sql");
+        fs::write(input_file, "id,first name\n1,Ann\n2,O'Brien\n").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+
+        let template = "INSERT INTO people VALUES ({{ id }}, {{ columns[\"first name\"] | sql }}); -- {{ row }}, {{ fields[1] }}".to_string();
+        process_csv(&options, &CsvTemplateOptions { template }).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, "INSERT INTO people VALUES (1, 'Ann'); -- 1, Ann\nINSERT INTO people VALUES (2, 'O''Brien'); -- 2, O'Brien\n");
+
+        let template = "{{ nmae }}".to_string();
+        let error = process_csv(&options, &CsvTemplateOptions { template }).expect_err("undefined variable accepted");
+        assert!(error.to_string().starts_with("Record 1:"));
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = ["csvtemplate", "--template", "{{ id }}"].iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let (_, action) = parse_args(args).expect("parse_args failed");
+        assert_eq!(action.template, "{{ id }}");
+    }
+}
@@ -0,0 +1,196 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+pub mod numbers;
+pub mod stats;
+
+use crate::args::global_args;
+use clap::Arg;
+use csv::StringRecord;
+use options::CsvOptions;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::BufRead;
+
+struct CsvTopOptions {
+    group_columns: Option<Vec<String>>,
+    order_column: String,
+    /// Whether the largest value comes first
+    descending: bool,
+    n: usize,
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>());
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_order(s: &str) -> (String, bool) {
+    match s.rsplit_once(':') {
+        Some((column, "desc")) => (column.to_string(), true),
+        Some((column, "asc")) => (column.to_string(), false),
+        _ => (s.to_string(), true),
+    }
+}
+
+fn parse_args(args: Vec<String>) -> (CsvOptions, CsvTopOptions) {
+    let executable_name = args[0].clone();
+
+    let command = args::in_place_args(args::contract_args(global_args()))
+        .display_name(executable_name)
+        .about("Keeps the top N rows of each group by a numeric column, e.g. the 3 largest orders per customer.")
+        .arg(Arg::new("group_columns")
+            .short('g')
+            .long("group-by")
+            .allow_negative_numbers(true)
+            .help("Columns identifying a group, using the same syntax as csvcut's --columns. Without it the whole input is one group")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("by")
+            .long("by")
+            .required(true)
+            .allow_negative_numbers(true)
+            .help("Numeric column to order each group by, largest first. Use column:asc for the smallest first. Rows without a number are dropped"))
+        .arg(Arg::new("n")
+            .short('N')
+            .long("rows")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .default_value("1")
+            .help("Rows to keep per group. Ties go to the earlier row"));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let (order_column, descending) = parse_order(&matches.remove_one::<String>("by").unwrap_or_default());
+    let action = CsvTopOptions {
+        group_columns: matches.remove_many::<String>("group_columns")
+            .map(|v| v.flat_map(|s| csvutil::parse_column_list(&s))
+                .collect::<Vec<_>>()),
+        order_column,
+        descending,
+        n: matches.remove_one::<u64>("n").unwrap_or(1) as usize,
+    };
+
+    (args::build_options(matches), action)
+}
+
+/// A row held as a candidate for its group's top N.
+struct Candidate {
+    value: f64,
+    row: usize,
+    record: StringRecord,
+}
+
+/// Orders candidates best first: by value, then the earlier row.
+fn compare(a: &Candidate, b: &Candidate, descending: bool) -> Ordering {
+    let by_value = if descending { b.value.total_cmp(&a.value) } else { a.value.total_cmp(&b.value) };
+    by_value.then(a.row.cmp(&b.row))
+}
+
+fn process_csv(options: &CsvOptions, top_options: &CsvTopOptions) -> Result<(), Box<dyn Error>> {
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    let mut reader = csvutil::csv_reader(options, input);
+
+    // Get the column headers
+    let first_row = csvutil::column_names(options, reader.headers()?.clone());
+
+    let order_idx = csvutil::select_column_index(&first_row, &top_options.order_column)?;
+    let group_indices = match &top_options.group_columns {
+        Some(_) => csvutil::select_column_indices(&first_row, &top_options.group_columns)?,
+        None => vec![],
+    };
+
+    // Only the best N rows of each group are held, so memory grows with the number of groups
+    let mut groups: HashMap<Vec<String>, Vec<Candidate>> = HashMap::new();
+    for (row, result) in reader.records().enumerate() {
+        let record = result?;
+        let Some(value) = record.get(order_idx).and_then(|v| numbers::parse_number(v, false)) else {
+            continue;
+        };
+        let key = group_indices.iter().map(|&i| record.get(i).unwrap_or_default().to_string()).collect::<Vec<_>>();
+        let candidates = groups.entry(key).or_default();
+        candidates.push(Candidate { value, row, record });
+        if candidates.len() > top_options.n {
+            let worst = (0..candidates.len())
+                .max_by(|&a, &b| compare(&candidates[a], &candidates[b], top_options.descending))
+                .unwrap_or_default();
+            candidates.swap_remove(worst);
+        }
+    }
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+
+    if options.config().report_headers {
+        let all_indices = (0..first_row.len()).collect::<Vec<_>>();
+        csv_writer.write_record(csvutil::enumerate_output_headers(options.config().has_headers, first_row, &all_indices))?;
+    }
+
+    // The kept rows come out in their input order
+    let mut kept = groups.into_values().flatten().collect::<Vec<_>>();
+    kept.sort_by_key(|c| c.row);
+    for candidate in kept {
+        csv_writer.write_record(&candidate.record)?;
+    }
+
+    csv_writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_process_csv() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_top.csv");
+        let output_file = &dir.path("test_output_top.csv");
+        fs::write(input_file, "customer,order,amount\na,1,10\nb,2,5\na,3,30\na,4,20\nb,5,\nb,6,7\na,7,30\n").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+
+        let mut top_options = CsvTopOptions {
+            group_columns: Some(vec!["customer".to_string()]),
+            order_column: "amount".to_string(),
+            descending: true,
+            n: 2,
+        };
+        process_csv(&options, &top_options).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, "customer,order,amount\nb,2,5\na,3,30\nb,6,7\na,7,30\n");
+
+        top_options.descending = false;
+        top_options.n = 1;
+        process_csv(&options, &top_options).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, "customer,order,amount\na,1,10\nb,2,5\n");
+
+        top_options.group_columns = None;
+        process_csv(&options, &top_options).expect("process_csv failed");
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, "customer,order,amount\nb,2,5\n");
+    }
+
+    #[test]
+    fn test_parse_order() {
+        assert_eq!(parse_order("amount"), ("amount".to_string(), true));
+        assert_eq!(parse_order("amount:asc"), ("amount".to_string(), false));
+        assert_eq!(parse_order("a:b"), ("a:b".to_string(), true));
+    }
+}
@@ -2,75 +2,206 @@ use std::io::BufRead;
 use csv::{Reader, ReaderBuilder, StringRecord, Trim};
 use std::ops::RangeInclusive;
 use std::error::Error;
+use crate::log;
 use crate::options::CsvOptions;
+use crate::reader;
+use std::collections::HashMap;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 pub fn csv_reader(options: &CsvOptions, input: Box<dyn BufRead>) -> Reader<Box<dyn BufRead>> {
-    let mut reader_builder = ReaderBuilder::new();
+    let config = options.config();
+    ReaderBuilder::new()
+        .has_headers(config.has_headers)
+        .comment(config.comment)
+        .escape(config.escape)
+        .flexible(config.flexible)
+        .delimiter(config.delimiter)
+        .trim(config.trim)
+        .quote(config.quote)
+        .from_reader(input)
+}
 
-    reader_builder.has_headers(options.input_has_headers.unwrap_or(true))
-        .comment(options.comment_char.map(|c| c as u8))
-        .escape(options.escape_char.map(|c| c as u8))
-        .flexible(options.flexible.unwrap_or(true));
+/// Records, each with the bytes it was read as under `--raw-passthrough`, from `raw_records`.
+pub type RawRecordIter = Box<dyn Iterator<Item = Result<(StringRecord, Option<Vec<u8>>), Box<dyn Error>>>>;
 
-    if let Some(c) = options.delimiter {
-        reader_builder.delimiter(c as u8);
+/// The header row and the records of the input, for the tools that only drop or reorder whole
+/// records. With `--raw-passthrough` each record comes with the bytes it was read as, for
+/// `CsvWriter::write_raw`; otherwise they are read by the csv reader as usual.
+pub fn raw_records(options: &CsvOptions, input: Box<dyn BufRead>) -> Result<(StringRecord, RawRecordIter), Box<dyn Error>> {
+    if !options.raw_passthrough {
+        let mut reader = csv_reader(options, input);
+        let headers = reader.headers()?.clone();
+        return Ok((headers, Box::new(reader.into_records().map(|r| r.map(|record| (record, None)).map_err(Box::from)))));
+    }
+    let config = options.config();
+    let mut records = reader::RawRecords::new(input, &config);
+    let mut headers = records.headers()?;
+    if config.trim == Trim::Headers {
+        headers.trim();
     }
+    Ok((headers, Box::new(records.map(|r| r.map(|(raw, record)| (record, Some(raw))).map_err(Box::from)))))
+}
+
+/// The record-reading interface shared by the default csv engine and `--engine fast`.
+pub trait RecordReader {
+    fn headers(&mut self) -> Result<StringRecord, Box<dyn Error>>;
+    fn read_record(&mut self, record: &mut StringRecord) -> Result<bool, Box<dyn Error>>;
+}
 
-    if options.trim_fields.unwrap_or(false) {
-        reader_builder.trim(Trim::All);
+impl<R: std::io::Read> RecordReader for Reader<R> {
+    fn headers(&mut self) -> Result<StringRecord, Box<dyn Error>> {
+        Ok(Reader::headers(self)?.clone())
     }
 
-    if let Some(c) = options.quote_char {
-        reader_builder.delimiter(c as u8);
+    fn read_record(&mut self, record: &mut StringRecord) -> Result<bool, Box<dyn Error>> {
+        Ok(Reader::read_record(self, record)?)
     }
+}
 
-    reader_builder.from_reader(input)
+/// Opens the input with the engine chosen by `--engine`.
+pub fn record_reader(options: &CsvOptions, input: Box<dyn BufRead>) -> Result<Box<dyn RecordReader>, Box<dyn Error>> {
+    if options.fast_engine {
+        #[cfg(feature = "fast-engine")]
+        return Ok(Box::new(crate::fastcsv::FastReader::new(options, input)?));
+        #[cfg(not(feature = "fast-engine"))]
+        return Err(Box::from("--engine fast needs csvstar to be built with the fast-engine feature"));
+    }
+    Ok(Box::new(csv_reader(options, input)))
 }
 
-pub fn parse_range(s: &str) -> Result<RangeInclusive<usize>, ()> {
-    let (min, max) = s.split_once('-').ok_or(())?;
-    Ok(RangeInclusive::new(
-            min.parse::<usize>().map_err(|_| ())?,
-            max.parse::<usize>().map_err(|_| ())?))
+pub fn parse_range(s: &str) -> Option<RangeInclusive<usize>> {
+    let (min, max) = s.split_once('-')?;
+    Some(RangeInclusive::new(
+            min.parse::<usize>().ok()?,
+            max.parse::<usize>().ok()?))
 }
 
-pub fn validate_range(range: RangeInclusive<usize>, first_row: &StringRecord) -> Result<Vec<usize>, Box<dyn Error>> {
+/// Splits a `-c` style list on the commas outside double quotes. Quoted items keep their quotes,
+/// which tell `select_column_indices` to take them as a literal name, e.g. `"revenue, net"`.
+pub fn parse_column_list(spec: &str) -> Vec<String> {
+    let mut columns = vec![];
+    let mut column = String::new();
+    let mut quoted = false;
+    for c in spec.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                column.push(c);
+            }
+            ',' if !quoted => columns.push(std::mem::take(&mut column).trim().to_string()),
+            _ => column.push(c),
+        }
+    }
+    columns.push(column.trim().to_string());
+    columns
+}
+
+/// The name in a quoted column spec, with `""` standing for a quote inside it.
+fn quoted_name(col: &str) -> Option<String> {
+    let inner = col.strip_prefix('"')?.strip_suffix('"')?;
+    Some(inner.replace("\"\"", "\""))
+}
+
+/// Resolves a range of header names such as "first_name-email", trying each dash in turn since
+/// names may contain dashes themselves. None if no split names two columns.
+pub fn parse_name_range(first_row: &StringRecord, s: &str) -> Option<Result<Vec<usize>, Box<dyn Error>>> {
+    let position = |name: &str| first_row.iter().position(|h| h == name);
+    let (start, end, first, last) = s.match_indices('-').find_map(|(i, _)| {
+        let (first, last) = (&s[..i], &s[i + 1..]);
+        Some((position(first)?, position(last)?, first, last))
+    })?;
+    if start > end {
+        return Some(Err(Box::from(format!("Invalid range. '{}' comes after '{}': {}", first, last, s))));
+    }
+    Some(Ok((start..=end).collect()))
+}
+
+pub fn validate_range(range: RangeInclusive<usize>, first_row: &StringRecord, zero_based: bool) -> Result<Vec<usize>, Box<dyn Error>> {
+    let first = usize::from(!zero_based);
     if range.start() >= range.end() {
         return Err(Box::from(format!("Invalid range. Must be increasing: {}-{}", range.start(), range.end())));
     }
-    if *range.end() > first_row.len() {
+    if *range.start() < first {
+        return Err(Box::from("Column 0 is invalid. Columns are 1-based."));
+    }
+    if *range.end() - first >= first_row.len() {
         return Err(Box::from(format!("Invalid range. There are only {} columns: {}-{}", first_row.len(), range.start(), range.end())));
     }
-    Ok(range.clone().map(|i| i - 1).collect::<Vec<_>>())
+    Ok(range.clone().map(|i| i - first).collect::<Vec<_>>())
 }
 
-pub fn add_numeric_col(first_row: &StringRecord, n_headers: i32, numeric: i32) -> Result<usize, Box<dyn Error>> {
-    if numeric == 0 {
+pub fn add_numeric_col(first_row: &StringRecord, n_headers: i32, numeric: i32, zero_based: bool) -> Result<usize, Box<dyn Error>> {
+    let first = i32::from(!zero_based);
+    if numeric == 0 && !zero_based {
         Err(Box::from("Column 0 is invalid. Columns are 1-based."))
-    } else if (numeric < -n_headers) || (numeric > n_headers) {
+    } else if (numeric < -n_headers) || (numeric - first >= n_headers) {
         Err(Box::from(format!("Column {} is invalid. There are {} columns.", numeric, first_row.len())))
-    } else if numeric > 0 {
-        Ok((numeric - 1) as usize)
+    } else if numeric >= 0 {
+        Ok((numeric - first) as usize)
     } else {
         Ok((n_headers + numeric) as usize)
     }
 }
 
+/// What selecting a name does when more than one column has it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DuplicateNames {
+    /// Fail, listing the columns, since picking one silently may pick the wrong one
+    #[default]
+    Error,
+    /// `--select-first`
+    First,
+    /// `--select-all-matches`
+    All,
+}
+
+/// The columns named `name`, none if there are none.
+fn select_name(first_row: &StringRecord, name: &str, duplicates: DuplicateNames, zero_based: bool) -> Result<Vec<usize>, String> {
+    let positions = first_row.iter().enumerate().filter(|(_, h)| *h == name).map(|(i, _)| i).collect::<Vec<_>>();
+    match (positions.len(), duplicates) {
+        (0 | 1, _) | (_, DuplicateNames::All) => Ok(positions),
+        (_, DuplicateNames::First) => Ok(positions[..1].to_vec()),
+        (_, DuplicateNames::Error) => {
+            let base = usize::from(!zero_based);
+            let offsets = positions.iter().map(|i| (i + base).to_string()).collect::<Vec<_>>();
+            Err(format!("Column name '{}' is ambiguous: columns {} all have it. Select one by offset instead", name, offsets.join(", ")))
+        }
+    }
+}
+
 pub fn select_column_indices(first_row: &StringRecord, columns: &Option<Vec<String>>) -> Result<Vec<usize>, Box<dyn Error>> {
+    select_column_indices_with_base(first_row, columns, false)
+}
+
+/// Like `select_column_indices`, counting offsets and ranges from 0 when `zero_based` is set.
+/// Negative offsets count back from the end either way.
+pub fn select_column_indices_with_base(first_row: &StringRecord, columns: &Option<Vec<String>>, zero_based: bool) -> Result<Vec<usize>, Box<dyn Error>> {
+    select_column_indices_with(first_row, columns, zero_based, DuplicateNames::Error)
+}
+
+/// Like `select_column_indices_with_base`, resolving names shared by several columns as `duplicates` says.
+pub fn select_column_indices_with(first_row: &StringRecord, columns: &Option<Vec<String>>, zero_based: bool, duplicates: DuplicateNames) -> Result<Vec<usize>, Box<dyn Error>> {
     Ok(match columns {
         Some(cols) => {
             let mut idx_vec = vec![];
             let n_headers = first_row.len() as i32;
             for col in cols {
-                if let Ok(numeric) = col.parse::<i32>() {
-                    idx_vec.push(add_numeric_col(&first_row, n_headers, numeric)?);
-                } else if let Ok(range) = parse_range(col) {
-                    idx_vec.extend(validate_range(range, &first_row)?);
+                if let Some(name) = quoted_name(col) {
+                    let indices = select_name(first_row, &name, duplicates, zero_based)?;
+                    if indices.is_empty() {
+                        return Err(Box::from(column_not_found(first_row, &name)));
+                    }
+                    idx_vec.extend(indices);
+                } else if let Ok(numeric) = col.parse::<i32>() {
+                    idx_vec.push(add_numeric_col(first_row, n_headers, numeric, zero_based)?);
+                } else if let Some(range) = parse_range(col) {
+                    idx_vec.extend(validate_range(range, first_row, zero_based)?);
+                } else if let indices @ [_, ..] = &select_name(first_row, col, duplicates, zero_based)?[..] {
+                    idx_vec.extend(indices);
+                } else if let Some(range) = parse_name_range(first_row, col) {
+                    idx_vec.extend(range?);
                 } else {
-                    idx_vec.push(first_row
-                        .iter()
-                        .position(|h| h == col)
-                        .ok_or_else(|| format!("Column '{}' not found in input file", col))?)
+                    return Err(Box::from(column_not_found(first_row, col)));
                 }
             }
             idx_vec
@@ -79,14 +210,512 @@ pub fn select_column_indices(first_row: &StringRecord, columns: &Option<Vec<Stri
     })
 }
 
-pub fn enumerate_output_headers(input_has_headers: bool, first_row: StringRecord, selected_indices: &Vec<usize>) -> Vec<String> {
-    let mut out_headers = vec![];
+fn column_not_found(first_row: &StringRecord, col: &str) -> String {
+    let mut names = first_row.iter().take(20).collect::<Vec<_>>().join(", ");
+    if first_row.len() > 20 {
+        names.push_str(", ...");
+    }
+    if first_row.iter().enumerate().all(|(i, h)| h == generated_column_name(i)) {
+        format!("Column '{}' not found in input file. It has no header row (-H), so its columns are named {}, or use offsets", col, names)
+    } else {
+        format!("Column '{}' not found in input file. Columns are: {}", col, names)
+    }
+}
+
+/// The names columns are selected by: the header row, or a, b, c, ... for input without one.
+/// Header names with an alias from `--header-aliases` are replaced by its canonical name.
+pub fn column_names(options: &CsvOptions, first_row: StringRecord) -> StringRecord {
+    if !options.config().has_headers {
+        return (0..first_row.len()).map(generated_column_name).collect();
+    }
+    let Some(aliases) = &options.header_aliases else {
+        return first_row;
+    };
+    first_row.iter().map(|name| match aliases.get(&alias_key(name)) {
+        Some(canonical) if canonical != name => {
+            if options.verbose {
+                log::info(&format!("--header-aliases: reading column '{}' as '{}'", name, canonical));
+            }
+            canonical.as_str()
+        }
+        _ => name,
+    }).collect()
+}
+
+/// Header spellings are matched ignoring case and surrounding whitespace.
+fn alias_key(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// Reads a `--header-aliases` file: a CSV file with a header row, then one `alias,name` row per
+/// spelling to read as the canonical name.
+pub fn parse_header_aliases(path: &str) -> Result<HashMap<String, String>, String> {
+    let mut reader = csv::Reader::from_path(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut aliases = HashMap::new();
+    for (row, result) in reader.records().enumerate() {
+        let record = result.map_err(|e| format!("{}: {}", path, e))?;
+        let (Some(alias), Some(canonical)) = (record.get(0), record.get(1)) else {
+            return Err(format!("{}: every row needs an alias and a canonical name", path));
+        };
+        if let Some(previous) = aliases.insert(alias_key(alias), canonical.to_string()).filter(|p| p != canonical) {
+            return Err(format!("{}: row {} maps '{}' to '{}', but an earlier row maps it to '{}'", path, row + 2, alias, canonical, previous));
+        }
+    }
+    Ok(aliases)
+}
+
+/// Resolves a spec that must name exactly one column, e.g. the key or value column of a tool.
+pub fn select_column_index(first_row: &StringRecord, column: &str) -> Result<usize, Box<dyn Error>> {
+    select_column_index_with_base(first_row, column, false)
+}
+
+/// Like `select_column_index`, counting offsets from 0 when `zero_based` is set.
+pub fn select_column_index_with_base(first_row: &StringRecord, column: &str, zero_based: bool) -> Result<usize, Box<dyn Error>> {
+    match select_column_indices_with_base(first_row, &Some(vec![column.to_string()]), zero_based)?[..] {
+        [i] => Ok(i),
+        _ => Err(Box::from(format!("'{}' must name a single column", column))),
+    }
+}
+
+/// The `-c` value for `indices` as offsets, with runs of three or more written as ranges,
+/// e.g. "1-3,7,5".
+pub fn index_spec(indices: &[usize], zero_based: bool) -> String {
+    let base = if zero_based { 0 } else { 1 };
+    let mut parts = vec![];
+    let mut i = 0;
+    while i < indices.len() {
+        let mut end = i;
+        while end + 1 < indices.len() && indices[end + 1] == indices[end] + 1 {
+            end += 1;
+        }
+        if end - i >= 2 {
+            parts.push(format!("{}-{}", indices[i] + base, indices[end] + base));
+        } else {
+            end = i;
+            parts.push((indices[i] + base).to_string());
+        }
+        i = end + 1;
+    }
+    parts.join(",")
+}
+
+/// `--first`, `--last` and `--move`, which rearrange the selected columns in the order given.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reorder {
+    First(Vec<String>),
+    Last(Vec<String>),
+    /// To a 1-based position in the output
+    Move(String, usize),
+}
+
+/// Parses a `--move` value, e.g. `email:2`.
+pub fn parse_move(s: &str) -> Result<Reorder, String> {
+    match s.rsplit_once(':').map(|(column, position)| (column, position.trim().parse::<usize>())) {
+        Some((column, Ok(position))) if !column.is_empty() && position > 0 => Ok(Reorder::Move(column.to_string(), position)),
+        _ => Err(format!("Invalid move '{}'. Expected column:position, e.g. email:2", s)),
+    }
+}
+
+/// Applies `reorders` to the selected column indices. The columns they name, resolved like
+/// `-c`, must be among the selected ones.
+pub fn reorder_indices(first_row: &StringRecord, mut indices: Vec<usize>, reorders: &[Reorder], zero_based: bool, duplicates: DuplicateNames) -> Result<Vec<usize>, Box<dyn Error>> {
+    // Takes the named columns out of the selection, in the order they're named
+    let take = |indices: &mut Vec<usize>, columns: &[String]| -> Result<Vec<usize>, Box<dyn Error>> {
+        select_column_indices_with(first_row, &Some(columns.to_vec()), zero_based, duplicates)?.into_iter().map(|i| {
+            let position = indices.iter().position(|&j| j == i)
+                .ok_or_else(|| format!("Column '{}' can't be moved since it isn't selected", first_row.get(i).unwrap_or_default()))?;
+            Ok(indices.remove(position))
+        }).collect()
+    };
+    for reorder in reorders {
+        match reorder {
+            Reorder::First(columns) => {
+                let moved = take(&mut indices, columns)?;
+                indices.splice(0..0, moved);
+            }
+            Reorder::Last(columns) => {
+                let moved = take(&mut indices, columns)?;
+                indices.extend(moved);
+            }
+            Reorder::Move(column, position) => {
+                let moved = take(&mut indices, std::slice::from_ref(column))?;
+                // A name matching several columns moves them all, so they must fit from the position on
+                if *position > indices.len() + 1 {
+                    let n_moved = if moved.len() > 1 { format!(" ({} columns)", moved.len()) } else { String::new() };
+                    return Err(Box::from(format!("Can't move '{}'{} to position {} of {} columns", column, n_moved, position, indices.len() + moved.len())));
+                }
+                indices.splice(position - 1..position - 1, moved);
+            }
+        }
+    }
+    Ok(indices)
+}
+
+/// The columns `--add-source-columns` appends: the input file and the 1-based record number in it.
+pub const SOURCE_COLUMNS: [&str; 2] = ["_source_file", "_source_row"];
+
+/// The `_source_file` value, or None without `--add-source-columns`. Standard input is "-".
+pub fn source_file(options: &CsvOptions) -> Option<String> {
+    options.add_source_columns.then(|| options.input_file.clone().unwrap_or_else(|| "-".to_string()))
+}
+
+/// The `SOURCE_COLUMNS` values for the `row`th record, none when `file` is None.
+pub fn source_values(file: &Option<String>, row: u64) -> Vec<String> {
+    match file {
+        Some(file) => vec![file.clone(), row.to_string()],
+        None => vec![],
+    }
+}
+
+pub fn enumerate_output_headers(input_has_headers: bool, first_row: StringRecord, selected_indices: &[usize]) -> Vec<String> {
+    let mut out_headers = Vec::with_capacity(selected_indices.len());
     if input_has_headers {
         out_headers.extend(selected_indices.iter().map(|&i| first_row[i].to_string()));
     } else {
-        let alphabet = ('a'..='z').map(String::from).collect::<Vec<_>>();
-        out_headers.extend(selected_indices.iter()
-            .map(|&i| alphabet[i % 26].repeat(1 + i / 26)));
+        out_headers.extend(selected_indices.iter().map(|&i| generated_column_name(i)));
     }
     out_headers
-}
\ No newline at end of file
+}
+
+/// The output order of columns merged from several inputs: the `--columns-from` columns first, then
+/// the rest, sorted by name with `--sort-columns`. Each is a name with its index in `names`, or
+/// None for a `--columns-from` column that isn't there, to be written empty.
+pub fn column_order(options: &CsvOptions, names: &[String]) -> Vec<(String, Option<usize>)> {
+    let mut rest = (0..names.len()).collect::<Vec<_>>();
+    if options.sort_columns {
+        rest.sort_by(|&a, &b| names[a].cmp(&names[b]));
+    }
+    let mut order = vec![];
+    for name in options.columns_from.iter().flatten() {
+        match rest.iter().position(|&i| names[i] == *name) {
+            Some(p) => order.push((name.clone(), Some(rest.remove(p)))),
+            None => order.push((name.clone(), None)),
+        }
+    }
+    order.extend(rest.into_iter().map(|i| (names[i].clone(), Some(i))));
+    order
+}
+
+/// The name given to column `i` of a headerless file: a, b, ..., z, aa, bb, ...
+pub fn generated_column_name(i: usize) -> String {
+    let letter = (b'a' + (i % 26) as u8) as char;
+    std::iter::repeat_n(letter, 1 + i / 26).collect()
+}
+
+/// The columns a value takes up in a terminal: two for wide East Asian characters and most emoji,
+/// none for combining marks, so tables line up whatever the script.
+pub fn display_width(value: &str) -> usize {
+    UnicodeWidthStr::width(value)
+}
+
+/// The longest start of a value that fits in `width` terminal columns, and the columns it takes up.
+pub fn truncate_to_width(value: &str, width: usize) -> (&str, usize) {
+    let mut used = 0;
+    for (i, c) in value.char_indices() {
+        let char_width = UnicodeWidthChar::width(c).unwrap_or(0);
+        if used + char_width > width {
+            return (&value[..i], used);
+        }
+        used += char_width;
+    }
+    (value, used)
+}
+
+/// A value followed by spaces to take up `width` terminal columns.
+pub fn pad_to_width(value: &str, width: usize) -> String {
+    format!("{}{}", value, " ".repeat(width.saturating_sub(display_width(value))))
+}
+
+/// Guesses whether a sample's first row is a header, voting per column like Python's `csv.Sniffer`:
+/// when the remaining rows agree on a type (all numeric) or a length, a first value that breaks the
+/// pattern votes for a header and one that fits votes against. Columns without a pattern abstain.
+pub fn looks_like_header(sample: &[StringRecord]) -> bool {
+    let Some((first, rows)) = sample.split_first() else {
+        return true;
+    };
+    if rows.is_empty() {
+        return true;
+    }
+    let is_numeric = |v: &str| v.trim().parse::<f64>().is_ok();
+    let mut votes = 0;
+    for (i, header) in first.iter().enumerate() {
+        let values = rows.iter().filter_map(|r| r.get(i)).filter(|v| !v.is_empty()).collect::<Vec<_>>();
+        if values.is_empty() {
+            continue;
+        }
+        if values.iter().all(|v| is_numeric(v)) {
+            votes += if is_numeric(header) { -1 } else { 1 };
+        } else if values.iter().all(|v| v.chars().count() == values[0].chars().count()) {
+            votes += if header.chars().count() == values[0].chars().count() { -1 } else { 1 };
+        }
+    }
+    votes > 0
+}
+
+/// Whether a path can be read more than once. Named pipes and `/dev/fd/*` from process
+/// substitution can only be read once, so features that look ahead must skip them.
+pub fn is_regular_file(path: &str) -> bool {
+    std::fs::metadata(path).is_ok_and(|m| m.is_file())
+}
+
+/// Applies `--auto-header` by sampling the start of the input file.
+pub fn detect_headers(options: &CsvOptions) -> Result<bool, Box<dyn Error>> {
+    if !options.input_file.as_deref().is_some_and(is_regular_file) {
+        return Err(Box::from("--auto-header can't sample a pipe without consuming it"));
+    }
+    // --follow would wait forever for the rest of the sample in a file shorter than it, and
+    // --drop-if-empty names columns by a header row the sample is read without
+    let sniff_options = CsvOptions { input_has_headers: Some(false), assertions: vec![], metrics: None, follow: false,
+                                     drop_if_empty: None, drop_empty_rows: false, ..options.clone() };
+    let mut reader = csv_reader(&sniff_options, sniff_options.get_input_file()?);
+    let sample = reader.records().take(20).collect::<Result<Vec<_>, _>>()?;
+    Ok(looks_like_header(&sample))
+}
+
+/// Deterministic numeric and text columns for benchmarks; every fifth column is text.
+pub fn synthetic_csv(rows: usize, columns: usize) -> String {
+    let mut csv = (1..=columns).map(|c| format!("col{}", c)).collect::<Vec<_>>().join(",");
+    csv.push('\n');
+    for row in 0..rows {
+        let values = (0..columns).map(|c| match c % 5 {
+            4 => format!("text {}", (row * 31 + c) % 97),
+            _ => ((row * 7919 + c * 104_729) % 100_000).to_string(),
+        });
+        csv.push_str(&values.collect::<Vec<_>>().join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Roughly what a string held in a collection costs beyond its text: its pointer, length and
+/// capacity, and its share of the table, for `--max-memory` estimates.
+pub const STRING_OVERHEAD: u64 = 48;
+
+/// Fails once a tool's estimate of the memory it holds passes `--max-memory`, saying what holds
+/// it and how to hold less.
+pub fn check_memory(options: &CsvOptions, used: u64, what: &str, mitigation: &str) -> Result<(), Box<dyn Error>> {
+    match options.max_memory {
+        Some(limit) if used > limit => Err(Box::from(format!("{} need more than --max-memory {} bytes. {}, or raise --max-memory",
+                                                                 what, limit, mitigation))),
+        _ => Ok(()),
+    }
+}
+
+/// Times a tool on synthetic wide and long files for the hidden `--self-bench` flag, printing CSV results.
+pub fn self_bench<F>(run: F) -> Result<(), Box<dyn Error>>
+    where F: Fn(&CsvOptions) -> Result<(), Box<dyn Error>> {
+    let dir = std::env::temp_dir();
+    println!("dataset,rows,columns,bytes,seconds,mb_per_second");
+    for (name, rows, columns) in [("wide", 2_000, 500), ("long", 200_000, 5)] {
+        let data = synthetic_csv(rows, columns);
+        let input = dir.join(format!("csvstar-bench-{}-{}.csv", std::process::id(), name));
+        let output = dir.join(format!("csvstar-bench-{}-{}.out.csv", std::process::id(), name));
+        std::fs::write(&input, &data)?;
+        let options = CsvOptions {
+            input_file: Some(input.to_string_lossy().to_string()),
+            output_file: Some(output.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        let start = std::time::Instant::now();
+        let result = run(&options);
+        let seconds = start.elapsed().as_secs_f64();
+        let _ = std::fs::remove_file(&input);
+        let _ = std::fs::remove_file(&output);
+        result?;
+        println!("{},{},{},{},{:.3},{:.1}", name, rows, columns, data.len(), seconds, data.len() as f64 / 1e6 / seconds);
+    }
+    Ok(())
+}
+
+/// A temporary directory for a test's files, removed with everything in it when dropped, so
+/// tests can run in parallel without sharing file names.
+#[cfg(test)]
+pub struct TestDir(tempfile::TempDir);
+
+#[cfg(test)]
+impl TestDir {
+    pub fn new() -> Self {
+        TestDir(tempfile::tempdir().expect("Unable to create temporary directory"))
+    }
+
+    /// The path of a file named `name` in the directory.
+    pub fn path(&self, name: &str) -> String {
+        self.0.path().join(name).to_string_lossy().to_string()
+    }
+}
+
+#[cfg(test)]
+impl Default for TestDir {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(rows: &[&[&str]]) -> Vec<StringRecord> {
+        rows.iter().map(|r| StringRecord::from(r.to_vec())).collect()
+    }
+
+    #[test]
+    fn test_csv_reader_quote_char() {
+        let options = CsvOptions { delimiter: Some(';'), quote_char: Some('\''), ..Default::default() };
+        let input = Box::new(std::io::Cursor::new("a;b\n'x;y';\"z\"\n"));
+        let records = csv_reader(&options, input).records().collect::<Result<Vec<_>, _>>().expect("Invalid csv");
+        assert_eq!(records, sample(&[&["x;y", "\"z\""]]));
+    }
+
+    #[test]
+    fn test_select_column_indices() {
+        let headers = StringRecord::from(vec!["a", "b", "c", "d"]);
+        let select = |spec: &[&str], zero_based: bool| {
+            let spec = Some(spec.iter().map(|s| s.to_string()).collect());
+            select_column_indices_with_base(&headers, &spec, zero_based).map_err(|e| e.to_string())
+        };
+
+        assert_eq!(select(&["1", "-1", "2-3", "a"], false), Ok(vec![0, 3, 1, 2, 0]));
+        assert_eq!(select(&["0", "-1", "2-3", "a"], true), Ok(vec![0, 3, 2, 3, 0]));
+        assert_eq!(select(&["-4"], false), select(&["-4"], true));
+        assert_eq!(select(&["0-3"], true), Ok(vec![0, 1, 2, 3]));
+
+        assert_eq!(select(&["0"], false), Err("Column 0 is invalid. Columns are 1-based.".to_string()));
+        assert_eq!(select(&["0-2"], false), Err("Column 0 is invalid. Columns are 1-based.".to_string()));
+        assert_eq!(select(&["4"], false), Ok(vec![3]));
+        assert_eq!(select(&["4"], true), Err("Column 4 is invalid. There are 4 columns.".to_string()));
+        assert_eq!(select(&["2-4"], false), Ok(vec![1, 2, 3]));
+        assert_eq!(select(&["2-4"], true), Err("Invalid range. There are only 4 columns: 2-4".to_string()));
+        assert_eq!(select(&["-5"], true), Err("Column -5 is invalid. There are 4 columns.".to_string()));
+    }
+
+    #[test]
+    fn test_index_spec() {
+        assert_eq!(index_spec(&[0, 1, 2, 6, 4, 5, 9, 10, 11, 12], false), "1-3,7,5,6,10-13");
+        assert_eq!(index_spec(&[0, 1, 2, 2], true), "0-2,2");
+        assert_eq!(index_spec(&[], false), "");
+    }
+
+    #[test]
+    fn test_duplicate_names() {
+        let headers = StringRecord::from(vec!["id", "name", "id"]);
+        let select = |spec: &str, duplicates| select_column_indices_with(&headers, &Some(parse_column_list(spec)), false, duplicates)
+            .map_err(|e| e.to_string());
+        assert_eq!(select("id", DuplicateNames::Error).unwrap_err(),
+                   "Column name 'id' is ambiguous: columns 1, 3 all have it. Select one by offset instead");
+        assert!(select("\"id\"", DuplicateNames::Error).is_err());
+        assert_eq!(select("name,id", DuplicateNames::First), Ok(vec![1, 0]));
+        assert_eq!(select("id,name", DuplicateNames::All), Ok(vec![0, 2, 1]));
+        assert_eq!(select("3,name", DuplicateNames::Error), Ok(vec![2, 1]));
+    }
+
+    #[test]
+    fn test_reorder_indices() {
+        let headers = StringRecord::from(vec!["id", "name", "email", "created_at", "notes"]);
+        let all = (0..headers.len()).collect::<Vec<_>>();
+        let first = Reorder::First(parse_column_list("created_at,id"));
+        let last = Reorder::Last(vec!["notes".to_string()]);
+        assert_eq!(reorder_indices(&headers, all.clone(), std::slice::from_ref(&first), false, DuplicateNames::Error).unwrap(), vec![3, 0, 1, 2, 4]);
+        assert_eq!(reorder_indices(&headers, all.clone(), &[parse_move("email:1").unwrap(), last], false, DuplicateNames::Error).unwrap(), vec![2, 0, 1, 3, 4]);
+        assert_eq!(reorder_indices(&headers, all.clone(), &[parse_move("1:5").unwrap()], false, DuplicateNames::Error).unwrap(), vec![1, 2, 3, 4, 0]);
+
+        assert!(reorder_indices(&headers, all.clone(), &[parse_move("id:6").unwrap()], false, DuplicateNames::Error).is_err());
+        assert!(reorder_indices(&headers, vec![1, 2], &[first], false, DuplicateNames::Error).is_err());
+
+        let headers = StringRecord::from(vec!["id", "id", "x"]);
+        assert_eq!(reorder_indices(&headers, vec![0, 1, 2], &[parse_move("id:2").unwrap()], false, DuplicateNames::All).unwrap(), vec![2, 0, 1]);
+        assert_eq!(reorder_indices(&headers, vec![0, 1, 2], &[parse_move("id:3").unwrap()], false, DuplicateNames::All).unwrap_err().to_string(),
+                   "Can't move 'id' (2 columns) to position 3 of 3 columns");
+        assert!(parse_move("email").is_err());
+        assert!(parse_move("email:0").is_err());
+    }
+
+    #[test]
+    fn test_parse_column_list() {
+        assert_eq!(parse_column_list("1, id ,3-5"), vec!["1", "id", "3-5"]);
+        assert_eq!(parse_column_list("\"revenue, net\",id"), vec!["\"revenue, net\"", "id"]);
+        assert_eq!(parse_column_list(" \"say \"\"hi\"\", ok\" "), vec!["\"say \"\"hi\"\", ok\""]);
+
+        let headers = StringRecord::from(vec!["revenue, net", "x-y", "x", "y", "2020", "say \"hi\""]);
+        let select = |spec: &str| select_column_indices(&headers, &Some(parse_column_list(spec))).map_err(|e| e.to_string());
+        assert_eq!(select("\"revenue, net\",\"x-y\",\"2020\",\"say \"\"hi\"\"\""), Ok(vec![0, 1, 4, 5]));
+        // Unquoted, 2020 is an offset
+        assert!(select("x-y,2020").is_err());
+        assert!(select("\"revenue\"").unwrap_err().starts_with("Column 'revenue' not found"));
+    }
+
+    #[test]
+    fn test_select_name_range() {
+        let headers = StringRecord::from(vec!["id", "first_name", "e-mail", "total", "x-y", "x"]);
+        let select = |spec: &str| {
+            select_column_indices(&headers, &Some(vec![spec.to_string()])).map_err(|e| e.to_string())
+        };
+
+        assert_eq!(select("first_name-total"), Ok(vec![1, 2, 3]));
+        assert_eq!(select("id-e-mail"), Ok(vec![0, 1, 2]));
+        assert_eq!(select("e-mail-x"), Ok(vec![2, 3, 4, 5]));
+        assert_eq!(select("total-total"), Ok(vec![3]));
+        // An exact name wins over a range
+        assert_eq!(select("x-y"), Ok(vec![4]));
+        assert_eq!(select("total-id"), Err("Invalid range. 'total' comes after 'id': total-id".to_string()));
+        assert!(select("id-missing").unwrap_err().starts_with("Column 'id-missing' not found"));
+    }
+
+    #[test]
+    fn test_display_width() {
+        assert_eq!(display_width("Zürich"), 6);
+        assert_eq!(display_width("東京"), 4);
+        assert_eq!(display_width("e\u{301}"), 1);
+        assert_eq!(truncate_to_width("東京都", 5), ("東京", 4));
+        assert_eq!(truncate_to_width("abc", 5), ("abc", 3));
+        assert_eq!(pad_to_width("東京", 6), "東京  ");
+    }
+
+    #[test]
+    fn test_detect_headers_follow() {
+        let dir = TestDir::new();
+        let input_file = dir.path("test_input_auto_header.csv");
+        std::fs::write(&input_file, "id,name\n1,Jane\n2,Bob\n").expect("Unable to write input file");
+        let options = CsvOptions { input_file: Some(input_file), follow: true, ..Default::default() };
+        assert!(detect_headers(&options).expect("detect_headers failed"));
+
+        // The sample has no header row to name --drop-if-empty's columns by
+        let options = CsvOptions { drop_if_empty: Some(vec!["name".to_string()]), follow: false, ..options };
+        assert!(detect_headers(&options).expect("detect_headers failed"));
+    }
+
+    #[test]
+    fn test_looks_like_header() {
+        assert!(looks_like_header(&sample(&[&["id", "name"], &["1", "Jane"], &["2", "Bob"]])));
+        assert!(!looks_like_header(&sample(&[&["0", "Ann"], &["1", "Jane"], &["2", "Bob"]])));
+        assert!(looks_like_header(&sample(&[&["code", "flag"], &["AB", "x"], &["CD", "y"]])));
+        assert!(!looks_like_header(&sample(&[&["ZZ", "Ann"], &["AB", "Jane"], &["CD", "Bob"]])));
+    }
+
+    #[test]
+    fn test_column_order() {
+        let names = ["id", "name", "city"].map(String::from);
+        let mut options = CsvOptions { sort_columns: true, ..Default::default() };
+        assert_eq!(column_order(&options, &names),
+                   vec![("city".to_string(), Some(2)), ("id".to_string(), Some(0)), ("name".to_string(), Some(1))]);
+        options.columns_from = Some(vec!["name".to_string(), "zip".to_string()]);
+        assert_eq!(column_order(&options, &names),
+                   vec![("name".to_string(), Some(1)), ("zip".to_string(), None), ("city".to_string(), Some(2)), ("id".to_string(), Some(0))]);
+    }
+
+    #[test]
+    fn test_header_aliases() {
+        let dir = TestDir::new();
+        let aliases_file = &dir.path("test_aliases.csv");
+        std::fs::write(aliases_file, "alias,name\nE-mail,email\nemail_address,email\nCust ID,id\n").expect("Unable to write aliases file");
+
+        let options = CsvOptions { header_aliases: Some(parse_header_aliases(aliases_file).unwrap()), ..Default::default() };
+        let names = column_names(&options, StringRecord::from(vec![" cust id", "EMAIL_ADDRESS", "name"]));
+        assert_eq!(names, vec!["id", "email", "name"]);
+        let indices = select_column_indices(&names, &Some(vec!["email".to_string()])).unwrap();
+        assert_eq!(indices, [1]);
+
+        std::fs::write(aliases_file, "alias,name\nmail,email\nMail,e_mail\n").expect("Unable to write aliases file");
+        assert!(parse_header_aliases(aliases_file).unwrap_err().ends_with("row 3 maps 'Mail' to 'e_mail', but an earlier row maps it to 'email'"));
+    }
+}
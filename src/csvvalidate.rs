@@ -0,0 +1,280 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+pub mod schema;
+
+use crate::args::global_args;
+use clap::Arg;
+use csv::{ReaderBuilder, StringRecord};
+use options::CsvOptions;
+use schema::TableSchema;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io::BufRead;
+use std::path::Path;
+
+struct CsvValidateOptions { schema: TableSchema }
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>());
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> (CsvOptions, CsvValidateOptions) {
+    let executable_name = args[0].clone();
+
+    let command = args::color_args(global_args())
+        .display_name(executable_name)
+        .about("Validates CSV files against a Table Schema, writing a row,column,value,error report of everything that fails.")
+        .after_help("With --color, the failing values are red.")
+        .arg(Arg::new("schema")
+            .short('s')
+            .long("schema")
+            .required(true)
+            .value_parser(TableSchema::from_file)
+            .help("Frictionless Table Schema JSON file giving each column's type, format and constraints, and the \
+                   primaryKey and foreignKeys. Columns are matched by name, or by position with -H. A foreign key's \
+                   reference resource is a CSV file relative to the schema, or \"\" for the input itself"));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let action = CsvValidateOptions {
+        schema: matches.remove_one::<TableSchema>("schema").expect("--schema is required"),
+    };
+
+    (args::build_options(matches), action)
+}
+
+/// Reads the values of `fields` from each record of a foreign key's reference file.
+fn read_keys(path: &Path, fields: &[String]) -> Result<HashSet<Vec<String>>, Box<dyn Error>> {
+    let mut reader = ReaderBuilder::new().from_path(path)
+        .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    let headers = reader.headers()?.clone();
+    let indices = fields.iter()
+        .map(|f| headers.iter().position(|h| h == f)
+            .ok_or_else(|| format!("Reference field '{}' is not in {}", f, path.display())))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut keys = HashSet::new();
+    for result in reader.records() {
+        keys.insert(key(&result?, &indices));
+    }
+    Ok(keys)
+}
+
+fn key(record: &StringRecord, indices: &[usize]) -> Vec<String> {
+    indices.iter().map(|&i| record.get(i).unwrap_or_default().to_string()).collect()
+}
+
+fn process_csv(options: &CsvOptions, validate_options: &CsvValidateOptions) -> Result<(), Box<dyn Error>> {
+    let schema = &validate_options.schema;
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    let mut reader = csvutil::csv_reader(options, input);
+
+    // Get the column headers
+    let first_row = reader.headers()?.clone();
+
+    // Match each schema field to its column
+    let indices = if options.config().has_headers {
+        schema.fields.iter()
+            .map(|f| first_row.iter().position(|h| h == f.name)
+                .ok_or_else(|| format!("Schema field '{}' is not in the input", f.name)))
+            .collect::<Result<Vec<_>, _>>()?
+    } else if schema.fields.len() <= first_row.len() {
+        (0..schema.fields.len()).collect()
+    } else {
+        return Err(Box::from(format!("The schema has {} fields but the input has {} columns", schema.fields.len(), first_row.len())));
+    };
+    let columns = |names: &[String]| names.iter()
+        .map(|name| indices[schema.fields.iter().position(|f| f.name == *name).unwrap_or_default()])
+        .collect::<Vec<_>>();
+
+    let primary_key = columns(&schema.primary_key);
+    let foreign_keys = schema.foreign_keys.iter()
+        .map(|fk| Ok((columns(&fk.fields), match &fk.resource {
+            Some(path) => Some(read_keys(path, &fk.reference_fields)?),
+            None => None,
+        })))
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    // Keys referring to the input itself can only be checked once all of it has been read
+    let mut own_keys: Vec<HashSet<Vec<String>>> = vec![HashSet::new(); foreign_keys.len()];
+    let mut pending: Vec<(usize, u64, Vec<String>)> = vec![];
+
+    let output_has_headers = options.output_has_headers();
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+
+    if output_has_headers {
+        csv_writer.write_record(["row", "column", "value", "error"])?;
+    }
+
+    // With --color the failing values are red
+    let color = options.use_color();
+    let mut failures = 0;
+    let mut fail = |row: u64, column: &str, value: &str, error: &str| -> csv::Result<()> {
+        failures += 1;
+        let value = if color { writer::colored(value, writer::RED) } else { value.to_string() };
+        csv_writer.write_record([row.to_string().as_str(), column, &value, error])
+    };
+
+    let mut unique_values: Vec<HashMap<String, u64>> = vec![HashMap::new(); schema.fields.len()];
+    let mut primary_keys: HashMap<Vec<String>, u64> = HashMap::new();
+    for (row, result) in reader.records().enumerate() {
+        let record = result?;
+        let row = row as u64 + 1;
+
+        for (f, (field, &i)) in schema.fields.iter().zip(&indices).enumerate() {
+            let value = record.get(i).unwrap_or_default();
+            if schema.is_missing(value) {
+                if field.constraints.required || schema.primary_key.contains(&field.name) {
+                    fail(row, &field.name, value, "is required")?;
+                }
+                continue;
+            }
+            if let Err(problem) = field.check(value) {
+                fail(row, &field.name, value, &problem)?;
+            }
+            if field.constraints.unique {
+                if let Some(first) = unique_values[f].insert(value.to_string(), row) {
+                    fail(row, &field.name, value, &format!("is not unique, it is also in row {}", first))?;
+                }
+            }
+        }
+
+        if !primary_key.is_empty() {
+            let values = key(&record, &primary_key);
+            if !values.iter().any(|v| schema.is_missing(v)) {
+                if let Some(first) = primary_keys.get(&values) {
+                    fail(row, &schema.primary_key.join(","), &values.join(","),
+                         &format!("is not a unique primary key, it is also in row {}", first))?;
+                } else {
+                    primary_keys.insert(values, row);
+                }
+            }
+        }
+
+        for (k, ((fk_columns, reference), fk)) in foreign_keys.iter().zip(&schema.foreign_keys).enumerate() {
+            if reference.is_none() {
+                own_keys[k].insert(key(&record, &columns(&fk.reference_fields)));
+            }
+            let values = key(&record, fk_columns);
+            // A key that is entirely missing doesn't refer to anything
+            if values.iter().all(|v| schema.is_missing(v)) {
+                continue;
+            }
+            match reference {
+                Some(keys) if !keys.contains(&values) => {
+                    let resource = fk.resource.as_ref().map(|p| p.display().to_string()).unwrap_or_default();
+                    fail(row, &fk.fields.join(","), &values.join(","), &format!("is not in {} {}", resource, fk.reference_fields.join(",")))?;
+                }
+                Some(_) => {}
+                None => pending.push((k, row, values)),
+            }
+        }
+    }
+
+    for (k, row, values) in pending {
+        if !own_keys[k].contains(&values) {
+            let fk = &schema.foreign_keys[k];
+            fail(row, &fk.fields.join(","), &values.join(","), &format!("is not in any row's {}", fk.reference_fields.join(",")))?;
+        }
+    }
+
+    csv_writer.finish()?;
+
+    if failures > 0 {
+        return Err(Box::from(format!("{} {} failed validation", failures, if failures == 1 { "value" } else { "values" })));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn validate(schema: &str, input: &str) -> (Result<(), String>, String) {
+        validate_in(&csvutil::TestDir::new(), schema, input)
+    }
+
+    fn validate_in(dir: &csvutil::TestDir, schema: &str, input: &str) -> (Result<(), String>, String) {
+        let schema_file = &dir.path("test_schema_validate.json");
+        let input_file = &dir.path("test_input_validate.csv");
+        let output_file = &dir.path("test_output_validate.csv");
+        fs::write(schema_file, schema).expect("Unable to write schema file");
+        fs::write(input_file, input).expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+        let action = CsvValidateOptions { schema: TableSchema::from_file(schema_file).expect("Invalid schema") };
+
+        let result = process_csv(&options, &action).map_err(|e| e.to_string());
+        // A schema that doesn't fit the input fails before the report is started
+        let output = fs::read_to_string(output_file).unwrap_or_default();
+        (result, output)
+    }
+
+    #[test]
+    fn test_process_csv_valid() {
+        let (result, output) = validate(
+            r#"{"fields": [{"name": "id", "type": "integer"}, {"name": "email", "format": "email"}], "primaryKey": "id"}"#,
+            "id,email,extra\n1,a@example.com,x\n2,,y\n");
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "row,column,value,error\n");
+    }
+
+    #[test]
+    fn test_process_csv_failures() {
+        let (result, output) = validate(
+            r#"{"fields": [
+                  {"name": "id", "type": "integer"},
+                  {"name": "qty", "type": "integer", "constraints": {"required": true, "maximum": 10}},
+                  {"name": "code", "constraints": {"unique": true}},
+                  {"name": "parent", "type": "integer"}],
+                "primaryKey": "id",
+                "foreignKeys": [{"fields": "parent", "reference": {"resource": "", "fields": "id"}}]}"#,
+            "id,qty,code,parent\n1,5,a,\n1,11,a,1\nx,,b,7\n");
+        assert_eq!(result, Err("6 values failed validation".to_string()));
+        assert_eq!(output, "row,column,value,error\n\
+                            2,qty,11,is greater than the maximum 10\n\
+                            2,code,a,\"is not unique, it is also in row 1\"\n\
+                            2,id,1,\"is not a unique primary key, it is also in row 1\"\n\
+                            3,id,x,is not a valid integer\n\
+                            3,qty,,is required\n\
+                            3,parent,7,is not in any row's id\n");
+    }
+
+    #[test]
+    fn test_process_csv_foreign_key_file() {
+        let dir = csvutil::TestDir::new();
+        fs::write(dir.path("test_countries_validate.csv"), "code,name\nGB,United Kingdom\nFR,France\n").expect("Unable to write reference file");
+        let (result, output) = validate_in(&dir, 
+            r#"{"fields": [{"name": "country"}],
+                "foreignKeys": [{"fields": ["country"], "reference": {"resource": "test_countries_validate.csv", "fields": ["code"]}}]}"#,
+            "country\nGB\nDE\n\n");
+        assert_eq!(result, Err("1 value failed validation".to_string()));
+        assert_eq!(output, format!("row,column,value,error\n2,country,DE,is not in {} code\n", dir.path("test_countries_validate.csv")));
+    }
+
+    #[test]
+    fn test_process_csv_missing_column() {
+        let (result, _) = validate(r#"{"fields": [{"name": "id"}, {"name": "name"}]}"#, "id\n1\n");
+        assert_eq!(result, Err("Schema field 'name' is not in the input".to_string()));
+    }
+}
@@ -0,0 +1,569 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+
+use crate::args::global_args;
+use clap::Arg;
+use options::CsvOptions;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::io::BufRead;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Window {
+    /// The current record and the N-1 before it
+    Rows(usize),
+    /// Records whose `--order-by` time is within this many seconds before the current one
+    Seconds(i64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Aggregate { Sum, Mean, Min, Max, Count }
+
+struct RollingOptions {
+    value_column: String,
+    window: Window,
+    window_spec: String,
+    order_by: Option<String>,
+    aggregates: Vec<Aggregate>,
+}
+
+/// Numbers each record's session: a key's events belong to one session until the gap between
+/// two of them exceeds `gap` seconds.
+struct SessionOptions {
+    gap: i64,
+    order_by: String,
+    key_columns: Option<Vec<String>>,
+}
+
+struct CsvWindowOptions {
+    rolling: Option<RollingOptions>,
+    session: Option<SessionOptions>,
+    cumsum: Vec<String>,
+    cumcount: bool,
+    /// Columns to rank by, and whether the largest value ranks first
+    rank: Vec<(String, bool)>,
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>())?;
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_window(s: &str) -> Result<Window, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("Window must be at least 1 row".to_string()),
+        Ok(n) => Ok(Window::Rows(n)),
+        Err(_) => dates::parse_duration(s).map(Window::Seconds),
+    }
+}
+
+fn parse_aggregate(s: &str) -> Result<Aggregate, String> {
+    match s {
+        "sum" => Ok(Aggregate::Sum),
+        "mean" => Ok(Aggregate::Mean),
+        "min" => Ok(Aggregate::Min),
+        "max" => Ok(Aggregate::Max),
+        "count" => Ok(Aggregate::Count),
+        _ => Err(format!("Unknown aggregate '{}'. Expected sum, mean, min, max or count", s)),
+    }
+}
+
+fn parse_rank(s: &str) -> Result<(String, bool), String> {
+    match s.rsplit_once(':') {
+        Some((column, "desc")) => Ok((column.to_string(), true)),
+        Some((column, "asc")) => Ok((column.to_string(), false)),
+        _ => Ok((s.to_string(), true)),
+    }
+}
+
+fn aggregate_name(aggregate: Aggregate) -> &'static str {
+    match aggregate {
+        Aggregate::Sum => "sum",
+        Aggregate::Mean => "mean",
+        Aggregate::Min => "min",
+        Aggregate::Max => "max",
+        Aggregate::Count => "count",
+    }
+}
+
+fn parse_args(args: Vec<String>) -> Result<(CsvOptions, CsvWindowOptions), String> {
+    let executable_name = args[0].clone();
+
+    let command = args::in_place_args(args::contract_args(global_args()))
+        .display_name(executable_name)
+        .about("Appends rolling aggregates, running totals and ranks to CSV files.")
+        .arg(Arg::new("value")
+            .long("value")
+            .requires("window")
+            .allow_negative_numbers(true)
+            .help("Name or offset of the numeric column to aggregate"))
+        .arg(Arg::new("window")
+            .short('w')
+            .long("window")
+            .requires("value")
+            .help("Window size: a number of records (e.g. 7), or a duration (e.g. 7d, 12h, 30m) measured on the --order-by column"))
+        .arg(Arg::new("order_by")
+            .long("order-by")
+            .allow_negative_numbers(true)
+            .help("Date or timestamp column for duration windows and sessions. The input must already be sorted by it"))
+        .arg(Arg::new("aggregate")
+            .short('a')
+            .long("agg")
+            .help("Aggregate to append: sum, mean, min, max or count. May be repeated, defaults to mean")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("cumsum")
+            .long("cumsum")
+            .allow_negative_numbers(true)
+            .help("Append a running total of this column. May be repeated")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("cumcount")
+            .long("cumcount")
+            .action(clap::ArgAction::SetTrue)
+            .help("Append a running count of records, starting at 1"))
+        .arg(Arg::new("session_gap")
+            .long("session-gap")
+            .requires("order_by")
+            .help("Append a session_id column, starting a new session when more than this duration (e.g. 30m) passes between events on the --order-by column"))
+        .arg(Arg::new("session_key")
+            .long("session-key")
+            .requires("session_gap")
+            .allow_negative_numbers(true)
+            .help("Columns identifying whose events a session groups, e.g. user_id, using the same syntax as csvcut's --columns. The input need only be sorted by time within each key")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("rank")
+            .long("rank")
+            .allow_negative_numbers(true)
+            .help("Append the rank of each record by this column, largest first. Use column:asc to rank smallest first. Ties share a rank. Buffers the whole input. May be repeated")
+            .action(clap::ArgAction::Append))
+        .group(clap::ArgGroup::new("operation")
+            .args(["window", "cumsum", "cumcount", "session_gap", "rank"])
+            .multiple(true)
+            .required(true));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let order_by: Option<String> = matches.remove_one("order_by");
+    let rolling = match matches.remove_one::<String>("window") {
+        Some(window_spec) => {
+            let window = parse_window(&window_spec)?;
+            if matches!(window, Window::Seconds(_)) && order_by.is_none() {
+                return Err(format!("A duration window ({}) requires --order-by", window_spec));
+            }
+            Some(RollingOptions {
+                value_column: matches.remove_one("value").unwrap(),
+                window,
+                window_spec,
+                order_by: order_by.clone(),
+                aggregates: matches.remove_many::<String>("aggregate")
+                    .map(|v| v.map(|s| parse_aggregate(&s)).collect::<Result<Vec<_>, _>>())
+                    .unwrap_or(Ok(vec![Aggregate::Mean]))?,
+            })
+        }
+        None => None,
+    };
+
+    let session = match (matches.remove_one::<String>("session_gap"), order_by) {
+        (Some(gap), Some(order_by)) => Some(SessionOptions {
+            gap: dates::parse_duration(&gap)?,
+            order_by,
+            key_columns: matches.remove_many::<String>("session_key")
+                .map(|v| v.flat_map(|s| csvutil::parse_column_list(&s)).collect()),
+        }),
+        _ => None,
+    };
+
+    let action = CsvWindowOptions {
+        rolling,
+        session,
+        cumsum: matches.remove_many::<String>("cumsum").map(|v| v.collect()).unwrap_or_default(),
+        cumcount: matches.remove_one("cumcount").unwrap_or(false),
+        rank: matches.remove_many::<String>("rank")
+            .map(|v| v.map(|s| parse_rank(&s)).collect::<Result<Vec<_>, _>>())
+            .unwrap_or(Ok(vec![]))?,
+    };
+
+    Ok((args::build_options(matches), action))
+}
+
+fn aggregate(window: &VecDeque<(i64, Option<f64>)>, aggregate: Aggregate) -> Option<f64> {
+    let values = window.iter().filter_map(|&(_, v)| v);
+    match aggregate {
+        Aggregate::Count => Some(values.count() as f64),
+        Aggregate::Sum => values.reduce(|a, b| a + b),
+        Aggregate::Min => values.reduce(f64::min),
+        Aggregate::Max => values.reduce(f64::max),
+        Aggregate::Mean => {
+            let (n, sum) = values.fold((0, 0.0), |(n, sum), v| (n + 1, sum + v));
+            if n == 0 { None } else { Some(sum / n as f64) }
+        }
+    }
+}
+
+/// Adds a value keyed by row number or time, dropping whatever falls out of the window.
+fn slide(window: &mut VecDeque<(i64, Option<f64>)>, size: Window, key: i64, value: Option<f64>) {
+    window.push_back((key, value));
+    match size {
+        Window::Rows(n) => {
+            while window.len() > n {
+                window.pop_front();
+            }
+        }
+        Window::Seconds(seconds) => {
+            while window.front().is_some_and(|&(t, _)| t <= key - seconds) {
+                window.pop_front();
+            }
+        }
+    }
+}
+
+/// Competition ranks ("1224") of each value, or None for non-numeric values.
+fn ranks(values: &[Option<f64>], descending: bool) -> Vec<Option<usize>> {
+    let mut sorted = values.iter().flatten().copied().collect::<Vec<_>>();
+    sorted.sort_by(|a, b| if descending { b.total_cmp(a) } else { a.total_cmp(b) });
+    values.iter()
+        .map(|v| v.map(|v| 1 + sorted.partition_point(|&s| if descending { s > v } else { s < v })))
+        .collect()
+}
+
+fn parse_time(record: &csv::StringRecord, idx: Option<usize>, row: usize) -> Result<i64, String> {
+    let time_value = idx.and_then(|i| record.get(i)).unwrap_or_default();
+    dates::parse_timestamp(time_value).ok_or_else(|| format!("Row {}: cannot parse '{}' as a date", row + 1, time_value))
+}
+
+fn process_csv(options: &CsvOptions, window_options: &CsvWindowOptions) -> Result<(), Box<dyn Error>> {
+    let input: Box<dyn BufRead> = options.get_input_file()?;
+
+    let mut reader = csvutil::csv_reader(options, input);
+
+    // Get the column headers
+    let first_row = csvutil::column_names(options, reader.headers()?.clone());
+
+    let rolling = window_options.rolling.as_ref();
+    let value_idx = rolling
+        .map(|r| csvutil::select_column_index(&first_row, &r.value_column))
+        .transpose()?;
+    let order_idx = rolling.and_then(|r| r.order_by.as_ref())
+        .map(|c| csvutil::select_column_index(&first_row, c))
+        .transpose()?;
+    let cumsum_indices = window_options.cumsum.iter()
+        .map(|c| csvutil::select_column_index(&first_row, c))
+        .collect::<Result<Vec<_>, _>>()?;
+    let session = window_options.session.as_ref();
+    let session_time_idx = session
+        .map(|s| csvutil::select_column_index(&first_row, &s.order_by))
+        .transpose()?;
+    let session_key_indices = match session.and_then(|s| s.key_columns.as_ref()) {
+        Some(columns) => csvutil::select_column_indices(&first_row, &Some(columns.clone()))?,
+        None => vec![],
+    };
+    let rank_indices = window_options.rank.iter()
+        .map(|(c, descending)| csvutil::select_column_index(&first_row, c).map(|i| (i, *descending)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let output_has_headers = options.output_has_headers();
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+
+    if output_has_headers {
+        let all_indices = (0..first_row.len()).collect::<Vec<_>>();
+        let mut out_headers = csvutil::enumerate_output_headers(options.config().has_headers, first_row, &all_indices);
+        let mut new_headers = vec![];
+        if let (Some(r), Some(i)) = (rolling, value_idx) {
+            new_headers.extend(r.aggregates.iter()
+                .map(|&a| format!("{}_{}_{}", out_headers[i], aggregate_name(a), r.window_spec)));
+        }
+        new_headers.extend(cumsum_indices.iter().map(|&i| format!("{}_cumsum", out_headers[i])));
+        if window_options.cumcount {
+            new_headers.push("cumcount".to_string());
+        }
+        if session.is_some() {
+            new_headers.push("session_id".to_string());
+        }
+        new_headers.extend(rank_indices.iter().map(|&(i, _)| format!("{}_rank", out_headers[i])));
+        out_headers.extend(new_headers);
+        csv_writer.write_record(out_headers)?;
+    }
+
+    let mut window: VecDeque<(i64, Option<f64>)> = VecDeque::new();
+    let mut sums = vec![0.0; cumsum_indices.len()];
+    // Each key's last event time and current session
+    let mut sessions: HashMap<Vec<String>, (i64, u64)> = HashMap::new();
+    let mut n_sessions = 0;
+    // Ranking needs every value first, so rows are held back until the end
+    let mut buffered: Vec<Vec<String>> = vec![];
+    let mut rank_values: Vec<Vec<Option<f64>>> = vec![vec![]; rank_indices.len()];
+
+    for (row, result) in reader.records().enumerate() {
+        let record = result?;
+        let mut out = record.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+
+        if let (Some(r), Some(value_idx)) = (rolling, value_idx) {
+            let key = match r.window {
+                Window::Rows(_) => row as i64,
+                Window::Seconds(_) => {
+                    let time = parse_time(&record, order_idx, row)?;
+                    if window.back().is_some_and(|&(t, _)| t > time) {
+                        return Err(Box::from(format!("Row {}: input is not sorted by the --order-by column", row + 1)));
+                    }
+                    time
+                }
+            };
+            slide(&mut window, r.window, key, record.get(value_idx).and_then(|v| numbers::parse_number(v, false)));
+            out.extend(r.aggregates.iter()
+                .map(|&a| aggregate(&window, a).map(transform::format_number).unwrap_or_default()));
+        }
+
+        for (sum, &i) in sums.iter_mut().zip(&cumsum_indices) {
+            *sum += record.get(i).and_then(|v| numbers::parse_number(v, false)).unwrap_or(0.0);
+            out.push(transform::format_number(*sum));
+        }
+
+        if window_options.cumcount {
+            out.push((row + 1).to_string());
+        }
+
+        if let Some(s) = session {
+            let time = parse_time(&record, session_time_idx, row)?;
+            let key = session_key_indices.iter().map(|&i| record.get(i).unwrap_or_default().to_string()).collect::<Vec<_>>();
+            let id = match sessions.get_mut(&key) {
+                Some((last, _)) if time < *last => {
+                    return Err(Box::from(format!("Row {}: input is not sorted by the --order-by column", row + 1)));
+                }
+                Some((last, id)) if time - *last <= s.gap => {
+                    *last = time;
+                    *id
+                }
+                _ => {
+                    n_sessions += 1;
+                    sessions.insert(key, (time, n_sessions));
+                    n_sessions
+                }
+            };
+            out.push(id.to_string());
+        }
+
+        if rank_indices.is_empty() {
+            csv_writer.write_record(out)?;
+        } else {
+            for (values, &(i, _)) in rank_values.iter_mut().zip(&rank_indices) {
+                values.push(record.get(i).and_then(|v| numbers::parse_number(v, false)));
+            }
+            buffered.push(out);
+        }
+    }
+
+    let rank_columns = rank_values.iter().zip(&rank_indices)
+        .map(|(values, &(_, descending))| ranks(values, descending))
+        .collect::<Vec<_>>();
+    for (row, mut out) in buffered.into_iter().enumerate() {
+        out.extend(rank_columns.iter().map(|r| r[row].map(|v| v.to_string()).unwrap_or_default()));
+        csv_writer.write_record(out)?;
+    }
+
+    csv_writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_process_csv_row_window() {
+        let dir = csvutil::TestDir::new();
+        let output_file = &dir.path("test_output_window.csv");
+
+        let options = CsvOptions {
+            input_file: Some("test/test_input.csv".to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+
+        let action = CsvWindowOptions {
+            rolling: Some(RollingOptions {
+                value_column: "col2".to_string(),
+                window: Window::Rows(2),
+                window_spec: "2".to_string(),
+                order_by: None,
+                aggregates: vec![Aggregate::Mean, Aggregate::Sum],
+            }),
+            session: None,
+            cumsum: vec![],
+            cumcount: false,
+            rank: vec![],
+        };
+
+        process_csv(&options, &action).expect("process_csv failed");
+
+        let expected_output = "\
+col1,col2,col3,col2_mean_2,col2_sum_2
+1,2,3,2,2
+4,5,6,3.5,7
+7,8,9,6.5,13
+";
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, expected_output);
+    }
+
+    #[test]
+    fn test_process_csv_cumulative_and_rank() {
+        let dir = csvutil::TestDir::new();
+        let output_file = &dir.path("test_output_window.csv");
+
+        let options = CsvOptions {
+            input_file: Some("test/test_input.csv".to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+
+        let action = CsvWindowOptions {
+            rolling: None,
+            session: None,
+            cumsum: vec!["col1".to_string()],
+            cumcount: true,
+            rank: vec![("col3".to_string(), true)],
+        };
+
+        process_csv(&options, &action).expect("process_csv failed");
+
+        let expected_output = "\
+col1,col2,col3,col1_cumsum,cumcount,col3_rank
+1,2,3,1,1,3
+4,5,6,5,2,2
+7,8,9,12,3,1
+";
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, expected_output);
+
+        // Numbers are read as the other tools read them: thousands separators, and NaN as a word
+        let output = options::SharedBuffer::new();
+        let options = CsvOptions::default().with_input(std::io::Cursor::new("n\n\"1,000\"\nNaN\n 2 \n")).with_output(output.clone());
+        let action = CsvWindowOptions { cumsum: vec!["n".to_string()], cumcount: false, rank: vec![("n".to_string(), true)], ..action };
+        process_csv(&options, &action).expect("process_csv failed");
+        assert_eq!(output.to_string_lossy(), "n,n_cumsum,n_rank\n\"1,000\",1000,1\nNaN,1000,\n 2 ,1002,2\n");
+    }
+
+    #[test]
+    fn test_process_csv_float_noise() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_window_float.csv");
+        let output_file = &dir.path("test_output_window.csv");
+        fs::write(input_file, "price\n0.1\n0.2\n0.3\n").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+
+        let action = CsvWindowOptions {
+            rolling: Some(RollingOptions {
+                value_column: "price".to_string(),
+                window: Window::Rows(2),
+                window_spec: "2".to_string(),
+                order_by: None,
+                aggregates: vec![Aggregate::Sum, Aggregate::Mean],
+            }),
+            session: None,
+            cumsum: vec!["price".to_string()],
+            cumcount: false,
+            rank: vec![],
+        };
+
+        process_csv(&options, &action).expect("process_csv failed");
+
+        let expected_output = "\
+price,price_sum_2,price_mean_2,price_cumsum
+0.1,0.1,0.1,0.1
+0.2,0.3,0.15,0.3
+0.3,0.5,0.25,0.6
+";
+        assert_eq!(fs::read_to_string(output_file).expect("Unable to read output file"), expected_output);
+    }
+
+    #[test]
+    fn test_process_csv_sessions() {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path("test_input_sessions.csv");
+        let output_file = &dir.path("test_output_window.csv");
+        fs::write(input_file, "user,ts\na,2024-03-01T10:00:00\nb,2024-03-01T10:05:00\na,2024-03-01T10:20:00\n\
+                               a,2024-03-01T11:00:00\nb,2024-03-01T10:30:00\n").expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..Default::default()
+        };
+
+        let mut action = CsvWindowOptions {
+            rolling: None,
+            session: Some(SessionOptions { gap: 30 * 60, order_by: "ts".to_string(), key_columns: Some(vec!["user".to_string()]) }),
+            cumsum: vec![],
+            cumcount: false,
+            rank: vec![],
+        };
+
+        process_csv(&options, &action).expect("process_csv failed");
+
+        let expected_output = "\
+user,ts,session_id
+a,2024-03-01T10:00:00,1
+b,2024-03-01T10:05:00,2
+a,2024-03-01T10:20:00,1
+a,2024-03-01T11:00:00,3
+b,2024-03-01T10:30:00,2
+";
+        let actual_output = fs::read_to_string(output_file).expect("Unable to read output file");
+        assert_eq!(actual_output, expected_output);
+
+        // Without a key the events are one stream, which must be in order
+        action.session.as_mut().unwrap().key_columns = None;
+        let error = process_csv(&options, &action).expect_err("input is out of order");
+        assert_eq!(error.to_string(), "Row 5: input is not sorted by the --order-by column");
+    }
+
+    #[test]
+    fn test_ranks() {
+        let values = [Some(10.0), Some(30.0), None, Some(10.0), Some(20.0)];
+        assert_eq!(ranks(&values, true), vec![Some(3), Some(1), None, Some(3), Some(2)]);
+        assert_eq!(ranks(&values, false), vec![Some(1), Some(4), None, Some(1), Some(3)]);
+        assert_eq!(parse_rank("score:asc"), Ok(("score".to_string(), false)));
+        assert_eq!(parse_rank("score"), Ok(("score".to_string(), true)));
+    }
+
+    #[test]
+    fn test_aggregate_duration_window() {
+        let mut window = VecDeque::new();
+        let days = ["2024-03-01", "2024-03-02", "2024-03-08", "2024-03-09"];
+        let mut sums = vec![];
+        for (i, day) in days.iter().enumerate() {
+            slide(&mut window, Window::Seconds(7 * 86400), dates::parse_timestamp(day).unwrap(), Some(i as f64 + 1.0));
+            sums.push(aggregate(&window, Aggregate::Sum).unwrap());
+        }
+        assert_eq!(sums, vec![1.0, 3.0, 5.0, 7.0]);
+    }
+
+    #[test]
+    fn test_parse_window() {
+        assert_eq!(parse_window("7"), Ok(Window::Rows(7)));
+        assert_eq!(parse_window("7d"), Ok(Window::Seconds(7 * 86400)));
+        assert_eq!(parse_window("30m"), Ok(Window::Seconds(1800)));
+        assert!(parse_window("0").is_err());
+        assert!(parse_window("7y").is_err());
+    }
+}
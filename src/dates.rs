@@ -0,0 +1,188 @@
+//! Minimal date handling for the tools that bucket or order records by time, and for `--convert`.
+//!
+//! Accepts ISO 8601 style values: `2024-03-07`, `2024-03-07 12:30`, `2024-03-07T12:30:15`
+//! (a trailing `Z` or fractional seconds are ignored), or a plain number of seconds since the epoch.
+
+/// Days since 1970-01-01 for a proleptic Gregorian date, from Howard Hinnant's `days_from_civil`.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = (if y >= 0 { y } else { y - 399 }) / 400;
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+/// The number of days in a month, 1 to 12, of the given year.
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn parse_date_part(s: &str) -> Option<i64> {
+    let mut parts = s.splitn(3, '-');
+    let year = parts.next()?.parse::<i64>().ok()?;
+    let month = parts.next()?.parse::<u32>().ok()?;
+    let day = parts.next()?.parse::<u32>().ok()?;
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return None;
+    }
+    Some(days_from_civil(year, month, day))
+}
+
+fn parse_time_part(s: &str) -> Option<i64> {
+    let s = s.trim_end_matches('Z');
+    let s = s.split('.').next()?;
+    let mut parts = s.splitn(3, ':');
+    let hours = parts.next()?.parse::<i64>().ok()?;
+    let minutes = parts.next()?.parse::<i64>().ok()?;
+    let seconds = parts.next().map(|p| p.parse::<i64>().ok()).unwrap_or(Some(0))?;
+    if hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    Some(hours * 3600 + minutes * 60 + seconds)
+}
+
+/// Days since the epoch of a date or timestamp value.
+pub fn parse_days(s: &str) -> Option<i64> {
+    parse_timestamp(s).map(|t| t.div_euclid(86400))
+}
+
+/// Seconds since the epoch of a date or timestamp value.
+pub fn parse_timestamp(s: &str) -> Option<i64> {
+    let s = s.trim();
+    if let Ok(seconds) = s.parse::<i64>() {
+        return Some(seconds);
+    }
+    match s.split_once(['T', ' ']) {
+        Some((date, time)) => Some(parse_date_part(date)? * 86400 + parse_time_part(time)?),
+        None => Some(parse_date_part(s)? * 86400),
+    }
+}
+
+/// Parses durations like `30s`, `30m`, `12h`, `7d` or `1h 30m` into seconds. A bare number is seconds.
+pub fn parse_duration(s: &str) -> Result<i64, String> {
+    let s = s.trim();
+    if let Ok(seconds) = s.parse::<i64>() {
+        return Ok(seconds);
+    }
+    let (sign, mut rest) = match s.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, s),
+    };
+    if rest.is_empty() {
+        return Err(format!("Invalid duration: {}", s));
+    }
+    let mut seconds = 0;
+    while !rest.is_empty() {
+        let digits_end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let unit_end = rest[digits_end..].find(|c: char| !c.is_ascii_alphabetic()).map_or(rest.len(), |i| digits_end + i);
+        let multiplier = match &rest[digits_end..unit_end] {
+            "" | "s" => 1,
+            "m" => 60,
+            "h" => 3600,
+            "d" => 86400,
+            "w" => 7 * 86400,
+            unit => return Err(format!("Unknown duration unit '{}' in {}", unit, s)),
+        };
+        let n = rest[..digits_end].parse::<i64>().map_err(|_| format!("Invalid duration: {}", s))?;
+        seconds += n * multiplier;
+        rest = rest[unit_end..].trim_start();
+    }
+    Ok(sign * seconds)
+}
+
+/// Formats seconds as a duration `parse_duration` reads back, e.g. `1d 2h 30m 5s`.
+pub fn format_duration(seconds: i64) -> String {
+    if seconds == 0 {
+        return "0s".to_string();
+    }
+    let mut rest = seconds.unsigned_abs();
+    let mut parts = vec![];
+    for (unit, size) in [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)] {
+        if rest >= size {
+            parts.push(format!("{}{}", rest / size, unit));
+            rest %= size;
+        }
+    }
+    let sign = if seconds < 0 { "-" } else { "" };
+    format!("{}{}", sign, parts.join(" "))
+}
+
+/// The proleptic Gregorian date of a day number, the inverse of `days_from_civil`.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = (if z >= 0 { z } else { z - 146096 }) / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Formats seconds since the epoch as an ISO 8601 UTC timestamp, e.g. `2024-03-07T12:30:15Z`.
+pub fn format_timestamp(seconds: i64) -> String {
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let time = seconds.rem_euclid(86400);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("1970-01-01"), Some(0));
+        assert_eq!(parse_timestamp("2024-03-07T12:30:15Z"), Some(1709814615));
+        assert_eq!(parse_timestamp(" 2024-03-07 12:30 "), Some(1709814600));
+        assert_eq!(parse_timestamp("2024-03-07T12:30:15.250"), Some(1709814615));
+        assert_eq!(parse_timestamp("1709814615"), Some(1709814615));
+        assert_eq!(parse_timestamp("2024-02-29"), Some(1709164800));
+        assert_eq!(parse_timestamp("2023-02-29"), None);
+        assert_eq!(parse_timestamp("2024-02-31"), None);
+        assert_eq!(parse_timestamp("2024-04-31"), None);
+        assert_eq!(parse_timestamp("1900-02-29"), None);
+        assert!(parse_timestamp("2000-02-29").is_some());
+        assert_eq!(parse_timestamp("2024-13-01"), None);
+        assert_eq!(parse_timestamp("2024-03-07T24:00"), None);
+        assert_eq!(parse_timestamp("March 7"), None);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(90));
+        assert_eq!(parse_duration("30m"), Ok(1800));
+        assert_eq!(parse_duration("1h 30m"), Ok(5400));
+        assert_eq!(parse_duration("1w2d"), Ok(9 * 86400));
+        assert_eq!(parse_duration("-12h"), Ok(-43200));
+        assert!(parse_duration("3y").is_err());
+        assert!(parse_duration("-").is_err());
+        assert_eq!(parse_duration(&format_duration(93785)), Ok(93785));
+        assert_eq!(format_duration(-93785), "-1d 2h 3m 5s");
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+        assert_eq!(civil_from_days(19783), (2024, 3, 1));
+        for days in [-719468, -1, 0, 11016, 19782, 2932896] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Reads dBase tables (.dbf), such as the attribute table of a shapefile.
+//!
+//! Character, numeric, float, date, logical, integer, double, currency and datetime fields are
+//! read. Memo fields are kept in a separate .dbt or .fpt file, so they come out empty. Text is
+//! read as UTF-8 when it is valid, and as Latin-1 otherwise. Deleted records are skipped.
+
+use crate::dates;
+
+struct Field {
+    name: String,
+    kind: u8,
+    length: usize,
+    decimals: u8,
+}
+
+/// The column names and records of a table.
+pub fn read(bytes: &[u8]) -> Result<(Vec<String>, Vec<Vec<String>>), String> {
+    if bytes.len() < 32 {
+        return Err("Not a DBF file: the header is shorter than 32 bytes".to_string());
+    }
+    let n_records = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+    let header_length = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+    let record_length = u16::from_le_bytes([bytes[10], bytes[11]]) as usize;
+
+    // 32-byte field descriptors follow the header, up to a 0x0D terminator
+    let mut fields = vec![];
+    let mut offset = 32;
+    while bytes.get(offset).is_some_and(|&b| b != 0x0D) {
+        let descriptor = bytes.get(offset..offset + 32).ok_or("Not a DBF file: the field descriptors run past its end")?;
+        let name_end = descriptor[..11].iter().position(|&b| b == 0).unwrap_or(11);
+        fields.push(Field {
+            name: decode(&descriptor[..name_end]),
+            kind: descriptor[11],
+            length: descriptor[16] as usize,
+            decimals: descriptor[17],
+        });
+        offset += 32;
+    }
+    if fields.iter().map(|f| f.length).sum::<usize>() + 1 != record_length {
+        return Err(format!("Not a DBF file: its fields take {} bytes but its records {}",
+                           fields.iter().map(|f| f.length).sum::<usize>() + 1, record_length));
+    }
+
+    let mut records = vec![];
+    for n in 0..n_records {
+        let start = header_length + n * record_length;
+        // 0x1A marks the end of the file
+        if bytes.get(start).is_none_or(|&b| b == 0x1A) {
+            break;
+        }
+        let record = bytes.get(start..start + record_length).ok_or_else(|| format!("The file ends in the middle of record {}", n + 1))?;
+        if record[0] == b'*' {
+            continue;
+        }
+        let mut values = Vec::with_capacity(fields.len());
+        let mut at = 1;
+        for field in &fields {
+            values.push(value(field, &record[at..at + field.length]));
+            at += field.length;
+        }
+        records.push(values);
+    }
+
+    Ok((fields.into_iter().map(|f| f.name).collect(), records))
+}
+
+/// A field's value as CSV text. Blank and unparseable values are empty.
+fn value(field: &Field, raw: &[u8]) -> String {
+    match (field.kind, raw.len()) {
+        (b'N' | b'F', _) => {
+            let text = decode(raw).trim().to_string();
+            if text.parse::<f64>().is_ok() { text } else { String::new() }
+        }
+        (b'D', _) => {
+            let text = decode(raw).trim().to_string();
+            match text.len() == 8 && text.bytes().all(|b| b.is_ascii_digit()) {
+                true => format!("{}-{}-{}", &text[..4], &text[4..6], &text[6..]),
+                false => String::new(),
+            }
+        }
+        (b'L', _) => match raw.first() {
+            Some(b'T' | b't' | b'Y' | b'y') => "true".to_string(),
+            Some(b'F' | b'f' | b'N' | b'n') => "false".to_string(),
+            _ => String::new(),
+        },
+        (b'I' | b'+', 4) => i32::from_le_bytes(raw.try_into().expect("checked length")).to_string(),
+        (b'O', 8) => f64::from_le_bytes(raw.try_into().expect("checked length")).to_string(),
+        // Currency is stored in ten-thousandths
+        (b'Y', 8) => {
+            let amount = i64::from_le_bytes(raw.try_into().expect("checked length"));
+            let sign = if amount < 0 { "-" } else { "" };
+            format!("{}{}.{:04}", sign, amount.unsigned_abs() / 10000, amount.unsigned_abs() % 10000)
+        }
+        // A Julian day number and milliseconds since midnight
+        (b'T' | b'@', 8) => {
+            let day = i32::from_le_bytes(raw[..4].try_into().expect("checked length")) as i64;
+            let millis = i32::from_le_bytes(raw[4..].try_into().expect("checked length")) as i64;
+            if day == 0 {
+                return String::new();
+            }
+            let seconds = (day - 2440588) * 86400 + millis / 1000;
+            dates::format_timestamp(seconds).trim_end_matches('Z').to_string()
+        }
+        (b'M' | b'B' | b'G' | b'P', _) => String::new(),
+        _ => {
+            let text = decode(raw);
+            if field.decimals == 0 { text.trim_end().to_string() } else { text.trim().to_string() }
+        }
+    }
+}
+
+/// Text as UTF-8 if it is valid, otherwise as Latin-1, up to any padding NULs.
+fn decode(raw: &[u8]) -> String {
+    let raw = &raw[..raw.iter().position(|&b| b == 0).unwrap_or(raw.len())];
+    match std::str::from_utf8(raw) {
+        Ok(text) => text.to_string(),
+        Err(_) => raw.iter().map(|&b| b as char).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A dBase III table with the given fields, as (name, type, length), and records.
+    fn table(fields: &[(&str, u8, u8)], records: &[&[u8]]) -> Vec<u8> {
+        let header_length = 32 + 32 * fields.len() + 1;
+        let record_length = 1 + fields.iter().map(|f| f.2 as usize).sum::<usize>();
+        let mut bytes = vec![0x03, 124, 1, 1];
+        bytes.extend((records.len() as u32).to_le_bytes());
+        bytes.extend((header_length as u16).to_le_bytes());
+        bytes.extend((record_length as u16).to_le_bytes());
+        bytes.resize(32, 0);
+        for (name, kind, length) in fields {
+            let mut descriptor = name.as_bytes().to_vec();
+            descriptor.resize(11, 0);
+            descriptor.push(*kind);
+            descriptor.resize(16, 0);
+            descriptor.push(*length);
+            descriptor.resize(32, 0);
+            bytes.extend(descriptor);
+        }
+        bytes.push(0x0D);
+        for record in records {
+            bytes.extend(*record);
+        }
+        bytes.push(0x1A);
+        bytes
+    }
+
+    #[test]
+    fn test_read() {
+        let fields = [("NAME", b'C', 6), ("POP", b'N', 5), ("FOUNDED", b'D', 8), ("CAPITAL", b'L', 1)];
+        let bytes = table(&fields, &[b" Leeds 7930019620101T", b"*York  2000019000101F", b" Bath\xe9     ?99999999?"]);
+        let (names, records) = read(&bytes).unwrap();
+        assert_eq!(names, ["NAME", "POP", "FOUNDED", "CAPITAL"]);
+        assert_eq!(records, [["Leeds", "79300", "1962-01-01", "true"], ["Bathé", "", "9999-99-99", ""]]);
+
+        assert_eq!(read(&bytes[..20]), Err("Not a DBF file: the header is shorter than 32 bytes".to_string()));
+        assert_eq!(read(&bytes[..bytes.len() - 10]), Err("The file ends in the middle of record 3".to_string()));
+    }
+
+    #[test]
+    fn test_value() {
+        let field = |kind: u8, length: usize| Field { name: String::new(), kind, length, decimals: 0 };
+        assert_eq!(value(&field(b'I', 4), &(-42i32).to_le_bytes()), "-42");
+        assert_eq!(value(&field(b'Y', 8), &(-123456i64).to_le_bytes()), "-12.3456");
+        let datetime = [2460377i32.to_le_bytes(), 45_015_000i32.to_le_bytes()].concat();
+        assert_eq!(value(&field(b'T', 8), &datetime), "2024-03-07T12:30:15");
+        assert_eq!(value(&field(b'M', 10), b"        12"), "");
+    }
+}
@@ -0,0 +1,182 @@
+//! The `--engine fast` reader: splits unquoted lines on the delimiter with memchr and only hands
+//! records containing the quote character to the csv crate. It supports the common dialect
+//! options (delimiter, quote, header row and --flexible); the rest need the default engine.
+
+use crate::csvutil::RecordReader;
+use crate::options::CsvOptions;
+use crate::reader::RecordScanner;
+use csv::StringRecord;
+use std::error::Error;
+use std::io::BufRead;
+
+pub struct FastReader {
+    input: Box<dyn BufRead>,
+    delimiter: u8,
+    quote: u8,
+    has_headers: bool,
+    flexible: bool,
+    /// The field count of the first record, which the others must have unless `--flexible`
+    expected_len: Option<usize>,
+    /// The records, lines and bytes read so far, for the position of an error
+    records: u64,
+    lines: u64,
+    bytes: u64,
+    headers: Option<StringRecord>,
+    /// Without a header row the first record is both the headers and the first data record
+    pending: Option<StringRecord>,
+    line: Vec<u8>,
+}
+
+impl FastReader {
+    pub fn new(options: &CsvOptions, input: Box<dyn BufRead>) -> Result<Self, Box<dyn Error>> {
+        let config = options.config();
+        if config.escape.is_some() || config.comment.is_some() || config.trim != csv::Trim::None {
+            return Err(Box::from("--engine fast doesn't support --escapechar, --commentchar or --trim"));
+        }
+        Ok(FastReader {
+            input,
+            delimiter: config.delimiter,
+            quote: config.quote,
+            has_headers: config.has_headers,
+            flexible: config.flexible,
+            expected_len: None,
+            records: 0,
+            lines: 0,
+            bytes: 0,
+            headers: None,
+            pending: None,
+            line: vec![],
+        })
+    }
+
+    fn read_line(&mut self, record: &mut StringRecord) -> Result<bool, Box<dyn Error>> {
+        if !self.read_fields(record)? {
+            return Ok(false);
+        }
+        if let Some(expected_len) = self.expected_len.filter(|&n| n != record.len() && !self.flexible) {
+            // Worded as the csv crate words it, so both engines fail alike
+            return Err(Box::from(format!(
+                "CSV error: record {} (line: {}, byte: {}): found record with {} fields, but the previous record has {} fields",
+                self.records, self.lines + 1, self.bytes, record.len(), expected_len
+            )));
+        }
+        self.expected_len.get_or_insert(record.len());
+        self.records += 1;
+        self.lines += memchr::memchr_iter(b'\n', &self.line).count() as u64;
+        self.bytes += self.line.len() as u64;
+        Ok(true)
+    }
+
+    /// Reads the next record's fields, leaving its raw lines in `self.line`.
+    fn read_fields(&mut self, record: &mut StringRecord) -> Result<bool, Box<dyn Error>> {
+        loop {
+            self.line.clear();
+            if self.input.read_until(b'\n', &mut self.line)? == 0 {
+                return Ok(false);
+            }
+            let mut len = self.line.len();
+            while len > 0 && (self.line[len - 1] == b'\n' || self.line[len - 1] == b'\r') {
+                len -= 1;
+            }
+            if len == 0 {
+                self.lines += 1;
+                self.bytes += self.line.len() as u64;
+                continue;
+            }
+
+            // One pass finds the delimiters, giving up on the first quote
+            record.clear();
+            let content = &self.line[..len];
+            let line = std::str::from_utf8(content)?;
+            let mut start = 0;
+            let mut quoted = false;
+            for end in memchr::memchr2_iter(self.delimiter, self.quote, content) {
+                if content[end] == self.quote {
+                    quoted = true;
+                    break;
+                }
+                record.push_field(&line[start..end]);
+                start = end + 1;
+            }
+            if !quoted {
+                record.push_field(&line[start..]);
+                return Ok(true);
+            }
+
+            // A quoted field may span lines: read on to the end of the record
+            let mut scanner = RecordScanner::new(self.quote);
+            if !scanner.line_ends_record(&self.line) {
+                scanner.read_record(&mut self.input, &mut self.line)?;
+            }
+            record.clear();
+            csv::ReaderBuilder::new()
+                .has_headers(false)
+                .delimiter(self.delimiter)
+                .quote(self.quote)
+                .from_reader(&self.line[..])
+                .read_record(record)?;
+            return Ok(true);
+        }
+    }
+}
+
+impl RecordReader for FastReader {
+    fn headers(&mut self) -> Result<StringRecord, Box<dyn Error>> {
+        if self.headers.is_none() {
+            let mut record = StringRecord::new();
+            if self.read_line(&mut record)? && !self.has_headers {
+                self.pending = Some(record.clone());
+            }
+            self.headers = Some(record);
+        }
+        Ok(self.headers.clone().unwrap_or_default())
+    }
+
+    fn read_record(&mut self, record: &mut StringRecord) -> Result<bool, Box<dyn Error>> {
+        if self.headers.is_none() {
+            self.headers()?;
+        }
+        if let Some(pending) = self.pending.take() {
+            *record = pending;
+            return Ok(true);
+        }
+        self.read_line(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn read_all(input: &str, has_headers: bool) -> Vec<Vec<String>> {
+        let options = CsvOptions { input_has_headers: Some(has_headers), ..Default::default() };
+        let mut reader = FastReader::new(&options, Box::new(Cursor::new(input.as_bytes().to_vec()))).unwrap();
+        let mut rows = vec![reader.headers().unwrap().iter().map(String::from).collect::<Vec<_>>()];
+        let mut record = StringRecord::new();
+        while reader.read_record(&mut record).unwrap() {
+            rows.push(record.iter().map(String::from).collect());
+        }
+        rows
+    }
+
+    #[test]
+    fn test_fast_reader() {
+        assert_eq!(read_all("a,b\r\n1,2\n\n3,\"x,\"\"y\"\"\nz\"\n4,\n", true),
+                   vec![vec!["a", "b"], vec!["1", "2"], vec!["3", "x,\"y\"\nz"], vec!["4", ""]]);
+        assert_eq!(read_all("1,2\n3,4", false), vec![vec!["1", "2"], vec!["1", "2"], vec!["3", "4"]]);
+        assert_eq!(read_all("", false), vec![Vec::<String>::new()]);
+        assert_eq!(read_all("", true), vec![Vec::<String>::new()]);
+
+        let options = CsvOptions { flexible: Some(false), ..Default::default() };
+        let mut reader = FastReader::new(&options, Box::new(Cursor::new(b"a,b\n\n\"1\n\",2\n3\n".to_vec()))).unwrap();
+        let mut record = StringRecord::new();
+        assert!(reader.read_record(&mut record).unwrap());
+        assert_eq!(reader.read_record(&mut record).unwrap_err().to_string(),
+                   "CSV error: record 2 (line: 5, byte: 12): found record with 1 fields, but the previous record has 2 fields");
+        let options = CsvOptions { flexible: Some(true), ..options };
+        let mut reader = FastReader::new(&options, Box::new(Cursor::new(b"a,b\n3\n".to_vec()))).unwrap();
+        assert!(reader.read_record(&mut record).unwrap());
+        assert_eq!(record.len(), 1);
+    }
+}
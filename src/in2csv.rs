@@ -0,0 +1,298 @@
+pub mod options;
+pub mod args;
+pub mod transform;
+pub mod writer;
+pub mod csvutil;
+pub mod reader;
+pub mod dates;
+pub mod numbers;
+pub mod stats;
+pub mod log;
+#[cfg(feature = "fast-engine")]
+pub mod fastcsv;
+#[cfg(feature = "clipboard")]
+pub mod clipboard;
+pub mod markup;
+#[cfg(feature = "dbf")]
+pub mod dbf;
+
+use crate::args::global_args;
+use clap::Arg;
+use markup::Element;
+use options::CsvOptions;
+use std::error::Error;
+use std::io::Read;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Xml,
+    Html,
+    #[cfg(feature = "dbf")]
+    Dbf,
+}
+
+#[cfg(feature = "dbf")]
+const FORMATS: [&str; 3] = ["xml", "html", "dbf"];
+#[cfg(not(feature = "dbf"))]
+const FORMATS: [&str; 2] = ["xml", "html"];
+
+struct In2CsvOptions {
+    /// Taken from the input file's extension when not given
+    format: Option<Format>,
+    record_path: Option<String>,
+    /// Column names and the paths their values are read from, relative to each record
+    fields: Vec<(String, String)>,
+    /// 1-based, in document order
+    table: usize,
+}
+
+fn main() -> Result<(), String> {
+    let (options, action) = parse_args(std::env::args().collect::<Vec<_>>());
+
+    options.finish(process_csv(&options, &action))
+}
+
+fn parse_args(args: Vec<String>) -> (CsvOptions, In2CsvOptions) {
+    let executable_name = args[0].clone();
+
+    let command = args::contract_args(global_args())
+        .display_name(executable_name)
+        .about("Converts XML records, an HTML table or a DBF file to CSV.")
+        .after_help("XML records are the elements --record-path leads to from the top of the document: a path like \
+                     feed/entry names child elements step by step, // reaches any depth, as in //item, and * matches \
+                     any name. Each --field reads a column from a path relative to the record, ending in @name for an \
+                     attribute, or . for the record's own text. Without --field the columns are the attributes and \
+                     child elements of the records, named by their paths.\n\n\
+                     HTML tables are read row by row, the first row being the header. A cell spanning several \
+                     columns is followed by empty ones.\n\n\
+                     DBF files, such as shapefile attribute tables, need in2csv built with the dbf feature. Memo \
+                     fields come out empty. The input is read into memory.")
+        .mut_arg("input", |a| a.help("Input file, or - for standard input"))
+        .arg(Arg::new("format")
+            .long("format")
+            .value_parser(FORMATS)
+            .help("Input format. Defaults to the input file's extension"))
+        .arg(Arg::new("record_path")
+            .long("record-path")
+            .help("Path to the XML elements holding the records, e.g. feed/entry or //item"))
+        .arg(Arg::new("field")
+            .long("field")
+            .value_parser(parse_field)
+            .help("An XML column as NAME=PATH, e.g. id=@id or author=author/name. May be repeated")
+            .action(clap::ArgAction::Append))
+        .arg(Arg::new("table")
+            .long("table")
+            .value_parser(clap::value_parser!(usize))
+            .default_value("1")
+            .help("Which HTML table to convert, counting from 1 in document order"));
+
+    let mut matches = args::get_matches_from(command, args);
+
+    let action = In2CsvOptions {
+        format: matches.remove_one::<String>("format").map(|f| format_of(&format!(".{}", f)).expect("checked by the value parser")),
+        record_path: matches.remove_one("record_path"),
+        fields: matches.remove_many("field").map(|v| v.collect()).unwrap_or_default(),
+        table: matches.remove_one("table").unwrap_or(1),
+    };
+
+    (args::build_options(matches), action)
+}
+
+fn parse_field(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => Ok((name.to_string(), path.to_string())),
+        _ => Err(format!("Expected NAME=PATH: {}", s)),
+    }
+}
+
+/// The format of a file from its extension.
+fn format_of(path: &str) -> Option<Format> {
+    match path.rsplit_once('.')?.1.to_ascii_lowercase().as_str() {
+        "xml" => Some(Format::Xml),
+        "html" | "htm" => Some(Format::Html),
+        #[cfg(feature = "dbf")]
+        "dbf" => Some(Format::Dbf),
+        _ => None,
+    }
+}
+
+fn parse_markup(bytes: &[u8], html: bool) -> Result<Element, String> {
+    let text = std::str::from_utf8(bytes).map_err(|_| "The input is not UTF-8".to_string())?;
+    markup::parse(text, html)
+}
+
+/// A value read from a record by a `--field` path.
+fn field_value(record: &Element, path: &str) -> String {
+    if path == "." {
+        return record.text();
+    }
+    let (elements, attribute) = match path.rsplit_once('/') {
+        Some((elements, last)) if last.starts_with('@') => (elements, Some(&last[1..])),
+        None if path.starts_with('@') => ("", Some(&path[1..])),
+        _ => (path, None),
+    };
+    let found = if elements.is_empty() { vec![record] } else { record.select(elements) };
+    let Some(element) = found.first() else {
+        return String::new();
+    };
+    match attribute {
+        Some(name) => element.attribute(name).unwrap_or_default().to_string(),
+        None => element.text(),
+    }
+}
+
+/// The records' attributes and child elements, as `--field` paths, in the order they first appear.
+fn default_fields(records: &[&Element]) -> Vec<(String, String)> {
+    let mut paths: Vec<String> = vec![];
+    for record in records {
+        let attributes = record.attributes.iter().map(|(name, _)| format!("@{}", name));
+        let children = record.elements().map(|e| e.name.clone());
+        for path in attributes.chain(children) {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+    paths.into_iter().map(|path| (path.clone(), path)).collect()
+}
+
+/// The rows of an HTML table, leaving out those of tables nested in it.
+fn table_rows(table: &Element) -> Vec<Vec<String>> {
+    let mut rows = vec![];
+    for element in table.elements() {
+        match element.name.as_str() {
+            "tr" => {
+                let mut row = vec![];
+                for cell in element.elements().filter(|e| e.name == "td" || e.name == "th") {
+                    row.push(cell.text());
+                    let span = cell.attribute("colspan").and_then(|s| s.trim().parse::<usize>().ok()).unwrap_or(1);
+                    row.extend((1..span).map(|_| String::new()));
+                }
+                rows.push(row);
+            }
+            "thead" | "tbody" | "tfoot" => rows.extend(table_rows(element)),
+            _ => {}
+        }
+    }
+    rows
+}
+
+fn process_csv(options: &CsvOptions, in2csv_options: &In2CsvOptions) -> Result<(), Box<dyn Error>> {
+    let input_file = options.input_file.as_deref();
+    if !cfg!(feature = "dbf") && input_file.is_some_and(|f| f.to_ascii_lowercase().ends_with(".dbf")) {
+        return Err("Reading DBF files needs in2csv built with the dbf feature".into());
+    }
+    let format = in2csv_options.format
+        .or_else(|| input_file.and_then(format_of))
+        .ok_or("Unable to tell the input format from its file name; use --format")?;
+
+    let mut bytes = vec![];
+    options.get_input_file()?.read_to_end(&mut bytes)?;
+
+    let mut rows = match format {
+        Format::Xml => {
+            let path = in2csv_options.record_path.as_deref().ok_or("XML input needs --record-path")?;
+            let document = parse_markup(&bytes, false)?;
+            let records = document.select(path);
+            let fields = if in2csv_options.fields.is_empty() { default_fields(&records) } else { in2csv_options.fields.clone() };
+            let header = fields.iter().map(|(name, _)| name.clone()).collect::<Vec<_>>();
+            let values = records.iter().map(|record| fields.iter().map(|(_, path)| field_value(record, path)).collect::<Vec<_>>());
+            [header].into_iter().chain(values).collect::<Vec<_>>()
+        }
+        Format::Html => {
+            let document = parse_markup(&bytes, true)?;
+            let tables = document.select("//table");
+            let table = tables.get(in2csv_options.table.wrapping_sub(1))
+                .ok_or_else(|| format!("The input has {} tables, so there is no table {}", tables.len(), in2csv_options.table))?;
+            let mut rows = table_rows(table);
+            // Without a header row the columns are given generated names
+            if !options.config().has_headers {
+                let n_columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+                rows.insert(0, (0..n_columns).map(csvutil::generated_column_name).collect());
+            }
+            rows
+        }
+        #[cfg(feature = "dbf")]
+        Format::Dbf => {
+            let (names, records) = dbf::read(&bytes)?;
+            [names].into_iter().chain(records).collect()
+        }
+    };
+
+    let n_columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    rows.iter_mut().for_each(|row| row.resize(n_columns, String::new()));
+    let mut rows = rows.into_iter();
+
+    let output_has_headers = options.output_has_headers();
+
+    let mut csv_writer = writer::CsvWriter::new(options)?;
+
+    if let Some(header) = rows.next().filter(|_| output_has_headers) {
+        csv_writer.write_record(&header)?;
+    }
+
+    for row in rows {
+        csv_writer.write_record(&row)?;
+    }
+
+    csv_writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn convert(input_file: &str, input: &str, options: &CsvOptions, in2csv_options: &In2CsvOptions) -> Result<String, String> {
+        let dir = csvutil::TestDir::new();
+        let input_file = &dir.path(input_file);
+        let output_file = &dir.path("test_output_in2csv.csv");
+        fs::write(input_file, input).expect("Unable to write input file");
+
+        let options = CsvOptions {
+            input_file: Some(input_file.to_string()),
+            output_file: Some(output_file.to_string()),
+            ..options.clone()
+        };
+
+        let result = process_csv(&options, in2csv_options).map_err(|e| e.to_string());
+        let output = fs::read_to_string(output_file).unwrap_or_default();
+        result.map(|_| output)
+    }
+
+    #[test]
+    fn test_process_csv_xml() {
+        let input = "<feed>\n  <entry id=\"1\"><title>First, post</title><author><name>Ann</name></author></entry>\n  \
+                     <entry id=\"2\"><title>Second</title><tag>x</tag></entry>\n</feed>\n";
+        let mut in2csv_options = In2CsvOptions { format: None, record_path: Some("feed/entry".to_string()), fields: vec![], table: 1 };
+        assert_eq!(convert("test_input_in2csv.xml", input, &CsvOptions::default(), &in2csv_options),
+                   Ok("@id,title,author,tag\n1,\"First, post\",Ann,\n2,Second,,x\n".to_string()));
+
+        in2csv_options.record_path = Some("//entry".to_string());
+        in2csv_options.fields = vec![("id".to_string(), "@id".to_string()), ("author".to_string(), "author/name".to_string())];
+        assert_eq!(convert("test_input_in2csv.xml", input, &CsvOptions::default(), &in2csv_options),
+                   Ok("id,author\n1,Ann\n2,\n".to_string()));
+
+        assert_eq!(convert("test_input_in2csv.txt", input, &CsvOptions::default(), &in2csv_options),
+                   Err("Unable to tell the input format from its file name; use --format".to_string()));
+    }
+
+    #[test]
+    fn test_process_csv_html() {
+        let input = "<html><body><table><tr><td>skipped</td></tr></table>\
+                     <table><thead><tr><th>Name</th><th>City</th></tr></thead>\
+                     <tbody><tr><td>Ann &amp; Bob<td>Leeds<tr><td colspan=\"2\">Total</td></tbody></table></body></html>";
+        let in2csv_options = In2CsvOptions { format: Some(Format::Html), record_path: None, fields: vec![], table: 2 };
+        assert_eq!(convert("test_input_in2csv.html", input, &CsvOptions::default(), &in2csv_options),
+                   Ok("Name,City\nAnn & Bob,Leeds\nTotal,\n".to_string()));
+
+        let options = CsvOptions { input_has_headers: Some(false), output_headers: Some(true), ..Default::default() };
+        assert_eq!(convert("test_input_in2csv.html", input, &options, &In2CsvOptions { table: 1, ..in2csv_options }),
+                   Ok("a\nskipped\n".to_string()));
+
+        assert_eq!(convert("test_input_in2csv.html", input, &CsvOptions::default(), &In2CsvOptions { format: Some(Format::Html), record_path: None, fields: vec![], table: 3 }),
+                   Err("The input has 2 tables, so there is no table 3".to_string()));
+    }
+}
@@ -0,0 +1,78 @@
+//! Messages on stderr: plain text, or with `--log-json` one JSON event per line, so wrappers
+//! such as Airflow or Dagster operators can follow a run without scraping text. Every event has
+//! `event`, `tool` and `time` (seconds since the Unix epoch) fields; the kinds are `start`,
+//! `progress`, `info`, `warning`, `error` and `end`.
+
+use serde_json::{json, Map, Value};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Switches every later message to JSON events, for `--log-json`.
+pub fn set_json(yes: bool) {
+    JSON.store(yes, Ordering::Relaxed);
+}
+
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// The running tool's name, e.g. csvcut.
+pub fn tool_name() -> String {
+    let name = std::env::args().next().unwrap_or_default();
+    Path::new(&name).file_name().map_or(name.clone(), |n| n.to_string_lossy().to_string())
+}
+
+/// The JSON line for an event, with `fields` after the common ones.
+pub fn format_event(kind: &str, fields: Value) -> String {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64());
+    let mut event = Map::new();
+    event.insert("event".to_string(), json!(kind));
+    event.insert("tool".to_string(), json!(tool_name()));
+    event.insert("time".to_string(), json!((time * 1000.0).round() / 1000.0));
+    if let Value::Object(fields) = fields {
+        event.extend(fields);
+    }
+    Value::Object(event).to_string()
+}
+
+/// Prints an event if `--log-json` is on.
+pub fn event(kind: &str, fields: Value) {
+    if is_json() {
+        eprintln!("{}", format_event(kind, fields));
+    }
+}
+
+/// Reports a decision or step, such as those `--verbose` describes.
+pub fn info(message: &str) {
+    if is_json() {
+        event("info", json!({ "message": message }));
+    } else {
+        eprintln!("{}", message);
+    }
+}
+
+/// Reports something that didn't stop the run but may make its output differ from what was expected.
+pub fn warning(message: &str) {
+    if is_json() {
+        event("warning", json!({ "message": message }));
+    } else {
+        eprintln!("Warning: {}", message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_event() {
+        let event: Value = serde_json::from_str(&format_event("warning", json!({ "message": "a \"b\"" }))).unwrap();
+        assert_eq!(event["event"], "warning");
+        assert_eq!(event["message"], "a \"b\"");
+        assert_eq!(event["tool"], json!(tool_name()));
+        assert!(event["time"].as_f64().unwrap() > 1.6e9);
+    }
+}
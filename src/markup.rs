@@ -0,0 +1,296 @@
+//! A small parser for XML documents and HTML pages, enough for in2csv to find records and tables.
+//!
+//! It builds a tree of elements and text. There is no DTD, namespace or encoding handling, and
+//! the input must be UTF-8. HTML is parsed leniently: tag names are case-insensitive, void
+//! elements like `<br>` need no closing tag, cells and rows are closed by the next one, and
+//! stray closing tags are ignored. XML must be well-formed.
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+}
+
+/// HTML elements that never have content or a closing tag.
+const VOID_ELEMENTS: [&str; 14] = ["area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source", "track", "wbr"];
+
+/// HTML elements whose content is skipped rather than parsed.
+const RAW_TEXT_ELEMENTS: [&str; 2] = ["script", "style"];
+
+impl Element {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /// The child elements, leaving out text.
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|child| match child {
+            Node::Element(e) => Some(e),
+            Node::Text(_) => None,
+        })
+    }
+
+    /// Every element below this one, in document order.
+    pub fn descendants(&self) -> Vec<&Element> {
+        let mut found = vec![];
+        for child in self.elements() {
+            found.push(child);
+            found.extend(child.descendants());
+        }
+        found
+    }
+
+    /// The text of the element and everything below it, with runs of whitespace collapsed to
+    /// one space and trimmed.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        self.collect_text(&mut text);
+        text.split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    fn collect_text(&self, text: &mut String) {
+        for child in &self.children {
+            match child {
+                Node::Text(t) => text.push_str(t),
+                Node::Element(e) if e.name == "br" => text.push(' '),
+                Node::Element(e) => e.collect_text(text),
+            }
+        }
+    }
+
+    /// The elements a path like `feed/entry`, `//item` or `*/row` leads to from this one. Each
+    /// step names child elements, or any descendant after `//`; `*` matches any name.
+    pub fn select(&self, path: &str) -> Vec<&Element> {
+        let mut current = vec![self];
+        let mut any_depth = false;
+        for step in path.split('/') {
+            if step.is_empty() {
+                any_depth = true;
+                continue;
+            }
+            let matches = |e: &&Element| step == "*" || e.name == step;
+            current = current.into_iter()
+                .flat_map(|e| if any_depth { e.descendants() } else { e.elements().collect() })
+                .filter(matches)
+                .collect();
+            any_depth = false;
+        }
+        current
+    }
+}
+
+/// Parses a document into a root element, named "", holding its top-level nodes.
+pub fn parse(input: &str, html: bool) -> Result<Element, String> {
+    let mut stack = vec![Element::default()];
+    let mut rest = input;
+    while !rest.is_empty() {
+        let offset = input.len() - rest.len();
+        let line = || input[..offset].matches('\n').count() + 1;
+        if let Some(after) = rest.strip_prefix("<!--") {
+            rest = after.find("-->").map_or("", |end| &after[end + 3..]);
+        } else if let Some(after) = rest.strip_prefix("<![CDATA[") {
+            let end = after.find("]]>").ok_or_else(|| format!("Unclosed CDATA section on line {}", line()))?;
+            push_text(&mut stack, &after[..end]);
+            rest = &after[end + 3..];
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            // A doctype or processing instruction
+            rest = rest.find('>').map_or("", |end| &rest[end + 1..]);
+        } else if let Some(after) = rest.strip_prefix("</") {
+            let end = after.find('>').ok_or_else(|| format!("Unclosed tag on line {}", line()))?;
+            let name = tag_name(after[..end].trim(), html);
+            match stack.iter().rposition(|e| e.name == name) {
+                Some(i) if i > 0 && (html || i == stack.len() - 1) => close_to(&mut stack, i),
+                _ if html => {}
+                _ => return Err(format!("Unexpected </{}> on line {}", name, line())),
+            }
+            rest = &after[end + 1..];
+        } else if rest.starts_with('<') && rest[1..].starts_with(|c: char| c.is_alphabetic() || c == '_') {
+            let (element, self_closing, after) = parse_start_tag(&rest[1..], html).ok_or_else(|| format!("Unclosed tag on line {}", line()))?;
+            rest = after;
+            if html {
+                close_implied(&mut stack, &element.name);
+            }
+            if html && RAW_TEXT_ELEMENTS.contains(&element.name.as_str()) {
+                // Kept empty; its closing tag is then a stray one
+                let close = format!("</{}", element.name);
+                rest = rest.to_ascii_lowercase().find(&close).map_or("", |end| &rest[end..]);
+                stack.last_mut().expect("the root is never closed").children.push(Node::Element(element));
+            } else if self_closing || (html && VOID_ELEMENTS.contains(&element.name.as_str())) {
+                stack.last_mut().expect("the root is never closed").children.push(Node::Element(element));
+            } else {
+                stack.push(element);
+            }
+        } else {
+            // A '<' that doesn't start a tag is text
+            let first = rest.chars().next().map_or(1, char::len_utf8);
+            let end = rest[first..].find('<').map_or(rest.len(), |end| end + first);
+            push_text(&mut stack, &decode_entities(&rest[..end]));
+            rest = &rest[end..];
+        }
+    }
+    if !html && stack.len() > 1 {
+        return Err(format!("<{}> is never closed", stack[stack.len() - 1].name));
+    }
+    close_to(&mut stack, 1);
+    Ok(stack.pop().expect("the root is never closed"))
+}
+
+fn tag_name(name: &str, html: bool) -> String {
+    if html { name.to_ascii_lowercase() } else { name.to_string() }
+}
+
+fn push_text(stack: &mut [Element], text: &str) {
+    let parent = stack.last_mut().expect("the root is never closed");
+    match parent.children.last_mut() {
+        Some(Node::Text(t)) => t.push_str(text),
+        _ => parent.children.push(Node::Text(text.to_string())),
+    }
+}
+
+/// Closes the open elements from the top of the stack down to and including `stack[i]`.
+fn close_to(stack: &mut Vec<Element>, i: usize) {
+    while stack.len() > i {
+        let element = stack.pop().expect("checked against the length");
+        stack.last_mut().expect("the root is never closed").children.push(Node::Element(element));
+    }
+}
+
+/// Closes the table parts an HTML start tag ends without saying so, as a new row ends the
+/// open cell and row.
+fn close_implied(stack: &mut Vec<Element>, name: &str) {
+    let (closes, scope): (&[&str], &[&str]) = match name {
+        "td" | "th" => (&["td", "th"], &["tr", "table"]),
+        "tr" => (&["tr", "td", "th"], &["thead", "tbody", "tfoot", "table"]),
+        "thead" | "tbody" | "tfoot" => (&["thead", "tbody", "tfoot", "tr", "td", "th"], &["table"]),
+        _ => return,
+    };
+    // Only the parts open inside the innermost enclosing row, section or table
+    let floor = stack.iter().rposition(|e| scope.contains(&e.name.as_str())).map_or(1, |i| i + 1);
+    if let Some(i) = (floor..stack.len()).find(|&i| closes.contains(&stack[i].name.as_str())) {
+        close_to(stack, i);
+    }
+}
+
+/// Parses a start tag after its `<`, returning the element, whether it ends in `/>`, and the
+/// input after the tag.
+fn parse_start_tag(input: &str, html: bool) -> Option<(Element, bool, &str)> {
+    let name_end = input.find(|c: char| c.is_whitespace() || c == '/' || c == '>')?;
+    let mut element = Element { name: tag_name(&input[..name_end], html), ..Default::default() };
+    let mut rest = &input[name_end..];
+    loop {
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix("/>") {
+            return Some((element, true, after));
+        } else if let Some(after) = rest.strip_prefix('>') {
+            return Some((element, false, after));
+        } else if let Some(after) = rest.strip_prefix('/') {
+            rest = after;
+            continue;
+        }
+        let name_end = rest.find(|c: char| c.is_whitespace() || c == '=' || c == '/' || c == '>')?;
+        let name = tag_name(&rest[..name_end], html);
+        rest = rest[name_end..].trim_start();
+        let value = match rest.strip_prefix('=') {
+            Some(after) => {
+                let after = after.trim_start();
+                let (value, after) = match after.chars().next()? {
+                    quote @ ('"' | '\'') => {
+                        let end = after[1..].find(quote)? + 1;
+                        (&after[1..end], &after[end + 1..])
+                    }
+                    _ => {
+                        let end = after.find(|c: char| c.is_whitespace() || c == '>').unwrap_or(after.len());
+                        (&after[..end], &after[end..])
+                    }
+                };
+                rest = after;
+                decode_entities(value)
+            }
+            None => String::new(),
+        };
+        element.attributes.push((name, value));
+    }
+}
+
+/// Replaces character references like `&amp;`, `&#233;` and `&#xE9;`. Unknown ones are kept.
+pub fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    let mut decoded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let reference = rest[1..].find(';').map(|end| &rest[1..end + 1]).filter(|r| r.len() <= 10);
+        let c = reference.and_then(|r| match r {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ => match r.strip_prefix("#x").or_else(|| r.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                None => r.strip_prefix('#').and_then(|n| n.parse().ok()).and_then(char::from_u32),
+            },
+        });
+        match (c, reference) {
+            (Some(c), Some(r)) => {
+                decoded.push(c);
+                rest = &rest[r.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_entities() {
+        assert_eq!(decode_entities("a &amp; b &lt;c&gt; &#233;&#xE9; &copy; & x"), "a & b <c> éé &copy; & x");
+    }
+
+    #[test]
+    fn test_parse_xml() {
+        let root = parse("<?xml version=\"1.0\"?>\n<feed><entry id='1'><title>A &amp; B</title></entry>\
+                          <!-- skipped --><entry id=\"2\"><title><![CDATA[<C>]]></title><empty/></entry></feed>", false).unwrap();
+        let entries = root.select("feed/entry");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].attribute("id"), Some("1"));
+        assert_eq!(entries[0].select("title")[0].text(), "A & B");
+        assert_eq!(entries[1].select("title")[0].text(), "<C>");
+        assert_eq!(root.select("//title").len(), 2);
+        assert_eq!(root.select("*/*/empty").len(), 1);
+
+        assert_eq!(parse("<a><b></a>", false), Err("Unexpected </a> on line 1".to_string()));
+        assert_eq!(parse("<a>\n<b></b>", false), Err("<a> is never closed".to_string()));
+    }
+
+    #[test]
+    fn test_parse_html() {
+        let root = parse("<!DOCTYPE html><HTML><body><script>if (a < b) {}</script><table border=1>\
+                          <tr><th>Name<th>City<tr><td>Ann<br>Lee<td>Leeds</table></p></body>", true).unwrap();
+        let rows = root.select("//tr");
+        assert_eq!(rows.len(), 2);
+        let cells = rows.iter().map(|r| r.elements().map(Element::text).collect::<Vec<_>>()).collect::<Vec<_>>();
+        assert_eq!(cells, [["Name", "City"], ["Ann Lee", "Leeds"]]);
+        assert_eq!(root.select("//table")[0].attribute("border"), Some("1"));
+        assert!(root.select("//script")[0].children.is_empty());
+    }
+}
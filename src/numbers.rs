@@ -0,0 +1,104 @@
+//! Recognizes numbers as they are written in exported data, for type inference and statistics.
+
+/// Parses a number, tolerating surrounding whitespace, a leading `+` and `,` thousands separators
+/// in groups of three, besides the plain and scientific forms `str::parse` accepts. `inf` and
+/// `NaN` are taken as words. With `strict` only what `str::parse::<f64>` accepts is a number.
+pub fn parse_number(value: &str, strict: bool) -> Option<f64> {
+    if strict {
+        return value.parse::<f64>().ok();
+    }
+    let value = value.trim();
+    let unsigned = value.strip_prefix(['+', '-']).unwrap_or(value);
+    if !unsigned.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+    if !unsigned.contains(',') {
+        return value.strip_prefix('+').unwrap_or(value).parse::<f64>().ok();
+    }
+
+    let integer_end = unsigned.find(['.', 'e', 'E']).unwrap_or(unsigned.len());
+    let mut groups = unsigned[..integer_end].split(',');
+    let first = groups.next().unwrap_or_default();
+    let grouped = (1..=3).contains(&first.len())
+        && !unsigned[integer_end..].contains(',')
+        && groups.all(|g| g.len() == 3 && g.bytes().all(|b| b.is_ascii_digit()));
+    if !grouped {
+        return None;
+    }
+    let negative = value.starts_with('-');
+    let digits = unsigned.replace(',', "");
+    digits.parse::<f64>().ok().map(|n| if negative { -n } else { n })
+}
+
+/// Parses a number written as an integer, with no decimal point or exponent, accepting the
+/// same forms as `parse_number`.
+pub fn parse_integer(value: &str, strict: bool) -> Option<i128> {
+    if strict {
+        return value.parse::<i128>().ok();
+    }
+    parse_number(value, false)?;
+    let value = value.trim();
+    if value.contains(['.', 'e', 'E']) {
+        return None;
+    }
+    value.strip_prefix('+').unwrap_or(value).replace(',', "").parse::<i128>().ok()
+}
+
+/// Whether a number is written with a leading zero, like a ZIP code or account number ("007",
+/// "-01"), which converting it to a number would lose. "0" and "0.5" don't count.
+pub fn has_leading_zero(value: &str) -> bool {
+    let value = value.trim();
+    let unsigned = value.strip_prefix(['+', '-']).unwrap_or(value).as_bytes();
+    unsigned.len() > 1 && unsigned[0] == b'0' && unsigned[1].is_ascii_digit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("1,234", false), Some(1234.0));
+        assert_eq!(parse_number(" 42 ", false), Some(42.0));
+        assert_eq!(parse_number("+7", false), Some(7.0));
+        assert_eq!(parse_number("-1,234,567.5", false), Some(-1234567.5));
+        assert_eq!(parse_number("1.5e3", false), Some(1500.0));
+        assert_eq!(parse_number(".5", false), Some(0.5));
+        assert_eq!(parse_number("12,34", false), None);
+        assert_eq!(parse_number("1,2345", false), None);
+        assert_eq!(parse_number(",123", false), None);
+        assert_eq!(parse_number("1,234,", false), None);
+        assert_eq!(parse_number("1.5,000", false), None);
+        assert_eq!(parse_number("+-1", false), None);
+        assert_eq!(parse_number("inf", false), None);
+        assert_eq!(parse_number("NaN", false), None);
+        assert_eq!(parse_number("", false), None);
+
+        assert_eq!(parse_number("1,234", true), None);
+        assert_eq!(parse_number(" 42", true), None);
+        assert_eq!(parse_number("1.5e3", true), Some(1500.0));
+    }
+
+    #[test]
+    fn test_parse_integer() {
+        assert_eq!(parse_integer("-1,234", false), Some(-1234));
+        assert_eq!(parse_integer(" +42 ", false), Some(42));
+        assert_eq!(parse_integer("9223372036854775808", false), Some(9223372036854775808));
+        assert_eq!(parse_integer("1.0", false), None);
+        assert_eq!(parse_integer("1e3", false), None);
+        assert_eq!(parse_integer("12,34", false), None);
+        assert_eq!(parse_integer("1,234", true), None);
+        assert_eq!(parse_integer("-7", true), Some(-7));
+    }
+
+    #[test]
+    fn test_has_leading_zero() {
+        assert!(has_leading_zero("02134"));
+        assert!(has_leading_zero("-01"));
+        assert!(has_leading_zero(" 007"));
+        assert!(!has_leading_zero("0"));
+        assert!(!has_leading_zero("0.5"));
+        assert!(!has_leading_zero("100"));
+        assert!(!has_leading_zero(""));
+    }
+}
@@ -1,38 +1,598 @@
+use csv::Trim;
+use serde_json::{json, Value};
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{stdin, BufRead, BufReader, BufWriter, Error, Read, Write};
+use std::io::{stdin, BufRead, BufReader, BufWriter, Error, IsTerminal, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use std::{error, io};
+use crate::csvutil;
+use crate::log;
+use crate::reader::{ColumnCheck, ColumnResolver, Constraint, ConstraintChecker, ConstraintResolver, CountingReader, FollowReader, LineCleaner, RecordCounter, RecordScanner, RowFilter, StrictReader, VerifyingReader};
+use crate::transform::Transform;
+use crate::writer::{self, Contract, RollingWriter, TeeWriter};
+
+/// A reader or writer standing in for a file. Clones share it, and the first to open it takes it.
+pub struct Stream<T: ?Sized>(Arc<Mutex<Option<Box<T>>>>);
+
+impl<T: ?Sized> Stream<T> {
+    fn new(inner: Box<T>) -> Self {
+        Stream(Arc::new(Mutex::new(Some(inner))))
+    }
+
+    fn take(&self) -> io::Result<Box<T>> {
+        self.0.lock().ok().and_then(|mut s| s.take())
+            .ok_or_else(|| Error::other("The in-memory stream has already been used"))
+    }
+}
+
+impl<T: ?Sized> Clone for Stream<T> {
+    fn clone(&self) -> Self {
+        Stream(Arc::clone(&self.0))
+    }
+}
+
+/// An in-memory output whose clones share the bytes written, so they can be read back once the
+/// writer given to `CsvOptions::with_output` has been used.
+#[derive(Default, Clone)]
+pub struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.lock().map(|b| b.clone()).unwrap_or_default()
+    }
+
+    pub fn to_string_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.contents()).to_string()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().map_err(|_| Error::other("Buffer poisoned"))?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// `--color`: whether to color output meant for a terminal, see `CsvOptions::use_color`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Counts for the `--summary` line, shared by the clones of the options and added to by the
+/// input layers and `CsvWriter` as records pass through.
+#[derive(Debug)]
+pub struct Metrics {
+    started: Instant,
+    /// Data records in the input, header rows excluded
+    pub records_read: AtomicU64,
+    /// Records `--drop-empty-rows` and `--drop-if-empty` dropped
+    pub records_skipped: AtomicU64,
+    pub records_written: AtomicU64,
+    pub bytes_read: AtomicU64,
+    pub bytes_written: AtomicU64,
+    /// Milliseconds after the start of the last `--log-json` progress event
+    last_progress: AtomicU64,
+}
+
+/// How often `--log-json` reports progress while reading.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            started: Instant::now(),
+            records_read: AtomicU64::new(0),
+            records_skipped: AtomicU64::new(0),
+            records_written: AtomicU64::new(0),
+            bytes_read: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+            last_progress: AtomicU64::new(0),
+        }
+    }
+
+    pub fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Counts a data record read, reporting progress every `PROGRESS_INTERVAL` with `--log-json`.
+    pub fn record_read(&self) {
+        let records = self.records_read.fetch_add(1, Ordering::Relaxed) + 1;
+        // Checking the clock for every record would slow reading down
+        if !records.is_multiple_of(1000) || !log::is_json() {
+            return;
+        }
+        let elapsed = self.started.elapsed();
+        let last = Duration::from_millis(self.last_progress.load(Ordering::Relaxed));
+        if elapsed - last >= PROGRESS_INTERVAL {
+            self.last_progress.store(elapsed.as_millis() as u64, Ordering::Relaxed);
+            log::event("progress", json!({
+                "records_read": records,
+                "bytes_read": self.bytes_read.load(Ordering::Relaxed),
+                "seconds": elapsed.as_secs_f64(),
+            }));
+        }
+    }
+
+    /// The counts for the `--log-json` end event.
+    pub fn to_json(&self) -> Value {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        json!({
+            "records_read": get(&self.records_read),
+            "records_written": get(&self.records_written),
+            "records_skipped": get(&self.records_skipped),
+            "bytes_read": get(&self.bytes_read),
+            "bytes_written": get(&self.bytes_written),
+            "seconds": self.started.elapsed().as_secs_f64(),
+        })
+    }
+
+    /// The `--summary` line for the run so far.
+    pub fn summary(&self) -> String {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let seconds = self.started.elapsed().as_secs_f64();
+        // A run too quick to time has no meaningful rate
+        let rate = |n: u64| if seconds > 0.0 { n as f64 / seconds } else { 0.0 };
+        format!("Summary: {} records read, {} written, {} skipped, {} bytes read, {} bytes written, {:.3} s, {:.0} records/s, {:.1} MB/s",
+                get(&self.records_read), get(&self.records_written), get(&self.records_skipped),
+                get(&self.bytes_read), get(&self.bytes_written), seconds,
+                rate(get(&self.records_read)), rate(get(&self.bytes_read)) / 1e6)
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 #[derive(Default, Clone)]
 pub struct CsvOptions {
     pub(crate) input_file: Option<String>,
     pub output_file: Option<String>,
+    /// Read instead of stdin when there is no input file, see `with_input`
+    pub(crate) input: Option<Stream<dyn BufRead + Send>>,
+    /// Written instead of stdout when there is no output file, see `with_output`
+    pub(crate) output: Option<Stream<dyn Write + Send>>,
     pub(crate) delimiter: Option<char>,
     pub input_has_headers: Option<bool>,
     pub output_headers: Option<bool>,
     pub(crate) quote_char: Option<char>,
     pub(crate) escape_char: Option<char>,
-    pub(crate) trim_fields: Option<bool>,
+    /// `--trim`, or `Trim::All` for `--trimfields`
+    pub(crate) trim: Trim,
     pub(crate) flexible: Option<bool>,
     pub(crate) comment_char: Option<char>,
+    pub(crate) max_output_bytes: Option<u64>,
+    pub(crate) transforms: Vec<Transform>,
+    pub verbose: bool,
+    pub(crate) strict_rfc4180: bool,
+    pub(crate) max_field_size: Option<u64>,
+    pub(crate) max_record_size: Option<u64>,
+    /// 1-based row holding the header row, a quoted field's line breaks included; earlier rows are skipped
+    pub(crate) header_row: Option<u64>,
+    pub(crate) manifest_file: Option<String>,
+    pub(crate) verify_sha256: Option<String>,
+    pub(crate) normalize_newlines: bool,
+    pub(crate) skip_blank_lines: bool,
+    pub self_bench: bool,
+    pub fast_engine: bool,
+    pub(crate) sort_columns: bool,
+    /// Column names to write first, in this order
+    pub(crate) columns_from: Option<Vec<String>>,
+    /// Keep reading as records are appended to the input, never reaching its end
+    pub follow: bool,
+    /// `--color`, for the tools taking it
+    pub color: ColorChoice,
+    /// `--true-values` and `--false-values`, for the tools that infer boolean columns
+    pub true_values: Option<Vec<String>>,
+    pub false_values: Option<Vec<String>>,
+    /// Count column offsets and ranges from 0, for the tools taking `--zero-based`
+    pub zero_based: bool,
+    /// Print the resolved column selection as offsets instead of processing, for `--emit-spec`
+    pub emit_spec: bool,
+    /// `--first`, `--last` and `--move` in command line order, for the tools taking them
+    pub reorders: Vec<csvutil::Reorder>,
+    /// Append `csvutil::SOURCE_COLUMNS`, for the tools taking `--add-source-columns`
+    pub add_source_columns: bool,
+    /// `--select-first` or `--select-all-matches`, for the tools taking them
+    pub duplicate_names: csvutil::DuplicateNames,
+    /// Read the input from and write the output to the system clipboard, tab separated by default
+    pub(crate) clipboard: bool,
+    /// `--enforce-schema`, for the tools taking it
+    pub contract: Option<Contract>,
+    /// `--drop-empty-rows`
+    pub(crate) drop_empty_rows: bool,
+    /// `--drop-if-empty`: drop records where any of these columns is empty
+    pub(crate) drop_if_empty: Option<Vec<String>>,
+    /// `--assert-unique`, `--assert-not-null` and `--assert-range`, each with the columns it checks
+    pub(crate) assertions: Vec<(Vec<String>, Constraint)>,
+    /// The counts for `--summary` and the `--log-json` end event
+    pub(crate) metrics: Option<Arc<Metrics>>,
+    /// `--summary`: print the counts once the tool has finished
+    pub(crate) summary: bool,
+    /// `--max-memory`: the bytes the tools holding values in memory may use, see `csvutil::check_memory`
+    pub(crate) max_memory: Option<u64>,
+    /// `--header-aliases`: canonical header names by lowercased alias, see `csvutil::column_names`
+    pub header_aliases: Option<HashMap<String, String>>,
+    /// Further outputs from repeated `-o`, each getting a copy of the output, - for stdout
+    pub(crate) tee: Vec<String>,
+    /// `--in-place` with its backup suffix, empty for no backup, for the tools taking it
+    pub in_place: Option<String>,
+    /// `--raw-passthrough`: write records as the bytes they were read as, see `reader::RawRecords`
+    pub raw_passthrough: bool,
+}
+
+/// The reading and writing options with their defaults applied, from `CsvOptions::config`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvConfig {
+    pub delimiter: u8,
+    pub quote: u8,
+    pub escape: Option<u8>,
+    pub comment: Option<u8>,
+    pub trim: Trim,
+    pub flexible: bool,
+    /// The first row is a header row
+    pub has_headers: bool,
+    /// Write a header row for columns taken from the input, by default when the input has one
+    pub output_headers: bool,
+    /// Write a header row for columns a tool makes up itself, such as counts or statistics
+    pub report_headers: bool,
+}
+
+/// Builds `CsvOptions`, checking that the options make sense together.
+#[derive(Default)]
+pub struct CsvOptionsBuilder {
+    options: CsvOptions,
+    /// The first dialect character given that isn't ASCII, reported by `build`
+    error: Option<String>,
+}
+
+impl CsvOptionsBuilder {
+    pub fn input_file(mut self, file: impl Into<String>) -> Self {
+        self.options.input_file = Some(file.into());
+        self
+    }
+
+    pub fn output_file(mut self, file: impl Into<String>) -> Self {
+        self.options.output_file = Some(file.into());
+        self
+    }
+
+    pub fn delimiter(mut self, c: char) -> Self {
+        self.options.delimiter = Some(self.ascii_char(c));
+        self
+    }
+
+    pub fn quote_char(mut self, c: char) -> Self {
+        self.options.quote_char = Some(self.ascii_char(c));
+        self
+    }
+
+    pub fn escape_char(mut self, c: char) -> Self {
+        self.options.escape_char = Some(self.ascii_char(c));
+        self
+    }
+
+    pub fn comment_char(mut self, c: char) -> Self {
+        self.options.comment_char = Some(self.ascii_char(c));
+        self
+    }
+
+    fn ascii_char(&mut self, c: char) -> char {
+        if let Err(e) = ascii_char(c) {
+            self.error.get_or_insert(e);
+        }
+        c
+    }
+
+    pub fn trim(mut self, trim: Trim) -> Self {
+        self.options.trim = trim;
+        self
+    }
+
+    pub fn flexible(mut self, flexible: bool) -> Self {
+        self.options.flexible = Some(flexible);
+        self
+    }
+
+    pub fn has_headers(mut self, has_headers: bool) -> Self {
+        self.options.input_has_headers = Some(has_headers);
+        self
+    }
+
+    pub fn output_headers(mut self, output_headers: bool) -> Self {
+        self.options.output_headers = Some(output_headers);
+        self
+    }
+
+    pub fn header_row(mut self, row: u64) -> Self {
+        self.options.header_row = Some(row);
+        self
+    }
+
+    pub fn max_output_bytes(mut self, bytes: u64) -> Self {
+        self.options.max_output_bytes = Some(bytes);
+        self
+    }
+
+    pub fn build(self) -> Result<CsvOptions, String> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        self.options.validate()?;
+        Ok(self.options)
+    }
+}
+
+/// Checks that a delimiter, quote, escape or comment character is ASCII, as the csv crate needs.
+pub fn ascii_char(c: char) -> Result<char, String> {
+    if c.is_ascii() {
+        Ok(c)
+    } else {
+        Err(format!("'{}' is not an ASCII character. Convert the input to use an ASCII separator first, e.g. with sed", c))
+    }
 }
 
 impl CsvOptions {
+    pub fn builder() -> CsvOptionsBuilder {
+        Default::default()
+    }
+
+    /// Checks for options that contradict each other or can't work, which would otherwise
+    /// misread the input or fail only once processing has started.
+    pub fn validate(&self) -> Result<(), String> {
+        // The csv crate takes single bytes
+        for c in [self.delimiter, self.quote_char, self.escape_char, self.comment_char].into_iter().flatten() {
+            ascii_char(c)?;
+        }
+        let config = self.config();
+        let name = |c: u8| if c.is_ascii_graphic() { (c as char).to_string() } else { (c as char).escape_default().to_string() };
+        if config.quote == config.delimiter {
+            return Err(format!("The quote character and the delimiter are both '{}'", name(config.quote)));
+        }
+        if let Some(escape) = config.escape.filter(|&e| e == config.delimiter) {
+            return Err(format!("The escape character and the delimiter are both '{}'", name(escape)));
+        }
+        if let Some(comment) = config.comment.filter(|&c| c == config.delimiter || c == config.quote) {
+            return Err(format!("The comment character '{}' is also the delimiter or quote character", name(comment)));
+        }
+        if self.header_row == Some(0) {
+            return Err("The header row counts from 1".to_string());
+        }
+        if self.header_row.is_some() && self.input_has_headers == Some(false) {
+            return Err("--header-row can't be used with input that has no header row".to_string());
+        }
+        if self.max_output_bytes.is_some() && self.clipboard {
+            return Err("--max-output-bytes can't be used with --clipboard".to_string());
+        }
+        if self.max_output_bytes.is_some() && self.output_file.is_none() {
+            return Err("--max-output-bytes requires an output file (-o)".to_string());
+        }
+        if self.follow && self.input_file.is_none() {
+            return Err("--follow requires an input file".to_string());
+        }
+        if !self.tee.is_empty() {
+            let mut outputs = self.tee.iter().map(String::as_str).collect::<Vec<_>>();
+            outputs.push(self.output_file.as_deref().unwrap_or("-"));
+            outputs.sort_unstable();
+            if let Some(pair) = outputs.windows(2).find(|pair| pair[0] == pair[1]) {
+                return Err(format!("-o {} is given more than once", pair[0]));
+            }
+            if self.max_output_bytes.is_some() {
+                return Err("--max-output-bytes can't be used with several outputs".to_string());
+            }
+        }
+        if self.in_place.is_some() && (self.input_file.is_none() || self.output_file.is_some() || self.follow) {
+            return Err("--in-place requires an input file and can't be used with -o or --follow".to_string());
+        }
+        if self.raw_passthrough {
+            // The copied records keep the input's dialect, so it has to be the output's
+            if (config.delimiter != b',' && !self.clipboard) || config.quote != b'"' || config.escape.is_some() {
+                return Err("--raw-passthrough keeps the input's delimiter and quoting, so it can't be used with -d, -q or -p".to_string());
+            }
+            if matches!(config.trim, Trim::Fields | Trim::All) || self.contract.is_some() {
+                return Err("--raw-passthrough copies values unchanged, so it can't be used with --trim or --enforce-schema".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// The options with their defaults applied.
+    pub fn config(&self) -> CsvConfig {
+        CsvConfig {
+            delimiter: self.delimiter.unwrap_or(',') as u8,
+            quote: self.quote_char.unwrap_or('"') as u8,
+            escape: self.escape_char.map(|c| c as u8),
+            comment: self.comment_char.map(|c| c as u8),
+            trim: self.trim,
+            flexible: self.flexible.unwrap_or(true),
+            has_headers: self.input_has_headers.unwrap_or(true),
+            output_headers: self.output_headers.or(self.input_has_headers).unwrap_or(true),
+            report_headers: self.output_headers.unwrap_or(true),
+        }
+    }
+
     pub fn get_input_file(&self) -> Result<Box<dyn BufRead>, Error> {
-        if let Some(file) = &self.input_file {
-            Ok(Box::new(BufReader::new(File::open(file)?)))
-        } else {
-            Ok(Box::new(BufReader::new(stdin())))
+        self.input(true).map(|(input, _)| input)
+    }
+
+    /// The input with the lines above the header row skipped and the checks on it made, but not
+    /// cleaned of blank lines or filtered of rows, so that offsets in it are the input's once the
+    /// skipped length, returned alongside, is added.
+    pub fn get_unaltered_input(&self) -> Result<(Box<dyn BufRead>, u64), Error> {
+        self.input(false)
+    }
+
+    fn input(&self, alter: bool) -> Result<(Box<dyn BufRead>, u64), Error> {
+        let mut raw: Box<dyn Read> = match &self.input_file {
+            // A pipe's end means its writer has gone, so there is nothing to wait for
+            Some(file) if self.follow && csvutil::is_regular_file(file) => Box::new(FollowReader::new(File::open(file)?)),
+            Some(file) => Box::new(File::open(file)?),
+            None if self.follow => return Err(Error::new(io::ErrorKind::InvalidInput, "--follow requires an input file")),
+            None => match &self.input {
+                Some(input) => Box::new(input.take()?),
+                #[cfg(feature = "clipboard")]
+                None if self.clipboard => Box::new(io::Cursor::new(crate::clipboard::paste()?)),
+                None => Box::new(stdin()),
+            },
+        };
+        if let Some(metrics) = &self.metrics {
+            raw = Box::new(CountingReader::new(raw, Arc::clone(metrics)));
+        }
+        if let Some(expected) = &self.verify_sha256 {
+            raw = Box::new(VerifyingReader::new(raw, expected));
+        }
+        let mut input: Box<dyn BufRead> = Box::new(BufReader::new(raw));
+        let config = self.config();
+        let quote = config.quote;
+        // Records above the header row, such as a title or export notes
+        let mut preamble = 0;
+        if let Some(header_row) = self.header_row {
+            let skipped;
+            (skipped, preamble) = RecordScanner::new(quote).escape(config.escape).skip_records(&mut input, header_row - 1)?;
+            if skipped < header_row - 1 || input.fill_buf()?.is_empty() {
+                return Err(Error::new(io::ErrorKind::InvalidInput, format!("Header row {} is past the end of the input ({} row{})", header_row, skipped, if skipped == 1 { "" } else { "s" })));
+            }
+        }
+        if alter && (self.normalize_newlines || self.skip_blank_lines) {
+            let cleaned = LineCleaner::new(input, quote)
+                .normalize_newlines(self.normalize_newlines)
+                .skip_blank_lines(self.skip_blank_lines);
+            input = Box::new(BufReader::new(cleaned));
+        }
+        if let Some(metrics) = &self.metrics {
+            input = Box::new(BufReader::new(RecordCounter::new(input, quote, config.has_headers, Arc::clone(metrics))));
+        }
+        if self.strict_rfc4180 || self.max_field_size.is_some() || self.max_record_size.is_some() {
+            let checked = StrictReader::new(input, config.delimiter, quote)
+                .rfc4180(self.strict_rfc4180)
+                .max_field_size(self.max_field_size)
+                .max_record_size(self.max_record_size);
+            input = Box::new(BufReader::new(checked));
+        }
+        // Before the row filter, so the checks see the rows it drops and count rows as in the input
+        if !self.assertions.is_empty() {
+            let options = self.clone();
+            let resolver = Box::new(move |first_row| {
+                let names = csvutil::column_names(&options, first_row);
+                let mut checks = vec![];
+                for (columns, constraint) in &options.assertions {
+                    let indices = csvutil::select_column_indices_with_base(&names, &Some(columns.clone()), options.zero_based)
+                        .map_err(|e| Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", constraint.flag(), e)))?;
+                    checks.extend(indices.into_iter().map(|column| ColumnCheck {
+                        column,
+                        name: names[column].to_string(),
+                        constraint: constraint.clone(),
+                    }));
+                }
+                Ok(checks)
+            }) as ConstraintResolver;
+            let checker = ConstraintChecker::new(input, &config, resolver);
+            input = Box::new(BufReader::new(checker));
+        }
+        if alter && (self.drop_empty_rows || self.drop_if_empty.is_some()) {
+            let resolver = self.drop_if_empty.clone().map(|columns| {
+                let options = self.clone();
+                Box::new(move |first_row| {
+                    let names = csvutil::column_names(&options, first_row);
+                    csvutil::select_column_indices_with_base(&names, &Some(columns), options.zero_based)
+                        .map_err(|e| Error::new(io::ErrorKind::InvalidInput, format!("--drop-if-empty: {}", e)))
+                }) as ColumnResolver
+            });
+            let filter = RowFilter::new(input, &config)
+                .drop_empty(self.drop_empty_rows)
+                .drop_if_empty(resolver)
+                .verbose(self.verbose)
+                .metrics(self.metrics.clone());
+            input = Box::new(BufReader::new(filter));
+        }
+        Ok((input, preamble))
+    }
+
+    /// Ends a run with the tool's result: prints the `--summary` line after a success, or with
+    /// `--log-json` an end event, preceded by an error event after a failure. The reader of the
+    /// output going away, as `head` does, counts as a success.
+    pub fn finish(&self, result: Result<(), Box<dyn error::Error>>) -> Result<(), String> {
+        let result = result.or_else(|e| if writer::is_broken_pipe(e.as_ref()) { Ok(()) } else { Err(e) });
+        if !log::is_json() {
+            result.map_err(|e| e.to_string())?;
+            if let Some(metrics) = self.metrics.as_ref().filter(|_| self.summary) {
+                eprintln!("{}", metrics.summary());
+            }
+            return Ok(());
+        }
+        let mut end = self.metrics.as_ref().map_or_else(|| json!({}), |m| m.to_json());
+        end["status"] = json!(if result.is_ok() { "ok" } else { "error" });
+        if let Err(e) = &result {
+            log::event("error", json!({ "message": e.to_string() }));
+        }
+        log::event("end", end);
+        if result.is_err() {
+            // The error has been reported as an event, which returning it would repeat as text
+            std::process::exit(1);
         }
+        Ok(())
+    }
+
+    pub fn output_has_headers(&self) -> bool {
+        self.config().output_headers
     }
 
-    pub fn get_output_file(&self) -> Result<Box<BufWriter<dyn Write>>, Box<dyn error::Error>> {
-        let csv_file_handle: Box<BufWriter<dyn Write>>;
-        if let Some(file) = &self.output_file {
+    /// Whether to color the output: with `--color auto`, when it goes to a terminal and `NO_COLOR`
+    /// isn't set to anything, see https://no-color.org.
+    pub fn use_color(&self) -> bool {
+        match self.color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => self.writes_to_terminal() && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()),
+        }
+    }
+
+    /// Whether the output goes only to standard output, and that is a terminal.
+    pub fn writes_to_terminal(&self) -> bool {
+        self.output_file.is_none() && self.output.is_none() && self.tee.is_empty() && !self.clipboard && io::stdout().is_terminal()
+    }
+
+    pub fn get_output_file(&self) -> Result<Box<dyn Write>, Box<dyn error::Error>> {
+        #[cfg(feature = "clipboard")]
+        if self.clipboard {
+            return Ok(Box::new(BufWriter::new(crate::clipboard::ClipboardWriter::new()?)));
+        }
+        let csv_file_handle: Box<dyn Write>;
+        if let Some(max_bytes) = self.max_output_bytes {
+            let file = self.output_file.as_ref().ok_or("--max-output-bytes requires an output file (-o)")?;
+            csv_file_handle = Box::new(RollingWriter::new(file, max_bytes, self.output_has_headers())?);
+        } else if let Some(file) = &self.output_file {
             csv_file_handle = Box::new(BufWriter::new(File::create(file)?));
+        } else if let Some(output) = &self.output {
+            csv_file_handle = Box::new(output.take()?);
         } else {
             csv_file_handle = Box::new(BufWriter::new(io::stdout()));
         }
-        Ok(csv_file_handle)
+        if self.tee.is_empty() {
+            return Ok(csv_file_handle);
+        }
+        let mut outputs = vec![csv_file_handle];
+        for file in &self.tee {
+            outputs.push(match file.as_str() {
+                "-" => Box::new(BufWriter::new(io::stdout())),
+                file => Box::new(BufWriter::new(File::create(file)?)),
+            });
+        }
+        Ok(Box::new(TeeWriter::new(outputs)))
     }
 }
 
@@ -40,5 +600,19 @@ impl CsvOptions {
     pub fn new() -> Self {
         Default::default()
     }
+
+    /// Reads the input from `input`, e.g. an in-memory buffer, instead of a file or stdin.
+    pub fn with_input(mut self, input: impl BufRead + Send + 'static) -> Self {
+        self.input_file = None;
+        self.input = Some(Stream::new(Box::new(input)));
+        self
+    }
+
+    /// Writes the output to `output`, e.g. a `SharedBuffer`, instead of a file or stdout.
+    pub fn with_output(mut self, output: impl Write + Send + 'static) -> Self {
+        self.output_file = None;
+        self.output = Some(Stream::new(Box::new(output)));
+        self
+    }
 }
 
@@ -0,0 +1,182 @@
+//! The `--interactive` column picker: lists the headers with sample values on the terminal and
+//! lets the user toggle columns with the keyboard. It draws on stderr so stdout stays free for
+//! the cut itself.
+
+use crate::csvutil;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::{cursor, execute, queue, terminal};
+use csv::StringRecord;
+use std::error::Error;
+use std::io::{self, Write};
+
+const HELP: &str = "Space: toggle  a: all  n: none  Enter: accept  q: cancel";
+
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    Continue,
+    Accept,
+    Cancel,
+}
+
+/// The cursor and selection, kept apart from the drawing so the key handling can be tested.
+pub struct PickerState {
+    pub cursor: usize,
+    pub selected: Vec<bool>,
+}
+
+impl PickerState {
+    pub fn new(n_columns: usize) -> Self {
+        PickerState { cursor: 0, selected: vec![false; n_columns] }
+    }
+
+    pub fn handle(&mut self, key: KeyEvent, page: usize) -> Action {
+        let last = self.selected.len().saturating_sub(1);
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Action::Cancel,
+            KeyCode::Esc | KeyCode::Char('q') => return Action::Cancel,
+            KeyCode::Enter => return Action::Accept,
+            KeyCode::Up | KeyCode::Char('k') => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => self.cursor = (self.cursor + 1).min(last),
+            KeyCode::PageUp => self.cursor = self.cursor.saturating_sub(page),
+            KeyCode::PageDown => self.cursor = (self.cursor + page).min(last),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = last,
+            KeyCode::Char(' ') => {
+                if let Some(selected) = self.selected.get_mut(self.cursor) {
+                    *selected = !*selected;
+                }
+                self.cursor = (self.cursor + 1).min(last);
+            }
+            KeyCode::Char('a') => self.selected.iter_mut().for_each(|s| *s = true),
+            KeyCode::Char('n') => self.selected.iter_mut().for_each(|s| *s = false),
+            _ => {}
+        }
+        Action::Continue
+    }
+
+    pub fn indices(&self) -> Vec<usize> {
+        self.selected.iter().enumerate().filter(|(_, &s)| s).map(|(i, _)| i).collect()
+    }
+}
+
+/// The `-c` value selecting `indices`, by name where the name is unique, quoted if it could be
+/// mistaken for an offset or range, otherwise by 1-based position.
+pub fn column_spec(headers: &StringRecord, indices: &[usize]) -> String {
+    indices.iter().map(|&i| {
+        let name = headers.get(i).unwrap_or_default();
+        if name.is_empty() || headers.iter().filter(|&h| h == name).count() > 1 {
+            (i + 1).to_string()
+        } else if name.contains([',', '-', '"']) || name.trim() != name || name.parse::<i64>().is_ok() {
+            format!("\"{}\"", name.replace('"', "\"\""))
+        } else {
+            name.to_string()
+        }
+    }).collect::<Vec<_>>().join(",")
+}
+
+/// The terminal's columns and rows, assuming 80x24 when it doesn't say.
+fn terminal_size() -> (usize, usize) {
+    match terminal::size() {
+        Ok((w, h)) if w > 0 && h > 0 => (w as usize, h as usize),
+        _ => (80, 24),
+    }
+}
+
+/// Restores the terminal however the picker exits.
+struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(io::stderr(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn draw(out: &mut impl Write, state: &PickerState, headers: &StringRecord, samples: &[StringRecord], offset: usize, page: usize) -> io::Result<()> {
+    let (width, _) = terminal_size();
+    let name_width = headers.iter().map(csvutil::display_width).max().unwrap_or(0).min(width / 3);
+    queue!(out, terminal::Clear(terminal::ClearType::All), cursor::MoveTo(0, 0),
+           Print(format!("{} of {} columns selected. {}", state.indices().len(), headers.len(), HELP)))?;
+    for (row, i) in (offset..headers.len().min(offset + page)).enumerate() {
+        let sample = samples.iter().map(|s| s.get(i).unwrap_or_default()).collect::<Vec<_>>().join(" | ");
+        let line = format!("{} {:>4} {}  {}", if state.selected[i] { "[x]" } else { "[ ]" }, i + 1,
+                           csvutil::pad_to_width(headers.get(i).unwrap_or_default(), name_width), sample);
+        let (line, _) = csvutil::truncate_to_width(&line, width.saturating_sub(1));
+        queue!(out, cursor::MoveTo(0, row as u16 + 1))?;
+        if i == state.cursor {
+            queue!(out, SetAttribute(Attribute::Reverse), Print(line), SetAttribute(Attribute::Reset))?;
+        } else {
+            queue!(out, Print(line))?;
+        }
+    }
+    out.flush()
+}
+
+/// Shows the picker, returning the selected column indices, or None if the user cancelled.
+pub fn pick_columns(headers: &StringRecord, samples: &[StringRecord]) -> Result<Option<Vec<usize>>, Box<dyn Error>> {
+    if headers.is_empty() {
+        return Err(Box::from("The input has no columns to pick from"));
+    }
+    let mut out = io::stderr();
+    terminal::enable_raw_mode()?;
+    let _guard = TerminalGuard;
+    execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+
+    let mut state = PickerState::new(headers.len());
+    let mut offset = 0;
+    loop {
+        let page = (terminal_size().1 - 1).max(1);
+        // Keep the cursor on screen
+        if state.cursor < offset {
+            offset = state.cursor;
+        } else if state.cursor >= offset + page {
+            offset = state.cursor + 1 - page;
+        }
+        draw(&mut out, &state, headers, samples, offset, page)?;
+
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match state.handle(key, page) {
+                Action::Continue => {}
+                Action::Accept => return Ok(Some(state.indices())),
+                Action::Cancel => return Ok(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_picker_state() {
+        let mut state = PickerState::new(4);
+        assert_eq!(state.handle(key(KeyCode::Char(' ')), 10), Action::Continue);
+        state.handle(key(KeyCode::Down), 10);
+        state.handle(key(KeyCode::Char(' ')), 10);
+        assert_eq!(state.indices(), vec![0, 2]);
+        assert_eq!(state.cursor, 3);
+        state.handle(key(KeyCode::Down), 10);
+        assert_eq!(state.cursor, 3);
+        state.handle(key(KeyCode::Char('a')), 10);
+        assert_eq!(state.indices(), vec![0, 1, 2, 3]);
+        state.handle(key(KeyCode::Char('n')), 10);
+        assert!(state.indices().is_empty());
+        assert_eq!(state.handle(key(KeyCode::Enter), 10), Action::Accept);
+        assert_eq!(state.handle(key(KeyCode::Esc), 10), Action::Cancel);
+    }
+
+    #[test]
+    fn test_column_spec() {
+        let headers = StringRecord::from(vec!["id", "first,last", "id", "2020", "x-y", "name"]);
+        assert_eq!(column_spec(&headers, &[0, 1, 3, 4, 5]), "1,\"first,last\",\"2020\",\"x-y\",name");
+    }
+}
@@ -0,0 +1,1085 @@
+//! Byte-level checks run on the raw input before it reaches the csv reader: RFC 4180 compliance
+//! for `--strict-rfc4180`, which the reader otherwise relaxes (stray quotes, bare CRs and, with
+//! --flexible, ragged rows), the `--max-field-size` and `--max-record-size` limits, which stop a
+//! runaway quoted field before the reader buffers all of it, `--verify-sha256`, `--follow`, the
+//! `--normalize-newlines` and `--skip-blank-lines` cleanups, the `--drop-empty-rows` and
+//! `--drop-if-empty` filters, the `--assert-unique`, `--assert-not-null` and `--assert-range`
+//! checks, the `--summary` counts, the record boundary scanner that anything counting rows
+//! in the raw input uses, and the `--raw-passthrough` record reader built on it.
+
+use crate::log;
+use crate::options::{CsvConfig, Metrics};
+use csv::{ByteRecord, StringRecord, Trim};
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    StartField,
+    Unquoted,
+    Quoted,
+    /// A quote inside a quoted field: either the closing quote or the first half of `""`
+    QuoteInQuoted,
+    AfterCr,
+}
+
+pub struct StrictReader<R> {
+    inner: R,
+    delimiter: u8,
+    quote: u8,
+    rfc4180: bool,
+    max_field_size: Option<u64>,
+    max_record_size: Option<u64>,
+    state: State,
+    byte: u64,
+    line: u64,
+    record: u64,
+    fields: usize,
+    field_size: u64,
+    record_size: u64,
+    expected_fields: Option<usize>,
+}
+
+impl<R: Read> StrictReader<R> {
+    pub fn new(inner: R, delimiter: u8, quote: u8) -> Self {
+        StrictReader {
+            inner, delimiter, quote, rfc4180: true, max_field_size: None, max_record_size: None,
+            state: State::StartField, byte: 0, line: 1, record: 1, fields: 1, field_size: 0, record_size: 0, expected_fields: None,
+        }
+    }
+
+    /// Whether to enforce RFC 4180, or only the size limits using the csv reader's relaxed rules.
+    pub fn rfc4180(mut self, yes: bool) -> Self {
+        self.rfc4180 = yes;
+        self
+    }
+
+    pub fn max_field_size(mut self, max: Option<u64>) -> Self {
+        self.max_field_size = max;
+        self
+    }
+
+    pub fn max_record_size(mut self, max: Option<u64>) -> Self {
+        self.max_record_size = max;
+        self
+    }
+
+    fn error(&self, message: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData,
+                       format!("Not RFC 4180 compliant at line {}, byte {} (record {}): {}", self.line, self.byte, self.record, message))
+    }
+
+    fn limit_error(&self, what: &str, max: u64) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData,
+                       format!("Record {} (line {}) has a {} larger than {} bytes, at field {}. An unterminated quote can cause this",
+                               self.record, self.line, what, max, self.fields))
+    }
+
+    fn end_field(&mut self) {
+        self.fields += 1;
+        self.field_size = 0;
+    }
+
+    fn end_record(&mut self) -> io::Result<()> {
+        if self.rfc4180 {
+            match self.expected_fields {
+                None => self.expected_fields = Some(self.fields),
+                Some(expected) if expected != self.fields => {
+                    return Err(self.error(&format!("record has {} fields, expected {}", self.fields, expected)));
+                }
+                Some(_) => {}
+            }
+        }
+        self.record += 1;
+        self.fields = 1;
+        self.field_size = 0;
+        self.record_size = 0;
+        Ok(())
+    }
+
+    fn check(&mut self, b: u8) -> io::Result<()> {
+        self.state = match (self.state, b) {
+            (State::AfterCr, b'\n') if !self.rfc4180 => State::StartField,
+            (State::AfterCr, b'\n') => {
+                self.end_record()?;
+                State::StartField
+            }
+            (State::AfterCr, _) if self.rfc4180 => return Err(self.error("bare CR not followed by LF")),
+            (State::AfterCr, _) => {
+                self.state = State::StartField;
+                return self.check(b);
+            }
+            (State::Quoted, _) if b == self.quote => State::QuoteInQuoted,
+            (State::Quoted, _) => State::Quoted,
+            (State::QuoteInQuoted, _) if b == self.quote => State::Quoted,
+            (State::StartField, _) if b == self.quote => State::Quoted,
+            (State::Unquoted, _) if b == self.quote && self.rfc4180 => return Err(self.error("unescaped quote in an unquoted field")),
+            (_, _) if b == self.delimiter => {
+                self.end_field();
+                State::StartField
+            }
+            // The relaxed reader ends a record at a bare CR
+            (_, b'\r') if !self.rfc4180 => {
+                self.end_record()?;
+                State::AfterCr
+            }
+            (_, b'\r') => State::AfterCr,
+            (_, b'\n') => {
+                self.end_record()?;
+                State::StartField
+            }
+            (State::QuoteInQuoted, _) if self.rfc4180 => return Err(self.error("characters after the closing quote")),
+            (_, _) => State::Unquoted,
+        };
+        if b == b'\n' {
+            self.line += 1;
+        }
+        if self.state != State::StartField && self.state != State::AfterCr {
+            self.field_size += 1;
+            self.record_size += 1;
+            if let Some(max) = self.max_field_size.filter(|&max| self.field_size > max) {
+                return Err(self.limit_error("field", max));
+            }
+            if let Some(max) = self.max_record_size.filter(|&max| self.record_size > max) {
+                return Err(self.limit_error("record", max));
+            }
+        }
+        self.byte += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.state {
+            _ if !self.rfc4180 => Ok(()),
+            State::Quoted => Err(self.error("unterminated quoted field")),
+            State::AfterCr => Err(self.error("bare CR at end of input")),
+            // No final line break, or an empty input
+            State::StartField if self.fields == 1 => Ok(()),
+            _ => self.end_record(),
+        }
+    }
+}
+
+impl<R: Read> Read for StrictReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            self.finish()?;
+        }
+        for &b in &buf[..n] {
+            self.check(b)?;
+        }
+        Ok(n)
+    }
+}
+
+/// Hashes everything read and fails at the end of the input if the digest isn't `expected`.
+pub struct VerifyingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    expected: String,
+    verified: bool,
+}
+
+impl<R: Read> VerifyingReader<R> {
+    pub fn new(inner: R, expected: &str) -> Self {
+        VerifyingReader { inner, hasher: Sha256::new(), expected: expected.to_ascii_lowercase(), verified: false }
+    }
+}
+
+impl<R: Read> Read for VerifyingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        if n == 0 && !buf.is_empty() && !self.verified {
+            self.verified = true;
+            let actual = format!("{:x}", self.hasher.clone().finalize());
+            if actual != self.expected {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          format!("SHA-256 mismatch: expected {}, but the input hashes to {}. It may be truncated or corrupted",
+                                                  self.expected, actual)));
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// Adds the bytes read to `Metrics::bytes_read`, for `--summary`.
+pub struct CountingReader<R> {
+    inner: R,
+    metrics: Arc<Metrics>,
+}
+
+impl<R: Read> CountingReader<R> {
+    pub fn new(inner: R, metrics: Arc<Metrics>) -> Self {
+        CountingReader { inner, metrics }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        Metrics::add(&self.metrics.bytes_read, n as u64);
+        Ok(n)
+    }
+}
+
+/// Never reaches the end of the input: at the current end it waits for more to be appended, like `tail -f`.
+pub struct FollowReader<R> {
+    inner: R,
+    poll_interval: Duration,
+}
+
+impl<R: Read> FollowReader<R> {
+    pub fn new(inner: R) -> Self {
+        FollowReader { inner, poll_interval: Duration::from_millis(200) }
+    }
+
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+}
+
+impl<R: Read> Read for FollowReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.inner.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            thread::sleep(self.poll_interval);
+        }
+    }
+}
+
+/// Finds where records end in raw input without parsing their fields, so that skipping or
+/// counting rows treats a quoted field's newlines as part of its record. A newline ends a
+/// record unless an odd number of quotes has been seen since the last boundary; an escaped
+/// `""` counts twice and cancels out.
+pub struct RecordScanner {
+    quote: u8,
+    escape: Option<u8>,
+    in_quotes: bool,
+    /// The last byte scanned was the escape character, inside quotes
+    escaped: bool,
+}
+
+impl RecordScanner {
+    pub fn new(quote: u8) -> Self {
+        RecordScanner { quote, escape: None, in_quotes: false, escaped: false }
+    }
+
+    /// Also take a quote after `escape` inside a quoted field as part of the field, as `-p` does.
+    pub fn escape(mut self, escape: Option<u8>) -> Self {
+        self.escape = escape;
+        self
+    }
+
+    /// Whether the input scanned so far ends inside a quoted field.
+    pub fn in_quotes(&self) -> bool {
+        self.in_quotes
+    }
+
+    /// Scans one line, up to and including its newline, returning whether it ends a record.
+    pub fn line_ends_record(&mut self, line: &[u8]) -> bool {
+        let Some(escape) = self.escape else {
+            if line.iter().filter(|&&b| b == self.quote).count() % 2 == 1 {
+                self.in_quotes = !self.in_quotes;
+            }
+            return !self.in_quotes;
+        };
+        for &b in line {
+            if self.escaped {
+                self.escaped = false;
+            } else if self.in_quotes && b == escape {
+                self.escaped = true;
+            } else if b == self.quote {
+                self.in_quotes = !self.in_quotes;
+            }
+        }
+        !self.in_quotes
+    }
+
+    /// Appends the rest of the current record to `record`, returning the number of bytes read,
+    /// 0 at the end of the input. An unterminated quote runs to the end of the input.
+    pub fn read_record<R: BufRead + ?Sized>(&mut self, input: &mut R, record: &mut Vec<u8>) -> io::Result<usize> {
+        let mut n = 0;
+        loop {
+            let start = record.len();
+            let read = input.read_until(b'\n', record)?;
+            n += read;
+            if read == 0 || self.line_ends_record(&record[start..]) {
+                return Ok(n);
+            }
+        }
+    }
+
+    /// Skips up to `n` records, returning how many there were and their length in bytes.
+    pub fn skip_records<R: BufRead + ?Sized>(&mut self, input: &mut R, n: u64) -> io::Result<(u64, u64)> {
+        let mut record = vec![];
+        let mut bytes = 0;
+        for skipped in 0..n {
+            record.clear();
+            match self.read_record(input, &mut record)? {
+                0 => return Ok((skipped, bytes)),
+                read => bytes += read as u64,
+            }
+        }
+        Ok((n, bytes))
+    }
+}
+
+/// Splits raw records, as `RecordScanner` finds them, into fields in the input's dialect, for the
+/// readers that look at values while passing the bytes through. One csv_core reader and its
+/// buffers are kept across records.
+pub struct FieldParser {
+    reader: csv_core::Reader,
+    trim: Trim,
+    output: Vec<u8>,
+    ends: Vec<usize>,
+}
+
+impl FieldParser {
+    pub fn new(config: &CsvConfig) -> Self {
+        let reader = csv_core::ReaderBuilder::new().delimiter(config.delimiter).quote(config.quote).escape(config.escape).build();
+        FieldParser { reader, trim: config.trim, output: vec![], ends: vec![] }
+    }
+
+    /// Parses `raw` into `record`, trimming it as `--trim` says for a header row or a record.
+    pub fn parse(&mut self, raw: &[u8], header: bool, record: &mut ByteRecord) {
+        // Unquoting only ever shrinks a record, so its raw length bounds both buffers
+        self.output.resize(raw.len(), 0);
+        self.ends.resize(raw.len() + 1, 0);
+        self.reader.reset();
+        let (mut input, mut n_output, mut n_ends) = (raw, 0, 0);
+        loop {
+            let (result, n_in, n_out, n_end) = self.reader.read_record(input, &mut self.output[n_output..], &mut self.ends[n_ends..]);
+            input = &input[n_in..];
+            n_output += n_out;
+            n_ends += n_end;
+            // The record ends at its line ending or, once the input is empty, at its end
+            if !matches!(result, csv_core::ReadRecordResult::InputEmpty) || n_in == 0 {
+                break;
+            }
+        }
+        record.clear();
+        let trim = matches!((self.trim, header), (Trim::All, _) | (Trim::Fields, false) | (Trim::Headers, true));
+        let mut start = 0;
+        for &end in &self.ends[..n_ends] {
+            let field = &self.output[start..end];
+            record.push_field(if trim { field.trim_ascii() } else { field });
+            start = end;
+        }
+    }
+}
+
+/// Rewrites CRLF and lone CR line endings as LF, including inside quoted fields, and drops lines
+/// holding only whitespace between records. A `RecordScanner` tells the two apart.
+pub struct LineCleaner<R> {
+    inner: R,
+    normalize_newlines: bool,
+    skip_blank_lines: bool,
+    scanner: RecordScanner,
+    chunk: Vec<u8>,
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: BufRead> LineCleaner<R> {
+    pub fn new(inner: R, quote: u8) -> Self {
+        LineCleaner {
+            inner, normalize_newlines: false, skip_blank_lines: false, scanner: RecordScanner::new(quote),
+            chunk: vec![], buffer: vec![], pos: 0,
+        }
+    }
+
+    pub fn normalize_newlines(mut self, yes: bool) -> Self {
+        self.normalize_newlines = yes;
+        self
+    }
+
+    pub fn skip_blank_lines(mut self, yes: bool) -> Self {
+        self.skip_blank_lines = yes;
+        self
+    }
+
+    /// Refills the buffer with the next lines kept, leaving it empty at the end of the input.
+    fn fill(&mut self) -> io::Result<()> {
+        self.buffer.clear();
+        self.pos = 0;
+        while self.buffer.is_empty() {
+            self.chunk.clear();
+            if self.inner.read_until(b'\n', &mut self.chunk)? == 0 {
+                return Ok(());
+            }
+            if self.normalize_newlines {
+                // A chunk only ends in CR at the end of the input, so a CR's LF is always in the same chunk
+                let mut normalized = Vec::with_capacity(self.chunk.len());
+                for (i, &b) in self.chunk.iter().enumerate() {
+                    match b {
+                        b'\r' if self.chunk.get(i + 1) == Some(&b'\n') => {}
+                        b'\r' => normalized.push(b'\n'),
+                        _ => normalized.push(b),
+                    }
+                }
+                self.chunk = normalized;
+            }
+            for line in self.chunk.split_inclusive(|&b| b == b'\n') {
+                let blank = line.iter().all(|b| matches!(b, b' ' | b'\t' | b'\r' | b'\n'));
+                if !(self.skip_blank_lines && blank && !self.scanner.in_quotes()) {
+                    self.buffer.extend_from_slice(line);
+                }
+                self.scanner.line_ends_record(line);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for LineCleaner<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.buffer.len() {
+            self.fill()?;
+        }
+        let n = buf.len().min(self.buffer.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Adds the data records passing through to `Metrics::records_read`, for `--summary`. Empty
+/// lines aren't counted, as the csv reader skips them.
+pub struct RecordCounter<R> {
+    inner: R,
+    scanner: RecordScanner,
+    header_pending: bool,
+    metrics: Arc<Metrics>,
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: BufRead> RecordCounter<R> {
+    pub fn new(inner: R, quote: u8, has_headers: bool, metrics: Arc<Metrics>) -> Self {
+        RecordCounter { inner, scanner: RecordScanner::new(quote), header_pending: has_headers, metrics, buffer: vec![], pos: 0 }
+    }
+}
+
+impl<R: BufRead> Read for RecordCounter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.buffer.len() {
+            self.buffer.clear();
+            self.pos = 0;
+            if self.scanner.read_record(&mut self.inner, &mut self.buffer)? > 0 && !is_empty_line(&self.buffer) {
+                if self.header_pending {
+                    self.header_pending = false;
+                } else {
+                    self.metrics.record_read();
+                }
+            }
+        }
+        let n = buf.len().min(self.buffer.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Resolves the columns `RowFilter` checks from the header row, or the first record without one.
+pub type ColumnResolver = Box<dyn FnOnce(csv::StringRecord) -> io::Result<Vec<usize>>>;
+
+/// Drops records whose fields are all empty or whitespace and, given columns, those where any
+/// of them is, passing the rest through unchanged. Fields are split on unquoted delimiters, so
+/// quoted delimiters and line breaks aren't mistaken for field or record boundaries.
+pub struct RowFilter<R> {
+    inner: R,
+    parser: FieldParser,
+    fields: ByteRecord,
+    comment: Option<u8>,
+    scanner: RecordScanner,
+    drop_empty: bool,
+    resolver: Option<ColumnResolver>,
+    columns: Vec<usize>,
+    /// The header row still to be passed through, if the input has one
+    header_pending: bool,
+    first: bool,
+    verbose: bool,
+    dropped: u64,
+    metrics: Option<Arc<Metrics>>,
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: BufRead> RowFilter<R> {
+    pub fn new(inner: R, config: &CsvConfig) -> Self {
+        RowFilter {
+            inner, parser: FieldParser::new(config), fields: ByteRecord::new(), comment: config.comment,
+            scanner: RecordScanner::new(config.quote).escape(config.escape), drop_empty: false, resolver: None,
+            columns: vec![], header_pending: config.has_headers, first: true, verbose: false, dropped: 0, metrics: None, buffer: vec![], pos: 0,
+        }
+    }
+
+    pub fn drop_empty(mut self, yes: bool) -> Self {
+        self.drop_empty = yes;
+        self
+    }
+
+    pub fn drop_if_empty(mut self, resolver: Option<ColumnResolver>) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
+    /// Report how many records were dropped at the end of the input
+    pub fn verbose(mut self, yes: bool) -> Self {
+        self.verbose = yes;
+        self
+    }
+
+    /// Count the records dropped as skipped, for `--summary`
+    pub fn metrics(mut self, metrics: Option<Arc<Metrics>>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Refills the buffer with the next record kept, leaving it empty at the end of the input.
+    fn fill(&mut self) -> io::Result<()> {
+        self.buffer.clear();
+        self.pos = 0;
+        loop {
+            if self.scanner.read_record(&mut self.inner, &mut self.buffer)? == 0 {
+                if self.verbose && self.dropped > 0 {
+                    log::info(&format!("Dropped {} records with empty fields", self.dropped));
+                }
+                return Ok(());
+            }
+            // Comments and empty lines aren't records to the csv reader: comments pass through and
+            // empty lines go, neither taken for the header row nor counted as dropped
+            if self.comment.is_some_and(|c| self.buffer.first() == Some(&c)) {
+                return Ok(());
+            }
+            if is_empty_line(&self.buffer) {
+                self.buffer.clear();
+                continue;
+            }
+            self.parser.parse(&self.buffer, self.header_pending, &mut self.fields);
+            if self.first {
+                self.first = false;
+                if let Some(resolver) = self.resolver.take() {
+                    self.columns = resolver(self.fields.iter().map(String::from_utf8_lossy).collect())?;
+                }
+            }
+            if self.header_pending {
+                self.header_pending = false;
+                return Ok(());
+            }
+            let fields = &self.fields;
+            let empty = |i: &usize| fields.get(*i).is_none_or(|f| f.iter().all(u8::is_ascii_whitespace));
+            let all_empty = self.drop_empty && (0..fields.len()).all(|i| empty(&i));
+            if !all_empty && !self.columns.iter().any(empty) {
+                return Ok(());
+            }
+            self.dropped += 1;
+            if let Some(metrics) = &self.metrics {
+                Metrics::add(&metrics.records_skipped, 1);
+            }
+            self.buffer.clear();
+        }
+    }
+}
+
+impl<R: BufRead> Read for RowFilter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.buffer.len() {
+            self.fill()?;
+        }
+        let n = buf.len().min(self.buffer.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Reads records as the bytes they were written with, alongside their values, for the tools that
+/// copy whole records with `--raw-passthrough`. Blank lines and comments are skipped, as the csv
+/// reader skips them.
+pub struct RawRecords<R> {
+    inner: R,
+    scanner: RecordScanner,
+    parser: FieldParser,
+    comment: Option<u8>,
+    has_headers: bool,
+    headers: Option<StringRecord>,
+    /// The first record, read by `headers` when the input has no header row
+    pending: Option<(Vec<u8>, StringRecord)>,
+}
+
+impl<R: BufRead> RawRecords<R> {
+    pub fn new(inner: R, config: &CsvConfig) -> Self {
+        RawRecords {
+            inner, scanner: RecordScanner::new(config.quote).escape(config.escape), parser: FieldParser::new(config),
+            comment: config.comment, has_headers: config.has_headers, headers: None, pending: None,
+        }
+    }
+
+    /// The header row, or the first record when the input has none, like `csv::Reader::headers`.
+    pub fn headers(&mut self) -> io::Result<StringRecord> {
+        if let Some(headers) = &self.headers {
+            return Ok(headers.clone());
+        }
+        let first = self.read()?;
+        let headers = first.as_ref().map(|(_, record)| record.clone()).unwrap_or_default();
+        if !self.has_headers {
+            self.pending = first;
+        }
+        self.headers = Some(headers.clone());
+        Ok(headers)
+    }
+
+    fn read(&mut self) -> io::Result<Option<(Vec<u8>, StringRecord)>> {
+        let mut raw = vec![];
+        loop {
+            raw.clear();
+            if self.scanner.read_record(&mut self.inner, &mut raw)? == 0 {
+                return Ok(None);
+            }
+            if is_empty_line(&raw) || self.comment.is_some_and(|c| raw.first() == Some(&c)) {
+                continue;
+            }
+            let mut fields = ByteRecord::new();
+            self.parser.parse(&raw, self.headers.is_none() && self.has_headers, &mut fields);
+            let record = StringRecord::from_byte_record(fields).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            return Ok(Some((raw, record)));
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for RawRecords<R> {
+    type Item = io::Result<(Vec<u8>, StringRecord)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.headers.is_none() {
+            if let Err(e) = self.headers() {
+                return Some(Err(e));
+            }
+        }
+        match self.pending.take() {
+            Some(first) => Some(Ok(first)),
+            None => self.read().transpose(),
+        }
+    }
+}
+
+/// A check `ConstraintChecker` makes on each value of a column. Empty and whitespace values
+/// only fail `NotNull`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constraint {
+    /// `--assert-unique`: no value repeats
+    Unique,
+    /// `--assert-not-null`: no value is empty or whitespace
+    NotNull,
+    /// `--assert-range`: every value is a number within these inclusive bounds
+    Range(Option<f64>, Option<f64>),
+}
+
+impl Constraint {
+    /// The option giving the constraint, for messages
+    pub fn flag(&self) -> &'static str {
+        match self {
+            Constraint::Unique => "--assert-unique",
+            Constraint::NotNull => "--assert-not-null",
+            Constraint::Range(..) => "--assert-range",
+        }
+    }
+}
+
+/// Parses an `--assert-range` spec, COLUMN:MIN..MAX, where either bound may be left out.
+pub fn parse_range_assertion(s: &str) -> Result<(String, Constraint), String> {
+    let invalid = || format!("Invalid range '{}'. Expected COLUMN:MIN..MAX, e.g. qty:0..1000", s);
+    let (column, range) = s.rsplit_once(':').ok_or_else(invalid)?;
+    let (min, max) = range.split_once("..").ok_or_else(invalid)?;
+    let bound = |b: &str| match b.trim() {
+        "" => Ok(None),
+        b => b.parse::<f64>().map(Some).map_err(|_| invalid()),
+    };
+    let (min, max) = (bound(min)?, bound(max)?);
+    if column.is_empty() || matches!((min, max), (Some(min), Some(max)) if min > max) {
+        return Err(invalid());
+    }
+    Ok((column.to_string(), Constraint::Range(min, max)))
+}
+
+/// A constraint resolved to the column it applies to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnCheck {
+    pub column: usize,
+    /// The column's name, for messages
+    pub name: String,
+    pub constraint: Constraint,
+}
+
+/// Resolves the checks `ConstraintChecker` makes from the header row, or the first record without one.
+pub type ConstraintResolver = Box<dyn FnOnce(csv::StringRecord) -> io::Result<Vec<ColumnCheck>>>;
+
+/// Fails at the first record breaking one of the checks, naming its row and column, and passes
+/// the input through unchanged until then. Rows are counted from 1 at the first record after
+/// the header row. Records are parsed in the input's dialect, and blank lines and comments are
+/// skipped, as the csv reader skips them.
+pub struct ConstraintChecker<R> {
+    inner: R,
+    parser: FieldParser,
+    fields: ByteRecord,
+    comment: Option<u8>,
+    scanner: RecordScanner,
+    resolver: Option<ConstraintResolver>,
+    checks: Vec<ColumnCheck>,
+    /// The row each value was first seen in, for each check
+    seen: Vec<HashMap<Vec<u8>, u64>>,
+    header_pending: bool,
+    row: u64,
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: BufRead> ConstraintChecker<R> {
+    pub fn new(inner: R, config: &CsvConfig, resolver: ConstraintResolver) -> Self {
+        ConstraintChecker {
+            inner, parser: FieldParser::new(config), fields: ByteRecord::new(), comment: config.comment, scanner: RecordScanner::new(config.quote).escape(config.escape),
+            resolver: Some(resolver), checks: vec![], seen: vec![], header_pending: config.has_headers, row: 0, buffer: vec![], pos: 0,
+        }
+    }
+
+    /// Refills the buffer with the next record, or a blank line or comment, leaving it empty at
+    /// the end of the input.
+    fn fill(&mut self) -> io::Result<()> {
+        self.buffer.clear();
+        self.pos = 0;
+        if self.scanner.read_record(&mut self.inner, &mut self.buffer)? == 0 {
+            return Ok(());
+        }
+        if is_empty_line(&self.buffer) || self.comment.is_some_and(|c| self.buffer.first() == Some(&c)) {
+            return Ok(());
+        }
+        let mut fields = std::mem::take(&mut self.fields);
+        self.parser.parse(&self.buffer, self.header_pending, &mut fields);
+        let checked = self.check(&fields);
+        self.fields = fields;
+        checked
+    }
+
+    /// Resolves the checks from the first record, then checks the values of the rest.
+    fn check(&mut self, fields: &ByteRecord) -> io::Result<()> {
+        if let Some(resolver) = self.resolver.take() {
+            self.checks = resolver(fields.iter().map(String::from_utf8_lossy).collect())?;
+            self.seen = vec![HashMap::new(); self.checks.len()];
+        }
+        if self.header_pending {
+            self.header_pending = false;
+            return Ok(());
+        }
+        self.row += 1;
+        for (check, seen) in self.checks.iter().zip(&mut self.seen) {
+            let value = fields.get(check.column).unwrap_or_default();
+            if value.iter().all(u8::is_ascii_whitespace) {
+                if check.constraint == Constraint::NotNull {
+                    return Err(self.error(check, "the value is empty"));
+                }
+                continue;
+            }
+            let text = String::from_utf8_lossy(value);
+            let problem = match &check.constraint {
+                Constraint::NotNull => continue,
+                Constraint::Unique => match seen.entry(value.to_vec()) {
+                    Entry::Occupied(first) => format!("'{}' repeats row {}", text, first.get()),
+                    Entry::Vacant(entry) => {
+                        entry.insert(self.row);
+                        continue;
+                    }
+                },
+                Constraint::Range(min, max) => match text.trim().parse::<f64>() {
+                    Ok(n) if min.is_none_or(|min| n >= min) && max.is_none_or(|max| n <= max) => continue,
+                    Ok(_) => format!("{} is outside {}..{}", text.trim(), format_bound(*min), format_bound(*max)),
+                    Err(_) => format!("'{}' isn't a number", text),
+                },
+            };
+            return Err(self.error(check, &problem));
+        }
+        Ok(())
+    }
+
+    fn error(&self, check: &ColumnCheck, problem: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData,
+                       format!("{} failed at row {}, column {}: {}", check.constraint.flag(), self.row, check.name, problem))
+    }
+}
+
+fn format_bound(bound: Option<f64>) -> String {
+    bound.map(|b| b.to_string()).unwrap_or_default()
+}
+
+impl<R: BufRead> Read for ConstraintChecker<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.buffer.len() {
+            self.fill()?;
+        }
+        let n = buf.len().min(self.buffer.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+pub fn is_empty_line(record: &[u8]) -> bool {
+    matches!(record, b"\n" | b"\r\n")
+}
+
+/// Parses a SHA-256 digest given as 64 hex digits.
+pub fn parse_sha256(s: &str) -> Result<String, String> {
+    let s = s.trim();
+    if s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(s.to_ascii_lowercase())
+    } else {
+        Err(format!("Invalid SHA-256 digest '{}'. Expected 64 hex digits", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::CsvOptions;
+
+    fn validate(input: &str) -> Result<(), String> {
+        let mut out = vec![];
+        StrictReader::new(input.as_bytes(), b',', b'"').read_to_end(&mut out).map(|_| ()).map_err(|e| e.to_string())
+    }
+
+    fn limit(input: &str, max_field_size: Option<u64>, max_record_size: Option<u64>) -> Result<(), String> {
+        let mut out = vec![];
+        StrictReader::new(input.as_bytes(), b',', b'"')
+            .rfc4180(false)
+            .max_field_size(max_field_size)
+            .max_record_size(max_record_size)
+            .read_to_end(&mut out).map(|_| ()).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_strict_reader() {
+        assert_eq!(validate("a,b\r\n\"x,\"\"y\"\"\",2\r\n3,\"multi\nline\"\n"), Ok(()));
+        assert_eq!(validate("a,b\n1,2"), Ok(()));
+        assert_eq!(validate(""), Ok(()));
+
+        assert_eq!(validate("a,b\n1,2,3\n").unwrap_err(),
+                   "Not RFC 4180 compliant at line 2, byte 9 (record 2): record has 3 fields, expected 2");
+        assert_eq!(validate("a,b\n1\r2,3\n").unwrap_err(),
+                   "Not RFC 4180 compliant at line 2, byte 6 (record 2): bare CR not followed by LF");
+        assert_eq!(validate("a,b\n1,2\"\n").unwrap_err(),
+                   "Not RFC 4180 compliant at line 2, byte 7 (record 2): unescaped quote in an unquoted field");
+        assert_eq!(validate("a,b\n\"1\"x,2\n").unwrap_err(),
+                   "Not RFC 4180 compliant at line 2, byte 7 (record 2): characters after the closing quote");
+        assert_eq!(validate("a,b\n\"1,2\n").unwrap_err(),
+                   "Not RFC 4180 compliant at line 3, byte 9 (record 2): unterminated quoted field");
+    }
+
+    #[test]
+    fn test_verifying_reader() {
+        let digest = "a9a066cf8e33bc1cf2cbcb5ed1a5e5d2b0e4f0a8c1d3a1b7e6a69a7cb0fca6b1";
+        let mut out = vec![];
+        let error = VerifyingReader::new("a,b\n".as_bytes(), digest).read_to_end(&mut out).unwrap_err();
+        assert!(error.to_string().starts_with("SHA-256 mismatch"));
+
+        let digest = format!("{:X}", Sha256::digest(b"a,b\n"));
+        assert!(VerifyingReader::new("a,b\n".as_bytes(), &digest).read_to_end(&mut out).is_ok());
+
+        assert!(parse_sha256(&digest).is_ok());
+        assert!(parse_sha256("abc").is_err());
+    }
+
+    #[test]
+    fn test_follow_reader() {
+        let dir = crate::csvutil::TestDir::new();
+        let file = dir.path("test_follow_reader.csv");
+        std::fs::write(&file, "a,b\n").unwrap();
+        let mut reader = FollowReader::new(std::fs::File::open(&file).unwrap()).poll_interval(Duration::from_millis(5));
+
+        let appender_file = file.clone();
+        let appender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            let mut output = std::fs::OpenOptions::new().append(true).open(appender_file).unwrap();
+            io::Write::write_all(&mut output, b"1,2\n").unwrap();
+        });
+        let mut buf = [0; 8];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"a,b\n1,2\n");
+
+        appender.join().unwrap();
+    }
+
+    fn filter(input: &str, drop_empty: bool, columns: Option<Vec<usize>>) -> String {
+        filter_with(&CsvOptions::default(), input, drop_empty, columns)
+    }
+
+    fn filter_with(options: &CsvOptions, input: &str, drop_empty: bool, columns: Option<Vec<usize>>) -> String {
+        let resolver = columns.map(|c| Box::new(move |_| Ok(c)) as ColumnResolver);
+        let mut out = String::new();
+        RowFilter::new(input.as_bytes(), &options.config())
+            .drop_empty(drop_empty)
+            .drop_if_empty(resolver)
+            .read_to_string(&mut out)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn test_row_filter() {
+        let input = "id,note\n,\n1,\"a\nb\"\n\" \",\"\"\n\n2,\n,\"x, y\"";
+        assert_eq!(filter(input, true, None), "id,note\n1,\"a\nb\"\n2,\n,\"x, y\"");
+        assert_eq!(filter(input, false, Some(vec![1])), "id,note\n1,\"a\nb\"\n,\"x, y\"");
+        assert_eq!(filter(input, true, Some(vec![0, 1])), "id,note\n1,\"a\nb\"\n");
+
+        // A leading comment isn't the header row, and fields are read as -p and --trim say
+        let options = CsvOptions { comment_char: Some('#'), escape_char: Some('\\'), trim: csv::Trim::All, ..Default::default() };
+        let input = "#export\nid,email\n1,\" \"\n2,\"\\\"b\\\"\"\n# 3,\n";
+        assert_eq!(filter_with(&options, input, false, Some(vec![1])), "#export\nid,email\n2,\"\\\"b\\\"\"\n# 3,\n");
+        let options = CsvOptions { drop_if_empty: Some(vec!["email".to_string()]), ..options }.with_input(io::Cursor::new(input));
+        let mut out = String::new();
+        options.get_input_file().unwrap().read_to_string(&mut out).unwrap();
+        assert_eq!(out, "#export\nid,email\n2,\"\\\"b\\\"\"\n# 3,\n");
+    }
+
+    #[test]
+    fn test_field_parser() {
+        let mut record = ByteRecord::new();
+        let mut parser = FieldParser::new(&CsvOptions::default().config());
+        parser.parse(b"\"a,\"\"b\"\"\", c \r\n", false, &mut record);
+        assert_eq!(record, ByteRecord::from(vec!["a,\"b\"", " c "]));
+        parser.parse(b"x", false, &mut record);
+        assert_eq!(record, ByteRecord::from(vec!["x"]));
+
+        let mut parser = FieldParser::new(&CsvOptions { trim: csv::Trim::Headers, ..Default::default() }.config());
+        parser.parse(b" id , note\n", true, &mut record);
+        assert_eq!(record, ByteRecord::from(vec!["id", "note"]));
+        parser.parse(b" 1 , x\n", false, &mut record);
+        assert_eq!(record, ByteRecord::from(vec![" 1 ", " x"]));
+    }
+
+    fn check(input: &str, checks: Vec<ColumnCheck>) -> Result<String, String> {
+        check_with(&CsvOptions::default(), input, checks)
+    }
+
+    fn check_with(options: &CsvOptions, input: &str, checks: Vec<ColumnCheck>) -> Result<String, String> {
+        let mut out = String::new();
+        ConstraintChecker::new(input.as_bytes(), &options.config(), Box::new(move |_| Ok(checks)))
+            .read_to_string(&mut out)
+            .map(|_| out)
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_constraint_checker() {
+        let column_check = |column: usize, name: &str, constraint: Constraint| ColumnCheck { column, name: name.to_string(), constraint };
+        let input = "id,email,qty\n1,a@x.org,5\n2,\"b,\nc\",\n3,,1000\n";
+        let unique = column_check(0, "id", Constraint::Unique);
+        let range = column_check(2, "qty", Constraint::Range(Some(0.0), Some(1000.0)));
+        assert_eq!(check(input, vec![unique.clone(), range.clone()]), Ok(input.to_string()));
+        assert_eq!(check(input, vec![column_check(1, "email", Constraint::NotNull)]),
+                   Err("--assert-not-null failed at row 3, column email: the value is empty".to_string()));
+
+        let input = "id,qty\n1,5\n2,1200\n1,x\n";
+        assert_eq!(check(input, vec![unique.clone()]), Err("--assert-unique failed at row 3, column id: '1' repeats row 1".to_string()));
+        let range = ColumnCheck { column: 1, ..range };
+        assert_eq!(check(input, vec![range.clone()]), Err("--assert-range failed at row 2, column qty: 1200 is outside 0..1000".to_string()));
+        let range = ColumnCheck { constraint: Constraint::Range(None, None), ..range };
+        assert_eq!(check(input, vec![range]), Err("--assert-range failed at row 3, column qty: 'x' isn't a number".to_string()));
+
+        // Blank lines and comments aren't rows, and quotes are escaped as -p says
+        let options = CsvOptions { comment_char: Some('#'), escape_char: Some('\\'), ..Default::default() };
+        let input = "#export\nid,qty\n\n1,5\n# 1,5\n\"\\\"1\",6\n1,7\n";
+        assert_eq!(check_with(&options, input, vec![unique.clone()]), Err("--assert-unique failed at row 3, column id: '1' repeats row 1".to_string()));
+
+        // The checks see the rows --drop-if-empty drops, numbered as in the input
+        let options = CsvOptions {
+            drop_if_empty: Some(vec!["email".to_string()]),
+            assertions: vec![(vec!["email".to_string()], Constraint::NotNull)],
+            ..Default::default()
+        }.with_input(io::Cursor::new("id,email\n1,\n2,b@x.org\n"));
+        assert_eq!(options.get_input_file().unwrap().read_to_string(&mut String::new()).unwrap_err().to_string(),
+                   "--assert-not-null failed at row 1, column email: the value is empty");
+
+        assert_eq!(parse_range_assertion("a:b:-1.5..").unwrap(), ("a:b".to_string(), Constraint::Range(Some(-1.5), None)));
+        assert!(parse_range_assertion("qty:10..1").is_err());
+        assert!(parse_range_assertion("qty").is_err());
+        assert!(parse_range_assertion(":0..1").is_err());
+    }
+
+    #[test]
+    fn test_record_scanner() {
+        let input = "title\n\"note,\nspanning\"\"lines\"\na,b\n1,2";
+        let mut reader = input.as_bytes();
+        let mut scanner = RecordScanner::new(b'"');
+        let mut record = vec![];
+        assert_eq!(scanner.read_record(&mut reader, &mut record).unwrap(), 6);
+        assert_eq!(scanner.skip_records(&mut reader, 1).unwrap(), (1, 24));
+        record.clear();
+        scanner.read_record(&mut reader, &mut record).unwrap();
+        assert_eq!(record, b"a,b\n");
+        assert_eq!(scanner.skip_records(&mut reader, 5).unwrap(), (1, 3));
+        assert!(!scanner.in_quotes());
+
+        let mut scanner = RecordScanner::new(b'\'');
+        assert!(!scanner.line_ends_record(b"1,'x\n"));
+        assert!(scanner.in_quotes());
+        assert!(!scanner.line_ends_record(b"\"\n"));
+        assert!(scanner.line_ends_record(b"y'\n"));
+
+        let mut scanner = RecordScanner::new(b'"').escape(Some(b'\\'));
+        assert!(scanner.line_ends_record(b"1,\"a\\\"b\"\n"));
+        assert!(!scanner.line_ends_record(b"2,\"c\\\"\n"));
+        assert!(scanner.line_ends_record(b"d\"\n"));
+    }
+
+    #[test]
+    fn test_raw_records() {
+        let config = CsvOptions::default().config();
+        let input = "id,note\r\n1,\"a\r\nb\"\r\n\r\n2,\"\"\"x\"\"\"";
+        let mut records = RawRecords::new(input.as_bytes(), &config);
+        assert_eq!(records.headers().unwrap(), vec!["id", "note"]);
+        let records = records.collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(records.iter().map(|(raw, _)| String::from_utf8_lossy(raw)).collect::<Vec<_>>(), ["1,\"a\r\nb\"\r\n", "2,\"\"\"x\"\"\""]);
+        assert_eq!(records[1].1, vec!["2", "\"x\""]);
+
+        let config = CsvOptions { input_has_headers: Some(false), comment_char: Some('#'), ..Default::default() }.config();
+        let mut records = RawRecords::new("#note\na,b\nc,d\n".as_bytes(), &config);
+        assert_eq!(records.headers().unwrap(), vec!["a", "b"]);
+        assert_eq!(records.map(|r| r.unwrap().1).collect::<Vec<_>>(), [vec!["a", "b"], vec!["c", "d"]]);
+    }
+
+    #[test]
+    fn test_line_cleaner() {
+        let clean = |input: &str, normalize: bool, skip: bool| {
+            let mut out = String::new();
+            LineCleaner::new(input.as_bytes(), b'"').normalize_newlines(normalize).skip_blank_lines(skip)
+                .read_to_string(&mut out).unwrap();
+            out
+        };
+        let input = "a,b\r\n1,\"x\r\n \r\ny\"\r  \r\n2,3\r\n\t\n4,5";
+        assert_eq!(clean(input, false, false), input);
+        assert_eq!(clean(input, true, false), "a,b\n1,\"x\n \ny\"\n  \n2,3\n\t\n4,5");
+        assert_eq!(clean(input, true, true), "a,b\n1,\"x\n \ny\"\n2,3\n4,5");
+        assert_eq!(clean(input, false, true), "a,b\r\n1,\"x\r\n \r\ny\"\r  \r\n2,3\r\n4,5");
+        assert_eq!(clean("\n\n", false, true), "");
+    }
+
+    #[test]
+    fn test_size_limits() {
+        assert_eq!(limit("abc,de\n1,2\"x\r3,\"4\n", Some(3), Some(5)), Ok(()));
+        assert_eq!(limit("a,b\n1,\"2\n3,4\n5,6\n", Some(5), None).unwrap_err(),
+                   "Record 2 (line 3) has a field larger than 5 bytes, at field 2. An unterminated quote can cause this");
+        assert_eq!(limit("a,b\n12,34\n123,45\n", None, Some(4)).unwrap_err(),
+                   "Record 3 (line 3) has a record larger than 4 bytes, at field 2. An unterminated quote can cause this");
+    }
+}
@@ -0,0 +1,436 @@
+//! Frictionless Table Schema, see https://specs.frictionlessdata.io/table-schema/
+
+use regex::Regex;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldType {
+    String,
+    Integer,
+    Number,
+    /// With the spellings accepted for true and false
+    Boolean { true_values: Vec<String>, false_values: Vec<String> },
+    Date,
+    DateTime,
+    Time,
+    Year,
+    YearMonth,
+    Duration,
+    GeoPoint,
+    Object,
+    Array,
+    Any,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Constraints {
+    pub required: bool,
+    pub unique: bool,
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    pub minimum: Option<Value>,
+    pub maximum: Option<Value>,
+    /// Anchored, since the pattern has to match the whole value
+    pub pattern: Option<Regex>,
+    pub allowed: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Field {
+    pub name: String,
+    pub field_type: FieldType,
+    /// `default`, `any` or a strftime-style pattern such as `%d/%m/%Y`
+    pub format: String,
+    pub constraints: Constraints,
+}
+
+/// Where a foreign key's values have to appear.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ForeignKey {
+    pub fields: Vec<String>,
+    /// A CSV file, resolved against the schema's directory, or None for the file being read
+    pub resource: Option<PathBuf>,
+    pub reference_fields: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TableSchema {
+    pub fields: Vec<Field>,
+    pub primary_key: Vec<String>,
+    pub foreign_keys: Vec<ForeignKey>,
+    pub missing_values: Vec<String>,
+}
+
+/// A field name or a list of them, as primaryKey and foreignKeys allow either.
+fn names(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(s) => vec![s.clone()],
+        Value::Array(a) => a.iter().filter_map(|v| v.as_str().map(str::to_string)).collect(),
+        _ => vec![],
+    }
+}
+
+/// A JSON value as it would be written in a CSV field.
+fn as_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+impl TableSchema {
+    pub fn from_file(path: &str) -> Result<TableSchema, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+        let json: Value = serde_json::from_str(&text).map_err(|e| format!("Invalid schema {}: {}", path, e))?;
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        TableSchema::from_json(&json, dir).map_err(|e| format!("Invalid schema {}: {}", path, e))
+    }
+
+    /// Reads a schema, resolving foreign key resources against `dir`.
+    pub fn from_json(json: &Value, dir: &Path) -> Result<TableSchema, String> {
+        let fields = json["fields"].as_array().ok_or("no list of fields")?
+            .iter().map(Field::from_json).collect::<Result<Vec<_>, _>>()?;
+
+        let primary_key = names(&json["primaryKey"]);
+        let foreign_keys = json["foreignKeys"].as_array().map(|a| a.as_slice()).unwrap_or_default().iter()
+            .map(|fk| {
+                let resource = fk["reference"]["resource"].as_str().unwrap_or_default();
+                ForeignKey {
+                    fields: names(&fk["fields"]),
+                    resource: (!resource.is_empty()).then(|| dir.join(resource)),
+                    reference_fields: names(&fk["reference"]["fields"]),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let own_references = foreign_keys.iter().filter(|fk| fk.resource.is_none()).flat_map(|fk| &fk.reference_fields);
+        for name in primary_key.iter().chain(foreign_keys.iter().flat_map(|fk| &fk.fields)).chain(own_references) {
+            if !fields.iter().any(|f| f.name == *name) {
+                return Err(format!("key field '{}' is not one of the fields", name));
+            }
+        }
+        if let Some(fk) = foreign_keys.iter().find(|fk| fk.fields.len() != fk.reference_fields.len() || fk.fields.is_empty()) {
+            return Err(format!("foreign key {} doesn't name as many reference fields", fk.fields.join(",")));
+        }
+
+        let missing_values = match json.get("missingValues") {
+            Some(values) => values.as_array().ok_or("missingValues is not a list")?.iter().map(as_text).collect(),
+            None => vec![String::new()],
+        };
+
+        Ok(TableSchema { fields, primary_key, foreign_keys, missing_values })
+    }
+
+    pub fn is_missing(&self, value: &str) -> bool {
+        self.missing_values.iter().any(|m| m == value)
+    }
+}
+
+impl Field {
+    fn from_json(json: &Value) -> Result<Field, String> {
+        let name = json["name"].as_str().ok_or("a field has no name")?.to_string();
+        let spellings = |key: &str, default: &[&str]| json[key].as_array()
+            .map(|v| v.iter().map(as_text).collect::<Vec<_>>())
+            .unwrap_or_else(|| default.iter().map(|s| s.to_string()).collect());
+        let field_type = match json["type"].as_str().unwrap_or("string") {
+            "string" => FieldType::String,
+            "integer" => FieldType::Integer,
+            "number" => FieldType::Number,
+            "boolean" => FieldType::Boolean {
+                true_values: spellings("trueValues", &["true", "True", "TRUE", "1"]),
+                false_values: spellings("falseValues", &["false", "False", "FALSE", "0"]),
+            },
+            "date" => FieldType::Date,
+            "datetime" => FieldType::DateTime,
+            "time" => FieldType::Time,
+            "year" => FieldType::Year,
+            "yearmonth" => FieldType::YearMonth,
+            "duration" => FieldType::Duration,
+            "geopoint" => FieldType::GeoPoint,
+            "object" | "geojson" => FieldType::Object,
+            "array" => FieldType::Array,
+            "any" => FieldType::Any,
+            other => return Err(format!("field '{}' has unknown type '{}'", name, other)),
+        };
+        let format = json["format"].as_str().unwrap_or("default").trim_start_matches("fmt:").to_string();
+
+        let c = &json["constraints"];
+        let length = |key: &str| c[key].as_u64().map(|n| n as usize);
+        let pattern = match c["pattern"].as_str() {
+            Some(p) => Some(Regex::new(&format!("^(?:{})$", p))
+                .map_err(|e| format!("field '{}' has an invalid pattern: {}", name, e))?),
+            None => None,
+        };
+        let constraints = Constraints {
+            required: c["required"].as_bool().unwrap_or(false),
+            unique: c["unique"].as_bool().unwrap_or(false),
+            min_length: length("minLength"),
+            max_length: length("maxLength"),
+            minimum: c.get("minimum").cloned(),
+            maximum: c.get("maximum").cloned(),
+            pattern,
+            allowed: c["enum"].as_array().map(|v| v.iter().map(as_text).collect()),
+        };
+
+        Ok(Field { name, field_type, format, constraints })
+    }
+
+    /// Checks a value that isn't missing against the field's type and constraints, describing
+    /// the first problem found.
+    pub fn check(&self, value: &str) -> Result<(), String> {
+        if !self.is_valid(value) {
+            let format = if self.format == "default" { String::new() } else { format!(" in format {}", self.format) };
+            return Err(format!("is not a valid {}{}", self.type_name(), format));
+        }
+
+        let c = &self.constraints;
+        let length = match self.field_type {
+            FieldType::Array | FieldType::Object => serde_json::from_str::<Value>(value).map(|v| match v {
+                Value::Array(a) => a.len(),
+                Value::Object(o) => o.len(),
+                _ => 0,
+            }).unwrap_or_default(),
+            _ => value.chars().count(),
+        };
+        if let Some(min) = c.min_length.filter(|&min| length < min) {
+            return Err(format!("is shorter than the minimum length {}", min));
+        }
+        if let Some(max) = c.max_length.filter(|&max| length > max) {
+            return Err(format!("is longer than the maximum length {}", max));
+        }
+        if let Some(min) = c.minimum.as_ref().filter(|min| self.compare(value, min) == Some(std::cmp::Ordering::Less)) {
+            return Err(format!("is less than the minimum {}", as_text(min)));
+        }
+        if let Some(max) = c.maximum.as_ref().filter(|max| self.compare(value, max) == Some(std::cmp::Ordering::Greater)) {
+            return Err(format!("is greater than the maximum {}", as_text(max)));
+        }
+        if c.pattern.as_ref().is_some_and(|p| !p.is_match(value)) {
+            return Err("does not match the pattern".to_string());
+        }
+        if let Some(allowed) = c.allowed.as_ref().filter(|a| !a.iter().any(|v| self.equal(value, v))) {
+            return Err(format!("is not one of {}", allowed.join(", ")));
+        }
+        Ok(())
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self.field_type {
+            FieldType::String => "string",
+            FieldType::Integer => "integer",
+            FieldType::Number => "number",
+            FieldType::Boolean { .. } => "boolean",
+            FieldType::Date => "date",
+            FieldType::DateTime => "datetime",
+            FieldType::Time => "time",
+            FieldType::Year => "year",
+            FieldType::YearMonth => "yearmonth",
+            FieldType::Duration => "duration",
+            FieldType::GeoPoint => "geopoint",
+            FieldType::Object => "object",
+            FieldType::Array => "array",
+            FieldType::Any => "value",
+        }
+    }
+
+    fn is_valid(&self, value: &str) -> bool {
+        match &self.field_type {
+            FieldType::String => match self.format.as_str() {
+                "email" => value.split_once('@').is_some_and(|(user, host)| !user.is_empty() && host.contains('.')),
+                "uri" => value.split_once(':').is_some_and(|(scheme, rest)| !rest.is_empty() && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))),
+                "uuid" => value.len() == 36 && value.char_indices()
+                    .all(|(i, c)| if [8, 13, 18, 23].contains(&i) { c == '-' } else { c.is_ascii_hexdigit() }),
+                _ => true,
+            },
+            FieldType::Integer => value.parse::<i64>().is_ok(),
+            FieldType::Number => value.parse::<f64>().is_ok() || ["NaN", "INF", "-INF"].contains(&value),
+            FieldType::Boolean { true_values, false_values } => true_values.iter().chain(false_values).any(|v| v == value),
+            FieldType::Date => match self.format.as_str() {
+                "default" | "any" => parse_with(value, "%Y-%m-%d"),
+                format => parse_with(value, format),
+            },
+            FieldType::DateTime => match self.format.as_str() {
+                "default" | "any" => parse_with(value.trim_end_matches('Z'), "%Y-%m-%dT%H:%M:%S"),
+                format => parse_with(value, format),
+            },
+            FieldType::Time => match self.format.as_str() {
+                "default" | "any" => parse_with(value, "%H:%M:%S"),
+                format => parse_with(value, format),
+            },
+            FieldType::Year => value.len() == 4 && value.parse::<u16>().is_ok(),
+            FieldType::YearMonth => parse_with(&format!("{}-01", value), "%Y-%m-%d"),
+            FieldType::Duration => {
+                static DURATION: OnceLock<Regex> = OnceLock::new();
+                DURATION.get_or_init(|| Regex::new(r"^P(\d+Y)?(\d+M)?(\d+W)?(\d+D)?(T(\d+H)?(\d+M)?(\d+(\.\d+)?S)?)?$").unwrap())
+                    .is_match(value) && value != "P" && !value.ends_with('T')
+            }
+            FieldType::GeoPoint => {
+                let parts = value.split(',').map(|p| p.trim().parse::<f64>()).collect::<Vec<_>>();
+                match parts.as_slice() {
+                    [Ok(lon), Ok(lat)] => lon.abs() <= 180.0 && lat.abs() <= 90.0,
+                    _ => false,
+                }
+            }
+            FieldType::Object => matches!(serde_json::from_str(value), Ok(Value::Object(_))),
+            FieldType::Array => matches!(serde_json::from_str(value), Ok(Value::Array(_))),
+            FieldType::Any => true,
+        }
+    }
+
+    /// Orders a valid value against a minimum or maximum: numerically for numbers, and as text
+    /// otherwise, which suits ISO dates and times.
+    fn compare(&self, value: &str, bound: &Value) -> Option<std::cmp::Ordering> {
+        match self.field_type {
+            FieldType::Integer | FieldType::Number | FieldType::Year => {
+                let bound = bound.as_f64().or_else(|| as_text(bound).parse().ok())?;
+                value.parse::<f64>().ok()?.partial_cmp(&bound)
+            }
+            _ => Some(value.cmp(&as_text(bound))),
+        }
+    }
+
+    fn equal(&self, value: &str, allowed: &str) -> bool {
+        match self.field_type {
+            FieldType::Integer | FieldType::Number => {
+                matches!((value.parse::<f64>(), allowed.parse::<f64>()), (Ok(a), Ok(b)) if a == b)
+            }
+            _ => value == allowed,
+        }
+    }
+}
+
+/// Whether a value fits a strftime-style pattern of `%Y`, `%m`, `%d`, `%H`, `%M` and `%S`
+/// and literal characters, and names a real date and time. Seconds may have a fraction, and
+/// a time may end in a UTC offset.
+pub fn parse_with(value: &str, format: &str) -> bool {
+    let mut rest = value;
+    let (mut year, mut month, mut day) = (2000, 1, 1);
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            match rest.strip_prefix(c) {
+                Some(r) => rest = r,
+                None => return false,
+            }
+            continue;
+        }
+        let spec = chars.next();
+        let (width, max) = match spec {
+            Some('Y') => (4, 9999),
+            Some('m') => (2, 12),
+            Some('d') => (2, 31),
+            Some('H') => (2, 23),
+            Some('M') => (2, 59),
+            Some('S') => (2, 60),
+            _ => return false,
+        };
+        if rest.chars().take_while(|c| c.is_ascii_digit()).count() != width {
+            return false;
+        }
+        let n = rest[..width].parse::<u32>().unwrap_or(u32::MAX);
+        match spec {
+            _ if n > max => return false,
+            Some('m' | 'd') if n == 0 => return false,
+            Some('Y') => year = n,
+            Some('m') => month = n,
+            Some('d') => day = n,
+            _ => {}
+        }
+        rest = &rest[width..];
+        if spec == Some('S') && chars.as_str().is_empty() {
+            if let Some(fraction) = rest.strip_prefix('.') {
+                rest = fraction.trim_start_matches(|c: char| c.is_ascii_digit());
+            }
+            if let Some(offset) = rest.strip_prefix(['+', '-']) {
+                rest = if parse_with(offset, "%H:%M") { "" } else { rest };
+            }
+        }
+    }
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let days_in_month = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    rest.is_empty() && day <= days_in_month
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn field(json: Value) -> Field {
+        Field::from_json(&json).expect("Invalid field")
+    }
+
+    #[test]
+    fn test_check_types() {
+        let integer = field(json!({"name": "n", "type": "integer"}));
+        assert!(integer.check("42").is_ok());
+        assert_eq!(integer.check("4.2"), Err("is not a valid integer".to_string()));
+
+        let boolean = field(json!({"name": "b", "type": "boolean", "trueValues": ["y"], "falseValues": ["n"]}));
+        assert!(boolean.check("y").is_ok());
+        assert!(boolean.check("true").is_err());
+
+        let date = field(json!({"name": "d", "type": "date"}));
+        assert!(date.check("2024-02-29").is_ok());
+        assert!(date.check("2023-02-29").is_err());
+        let date = field(json!({"name": "d", "type": "date", "format": "%d/%m/%Y"}));
+        assert!(date.check("31/12/2024").is_ok());
+        assert_eq!(date.check("2024-12-31"), Err("is not a valid date in format %d/%m/%Y".to_string()));
+
+        let datetime = field(json!({"name": "t", "type": "datetime"}));
+        assert!(datetime.check("2024-03-01T10:00:00Z").is_ok());
+        assert!(datetime.check("2024-03-01T10:00:00.25+01:00").is_ok());
+        assert!(datetime.check("2024-03-01 10:00").is_err());
+
+        let object = field(json!({"name": "o", "type": "object"}));
+        assert!(object.check("{\"a\": 1}").is_ok());
+        assert!(object.check("[1]").is_err());
+
+        assert!(field(json!({"name": "p", "type": "geopoint"})).check("12.5, 41.9").is_ok());
+        assert!(field(json!({"name": "p", "type": "duration"})).check("P1DT2H").is_ok());
+        assert!(Field::from_json(&json!({"name": "x", "type": "decimal"})).is_err());
+    }
+
+    #[test]
+    fn test_check_constraints() {
+        let qty = field(json!({"name": "qty", "type": "integer", "constraints": {"minimum": 0, "maximum": 10}}));
+        assert!(qty.check("10").is_ok());
+        assert_eq!(qty.check("11"), Err("is greater than the maximum 10".to_string()));
+        assert_eq!(qty.check("-1"), Err("is less than the minimum 0".to_string()));
+
+        let code = field(json!({"name": "code", "constraints": {"pattern": "[A-Z]{2}", "maxLength": 2}}));
+        assert!(code.check("GB").is_ok());
+        assert_eq!(code.check("GBR"), Err("is longer than the maximum length 2".to_string()));
+        assert_eq!(code.check("gb"), Err("does not match the pattern".to_string()));
+
+        let size = field(json!({"name": "size", "type": "number", "constraints": {"enum": [1, 2.5]}}));
+        assert!(size.check("2.50").is_ok());
+        assert_eq!(size.check("3"), Err("is not one of 1, 2.5".to_string()));
+
+        let day = field(json!({"name": "day", "type": "date", "constraints": {"minimum": "2024-01-01"}}));
+        assert!(day.check("2024-01-01").is_ok());
+        assert!(day.check("2023-12-31").is_err());
+    }
+
+    #[test]
+    fn test_from_json() {
+        let schema = TableSchema::from_json(&json!({
+            "fields": [{"name": "id", "type": "integer"}, {"name": "parent"}],
+            "primaryKey": "id",
+            "foreignKeys": [{"fields": "parent", "reference": {"resource": "", "fields": "id"}}],
+            "missingValues": ["", "NA"],
+        }), Path::new("schemas")).expect("Invalid schema");
+        assert_eq!(schema.primary_key, vec!["id"]);
+        assert_eq!(schema.foreign_keys[0].resource, None);
+        assert!(schema.is_missing("NA"));
+
+        let error = TableSchema::from_json(&json!({"fields": [{"name": "id"}], "primaryKey": ["code"]}), Path::new(""));
+        assert_eq!(error.err(), Some("key field 'code' is not one of the fields".to_string()));
+    }
+}
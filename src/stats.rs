@@ -0,0 +1,550 @@
+use crate::csvutil;
+use crate::log;
+use crate::numbers;
+use multiset::HashMultiSet;
+use priority_queue::DoublePriorityQueue;
+use std::cmp::Reverse;
+
+/// 2^53: above this not every integer is an f64, so a float sum may be off by units.
+const MAX_EXACT_FLOAT: f64 = 9_007_199_254_740_992.0;
+
+/// Spellings of true and false that make a column boolean, compared case-insensitively.
+#[derive(Clone)]
+pub struct BooleanValues {
+    pub true_values: Vec<String>,
+    pub false_values: Vec<String>,
+}
+
+impl BooleanValues {
+    /// The given spellings, or true/yes/t/1 and false/no/f/0 for those not given.
+    pub fn new(true_values: Option<Vec<String>>, false_values: Option<Vec<String>>) -> Self {
+        let defaults = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+        BooleanValues {
+            true_values: true_values.unwrap_or_else(|| defaults(&["true", "yes", "t", "1"])),
+            false_values: false_values.unwrap_or_else(|| defaults(&["false", "no", "f", "0"])),
+        }
+    }
+
+    pub fn parse(&self, value: &str) -> Option<bool> {
+        if self.true_values.iter().any(|v| v.eq_ignore_ascii_case(value)) {
+            Some(true)
+        } else if self.false_values.iter().any(|v| v.eq_ignore_ascii_case(value)) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for BooleanValues {
+    fn default() -> Self {
+        BooleanValues::new(None, None)
+    }
+}
+
+pub struct CsvColumnStat {
+    pub idx: usize,
+    pub name: String,
+    pub n: u64,
+    pub n_numeric: u64,
+    pub sum: f64,
+    pub mean: f64,
+    pub variance: f64,
+    pub min: f64,
+    pub max: f64,
+    pub min_str: String,
+    pub max_str: String,
+    pub n_missing: u64,
+    pub n_empty: u64,
+    pub distinct: HashMultiSet<String>,
+    /// Estimated memory held by `distinct`, see `csvutil::check_memory`
+    pub distinct_bytes: u64,
+    pub max_len: usize,
+    /// Whether empty strings count towards `min_str` and `max_str`, where they would always be the minimum
+    pub include_empty: bool,
+    /// Only recognize numbers `str::parse` accepts, see `numbers::parse_number`
+    pub strict_numeric: bool,
+    pub boolean_values: BooleanValues,
+    pub n_true: u64,
+    pub n_false: u64,
+    /// Numeric values written with a leading zero, see `numbers::has_leading_zero`
+    pub n_leading_zeros: u64,
+    /// Numeric values written as integers, and their range, kept exactly for choosing integer column types
+    pub n_integer: u64,
+    pub min_integer: i128,
+    pub max_integer: i128,
+    /// The exact sum of the integers, or None once it overflows
+    pub integer_sum: Option<i128>,
+}
+
+impl CsvColumnStat {
+    pub fn new(idx: usize, name: String) -> Self {
+        CsvColumnStat {
+            idx,
+            name,
+            n: 0,
+            n_numeric: 0,
+            sum: 0.0,
+            mean: 0.0,
+            variance: 0.0,
+            min: 0.0,
+            max: 0.0,
+            min_str: "".to_string(),
+            max_str: "".to_string(),
+            max_len: 0,
+            n_missing: 0,
+            n_empty: 0,
+            distinct: HashMultiSet::new(),
+            distinct_bytes: 0,
+            include_empty: false,
+            strict_numeric: false,
+            boolean_values: BooleanValues::default(),
+            n_true: 0,
+            n_false: 0,
+            n_leading_zeros: 0,
+            n_integer: 0,
+            min_integer: 0,
+            max_integer: 0,
+            integer_sum: Some(0),
+        }
+    }
+
+    pub fn include_empty(mut self, include_empty: bool) -> Self {
+        self.include_empty = include_empty;
+        self
+    }
+
+    pub fn strict_numeric(mut self, strict_numeric: bool) -> Self {
+        self.strict_numeric = strict_numeric;
+        self
+    }
+
+    pub fn boolean_values(mut self, boolean_values: BooleanValues) -> Self {
+        self.boolean_values = boolean_values;
+        self
+    }
+
+    /// How many of the values added are ranked for `min_str` and `max_str`.
+    fn n_ranked(&self) -> u64 {
+        self.n - self.n_missing - if self.include_empty { 0 } else { self.n_empty }
+    }
+
+    /// The most frequent values, most frequent first and equally frequent ones in order, at most
+    /// `limit` of them.
+    pub fn top_values(&self, limit: usize) -> Vec<(&String, usize)> {
+        let mut p: DoublePriorityQueue<&String, (usize, Reverse<&String>)> = DoublePriorityQueue::new();
+        self.distinct.distinct_elements().for_each(|d| {
+            p.push(d, (self.distinct.count_of(d), Reverse(d)));
+            while p.len() > limit {
+                p.pop_min();
+            }
+        });
+
+        let mut v = vec![];
+        while let Some((d, (c, _))) = p.pop_max() {
+            v.push((d, c));
+        }
+        v
+    }
+
+    pub fn freq(&self) -> Vec<String> {
+        self.top_values(100).into_iter()
+            .map(|(d, c)| d.clone() + " (" + &c.to_string() + "X)")
+            .collect()
+    }
+
+    pub fn stdev(&self) -> f64 {
+        if self.n_numeric < 2 {
+            return 0.0;
+        }
+        (self.variance / (self.n_numeric as f64 - 1.0)).sqrt()
+    }
+
+    pub fn median(&self) -> f64 {
+        if self.n_numeric < 2 {
+            return 0.0;
+        }
+        0.0
+    }
+
+    pub fn mean(&self) -> f64 {
+        match self.exact_sum() {
+            Some(sum) => sum as f64 / self.n_numeric as f64,
+            None => self.mean,
+        }
+    }
+
+    /// The sum of an integer column, kept exactly unless it overflowed an i128.
+    pub fn exact_sum(&self) -> Option<i128> {
+        self.integer_sum.filter(|_| self.is_integer())
+    }
+
+    /// The sum, exact for integer columns.
+    pub fn sum(&self) -> String {
+        match self.exact_sum() {
+            Some(sum) => sum.to_string(),
+            None => self.sum.to_string(),
+        }
+    }
+
+    /// Whether the reported sum may be off: an integer sum that overflowed, or a float sum too
+    /// large for f64 to hold every unit.
+    pub fn sum_is_imprecise(&self) -> bool {
+        self.is_numeric() && self.exact_sum().is_none() && (self.is_integer() || self.sum.abs() > MAX_EXACT_FLOAT)
+    }
+
+    pub fn unique(&self) -> usize {
+        self.distinct.distinct_elements().count()
+    }
+
+    pub fn nulls(&self) -> bool {
+        self.n_missing > 0
+    }
+
+    /// Whether any value was missing or empty.
+    pub fn nullable(&self) -> bool {
+        self.n_missing + self.n_empty > 0
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn infer_type(&self) -> String {
+        if self.is_boolean() {
+            "Boolean".to_string()
+        } else if self.is_integer() {
+            "Integer".to_string()
+        } else if self.is_numeric() {
+            "Number".to_string()
+        } else {
+            "Text".to_string()
+        }
+    }
+
+    /// Numbers with leading zeros are identifiers such as ZIP codes, which a numeric column would corrupt.
+    pub fn is_numeric(&self) -> bool {
+        (self.n_numeric == self.n - self.n_missing - self.n_empty) && (self.n_numeric > 0) && (self.n_leading_zeros == 0)
+    }
+
+    /// Whether the column is numeric with every value written as an integer.
+    pub fn is_integer(&self) -> bool {
+        self.is_numeric() && self.n_integer == self.n_numeric
+    }
+
+    /// The largest absolute value among the integers.
+    pub fn max_integer_magnitude(&self) -> u128 {
+        self.min_integer.unsigned_abs().max(self.max_integer.unsigned_abs())
+    }
+
+    /// Whether the column would be numeric but for leading zeros.
+    pub fn has_leading_zeros(&self) -> bool {
+        (self.n_numeric == self.n - self.n_missing - self.n_empty) && (self.n_leading_zeros > 0)
+    }
+
+    /// Whether every non-empty value is one of the `boolean_values`.
+    pub fn is_boolean(&self) -> bool {
+        (self.n_true + self.n_false == self.n - self.n_missing - self.n_empty) && (self.n_true + self.n_false > 0)
+    }
+
+    /// The distinct values seen that mean true, and those that mean false, each sorted.
+    pub fn boolean_spellings(&self) -> (Vec<String>, Vec<String>) {
+        let (mut true_values, mut false_values) = (vec![], vec![]);
+        for value in self.distinct.distinct_elements() {
+            match self.boolean_values.parse(value) {
+                Some(true) => true_values.push(value.clone()),
+                Some(false) => false_values.push(value.clone()),
+                None => {}
+            }
+        }
+        true_values.sort();
+        false_values.sort();
+        (true_values, false_values)
+    }
+
+    pub fn max(&self) -> String {
+        if self.is_integer() {
+            self.max_integer.to_string()
+        } else if self.is_numeric() {
+            self.max.to_string()
+        } else {
+            self.max_str.clone()
+        }
+    }
+
+    pub fn min(&self) -> String {
+        if self.is_integer() {
+            self.min_integer.to_string()
+        } else if self.is_numeric() {
+            self.min.to_string()
+        } else {
+            self.min_str.clone()
+        }
+    }
+
+    /// Folds in the statistics of another chunk of the same column, as if its values had been added here.
+    pub fn merge(&mut self, other: CsvColumnStat) {
+        let has_strings = self.n_ranked() > 0;
+        if other.n_ranked() > 0 {
+            if !has_strings || other.max_str > self.max_str {
+                self.max_str.clone_from(&other.max_str);
+            }
+            if !has_strings || other.min_str < self.min_str {
+                self.min_str.clone_from(&other.min_str);
+            }
+        }
+        if other.n_integer > 0 {
+            if self.n_integer == 0 || other.max_integer > self.max_integer {
+                self.max_integer = other.max_integer;
+            }
+            if self.n_integer == 0 || other.min_integer < self.min_integer {
+                self.min_integer = other.min_integer;
+            }
+        }
+        if other.n_numeric > 0 {
+            if self.n_numeric == 0 || other.max > self.max {
+                self.max = other.max;
+            }
+            if self.n_numeric == 0 || other.min < self.min {
+                self.min = other.min;
+            }
+            // Chan et al.'s pairwise combination of the Welford mean and sum of squared differences
+            let (n_a, n_b) = (self.n_numeric as f64, other.n_numeric as f64);
+            let delta = other.mean - self.mean;
+            self.mean += delta * n_b / (n_a + n_b);
+            self.variance += other.variance + delta * delta * n_a * n_b / (n_a + n_b);
+        }
+        self.n += other.n;
+        self.n_numeric += other.n_numeric;
+        self.sum += other.sum;
+        self.integer_sum = self.integer_sum.zip(other.integer_sum).and_then(|(a, b)| a.checked_add(b));
+        self.n_missing += other.n_missing;
+        self.n_empty += other.n_empty;
+        self.n_true += other.n_true;
+        self.n_false += other.n_false;
+        self.n_leading_zeros += other.n_leading_zeros;
+        self.n_integer += other.n_integer;
+        self.max_len = self.max_len.max(other.max_len);
+        self.distinct = std::mem::replace(&mut self.distinct, HashMultiSet::new()) + other.distinct;
+        self.distinct_bytes = self.distinct.distinct_elements().map(|s| s.len() as u64 + csvutil::STRING_OVERHEAD).sum();
+    }
+
+    pub fn add(&mut self, value: Option<&str>) {
+        self.n += 1;
+
+        if value.is_none() {
+            self.n_missing += 1;
+            return;
+        }
+        let string = value.unwrap().to_string();
+
+        if string.is_empty() {
+            self.n_empty += 1;
+        }
+        if self.include_empty || !string.is_empty() {
+            if self.n_ranked() == 1 || string > self.max_str {
+                self.max_str = string.clone();
+            }
+            if self.n_ranked() == 1 || string < self.min_str {
+                self.min_str = string.clone();
+            }
+        }
+        self.max_len = self.max_len.max(string.chars().count());
+        match self.boolean_values.parse(&string) {
+            Some(true) => self.n_true += 1,
+            Some(false) => self.n_false += 1,
+            None => {}
+        }
+        if let Some(float) = numbers::parse_number(&string, self.strict_numeric) {
+            self.n_numeric += 1;
+            if numbers::has_leading_zero(&string) {
+                self.n_leading_zeros += 1;
+            }
+            if let Some(integer) = numbers::parse_integer(&string, self.strict_numeric) {
+                self.n_integer += 1;
+                if self.n_integer == 1 || integer > self.max_integer {
+                    self.max_integer = integer;
+                }
+                if self.n_integer == 1 || integer < self.min_integer {
+                    self.min_integer = integer;
+                }
+                self.integer_sum = self.integer_sum.and_then(|sum| sum.checked_add(integer));
+            }
+            self.sum += float;
+            let prev_mean = self.mean;
+            // This method for computing the stream mean and variance is apparently from Knuth
+            // and I found it at https://math.stackexchange.com/questions/20593/calculate-variance-from-a-stream-of-sample-values
+            // n.b. if this is the first numeric value, then m_1 will be x_1 here as long as n_numeric has been previously incremented.
+            self.mean += (float - self.mean) / self.n_numeric as f64;
+            self.variance += (float - self.mean) * (float - prev_mean);
+            if self.n_numeric == 1 || float > self.max {
+                self.max = float;
+            }
+            if self.n_numeric == 1 || float < self.min {
+                self.min = float;
+            }
+        }
+        let (n_distinct, len) = (self.distinct.distinct_elements().len(), string.len() as u64);
+        self.distinct.insert(string);
+        if self.distinct.distinct_elements().len() > n_distinct {
+            self.distinct_bytes += len + csvutil::STRING_OVERHEAD;
+        }
+    }
+}
+
+/// Warns about sums that can't be trusted to the unit, which matters when reconciling totals.
+pub fn warn_sum_precision(statistics: &[CsvColumnStat]) {
+    for statistic in statistics.iter().filter(|s| s.sum_is_imprecise()) {
+        log::warning(&format!("the sum of column '{}' is too large to be exact and was computed in floating point", statistic.name()));
+    }
+}
+
+/// Tells the user about columns read as text only because of leading zeros, which can be surprising.
+pub fn warn_leading_zeros(statistics: &[CsvColumnStat]) {
+    for statistic in statistics.iter().filter(|s| s.has_leading_zeros()) {
+        log::warning(&format!("column '{}' looks numeric but has leading zeros, so it is treated as text", statistic.name()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stat_with(values: &[Option<&str>], include_empty: bool) -> CsvColumnStat {
+        let mut stat = CsvColumnStat::new(0, "col".to_string()).include_empty(include_empty);
+        values.iter().for_each(|v| stat.add(*v));
+        stat
+    }
+
+    fn stat(values: &[Option<&str>]) -> CsvColumnStat {
+        stat_with(values, false)
+    }
+
+    #[test]
+    fn test_top_values() {
+        let s = stat(&[Some("b"), Some("c"), Some("a"), Some("c"), Some("d")]);
+        assert_eq!(s.freq(), vec!["c (2X)", "a (1X)", "b (1X)", "d (1X)"]);
+        assert_eq!(s.top_values(2), vec![(&"c".to_string(), 2), (&"a".to_string(), 1)]);
+    }
+
+    #[test]
+    fn test_mean_stdev_unique_len() {
+        let s = stat(&[Some("2"), Some("4"), Some("4"), Some("4"), Some("5"), Some("5"), Some("7"), Some("9")]);
+        assert_eq!(s.mean(), 5.0);
+        assert!((s.stdev() - (32.0f64 / 7.0).sqrt()).abs() < 1e-9);
+        assert_eq!(s.unique(), 5);
+        assert_eq!(stat(&[Some("1")]).stdev(), 0.0);
+
+        let names = stat(&[Some("ab"), Some("ünïcode"), Some("")]);
+        assert_eq!((names.max_len, names.unique()), (7, 3));
+    }
+
+    #[test]
+    fn test_empty_values_stay_numeric() {
+        let s = stat(&[Some("1.5"), Some(""), None, Some("2.5")]);
+        assert!(s.is_numeric());
+        assert_eq!((s.infer_type().as_str(), s.mean()), ("Number", 2.0));
+    }
+
+    #[test]
+    fn test_min_max_str() {
+        let values = [Some("b"), Some(""), None, Some("a"), Some("c")];
+        let without_empty = stat(&values);
+        assert_eq!((without_empty.min(), without_empty.max()), ("a".to_string(), "c".to_string()));
+        let with_empty = stat_with(&values, true);
+        assert_eq!((with_empty.min(), with_empty.max()), ("".to_string(), "c".to_string()));
+        let only_empty = stat(&[Some(""), None]);
+        assert_eq!((only_empty.min(), only_empty.max()), ("".to_string(), "".to_string()));
+    }
+
+    #[test]
+    fn test_numeric() {
+        let values = [Some("1,234"), Some(" 42 "), Some("+7")];
+        let lenient = stat(&values);
+        assert!(lenient.is_numeric());
+        assert_eq!((lenient.min(), lenient.max(), lenient.sum), ("7".to_string(), "1234".to_string(), 1283.0));
+
+        let mut strict = CsvColumnStat::new(0, "col".to_string()).strict_numeric(true);
+        values.iter().for_each(|v| strict.add(*v));
+        assert!(!strict.is_numeric());
+    }
+
+    #[test]
+    fn test_integer() {
+        let ids = stat(&[Some("12"), Some("-3,000"), None, Some("")]);
+        assert!(ids.is_integer());
+        assert_eq!((ids.infer_type().as_str(), ids.min_integer, ids.max_integer, ids.max_integer_magnitude()), ("Integer", -3000, 12, 3000));
+
+        let prices = stat(&[Some("12"), Some("3.50")]);
+        assert!(prices.is_numeric() && !prices.is_integer());
+        assert_eq!(prices.infer_type(), "Number");
+        assert!(!stat(&[Some("12"), Some("x")]).is_integer());
+    }
+
+    #[test]
+    fn test_exact_sum() {
+        let ids = stat(&[Some("9007199254740993"), Some("1"), Some("-2")]);
+        assert_eq!((ids.sum(), ids.min(), ids.max()), ("9007199254740992".to_string(), "-2".to_string(), "9007199254740993".to_string()));
+        assert!(!ids.sum_is_imprecise());
+
+        let huge = i128::MAX.to_string();
+        let overflowed = stat(&[Some(&huge), Some(&huge)]);
+        assert_eq!(overflowed.exact_sum(), None);
+        assert!(overflowed.sum_is_imprecise());
+
+        assert!(stat(&[Some("9007199254740993.5"), Some("1")]).sum_is_imprecise());
+        let prices = stat(&[Some("12"), Some("3.50")]);
+        assert_eq!(prices.sum(), "15.5");
+        assert!(!prices.sum_is_imprecise());
+    }
+
+    #[test]
+    fn test_leading_zeros() {
+        let zip = stat(&[Some("02134"), Some("90210"), Some("")]);
+        assert!(!zip.is_numeric());
+        assert!(zip.has_leading_zeros());
+        assert_eq!(zip.infer_type(), "Text");
+
+        let amounts = stat(&[Some("0"), Some("0.25"), Some("10")]);
+        assert!(amounts.is_numeric());
+        assert!(!amounts.has_leading_zeros());
+        assert!(!stat(&[Some("007"), Some("bond")]).has_leading_zeros());
+    }
+
+    #[test]
+    fn test_boolean() {
+        let flags = stat(&[Some("Yes"), Some("no"), Some(""), None, Some("TRUE"), Some("t")]);
+        assert!(flags.is_boolean());
+        assert_eq!((flags.infer_type().as_str(), flags.n_true, flags.n_false), ("Boolean", 3, 1));
+        assert_eq!(flags.boolean_spellings(), (vec!["TRUE".to_string(), "Yes".to_string(), "t".to_string()], vec!["no".to_string()]));
+
+        assert!(!stat(&[Some("yes"), Some("maybe")]).is_boolean());
+        assert!(!stat(&[Some(""), None]).is_boolean());
+
+        let mut custom = CsvColumnStat::new(0, "col".to_string())
+            .boolean_values(BooleanValues::new(Some(vec!["y".to_string()]), Some(vec!["n".to_string()])));
+        [Some("Y"), Some("n")].iter().for_each(|v| custom.add(*v));
+        assert!(custom.is_boolean());
+        assert!(!stat(&[Some("Y"), Some("n")]).is_boolean());
+    }
+
+    #[test]
+    fn test_merge() {
+        let values = [Some("4"), Some("x"), None, Some("1.5"), Some(""), Some("010"), Some("x"), Some("-2")];
+        for include_empty in [false, true] {
+            let whole = stat_with(&values, include_empty);
+            for split in 0..=values.len() {
+                let mut merged = stat_with(&values[..split], include_empty);
+                merged.merge(stat_with(&values[split..], include_empty));
+                assert_eq!((merged.n, merged.n_numeric, merged.n_missing, merged.n_empty), (whole.n, whole.n_numeric, whole.n_missing, whole.n_empty));
+                assert_eq!((merged.n_true, merged.n_false, merged.n_leading_zeros), (whole.n_true, whole.n_false, whole.n_leading_zeros));
+                assert_eq!((merged.n_integer, merged.min_integer, merged.max_integer), (whole.n_integer, whole.min_integer, whole.max_integer));
+                assert_eq!((merged.min, merged.max, merged.sum, merged.integer_sum), (whole.min, whole.max, whole.sum, whole.integer_sum));
+                assert_eq!((&merged.min_str, &merged.max_str, merged.max_len), (&whole.min_str, &whole.max_str, whole.max_len));
+                assert!((merged.mean - whole.mean).abs() < 1e-9);
+                assert!((merged.stdev() - whole.stdev()).abs() < 1e-9);
+                assert_eq!(merged.distinct, whole.distinct);
+            }
+        }
+    }
+}
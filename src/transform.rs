@@ -0,0 +1,918 @@
+//! Per-record value transforms applied in-stream before column selection.
+//!
+//! Each transform names its columns with the usual column spec, resolved once against the
+//! input headers. Transforms run in the order they were given on the command line; transforms
+//! that add columns append them, so later transforms and the column selection can refer to them.
+
+use crate::csvutil;
+use crate::dates;
+use crate::log;
+use crate::numbers;
+use crate::stats::BooleanValues;
+use csv::StringRecord;
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+
+#[derive(Clone, Debug)]
+pub enum Transform {
+    /// Recode exact values, e.g. `M` to `Male`
+    Replace { columns: String, map: HashMap<String, String> },
+    /// Regex substitution, of the first match or of every match when `all` is set
+    Sub { columns: String, regex: Regex, replacement: String, all: bool },
+    /// Split one column on a separator into new columns appended to the record
+    Split { column: String, separator: String, names: Vec<String>, max_splits: Option<usize> },
+    /// Join columns and quoted literals into a new column appended to the record
+    Concat { name: String, parts: Vec<ConcatPart> },
+    /// Convert values between units, leaving values that aren't numbers or timestamps alone.
+    /// Numbers are written with `float_format` when given, see `--float-format`
+    Convert { columns: String, conversion: Conversion, float_format: Option<FloatFormat> },
+    /// Extract a value from a column holding JSON into a new column appended to the record
+    JsonExtract { column: String, path: Vec<JsonStep>, name: String },
+    /// Cut values longer than `limit` bytes short at a character boundary, logging the row of each
+    Truncate { columns: String, limit: usize },
+    /// One column's rules from a `--transform-config` file
+    Normalize { columns: String, rule: ColumnRule },
+}
+
+/// What `--transform-config` does to a column's values, in this order: trim, map nulls, change
+/// case, cast. A value mapped to `null_as` is left at that.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ColumnRule {
+    pub trim: bool,
+    /// Values that stand for null, e.g. `N/A`, compared after trimming
+    pub nulls: Vec<String>,
+    pub null_as: String,
+    pub case: Option<Case>,
+    pub cast: Option<Cast>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Case {
+    Lower,
+    Upper,
+    /// The first letter of each word upper case, the rest lower case
+    Title,
+}
+
+/// The type a value is rewritten as, leaving values that aren't of that type alone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cast {
+    /// Whole numbers, so `42.0` becomes `42`
+    Integer,
+    /// Numbers without float noise or trailing zeros, as `--convert` writes them
+    Number,
+    /// `true` or `false` from the usual spellings, e.g. `Y`, `no` or `1`
+    Boolean,
+    /// `YYYY-MM-DD` from a date or timestamp
+    Date,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonStep {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Conversion {
+    /// Multiply by a factor, e.g. 0.3048 for feet to meters
+    Scale(f64),
+    /// Scale to bytes, then write as a size like `1.5 MB`
+    HumanBytes(f64),
+    /// Scale to seconds, then write as a duration like `1h 30m`
+    HumanDuration(f64),
+    /// Read a duration like `1h 30m`, then divide by the target unit in seconds
+    FromHumanDuration(f64),
+    /// Seconds since the epoch to an ISO 8601 UTC timestamp
+    EpochToIso,
+    /// A date or timestamp to seconds since the epoch
+    IsoToEpoch,
+}
+
+/// A printf-style format for floats, `%.2f` or `%.3e`, from `--float-format`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FloatFormat {
+    /// This many decimals, like `%.2f`
+    Fixed(usize),
+    /// Scientific notation with this many decimals, like `%.3e` giving `1.500e+03`
+    Scientific(usize),
+}
+
+impl FloatFormat {
+    pub fn format(&self, n: f64) -> String {
+        if !n.is_finite() {
+            return n.to_string();
+        }
+        let formatted = match *self {
+            FloatFormat::Fixed(decimals) => format!("{:.*}", decimals, n),
+            FloatFormat::Scientific(decimals) => {
+                // Rust writes 1.5e3 where printf writes 1.5e+03
+                let formatted = format!("{:.*e}", decimals, n);
+                let (mantissa, exponent) = formatted.split_once('e').unwrap_or((&formatted, "0"));
+                let exponent = exponent.parse::<i32>().unwrap_or(0);
+                format!("{}e{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+            }
+        };
+        // Rounding a small negative number gives -0.00, which is just noise in a report
+        match formatted.strip_prefix('-') {
+            Some(unsigned) if unsigned.bytes().take_while(|&b| b != b'e').all(|b| b == b'0' || b == b'.') => unsigned.to_string(),
+            _ => formatted,
+        }
+    }
+}
+
+/// Parses `%.Nf` or `%.Ne`, where N defaults to 6 as in printf.
+pub fn parse_float_format(s: &str) -> Result<FloatFormat, String> {
+    let invalid = || format!("Invalid float format '{}'. Expected %.Nf or %.Ne, e.g. %.2f", s);
+    let spec = s.trim().strip_prefix('%').ok_or_else(invalid)?;
+    let (precision, conversion) = spec.split_at(spec.len().saturating_sub(1));
+    let decimals = match precision.strip_prefix('.') {
+        Some(digits) => digits.parse::<usize>().map_err(|_| invalid())?,
+        None if precision.is_empty() => 6,
+        None => return Err(invalid()),
+    };
+    match conversion {
+        "f" | "F" => Ok(FloatFormat::Fixed(decimals)),
+        "e" | "E" => Ok(FloatFormat::Scientific(decimals)),
+        _ => Err(invalid()),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConcatPart {
+    Column(String),
+    Literal(String),
+}
+
+impl Transform {
+    fn columns(&self) -> &str {
+        match self {
+            Transform::Replace { columns, .. } => columns,
+            Transform::Sub { columns, .. } => columns,
+            Transform::Split { column, .. } => column,
+            Transform::Concat { name, .. } => name,
+            Transform::Convert { columns, .. } => columns,
+            Transform::JsonExtract { column, .. } => column,
+            Transform::Truncate { columns, .. } => columns,
+            Transform::Normalize { columns, .. } => columns,
+        }
+    }
+
+    /// Names of the columns this transform appends.
+    fn new_columns(&self) -> &[String] {
+        match self {
+            Transform::Split { names, .. } => names,
+            Transform::Concat { name, .. } => std::slice::from_ref(name),
+            Transform::JsonExtract { name, .. } => std::slice::from_ref(name),
+            _ => &[],
+        }
+    }
+
+    fn apply(&self, value: &mut String, boolean_values: &BooleanValues) {
+        match self {
+            Transform::Replace { map, .. } => {
+                if let Some(new) = map.get(value.as_str()) {
+                    value.clone_from(new);
+                }
+            }
+            Transform::Sub { regex, replacement, all, .. } => {
+                let limit = if *all { 0 } else { 1 };
+                if let Cow::Owned(new) = regex.replacen(value, limit, replacement.as_str()) {
+                    *value = new;
+                }
+            }
+            Transform::Convert { conversion, float_format, .. } => {
+                if let Some(new) = convert(value, *conversion, *float_format) {
+                    *value = new;
+                }
+            }
+            Transform::Truncate { limit, .. } => {
+                if value.len() > *limit {
+                    value.truncate(value.floor_char_boundary(*limit));
+                }
+            }
+            Transform::Normalize { rule, .. } => normalize(value, rule, boolean_values),
+            Transform::Split { .. } | Transform::Concat { .. } | Transform::JsonExtract { .. } => {}
+        }
+    }
+
+    /// Values for the appended columns, padded with empty strings when the split yields fewer parts.
+    fn split(&self, value: &str) -> Vec<String> {
+        let Transform::Split { separator, names, max_splits, .. } = self else {
+            return vec![];
+        };
+        let parts = max_splits.map_or(names.len(), |max| names.len().min(max + 1));
+        let mut values = value.splitn(parts, separator.as_str()).map(|v| v.trim().to_string()).collect::<Vec<_>>();
+        values.resize(names.len(), String::new());
+        values
+    }
+}
+
+/// Parses `col:old=new` into a single-entry replacement.
+pub fn parse_replace(s: &str) -> Result<Transform, String> {
+    let (columns, mapping) = s.split_once(':')
+        .ok_or_else(|| format!("Invalid replacement '{}'. Expected column:old=new", s))?;
+    let (old, new) = mapping.split_once('=')
+        .ok_or_else(|| format!("Invalid replacement '{}'. Expected column:old=new", s))?;
+    Ok(Transform::Replace {
+        columns: columns.trim().to_string(),
+        map: HashMap::from([(old.to_string(), new.to_string())]),
+    })
+}
+
+/// Parses `col:map.csv`, loading old,new pairs from the first two columns of the file (after its header row).
+pub fn parse_replace_file(s: &str) -> Result<Transform, String> {
+    let (columns, file) = s.split_once(':')
+        .ok_or_else(|| format!("Invalid replacement file '{}'. Expected column:file.csv", s))?;
+    let mut reader = csv::Reader::from_path(file).map_err(|e| format!("{}: {}", file, e))?;
+    let mut map = HashMap::new();
+    for result in reader.records() {
+        let record = result.map_err(|e| format!("{}: {}", file, e))?;
+        match (record.get(0), record.get(1)) {
+            (Some(old), Some(new)) => map.insert(old.to_string(), new.to_string()),
+            _ => return Err(format!("{}: every row needs an old and a new value", file)),
+        };
+    }
+    Ok(Transform::Replace { columns: columns.trim().to_string(), map })
+}
+
+/// Splits sed-style `pattern/replacement/flags` on an unescaped delimiter; `\/` becomes `/`.
+fn split_unescaped(s: &str, delimiter: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(d) if d == delimiter => parts.last_mut().unwrap().push(d),
+                Some(d) => {
+                    parts.last_mut().unwrap().push(c);
+                    parts.last_mut().unwrap().push(d);
+                }
+                None => parts.last_mut().unwrap().push(c),
+            },
+            _ if c == delimiter => parts.push(String::new()),
+            _ => parts.last_mut().unwrap().push(c),
+        }
+    }
+    parts
+}
+
+/// Parses `col:/pattern/replacement/flags`, where any character may stand in for `/`, flags are
+/// `g` (replace every match) and `i` (ignore case), and the replacement may refer to capture
+/// groups as `\1`, `$1` or `${name}`.
+pub fn parse_sub(s: &str) -> Result<Transform, String> {
+    let invalid = || format!("Invalid substitution '{}'. Expected column:/pattern/replacement/", s);
+    let (columns, expression) = s.split_once(':').ok_or_else(invalid)?;
+    let delimiter = expression.chars().next().ok_or_else(invalid)?;
+    let parts = split_unescaped(&expression[delimiter.len_utf8()..], delimiter);
+    let [pattern, replacement, flags] = &parts[..] else {
+        return Err(invalid());
+    };
+    if let Some(flag) = flags.chars().find(|c| *c != 'g' && *c != 'i') {
+        return Err(format!("Unknown substitution flag '{}' in {}", flag, s));
+    }
+    let pattern = if flags.contains('i') { format!("(?i){}", pattern) } else { pattern.clone() };
+    let regex = Regex::new(&pattern).map_err(|e| e.to_string())?;
+    let backreference = Regex::new(r"\\(\d)").unwrap();
+    Ok(Transform::Sub {
+        columns: columns.trim().to_string(),
+        regex,
+        replacement: backreference.replace_all(replacement, "$${$1}").to_string(),
+        all: flags.contains('g'),
+    })
+}
+
+/// Parses `col:separator:name1,name2,...`. Spaces around the separator are ignored unless it is
+/// only spaces, and the split values are trimmed, so `name: ,:first,last` splits `Doe, Jane`.
+pub fn parse_split(s: &str) -> Result<Transform, String> {
+    let invalid = || format!("Invalid split '{}'. Expected column:separator:name1,name2", s);
+    let (column, rest) = s.split_once(':').ok_or_else(invalid)?;
+    let (separator, names) = rest.rsplit_once(':').ok_or_else(invalid)?;
+    let separator = if separator.trim().is_empty() { separator } else { separator.trim() };
+    let names = names.split(',').map(|n| n.trim().to_string()).collect::<Vec<_>>();
+    if separator.is_empty() || names.iter().any(|n| n.is_empty()) {
+        return Err(invalid());
+    }
+    Ok(Transform::Split { column: column.trim().to_string(), separator: separator.to_string(), names, max_splits: None })
+}
+
+/// Parses `name=part,part,...`, where each part is a column name or offset, or a literal in
+/// single or double quotes, e.g. `full_name=first,' ',last`.
+pub fn parse_concat(s: &str) -> Result<Transform, String> {
+    let invalid = || format!("Invalid concatenation '{}'. Expected name=column,'separator',column", s);
+    let (name, spec) = s.split_once('=').ok_or_else(invalid)?;
+    let mut parts = vec![];
+    let mut chars = spec.trim().chars().peekable();
+    while chars.peek().is_some() {
+        match chars.peek() {
+            Some(&quote) if quote == '\'' || quote == '"' => {
+                chars.next();
+                let literal = chars.by_ref().take_while(|&c| c != quote).collect::<String>();
+                parts.push(ConcatPart::Literal(literal));
+                while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                    chars.next();
+                }
+                if chars.next().is_some_and(|c| c != ',') {
+                    return Err(invalid());
+                }
+            }
+            _ => {
+                let column = chars.by_ref().take_while(|&c| c != ',').collect::<String>();
+                if column.trim().is_empty() {
+                    return Err(invalid());
+                }
+                parts.push(ConcatPart::Column(column.trim().to_string()));
+            }
+        }
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+    }
+    if name.trim().is_empty() || parts.is_empty() {
+        return Err(invalid());
+    }
+    Ok(Transform::Concat { name: name.trim().to_string(), parts })
+}
+
+/// The dimension and size of a unit, in bytes, meters or seconds. Size units are binary
+/// multiples, as in `--max-output-bytes`.
+fn unit(name: &str) -> Option<(&'static str, f64)> {
+    let unit = match name.to_ascii_lowercase().as_str() {
+        "b" | "byte" | "bytes" => ("size", 1.0),
+        "kb" | "kib" => ("size", 1024.0),
+        "mb" | "mib" => ("size", 1024.0 * 1024.0),
+        "gb" | "gib" => ("size", 1024.0 * 1024.0 * 1024.0),
+        "tb" | "tib" => ("size", 1024.0 * 1024.0 * 1024.0 * 1024.0),
+        "mm" => ("length", 0.001),
+        "cm" => ("length", 0.01),
+        "m" => ("length", 1.0),
+        "km" => ("length", 1000.0),
+        "in" => ("length", 0.0254),
+        "ft" => ("length", 0.3048),
+        "yd" => ("length", 0.9144),
+        "mi" => ("length", 1609.344),
+        "ms" => ("time", 0.001),
+        "s" | "sec" => ("time", 1.0),
+        "min" => ("time", 60.0),
+        "h" | "hr" => ("time", 3600.0),
+        "d" | "day" => ("time", 86400.0),
+        _ => return None,
+    };
+    Some(unit)
+}
+
+/// Parses `col:from:to`, e.g. `size:bytes:MB`, `height:ft:m`, `elapsed:s:human` or
+/// `created:epoch:iso`. `human` writes sizes and durations for reading, and reads durations back.
+pub fn parse_convert(s: &str) -> Result<Transform, String> {
+    let mut parts = s.rsplitn(3, ':');
+    let (Some(to), Some(from), Some(columns)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(format!("Invalid conversion '{}'. Expected column:from:to, e.g. size:bytes:MB", s));
+    };
+    let (from, to) = (from.trim(), to.trim());
+    let unknown = |u: &str| format!("Unknown unit '{}' in {}", u, s);
+    let conversion = match (from.to_ascii_lowercase().as_str(), to.to_ascii_lowercase().as_str()) {
+        ("epoch", "iso") => Conversion::EpochToIso,
+        ("iso", "epoch") => Conversion::IsoToEpoch,
+        (_, "human") => match unit(from).ok_or_else(|| unknown(from))? {
+            ("size", bytes) => Conversion::HumanBytes(bytes),
+            ("time", seconds) => Conversion::HumanDuration(seconds),
+            _ => return Err(format!("Only sizes and durations convert to human in {}", s)),
+        },
+        ("human", _) => match unit(to).ok_or_else(|| unknown(to))? {
+            ("time", seconds) => Conversion::FromHumanDuration(seconds),
+            _ => return Err(format!("Only durations convert from human in {}", s)),
+        },
+        _ => {
+            let (from_dimension, from_size) = unit(from).ok_or_else(|| unknown(from))?;
+            let (to_dimension, to_size) = unit(to).ok_or_else(|| unknown(to))?;
+            if from_dimension != to_dimension {
+                return Err(format!("Cannot convert {} to {}", from, to));
+            }
+            Conversion::Scale(from_size / to_size)
+        }
+    };
+    Ok(Transform::Convert { columns: columns.trim().to_string(), conversion, float_format: None })
+}
+
+/// Writes a computed number without float noise: at most six decimals, trailing zeros dropped.
+pub fn format_number(n: f64) -> String {
+    let formatted = format!("{:.6}", n);
+    let trimmed = formatted.trim_end_matches('0').trim_end_matches('.');
+    if trimmed == "-0" { "0".to_string() } else { trimmed.to_string() }
+}
+
+fn format_bytes(bytes: f64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB", "PB"];
+    let mut size = bytes;
+    let mut i = 0;
+    while size.abs() >= 1024.0 && i < units.len() - 1 {
+        size /= 1024.0;
+        i += 1;
+    }
+    if i == 0 { format!("{} B", format_number(size)) } else { format!("{:.1} {}", size, units[i]) }
+}
+
+/// The converted value, or None if it isn't a number, duration or timestamp as expected.
+/// `float_format` applies to the numbers written, not to sizes, durations or timestamps.
+fn convert(value: &str, conversion: Conversion, float_format: Option<FloatFormat>) -> Option<String> {
+    let number = || value.trim().parse::<f64>().ok().filter(|n| n.is_finite());
+    let write_number = |n: f64| float_format.map_or_else(|| format_number(n), |f| f.format(n));
+    match conversion {
+        Conversion::Scale(factor) => number().map(|n| write_number(n * factor)),
+        Conversion::HumanBytes(bytes) => number().map(|n| format_bytes(n * bytes)),
+        Conversion::HumanDuration(seconds) => number().map(|n| dates::format_duration((n * seconds).round() as i64)),
+        Conversion::FromHumanDuration(seconds) => {
+            dates::parse_duration(value).ok().filter(|_| !value.trim().is_empty()).map(|d| write_number(d as f64 / seconds))
+        }
+        Conversion::EpochToIso => number().map(|n| dates::format_timestamp(n.floor() as i64)),
+        Conversion::IsoToEpoch => dates::parse_timestamp(value).map(|t| t.to_string()),
+    }
+}
+
+/// Parses `col=limit`, e.g. `name=255`, where the limit is in bytes as legacy schemas count them.
+pub fn parse_truncate(s: &str) -> Result<Transform, String> {
+    let invalid = || format!("Invalid truncation '{}'. Expected column=limit, e.g. name=255", s);
+    let (columns, limit) = s.rsplit_once('=').ok_or_else(invalid)?;
+    let limit = limit.trim().parse::<usize>().map_err(|_| invalid())?;
+    if columns.trim().is_empty() {
+        return Err(invalid());
+    }
+    Ok(Transform::Truncate { columns: columns.trim().to_string(), limit })
+}
+
+fn title_case(value: &str) -> String {
+    let mut word_start = true;
+    value.chars().flat_map(|c| {
+        let cased = if word_start { c.to_uppercase().collect::<Vec<_>>() } else { c.to_lowercase().collect() };
+        word_start = !c.is_alphanumeric() && c != '\'';
+        cased
+    }).collect()
+}
+
+/// The value as `cast` writes it, or None if it isn't of that type. Booleans are spelled as
+/// `--true-values` and `--false-values` say.
+fn cast(value: &str, cast: Cast, boolean_values: &BooleanValues) -> Option<String> {
+    match cast {
+        // Read as an integer rather than a float, so large values keep every digit
+        Cast::Integer => {
+            let value = value.trim();
+            let value = match value.split_once('.') {
+                Some((integer, fraction)) if fraction.bytes().all(|b| b == b'0') => integer,
+                _ => value,
+            };
+            numbers::parse_integer(value, false).map(|n| n.to_string())
+        }
+        Cast::Number => numbers::parse_number(value, false).filter(|n| n.is_finite()).map(format_number),
+        Cast::Boolean => boolean_values.parse(value.trim()).map(|b| b.to_string()),
+        // A bare number would be taken as seconds since the epoch, which is rarely what a date column means
+        Cast::Date => dates::parse_days(value).filter(|_| value.trim().parse::<i64>().is_err()).map(|days| {
+            let (year, month, day) = dates::civil_from_days(days);
+            format!("{:04}-{:02}-{:02}", year, month, day)
+        }),
+    }
+}
+
+fn normalize(value: &mut String, rule: &ColumnRule, boolean_values: &BooleanValues) {
+    if rule.trim && value.trim().len() != value.len() {
+        *value = value.trim().to_string();
+    }
+    if rule.nulls.iter().any(|null| null == value) {
+        value.clone_from(&rule.null_as);
+        return;
+    }
+    match rule.case {
+        Some(Case::Lower) => *value = value.to_lowercase(),
+        Some(Case::Upper) => *value = value.to_uppercase(),
+        Some(Case::Title) => *value = title_case(value),
+        None => {}
+    }
+    if let Some(new) = rule.cast.and_then(|c| cast(value, c, boolean_values)) {
+        *value = new;
+    }
+}
+
+/// Reads the per-column rules of a TOML file, one `[columns.NAME]` table per column spec:
+///
+/// ```toml
+/// [columns.email]
+/// trim = true
+/// nulls = ["", "N/A"]
+/// case = "lower"
+///
+/// [columns."signup date"]
+/// cast = "date"
+/// ```
+///
+/// Keys are `trim`, `nulls`, `null_as` (default empty), `case` (lower, upper or title) and
+/// `cast` (integer, number, boolean or date). Unknown keys are errors, so typos don't go unnoticed.
+pub fn parse_transform_config(path: &str) -> Result<Vec<Transform>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+    let config = text.parse::<toml::Table>().map_err(|e| format!("Invalid transform config {}: {}", path, e))?;
+    if let Some(key) = config.keys().find(|k| *k != "columns") {
+        return Err(format!("{}: unknown key '{}'. Rules go in [columns.NAME] tables", path, key));
+    }
+    let Some(columns) = config.get("columns") else {
+        return Ok(vec![]);
+    };
+    let columns = columns.as_table().ok_or_else(|| format!("{}: columns must be a table", path))?;
+    columns.iter().map(|(column, rules)| {
+        let invalid = |key: &str, expected: &str| format!("{}: {} for column {} must be {}", path, key, column, expected);
+        let rules = rules.as_table().ok_or_else(|| invalid("the rules", "a table"))?;
+        let mut rule = ColumnRule::default();
+        for (key, value) in rules {
+            match key.as_str() {
+                "trim" => rule.trim = value.as_bool().ok_or_else(|| invalid(key, "true or false"))?,
+                "nulls" => rule.nulls = value.as_array()
+                    .and_then(|values| values.iter().map(|v| v.as_str().map(str::to_string)).collect::<Option<Vec<_>>>())
+                    .ok_or_else(|| invalid(key, "a list of strings"))?,
+                "null_as" => rule.null_as = value.as_str().ok_or_else(|| invalid(key, "a string"))?.to_string(),
+                "case" => rule.case = Some(match value.as_str() {
+                    Some("lower") => Case::Lower,
+                    Some("upper") => Case::Upper,
+                    Some("title") => Case::Title,
+                    _ => return Err(invalid(key, "lower, upper or title")),
+                }),
+                "cast" => rule.cast = Some(match value.as_str() {
+                    Some("integer") => Cast::Integer,
+                    Some("number") => Cast::Number,
+                    Some("boolean") => Cast::Boolean,
+                    Some("date") => Cast::Date,
+                    _ => return Err(invalid(key, "integer, number, boolean or date")),
+                }),
+                _ => return Err(format!("{}: unknown rule '{}' for column {}", path, key, column)),
+            }
+        }
+        Ok(Transform::Normalize { columns: column.clone(), rule })
+    }).collect()
+}
+
+/// Parses a JSONPath-style path: `$` followed by `.key`, `[0]` or `["key"]` steps, e.g. `$.user.id`
+/// or `$.items[0]["unit price"]`.
+fn parse_json_path(path: &str) -> Option<Vec<JsonStep>> {
+    let mut rest = path.trim().strip_prefix('$')?;
+    let mut steps = vec![];
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            if end == 0 {
+                return None;
+            }
+            steps.push(JsonStep::Key(after[..end].to_string()));
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']')?;
+            let inside = after[..end].trim();
+            let key = inside.strip_prefix('"').and_then(|k| k.strip_suffix('"'))
+                .or_else(|| inside.strip_prefix('\'').and_then(|k| k.strip_suffix('\'')));
+            steps.push(match key {
+                Some(key) => JsonStep::Key(key.to_string()),
+                None => JsonStep::Index(inside.parse().ok()?),
+            });
+            rest = &after[end + 1..];
+        } else {
+            return None;
+        }
+    }
+    Some(steps)
+}
+
+/// Parses `column:path=name`, e.g. `payload:$.user.id=user_id`.
+pub fn parse_json_extract(s: &str) -> Result<Transform, String> {
+    let invalid = || format!("Invalid JSON extraction '{}'. Expected column:$.path=name, e.g. payload:$.user.id=user_id", s);
+    let (column, rest) = s.split_once(":$").ok_or_else(invalid)?;
+    let (path, name) = rest.rsplit_once('=').ok_or_else(invalid)?;
+    let path = parse_json_path(&format!("${}", path)).ok_or_else(invalid)?;
+    if column.trim().is_empty() || name.trim().is_empty() {
+        return Err(invalid());
+    }
+    Ok(Transform::JsonExtract { column: column.trim().to_string(), path, name: name.trim().to_string() })
+}
+
+/// The value at `path` in a JSON document: strings as they are, other scalars as JSON, objects
+/// and arrays as compact JSON. Invalid JSON, a missing value and null all give an empty string.
+fn json_extract(document: &str, path: &[JsonStep]) -> String {
+    let Ok(json) = serde_json::from_str::<serde_json::Value>(document) else {
+        return String::new();
+    };
+    let value = path.iter().try_fold(&json, |value, step| match step {
+        JsonStep::Key(key) => value.get(key),
+        JsonStep::Index(i) => value.get(i),
+    });
+    match value {
+        None | Some(serde_json::Value::Null) => String::new(),
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(other) => other.to_string(),
+    }
+}
+
+pub struct Pipeline {
+    steps: Vec<(Vec<usize>, Transform)>,
+    /// Input headers followed by the names of any appended columns
+    headers: StringRecord,
+    input_len: usize,
+    /// The spellings `cast = "boolean"` recognizes
+    boolean_values: BooleanValues,
+}
+
+impl Pipeline {
+    /// Resolves each transform's columns against the headers and the columns appended before it,
+    /// counting offsets from 0 when `zero_based` is set.
+    pub fn new(transforms: &[Transform], headers: &StringRecord, zero_based: bool) -> Result<Self, Box<dyn Error>> {
+        let mut steps = vec![];
+        let mut all_headers = headers.clone();
+        for transform in transforms {
+            let indices = match transform {
+                Transform::Split { column, .. } | Transform::JsonExtract { column, .. } => vec![csvutil::select_column_index_with_base(&all_headers, column, zero_based)?],
+                Transform::Concat { parts, .. } => parts.iter()
+                    .filter_map(|part| match part {
+                        ConcatPart::Column(column) => Some(csvutil::select_column_index_with_base(&all_headers, column, zero_based)),
+                        ConcatPart::Literal(_) => None,
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+                _ => csvutil::select_column_indices_with_base(&all_headers, &Some(vec![transform.columns().to_string()]), zero_based)?,
+            };
+            transform.new_columns().iter().for_each(|name| all_headers.push_field(name));
+            steps.push((indices, transform.clone()));
+        }
+        Ok(Pipeline { steps, headers: all_headers, input_len: headers.len(), boolean_values: BooleanValues::default() })
+    }
+
+    /// Cast booleans with these spellings rather than the defaults.
+    pub fn boolean_values(mut self, boolean_values: BooleanValues) -> Self {
+        self.boolean_values = boolean_values;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// The headers of the transformed records, to resolve column selections against.
+    pub fn headers(&self) -> &StringRecord {
+        &self.headers
+    }
+
+    /// Transforms the values of the `row`th record, counting from 1, which `--truncate` reports.
+    pub fn apply(&self, row: u64, values: &mut Vec<String>) {
+        if self.headers.len() > self.input_len && values.len() < self.input_len {
+            // Keep appended columns aligned on short records
+            values.resize(self.input_len, String::new());
+        }
+        for (indices, transform) in &self.steps {
+            if let Transform::Split { .. } = transform {
+                let parts = transform.split(values.get(indices[0]).map_or("", |v| v.as_str()));
+                values.extend(parts);
+                continue;
+            }
+            if let Transform::JsonExtract { path, .. } = transform {
+                let extracted = json_extract(values.get(indices[0]).map_or("", |v| v.as_str()), path);
+                values.push(extracted);
+                continue;
+            }
+            if let Transform::Concat { parts, .. } = transform {
+                let mut columns = indices.iter();
+                let joined = parts.iter().map(|part| match part {
+                    ConcatPart::Column(_) => columns.next().and_then(|&i| values.get(i)).map_or("", |v| v.as_str()),
+                    ConcatPart::Literal(literal) => literal.as_str(),
+                }).collect::<String>();
+                values.push(joined);
+                continue;
+            }
+            if let Transform::Truncate { limit, .. } = transform {
+                for &i in indices {
+                    if let Some(value) = values.get_mut(i).filter(|v| v.len() > *limit) {
+                        log::warning(&format!("Row {}: truncated {} from {} to {} bytes", row, &self.headers[i], value.len(), limit));
+                        transform.apply(value, &self.boolean_values);
+                    }
+                }
+                continue;
+            }
+            for &i in indices {
+                if let Some(value) = values.get_mut(i) {
+                    transform.apply(value, &self.boolean_values);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(transform: &Transform, value: &str) -> String {
+        let mut value = value.to_string();
+        transform.apply(&mut value, &BooleanValues::default());
+        value
+    }
+
+    #[test]
+    fn test_parse_sub() {
+        let sub = parse_sub("phone:/(\\d{3})-(\\d{4})/\\1\\2/").unwrap();
+        assert_eq!(apply(&sub, "555-1234 or 555-9876"), "5551234 or 555-9876");
+
+        let sub = parse_sub("phone:/(\\d{3})-(\\d{4})/$2.$1/g").unwrap();
+        assert_eq!(apply(&sub, "555-1234 or 555-9876"), "1234.555 or 9876.555");
+
+        let sub = parse_sub("path:|a/b|\\||i").unwrap();
+        assert_eq!(apply(&sub, "A/B/C"), "|/C");
+
+        assert!(parse_sub("col:/a/b").is_err());
+        assert!(parse_sub("col:/a/b/x").is_err());
+        assert!(parse_sub("col:/(/b/").is_err());
+    }
+
+    #[test]
+    fn test_parse_convert() {
+        let convert = |spec: &str, value: &str| apply(&parse_convert(spec).unwrap(), value);
+        assert_eq!(convert("size:bytes:MB", "1572864"), "1.5");
+        assert_eq!(convert("size:MB:bytes", "2"), "2097152");
+        assert_eq!(convert("size:bytes:human", "1572864"), "1.5 MB");
+        assert_eq!(convert("size:bytes:human", "512"), "512 B");
+        assert_eq!(convert("height:ft:m", "3"), "0.9144");
+        assert_eq!(convert("height:m:ft", "1"), "3.28084");
+        assert_eq!(convert("elapsed:s:human", "95405"), "1d 2h 30m 5s");
+        assert_eq!(convert("elapsed:ms:human", "1500"), "2s");
+        assert_eq!(convert("elapsed:human:s", "1h 30m"), "5400");
+        assert_eq!(convert("elapsed:human:min", "1h30m"), "90");
+        assert_eq!(convert("created:epoch:iso", "1709814615"), "2024-03-07T12:30:15Z");
+        assert_eq!(convert("created:epoch:iso", "-1"), "1969-12-31T23:59:59Z");
+        assert_eq!(convert("created:iso:epoch", "2024-03-07T12:30:15Z"), "1709814615");
+
+        // Values that don't parse are left alone
+        assert_eq!(convert("size:bytes:MB", "n/a"), "n/a");
+        assert_eq!(convert("size:bytes:MB", ""), "");
+        assert_eq!(convert("elapsed:human:s", ""), "");
+        assert_eq!(convert("created:iso:epoch", "soon"), "soon");
+
+        assert!(parse_convert("height:ft:kg").is_err());
+        assert!(parse_convert("height:ft:s").is_err());
+        assert!(parse_convert("height:ft:human").is_err());
+        assert!(parse_convert("height:ft").is_err());
+        let Ok(Transform::Convert { columns, .. }) = parse_convert("a:b:s:ms") else {
+            panic!("Expected a conversion");
+        };
+        assert_eq!(columns, "a:b");
+    }
+
+    #[test]
+    fn test_float_format() {
+        assert_eq!(parse_float_format("%.2f"), Ok(FloatFormat::Fixed(2)));
+        assert_eq!(parse_float_format("%f"), Ok(FloatFormat::Fixed(6)));
+        assert_eq!(parse_float_format("%.3e"), Ok(FloatFormat::Scientific(3)));
+        assert!(parse_float_format("%.2d").is_err());
+        assert!(parse_float_format(".2f").is_err());
+        assert!(parse_float_format("%2f").is_err());
+
+        assert_eq!(FloatFormat::Fixed(2).format(0.1 + 0.2), "0.30");
+        assert_eq!(FloatFormat::Fixed(0).format(2.5), "2");
+        assert_eq!(FloatFormat::Fixed(2).format(-0.001), "0.00");
+        assert_eq!(FloatFormat::Scientific(3).format(1500.0), "1.500e+03");
+        assert_eq!(FloatFormat::Scientific(1).format(-0.00012), "-1.2e-04");
+        assert_eq!(FloatFormat::Fixed(2).format(f64::NAN), "NaN");
+
+        let Ok(Transform::Convert { columns, conversion, .. }) = parse_convert("height:ft:m") else {
+            panic!("Expected a conversion");
+        };
+        let convert = Transform::Convert { columns, conversion, float_format: Some(FloatFormat::Fixed(2)) };
+        assert_eq!(apply(&convert, "3"), "0.91");
+    }
+
+    #[test]
+    fn test_json_extract() {
+        let Ok(Transform::JsonExtract { column, path, name }) = parse_json_extract("payload:$.user.id=user_id") else {
+            panic!("Expected a JSON extraction");
+        };
+        assert_eq!((column.as_str(), name.as_str()), ("payload", "user_id"));
+        assert_eq!(path, vec![JsonStep::Key("user".to_string()), JsonStep::Key("id".to_string())]);
+        assert_eq!(parse_json_path("$.items[1][\"unit price\"]"),
+                   Some(vec![JsonStep::Key("items".to_string()), JsonStep::Index(1), JsonStep::Key("unit price".to_string())]));
+        assert_eq!(parse_json_path("$"), Some(vec![]));
+        assert!(parse_json_extract("payload:user.id=user_id").is_err());
+        assert!(parse_json_extract("payload:$.user..id=user_id").is_err());
+        assert!(parse_json_extract("payload:$.user[x]=user_id").is_err());
+        assert!(parse_json_extract("payload:$.user.id=").is_err());
+
+        let document = r#"{"user": {"id": 42, "name": "Ann", "tags": ["a", "b"]}, "ok": true, "none": null}"#;
+        let extract = |path: &str| json_extract(document, &parse_json_path(path).unwrap());
+        assert_eq!(extract("$.user.id"), "42");
+        assert_eq!(extract("$.user.name"), "Ann");
+        assert_eq!(extract("$.user.tags"), r#"["a","b"]"#);
+        assert_eq!(extract("$.user.tags[1]"), "b");
+        assert_eq!(extract("$.ok"), "true");
+        assert_eq!(extract("$.none"), "");
+        assert_eq!(extract("$.missing.id"), "");
+        assert_eq!(json_extract("not json", &[]), "");
+    }
+
+    #[test]
+    fn test_parse_split() {
+        let split = parse_split("name: ,:first,last").unwrap();
+        assert_eq!(split.split("Doe, Jane"), vec!["Doe", "Jane"]);
+        assert_eq!(split.split("Cher"), vec!["Cher", ""]);
+        assert_eq!(split.split("Doe, Jane, Jr"), vec!["Doe", "Jane, Jr"]);
+
+        let mut split = parse_split("date:-:y,m,d").unwrap();
+        assert_eq!(split.split("2024-01-02"), vec!["2024", "01", "02"]);
+        if let Transform::Split { max_splits, .. } = &mut split {
+            *max_splits = Some(1);
+        }
+        assert_eq!(split.split("2024-01-02"), vec!["2024", "01-02", ""]);
+
+        let split = parse_split("name: :first,last").unwrap();
+        assert_eq!(split.split("Jane Doe"), vec!["Jane", "Doe"]);
+
+        assert!(parse_split("name:first,last").is_err());
+        assert!(parse_split("name::first,last").is_err());
+        assert!(parse_split("name:,:first,").is_err());
+    }
+
+    #[test]
+    fn test_parse_concat() {
+        let Transform::Concat { name, parts } = parse_concat("full_name=first, ' ', last").unwrap() else {
+            panic!("Expected a concatenation");
+        };
+        assert_eq!(name, "full_name");
+        assert_eq!(parts, vec![ConcatPart::Column("first".to_string()), ConcatPart::Literal(" ".to_string()),
+                               ConcatPart::Column("last".to_string())]);
+
+        let Transform::Concat { parts, .. } = parse_concat("key=1,\",\",-1").unwrap() else {
+            panic!("Expected a concatenation");
+        };
+        assert_eq!(parts[1], ConcatPart::Literal(",".to_string()));
+
+        assert!(parse_concat("first,last").is_err());
+        assert!(parse_concat("key=").is_err());
+        assert!(parse_concat("key=a,,b").is_err());
+        assert!(parse_concat("key='x'y").is_err());
+    }
+
+    #[test]
+    fn test_parse_truncate() {
+        let truncate = parse_truncate("name=5").unwrap();
+        assert_eq!(apply(&truncate, "Anne"), "Anne");
+        assert_eq!(apply(&truncate, "Annabelle"), "Annab");
+        // ë is two bytes, so it doesn't fit after the first four
+        assert_eq!(apply(&truncate, "Chloë"), "Chlo");
+        assert_eq!(apply(&truncate, "東京都"), "東");
+
+        assert!(parse_truncate("name").is_err());
+        assert!(parse_truncate("name=many").is_err());
+        assert!(parse_truncate("=5").is_err());
+    }
+
+    #[test]
+    fn test_normalize() {
+        let rule = ColumnRule {
+            trim: true,
+            nulls: vec!["N/A".to_string(), "".to_string()],
+            null_as: "NULL".to_string(),
+            case: Some(Case::Title),
+            cast: None,
+        };
+        let normalize = |rule: &ColumnRule, value: &str| apply(&Transform::Normalize { columns: "1".to_string(), rule: rule.clone() }, value);
+        assert_eq!(normalize(&rule, "  jean-luc O'BRIEN "), "Jean-Luc O'brien");
+        assert_eq!(normalize(&rule, " N/A "), "NULL");
+        assert_eq!(normalize(&rule, "  "), "NULL");
+
+        let cast_to = |c: Cast, value: &str| normalize(&ColumnRule { cast: Some(c), ..Default::default() }, value);
+        assert_eq!(cast_to(Cast::Integer, "42.0"), "42");
+        assert_eq!(cast_to(Cast::Integer, "42.5"), "42.5");
+        assert_eq!(cast_to(Cast::Integer, "9007199254740993"), "9007199254740993");
+        assert_eq!(cast_to(Cast::Integer, "1,234"), "1234");
+        assert_eq!(cast_to(Cast::Number, "1.50"), "1.5");
+        assert_eq!(cast_to(Cast::Boolean, "Yes"), "true");
+        assert_eq!(cast_to(Cast::Boolean, "maybe"), "maybe");
+        let spellings = BooleanValues::new(Some(vec!["Y".to_string()]), Some(vec!["N".to_string()]));
+        assert_eq!(cast(" y ", Cast::Boolean, &spellings), Some("true".to_string()));
+        assert_eq!(cast("yes", Cast::Boolean, &spellings), None);
+        assert_eq!(cast_to(Cast::Date, "2024-03-07T12:30:15Z"), "2024-03-07");
+        assert_eq!(cast_to(Cast::Date, "20240307"), "20240307");
+        // An impossible date is left as it is, not rolled over into March
+        assert_eq!(cast_to(Cast::Date, "2024-02-31"), "2024-02-31");
+    }
+
+    #[test]
+    fn test_parse_transform_config() {
+        let dir = csvutil::TestDir::new();
+        let path = dir.path("rules.toml");
+        std::fs::write(&path, "[columns.email]\ntrim = true\nnulls = [\"N/A\"]\ncase = \"lower\"\n\n[columns.\"signup date\"]\ncast = \"date\"\n").unwrap();
+        let transforms = parse_transform_config(&path).unwrap();
+        assert_eq!(transforms.len(), 2);
+        let Transform::Normalize { columns, rule } = &transforms[0] else {
+            panic!("Expected a normalization");
+        };
+        assert_eq!(columns, "email");
+        assert_eq!(rule, &ColumnRule { trim: true, nulls: vec!["N/A".to_string()], case: Some(Case::Lower), ..Default::default() });
+        assert!(matches!(&transforms[1], Transform::Normalize { columns, rule } if columns == "signup date" && rule.cast == Some(Cast::Date)));
+
+        std::fs::write(&path, "[columns.email]\ncase = \"camel\"\n").unwrap();
+        assert!(parse_transform_config(&path).unwrap_err().contains("lower, upper or title"));
+        std::fs::write(&path, "[columns.email]\ntirm = true\n").unwrap();
+        assert!(parse_transform_config(&path).unwrap_err().contains("unknown rule 'tirm'"));
+        std::fs::write(&path, "[email]\ntrim = true\n").unwrap();
+        assert!(parse_transform_config(&path).is_err());
+    }
+}
@@ -0,0 +1,577 @@
+use crate::args;
+use crate::csvutil;
+use crate::log;
+use crate::options::{CsvOptions, Metrics};
+use crate::reader::RecordScanner;
+use csv::{StringRecord, Writer, WriterBuilder};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The csv writer every binary writes its records through, on top of `CsvOptions::get_output_file`.
+pub struct CsvWriter {
+    /// The csv writer, or once `write_raw` has been called the output it wrote to, see `Output`
+    output: Option<Output>,
+    delimiter: u8,
+    // --follow flushes each record so it shows up as soon as it's read
+    flush_each_record: bool,
+    manifest: Option<Manifest>,
+    enforcer: Option<Enforcer>,
+    in_place: Option<InPlace>,
+    metrics: Option<Arc<Metrics>>,
+    /// Records written, the header row included
+    records: u64,
+    has_headers: bool,
+}
+
+/// Records are written through the csv writer, or for `--raw-passthrough` straight to the
+/// output it wraps, which the csv writer can't hand out while it owns it.
+enum Output {
+    Csv(Box<Writer<HashingWriter>>),
+    Raw(HashingWriter),
+}
+
+/// What `--on-schema-violation` does with a value that doesn't fit its column's contract.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ViolationPolicy {
+    /// Stop with an error naming the record and column
+    #[default]
+    Fail,
+    /// Empty the value, failing only where the column is required
+    Blank,
+    /// Write it anyway, reporting how many there were at the end
+    Keep,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum FieldKind {
+    Integer,
+    Number,
+    /// With the accepted spellings of true and false
+    Boolean(Vec<String>),
+    Any,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct ContractField {
+    name: String,
+    kind: FieldKind,
+    required: bool,
+}
+
+impl ContractField {
+    fn check(&self, value: &[u8]) -> Option<String> {
+        if value.is_empty() {
+            return self.required.then(|| "is required but empty".to_string());
+        }
+        let value = String::from_utf8_lossy(value);
+        let (fits, expected) = match &self.kind {
+            FieldKind::Integer => (value.parse::<i128>().is_ok(), "an integer"),
+            FieldKind::Number => (value.parse::<f64>().is_ok(), "a number"),
+            FieldKind::Boolean(spellings) => (spellings.iter().any(|s| *s == value), "a boolean"),
+            FieldKind::Any => (true, ""),
+        };
+        (!fits).then(|| format!("should be {}, got {:?}", expected, value))
+    }
+}
+
+/// `--enforce-schema`: the columns the output must have, in order, read from a Table Schema or
+/// a `csvschema --format json` file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Contract {
+    fields: Vec<ContractField>,
+    pub policy: ViolationPolicy,
+}
+
+pub fn parse_contract(path: &str) -> Result<Contract, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+    let schema: Value = serde_json::from_str(&text).map_err(|e| format!("Invalid schema {}: {}", path, e))?;
+    let fields = schema.get("fields").unwrap_or(&schema).as_array()
+        .ok_or_else(|| format!("Schema {} has no list of fields", path))?;
+    let fields = fields.iter().map(|field| {
+        let name = field["name"].as_str().ok_or_else(|| format!("Schema {} has a field without a name", path))?;
+        let spellings = |key: &str, default: &[&str]| field[key].as_array()
+            .map(|v| v.iter().filter_map(|s| s.as_str().map(str::to_string)).collect::<Vec<_>>())
+            .unwrap_or_else(|| default.iter().map(|s| s.to_string()).collect());
+        let kind = match field["type"].as_str().unwrap_or_default().to_lowercase().as_str() {
+            "integer" => FieldKind::Integer,
+            "number" => FieldKind::Number,
+            "boolean" => FieldKind::Boolean([
+                spellings("trueValues", &["true", "True", "TRUE", "1"]),
+                spellings("falseValues", &["false", "False", "FALSE", "0"]),
+            ].concat()),
+            _ => FieldKind::Any,
+        };
+        let required = field["constraints"]["required"].as_bool()
+            .or_else(|| field["nullable"].as_bool().map(|n| !n))
+            .unwrap_or(false);
+        Ok(ContractField { name: name.to_string(), kind, required })
+    }).collect::<Result<Vec<_>, String>>()?;
+    Ok(Contract { fields, policy: ViolationPolicy::Fail })
+}
+
+/// Rearranges each record to the contract's columns: by name after the header row, or by
+/// position when the output has none.
+struct Enforcer {
+    contract: Contract,
+    headers_pending: bool,
+    /// Per contracted column, where it is in the tool's records, if anywhere
+    indices: Option<Vec<Option<usize>>>,
+    records: u64,
+    violations: u64,
+}
+
+impl Enforcer {
+    fn apply(&mut self, fields: Vec<Vec<u8>>) -> csv::Result<Vec<Vec<u8>>> {
+        let contract = &self.contract.fields;
+        if self.headers_pending {
+            self.headers_pending = false;
+            let names = fields.iter().map(|f| String::from_utf8_lossy(f).to_string()).collect::<Vec<_>>();
+            let indices = contract.iter().map(|f| names.iter().position(|n| *n == f.name)).collect::<Vec<_>>();
+            let missing = contract.iter().zip(&indices).filter(|(_, i)| i.is_none()).map(|(f, _)| f.name.as_str()).collect::<Vec<_>>();
+            if !missing.is_empty() {
+                log::warning(&format!("--enforce-schema added empty columns missing from the output: {}", missing.join(", ")));
+            }
+            let extra = names.iter().filter(|n| !contract.iter().any(|f| f.name == **n)).map(String::as_str).collect::<Vec<_>>();
+            if !extra.is_empty() {
+                log::warning(&format!("--enforce-schema dropped columns not in the schema: {}", extra.join(", ")));
+            }
+            self.indices = Some(indices);
+            return Ok(contract.iter().map(|f| f.name.as_bytes().to_vec()).collect());
+        }
+
+        self.records += 1;
+        let mut out = Vec::with_capacity(contract.len());
+        for (i, field) in contract.iter().enumerate() {
+            let index = match &self.indices {
+                Some(indices) => indices[i],
+                None => Some(i),
+            };
+            let mut value = index.and_then(|j| fields.get(j)).cloned().unwrap_or_default();
+            if let Some(problem) = field.check(&value) {
+                match (self.contract.policy, field.required) {
+                    (ViolationPolicy::Blank, false) => value.clear(),
+                    (ViolationPolicy::Keep, _) => self.violations += 1,
+                    _ => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                                   format!("Record {}: column {} {}", self.records, field.name, problem)).into()),
+                }
+            }
+            out.push(value);
+        }
+        Ok(out)
+    }
+}
+
+/// What `--write-manifest` reports about the records written, gathered as they pass through.
+struct Manifest {
+    path: String,
+    headers_pending: bool,
+    names: Vec<String>,
+    /// Per column, whether every non-empty value so far is a number
+    numeric: Vec<bool>,
+    records: u64,
+}
+
+impl Manifest {
+    fn add(&mut self, fields: &[Vec<u8>]) {
+        if self.headers_pending {
+            self.headers_pending = false;
+            self.names = fields.iter().map(|f| String::from_utf8_lossy(f).to_string()).collect();
+            return;
+        }
+        self.records += 1;
+        if self.numeric.len() < fields.len() {
+            self.numeric.resize(fields.len(), true);
+        }
+        for (numeric, field) in self.numeric.iter_mut().zip(fields) {
+            *numeric &= field.is_empty() || std::str::from_utf8(field).is_ok_and(|v| v.trim().parse::<f64>().is_ok());
+        }
+    }
+}
+
+/// Passes output through while counting bytes and hashing them, for the manifest.
+struct HashingWriter {
+    inner: Box<dyn Write>,
+    hasher: Option<Sha256>,
+    bytes: u64,
+}
+
+/// Whether an error comes from the reader of the output having gone, as when piping into
+/// `head`. `CsvOptions::finish` ends such a run quietly, like the standard Unix filters do on
+/// SIGPIPE.
+pub fn is_broken_pipe(error: &(dyn Error + 'static)) -> bool {
+    let io_error = match error.downcast_ref::<csv::Error>().map(csv::Error::kind) {
+        Some(csv::ErrorKind::Io(e)) => Some(e),
+        _ => error.downcast_ref::<io::Error>(),
+    };
+    io_error.is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe)
+}
+
+pub const RED: &str = "31";
+pub const GREEN: &str = "32";
+
+/// A value wrapped in an ANSI SGR color code, for `--color`. Empty values are left as they are.
+pub fn colored(value: &str, code: &str) -> String {
+    if value.is_empty() {
+        return String::new();
+    }
+    format!("\x1b[{}m{}\x1b[0m", code, value)
+}
+
+impl Write for HashingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(&buf[..n]);
+        }
+        self.bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl CsvWriter {
+    pub fn new(options: &CsvOptions) -> Result<Self, Box<dyn Error>> {
+        let manifest = options.manifest_file.as_ref().map(|path| Manifest {
+            path: path.clone(),
+            headers_pending: options.output_has_headers(),
+            names: vec![],
+            numeric: vec![],
+            records: 0,
+        });
+        let enforcer = options.contract.as_ref().map(|contract| Enforcer {
+            contract: contract.clone(),
+            headers_pending: options.output_has_headers(),
+            indices: None,
+            records: 0,
+            violations: 0,
+        });
+        let (inner, in_place): (Box<dyn Write>, _) = match (&options.in_place, &options.input_file) {
+            (Some(backup_suffix), Some(input)) => {
+                let (file, in_place) = InPlace::create(input, backup_suffix)?;
+                (Box::new(BufWriter::new(file)), Some(in_place))
+            }
+            _ => (options.get_output_file()?, None),
+        };
+        let output = HashingWriter { inner, hasher: manifest.as_ref().map(|_| Sha256::new()), bytes: 0 };
+        // What goes back on the clipboard keeps the delimiter it was copied with
+        let delimiter = if options.clipboard { options.config().delimiter } else { b',' };
+        Ok(CsvWriter {
+            output: Some(Output::Csv(Box::new(WriterBuilder::new().delimiter(delimiter).from_writer(output)))),
+            delimiter,
+            flush_each_record: options.follow,
+            manifest,
+            enforcer,
+            in_place,
+            metrics: options.metrics.clone(),
+            records: 0,
+            has_headers: options.output_has_headers(),
+        })
+    }
+
+    fn writer(&mut self) -> &mut Writer<HashingWriter> {
+        self.output = match self.output.take() {
+            Some(Output::Raw(output)) => Some(Output::Csv(Box::new(WriterBuilder::new().delimiter(self.delimiter).from_writer(output)))),
+            output => output,
+        };
+        match self.output.as_mut() {
+            Some(Output::Csv(writer)) => writer,
+            _ => unreachable!("the output is always set"),
+        }
+    }
+
+    /// The output with whatever the csv writer held written to it.
+    fn raw_output(&mut self) -> io::Result<&mut HashingWriter> {
+        self.output = match self.output.take() {
+            Some(Output::Csv(writer)) => Some(Output::Raw(writer.into_inner().map_err(|e| e.into_error())?)),
+            output => output,
+        };
+        match self.output.as_mut() {
+            Some(Output::Raw(output)) => Ok(output),
+            _ => unreachable!("the output is always set"),
+        }
+    }
+
+    pub fn write_record<I, T>(&mut self, record: I) -> csv::Result<()>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        if self.manifest.is_none() && self.enforcer.is_none() {
+            self.writer().write_record(record)?;
+        } else {
+            let mut fields = record.into_iter().map(|f| f.as_ref().to_vec()).collect::<Vec<_>>();
+            if let Some(enforcer) = self.enforcer.as_mut() {
+                fields = enforcer.apply(fields)?;
+            }
+            if let Some(manifest) = self.manifest.as_mut() {
+                manifest.add(&fields);
+            }
+            self.writer().write_record(&fields)?;
+        }
+        self.records += 1;
+        if self.flush_each_record {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes a record as the bytes it was read as, for `--raw-passthrough`, with `values` for
+    /// the manifest. A last record without a line ending gets one. `CsvOptions::validate` keeps
+    /// `--enforce-schema` away, as it would have to rewrite the record.
+    pub fn write_raw(&mut self, raw: &[u8], values: &StringRecord) -> io::Result<()> {
+        if let Some(manifest) = self.manifest.as_mut() {
+            manifest.add(&values.iter().map(|v| v.as_bytes().to_vec()).collect::<Vec<_>>());
+        }
+        let output = self.raw_output()?;
+        output.write_all(raw)?;
+        if !raw.ends_with(b"\n") {
+            output.write_all(b"\n")?;
+        }
+        self.records += 1;
+        if self.flush_each_record {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.output.as_mut() {
+            Some(Output::Csv(writer)) => writer.flush(),
+            Some(Output::Raw(output)) => output.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Flushes the output, writes the manifest if one was asked for, adds to the `--summary`
+    /// counts and, with `--in-place`, replaces the input with the output.
+    pub fn finish(mut self) -> Result<(), Box<dyn Error>> {
+        self.flush()?;
+        if let Some(enforcer) = self.enforcer.as_ref().filter(|e| e.violations > 0) {
+            log::warning(&format!("{} values don't fit --enforce-schema", enforcer.violations));
+        }
+        self.raw_output()?;
+        let Some(Output::Raw(mut output)) = self.output.take() else {
+            unreachable!("raw_output leaves the output raw");
+        };
+        if let Some(metrics) = &self.metrics {
+            Metrics::add(&metrics.records_written, self.records.saturating_sub(self.has_headers as u64));
+            Metrics::add(&metrics.bytes_written, output.bytes);
+        }
+        if let Some(manifest) = self.manifest.take() {
+            write_manifest(manifest, &mut output)?;
+        }
+        drop(output);
+        if let Some(in_place) = self.in_place.take() {
+            in_place.commit()?;
+        }
+        Ok(())
+    }
+}
+
+fn write_manifest(manifest: Manifest, output: &mut HashingWriter) -> Result<(), Box<dyn Error>> {
+    let checksum = output.hasher.take().map(|h| format!("{:x}", h.finalize())).unwrap_or_default();
+    let n_columns = manifest.names.len().max(manifest.numeric.len());
+    let columns = (0..n_columns).map(|i| json!({
+        "name": manifest.names.get(i).cloned().unwrap_or_else(|| csvutil::generated_column_name(i)),
+        "type": if manifest.numeric.get(i).copied().unwrap_or(false) { "number" } else { "text" },
+    })).collect::<Vec<_>>();
+    let mut args = std::env::args();
+    let tool = args.next().map(|a| Path::new(&a).file_name().map_or(a.clone(), |n| n.to_string_lossy().to_string()));
+    let json = json!({
+        "tool": tool,
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_hash": args::GIT_HASH,
+        "features": args::FEATURES.split(',').filter(|f| !f.is_empty()).collect::<Vec<_>>(),
+        "arguments": args.collect::<Vec<_>>(),
+        "records": manifest.records,
+        "bytes": output.bytes,
+        "sha256": checksum,
+        "columns": columns,
+    });
+    std::fs::write(&manifest.path, serde_json::to_string_pretty(&json)? + "\n")?;
+    Ok(())
+}
+
+/// Writes output to a series of files named `out.part-0001.csv`, `out.part-0002.csv`, ...
+/// starting a new part whenever the next record would push the current one past `max_bytes`.
+///
+/// The csv writer in front of this flushes whenever its buffer fills, which can be in the middle
+/// of a record, so bytes are held back until a `RecordScanner` sees the record end before
+/// deciding which part it goes to. When `has_headers` is set the first record is remembered and
+/// repeated at the top of every part.
+pub struct RollingWriter {
+    path: PathBuf,
+    max_bytes: u64,
+    header_pending: bool,
+    header: Vec<u8>,
+    scanner: RecordScanner,
+    /// The record being written, up to the last byte seen
+    record: Vec<u8>,
+    part: usize,
+    written: u64,
+    current: BufWriter<File>,
+}
+
+impl RollingWriter {
+    pub fn new(path: &str, max_bytes: u64, has_headers: bool) -> io::Result<Self> {
+        let path = PathBuf::from(path);
+        let current = BufWriter::new(File::create(part_path(&path, 1))?);
+        Ok(RollingWriter {
+            path, max_bytes, header_pending: has_headers, header: vec![], scanner: RecordScanner::new(b'"'),
+            record: vec![], part: 1, written: 0, current,
+        })
+    }
+
+    fn roll(&mut self) -> io::Result<()> {
+        self.current.flush()?;
+        self.part += 1;
+        self.current = BufWriter::new(File::create(part_path(&self.path, self.part))?);
+        self.current.write_all(&self.header)?;
+        self.written = self.header.len() as u64;
+        Ok(())
+    }
+
+    /// Writes the record held back, to a new part if it doesn't fit in the current one.
+    fn end_record(&mut self) -> io::Result<()> {
+        let record = std::mem::take(&mut self.record);
+        if self.header_pending {
+            self.header_pending = false;
+            self.header = record.clone();
+        } else if self.written > self.header.len() as u64 && self.written + record.len() as u64 > self.max_bytes {
+            self.roll()?;
+        }
+        self.current.write_all(&record)?;
+        self.written += record.len() as u64;
+        Ok(())
+    }
+}
+
+impl Write for RollingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for line in buf.split_inclusive(|&b| b == b'\n') {
+            self.record.extend_from_slice(line);
+            // Quotes are counted a piece at a time, so a line split across writes scans the same
+            if self.scanner.line_ends_record(line) && line.ends_with(b"\n") {
+                self.end_record()?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.current.flush()
+    }
+}
+
+impl Drop for RollingWriter {
+    /// Writes out a last record left without a line ending, as by an unterminated quote.
+    fn drop(&mut self) {
+        if !self.record.is_empty() {
+            let _ = self.end_record().and_then(|_| self.current.flush());
+        }
+    }
+}
+
+/// Inserts the part number before the extension, e.g. `out.csv` becomes `out.part-0001.csv`.
+pub fn part_path(path: &Path, part: usize) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}.part-{:04}.{}", stem, part, ext.to_string_lossy()),
+        None => format!("{}.part-{:04}", stem, part),
+    };
+    path.with_file_name(name)
+}
+
+/// `--in-place`: the output goes to a temporary file beside the input, which replaces the input
+/// only once everything has been written, so a failure leaves the input as it was.
+pub struct InPlace {
+    input: PathBuf,
+    temp: tempfile::TempPath,
+    /// A handle on the temporary file, to sync it before it replaces the input
+    file: File,
+    /// Keep the original under its name with this appended, unless empty
+    backup_suffix: String,
+}
+
+impl InPlace {
+    pub fn create(input: &str, backup_suffix: &str) -> io::Result<(File, InPlace)> {
+        // Rewriting a symlink's target rather than replacing the link with a file
+        let input = std::fs::canonicalize(input)?;
+        let dir = input.parent().unwrap_or(Path::new("."));
+        let (file, temp) = tempfile::Builder::new().prefix(".csvstar-").suffix(".tmp").tempfile_in(dir)?.into_parts();
+        file.set_permissions(std::fs::metadata(&input)?.permissions())?;
+        let handle = file.try_clone()?;
+        Ok((file, InPlace { input, temp, file: handle, backup_suffix: backup_suffix.to_string() }))
+    }
+
+    pub fn commit(self) -> io::Result<()> {
+        if !self.backup_suffix.is_empty() {
+            let mut backup = self.input.clone().into_os_string();
+            backup.push(&self.backup_suffix);
+            match std::fs::remove_file(&backup) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            std::fs::hard_link(&self.input, &backup).or_else(|_| std::fs::copy(&self.input, &backup).map(drop))?;
+        }
+        // On disk before the rename, so a crash can't leave the input replaced by a partial file
+        self.file.sync_all()?;
+        self.temp.persist(&self.input).map_err(|e| e.error)?;
+        // And the rename itself on disk, which on unix is the directory's to sync
+        #[cfg(unix)]
+        File::open(self.input.parent().unwrap_or(Path::new(".")))?.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Copies everything written to each of several outputs, for `-o` given more than once.
+///
+/// An output whose reader has gone, as `-o -` piped into `head`, is dropped and the others are
+/// written in full. Only once every output has gone is the broken pipe returned.
+pub struct TeeWriter {
+    outputs: Vec<Box<dyn Write>>,
+}
+
+impl TeeWriter {
+    pub fn new(outputs: Vec<Box<dyn Write>>) -> Self {
+        TeeWriter { outputs }
+    }
+
+    /// Runs `f` on each output, dropping those whose reader has gone.
+    fn for_each_output(&mut self, mut f: impl FnMut(&mut dyn Write) -> io::Result<()>) -> io::Result<()> {
+        let mut error = None;
+        self.outputs.retain_mut(|output| match f(output.as_mut()) {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => false,
+            Err(e) => {
+                error.get_or_insert(e);
+                true
+            }
+            Ok(()) => true,
+        });
+        match error {
+            Some(e) => Err(e),
+            None if self.outputs.is_empty() => Err(io::ErrorKind::BrokenPipe.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for TeeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.for_each_output(|output| output.write_all(buf))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.for_each_output(|output| output.flush())
+    }
+}
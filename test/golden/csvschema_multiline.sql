@@ -0,0 +1,5 @@
+CREATE TABLE "multiline" (
+    "id" INTEGER NOT NULL,
+    "name" TEXT NOT NULL,
+    "notes" TEXT NOT NULL
+);
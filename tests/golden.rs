@@ -0,0 +1,87 @@
+//! Runs the compiled tools on the fixtures in `test/fixtures` and compares their output with the
+//! golden files in `test/golden`. Set `UPDATE_GOLDEN=1` to rewrite the golden files from the
+//! current output, then review the diff.
+
+use assert_cmd::Command;
+use std::fs;
+use std::path::Path;
+
+fn assert_golden(golden: &str, tool: &str, args: &[&str]) {
+    let output = Command::cargo_bin(tool).expect("Unable to find tool")
+        .args(args)
+        .output()
+        .expect("Unable to run tool");
+    assert!(output.status.success(), "{} failed: {}", golden, String::from_utf8_lossy(&output.stderr));
+
+    let path = Path::new("test/golden").join(golden);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, &output.stdout).expect("Unable to write golden file");
+        return;
+    }
+    let expected = fs::read(&path).unwrap_or_else(|e| panic!("Unable to read {}: {}. Run with UPDATE_GOLDEN=1 to create it", path.display(), e));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&expected), "{}", golden);
+}
+
+#[test]
+fn test_quoted() {
+    assert_golden("csvcut_quoted.csv", "csvcut", &["-c", "comment,name", "test/fixtures/quoted.csv"]);
+    assert_golden("csvmask_quoted.csv", "csvmask", &["--mask", "name=redact:2", "test/fixtures/quoted.csv"]);
+    assert_golden("csvlook_quoted.txt", "csvlook", &["test/fixtures/quoted.csv"]);
+}
+
+#[test]
+fn test_multiline() {
+    assert_golden("csvcut_multiline.csv", "csvcut", &["-c", "notes,id", "test/fixtures/multiline.csv"]);
+    if cfg!(feature = "fast-engine") {
+        assert_golden("csvcut_multiline_fast.csv", "csvcut", &["--engine", "fast", "-c", "notes,id", "test/fixtures/multiline.csv"]);
+    }
+    assert_golden("csvschema_multiline.sql", "csvschema", &["--format", "sql", "test/fixtures/multiline.csv"]);
+    assert_golden("csvlook_multiline.txt", "csvlook", &["--sample", "1", "test/fixtures/multiline.csv"]);
+}
+
+#[test]
+fn test_unicode() {
+    assert_golden("csvfreq_unicode.csv", "csvfreq", &["-c", "city", "test/fixtures/unicode.csv"]);
+    assert_golden("csvtop_unicode.csv", "csvtop", &["-g", "country", "--by", "population", "test/fixtures/unicode.csv"]);
+    assert_golden("csvmelt_unicode.csv", "csvmelt", &["-i", "city", "test/fixtures/unicode.csv"]);
+    assert_golden("csvlook_unicode.txt", "csvlook", &["test/fixtures/unicode.csv"]);
+}
+
+#[test]
+fn test_ragged() {
+    assert_golden("csvcut_ragged.csv", "csvcut", &["--flexible", "-c", "1,2", "test/fixtures/ragged.csv"]);
+
+    let output = Command::cargo_bin("csvcut").expect("Unable to find tool")
+        .args(["-c", "1", "test/fixtures/ragged.csv"])
+        .output()
+        .expect("Unable to run tool");
+    assert!(!output.status.success(), "ragged input accepted without --flexible");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("found record with 2 fields"));
+
+    if cfg!(feature = "fast-engine") {
+        assert_golden("csvcut_ragged_fast.csv", "csvcut", &["--engine", "fast", "--flexible", "-c", "1,2", "test/fixtures/ragged.csv"]);
+
+        let output = Command::cargo_bin("csvcut").expect("Unable to find tool")
+            .args(["--engine", "fast", "-c", "1", "test/fixtures/ragged.csv"])
+            .output()
+            .expect("Unable to run tool");
+        assert!(!output.status.success(), "ragged input accepted by --engine fast without --flexible");
+        assert!(String::from_utf8_lossy(&output.stderr).contains("found record with 2 fields"));
+    }
+}
+
+#[test]
+fn test_generated() {
+    // The same seed must keep giving the same fixtures
+    assert_golden("csvgen_spec.csv", "csvgen", &["--rows", "25", "--seed", "42", "test/fixtures/gen_spec.json"]);
+}
+
+#[test]
+fn test_excel_artifacts() {
+    assert_golden("csvclean_excel.csv", "csvclean", &["--unformula", "--nbsp", "--smart-quotes", "--trailing-delimiter", "test/fixtures/excel.csv"]);
+}
+
+#[test]
+fn test_transform_config() {
+    assert_golden("csvcut_feed_rules.csv", "csvcut", &["--transform-config", "test/fixtures/feed_rules.toml", "--true-values", "y,yes,1", "--false-values", "n,no,0", "test/fixtures/feed.csv"]);
+}
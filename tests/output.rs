@@ -0,0 +1,170 @@
+//! Every tool writes the same bytes to standard output (`-o -`) as to a file (`-o FILE`), since
+//! both go through `CsvOptions::get_output_file`.
+
+use std::fs;
+use std::process::Command;
+
+fn assert_same_output(name: &str, exe: &str, args: &[&str]) {
+    let stdout = Command::new(exe).args(args).args(["-o", "-"]).output().expect("Unable to run tool");
+    assert!(stdout.status.success(), "{} failed: {}", name, String::from_utf8_lossy(&stdout.stderr));
+    assert!(!stdout.stdout.is_empty(), "{} wrote nothing", name);
+
+    let output_file = std::env::temp_dir().join(format!("csvstar-output-{}-{}.out", std::process::id(), name));
+    let file = Command::new(exe).args(args).arg("-o").arg(&output_file).output().expect("Unable to run tool");
+    assert!(file.status.success(), "{} failed: {}", name, String::from_utf8_lossy(&file.stderr));
+    assert!(file.stdout.is_empty(), "{} wrote to stdout with -o FILE", name);
+
+    let written = fs::read(&output_file).expect("Unable to read output file");
+    fs::remove_file(&output_file).expect("Unable to delete output file");
+    assert_eq!(String::from_utf8_lossy(&stdout.stdout), String::from_utf8_lossy(&written), "{}", name);
+}
+
+#[test]
+fn test_csvcut() {
+    assert_same_output("csvcut", env!("CARGO_BIN_EXE_csvcut"), &["-c", "col3,col1", "test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvstat() {
+    assert_same_output("csvstat", env!("CARGO_BIN_EXE_csvstat"), &["--csv", "--jobs", "1", "test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvschema() {
+    assert_same_output("csvschema", env!("CARGO_BIN_EXE_csvschema"), &["--format", "sql", "test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvmask() {
+    assert_same_output("csvmask", env!("CARGO_BIN_EXE_csvmask"), &["--mask", "col2=hash", "test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvwindow() {
+    assert_same_output("csvwindow", env!("CARGO_BIN_EXE_csvwindow"), &["--cumsum", "col2", "test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvmelt() {
+    assert_same_output("csvmelt", env!("CARGO_BIN_EXE_csvmelt"), &["-i", "col1", "test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvpivot() {
+    assert_same_output("csvpivot", env!("CARGO_BIN_EXE_csvpivot"), &["-k", "col1", "--pivot", "col2", "--value", "col3", "test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvlookup() {
+    assert_same_output("csvlookup", env!("CARGO_BIN_EXE_csvlookup"),
+                       &["-l", "test/test_input.csv", "--on", "col1", "--add", "col3", "test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvencoding() {
+    assert_same_output("csvencoding", env!("CARGO_BIN_EXE_csvencoding"), &["test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvfreq() {
+    assert_same_output("csvfreq", env!("CARGO_BIN_EXE_csvfreq"), &["-c", "col1,col2", "test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvtop() {
+    assert_same_output("csvtop", env!("CARGO_BIN_EXE_csvtop"), &["-g", "col1", "--by", "col3", "test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvtemplate() {
+    assert_same_output("csvtemplate", env!("CARGO_BIN_EXE_csvtemplate"), &["-t", "{{ col1 }}-{{ col3 }}", "test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvgen() {
+    assert_same_output("csvgen", env!("CARGO_BIN_EXE_csvgen"), &["--rows", "20", "test/fixtures/gen_spec.json"]);
+}
+
+#[test]
+fn test_csvperturb() {
+    assert_same_output("csvperturb", env!("CARGO_BIN_EXE_csvperturb"), &["--seed", "1", "test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvclean() {
+    assert_same_output("csvclean", env!("CARGO_BIN_EXE_csvclean"), &["--nbsp", "test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvvalidate() {
+    let schema_file = std::env::temp_dir().join(format!("csvstar-output-{}-schema.json", std::process::id()));
+    fs::write(&schema_file, r#"{"fields": [{"name": "col1", "type": "integer"}]}"#).expect("Unable to write schema file");
+    assert_same_output("csvvalidate", env!("CARGO_BIN_EXE_csvvalidate"), &["-s", schema_file.to_str().unwrap(), "test/test_input.csv"]);
+    fs::remove_file(&schema_file).expect("Unable to delete schema file");
+}
+
+#[test]
+fn test_csvjson() {
+    assert_same_output("csvjson", env!("CARGO_BIN_EXE_csvjson"), &["test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvsample() {
+    assert_same_output("csvsample", env!("CARGO_BIN_EXE_csvsample"), &["-N", "2", "--seed", "1", "test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvdedup() {
+    assert_same_output("csvdedup", env!("CARGO_BIN_EXE_csvdedup"), &["-c", "col1", "test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvslice() {
+    assert_same_output("csvslice", env!("CARGO_BIN_EXE_csvslice"), &["-r", "2-3", "test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvstack() {
+    assert_same_output("csvstack", env!("CARGO_BIN_EXE_csvstack"), &["test/test_input.csv", "test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvjoin() {
+    assert_same_output("csvjoin", env!("CARGO_BIN_EXE_csvjoin"), &["-c", "col1", "test/test_input.csv", "test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvdiff() {
+    assert_same_output("csvdiff", env!("CARGO_BIN_EXE_csvdiff"), &["-k", "col1", "test/test_input.csv", "test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvpatch() {
+    let patch_file = std::env::temp_dir().join(format!("csvstar-output-{}-patch.csv", std::process::id()));
+    fs::write(&patch_file, "col1,change,column,old_value,new_value\n1,changed,col2,2,20\n").expect("Unable to write patch file");
+    assert_same_output("csvpatch", env!("CARGO_BIN_EXE_csvpatch"), &["--patch", patch_file.to_str().unwrap(), "test/test_input.csv"]);
+    fs::remove_file(&patch_file).expect("Unable to delete patch file");
+}
+
+#[test]
+fn test_csvgrep() {
+    assert_same_output("csvgrep", env!("CARGO_BIN_EXE_csvgrep"), &["-c", "col2", "--match", "5", "test/test_input.csv"]);
+}
+
+#[test]
+fn test_csvlook() {
+    assert_same_output("csvlook", env!("CARGO_BIN_EXE_csvlook"), &["test/test_input.csv"]);
+}
+
+#[test]
+fn test_in2csv() {
+    let input_file = std::env::temp_dir().join(format!("csvstar-output-{}-in2csv.html", std::process::id()));
+    fs::write(&input_file, "<table><tr><th>a</th><th>b</th></tr><tr><td>1</td><td>2</td></tr></table>").expect("Unable to write input file");
+    assert_same_output("in2csv", env!("CARGO_BIN_EXE_in2csv"), &[input_file.to_str().unwrap()]);
+    fs::remove_file(&input_file).expect("Unable to delete input file");
+}
+
+#[test]
+fn test_csvsort() {
+    assert_same_output("csvsort", env!("CARGO_BIN_EXE_csvsort"), &["-c", "col2", "--collate", "natural", "test/test_input.csv"]);
+}